@@ -0,0 +1,93 @@
+//! parsing of a large template, `cargo bench`.
+//! `borrowed` is how the sources are parsed, `copied` parses a copy of the source like the parser used to.
+//! Both print the memory allocated at the peak of a parse, which is what the borrowing saves.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use flan::driver::{file_to_parser, string_to_parser_in};
+use flan::error::{ErrorFlags, Handler};
+use flan::sourcemap::{SourceInfo, SrcFile, SrcMap};
+use flan::utils::pp_bytes;
+
+/// counts the allocated bytes, see [`peak_alloc`]
+struct Counting;
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let p = System.alloc(layout);
+        if !p.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Relaxed) + layout.size();
+            PEAK.fetch_max(current, Relaxed);
+        }
+        p
+    }
+    unsafe fn dealloc(&self, p: *mut u8, layout: Layout) {
+        System.dealloc(p, layout);
+        CURRENT.fetch_sub(layout.size(), Relaxed);
+    }
+}
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// bytes allocated at the peak of `f`, on top of the ones allocated before
+fn peak_alloc<T, F: FnOnce() -> T>(f: F) -> usize {
+    let base = CURRENT.load(Relaxed);
+    PEAK.store(base, Relaxed);
+    drop(f());
+    PEAK.load(Relaxed) - base
+}
+
+/// about 2MiB of text, variables and dimensions
+fn large_file() -> (Arc<SrcMap>, SrcFile) {
+    let line = "key = #os{linux: apt##windows: choco}# #$user:-nobody# some text \\## escaped\n";
+    let path = std::env::temp_dir().join(format!("flan-bench-{}", std::process::id()));
+    std::fs::write(&path, line.repeat(2 * 1024 * 1024 / line.len())).unwrap();
+    let sources = SrcMap::new();
+    let f = sources.load_file(path.clone(), "out".into()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    (sources, f)
+}
+
+/// runs `f` for about a second and prints the mean time of a run and the throughput of `bytes`
+fn bench<T, F: FnMut() -> T>(name: &str, bytes: usize, mut f: F) {
+    let (start, mut runs) = (Instant::now(), 0u32);
+    while runs == 0 || start.elapsed() < Duration::from_secs(1) {
+        drop(f());
+        runs += 1;
+    }
+    let mean = start.elapsed() / runs;
+    let throughput = bytes as f64 / mean.as_secs_f64();
+    println!("{}: {:?} per run ({} runs), {}/s", name, mean, runs, pp_bytes(throughput as usize));
+}
+
+fn parse_borrowed() {
+    let (sources, f) = large_file();
+    let parse = || {
+        let mut h = Handler::new(ErrorFlags::default(), sources.clone());
+        file_to_parser(&mut h, &f).unwrap().parse().unwrap()
+    };
+    eprintln!("borrowed: peak of {} for a {} source", pp_bytes(peak_alloc(parse)), pp_bytes(f.size()));
+    bench("borrowed", f.size(), parse);
+}
+
+fn parse_copied() {
+    let (sources, f) = large_file();
+    let src = match &f.src {
+        SourceInfo::Source(s) => s,
+        SourceInfo::Binary | SourceInfo::Symlink(_) => unreachable!(),
+    };
+    let parse = || {
+        let mut h = Handler::new(ErrorFlags::default(), sources.clone());
+        string_to_parser_in(&mut h, src.clone(), f.id).unwrap().parse().unwrap()
+    };
+    eprintln!("copied: peak of {} for a {} source", pp_bytes(peak_alloc(parse)), pp_bytes(src.len()));
+    bench("copied", src.len(), parse);
+}
+
+fn main() {
+    parse_borrowed();
+    parse_copied();
+}
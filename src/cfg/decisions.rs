@@ -0,0 +1,105 @@
+//! the decisions of the command line, the matrix and the lockfile: `NAME` or `DIMENSION=CHOICE`
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+use super::Error;
+use crate::infer::ChoiceIdx;
+
+/// parse a list of decisions into standalone names and `dimension=index` pairs
+pub fn parse_decisions<Str: AsRef<str>>(decisions: &[Str]) -> Result<(HashSet<String>, HashMap<String, Index>), Error> {
+    let mut nc = HashSet::new();
+    let mut dc = HashMap::new();
+    for s in decisions {
+        match Decision::from_str(s.as_ref())? {
+            Decision::Name(s) => {
+                nc.insert(s);
+            }
+            Decision::WithDim(dname, idx) => {
+                dc.insert(dname, idx);
+            }
+        }
+    }
+    Ok((nc, dc))
+}
+
+#[derive(Hash, Debug, PartialEq, Clone)]
+/// command line passed Decision
+pub enum Decision {
+    /// by name
+    Name(String),
+    /// (dimension name, decision index or name) pair.
+    WithDim(String, Index),
+}
+impl FromStr for Decision {
+    type Err = Error;
+    /// parse one decision
+    fn from_str(str: &str) -> Result<Self, Error> {
+        let mut it = str.splitn(2, '=');
+        // splitn will give us at the very least "" as first elem
+        let k = it.next().unwrap().trim();
+        let i = it.next();
+        match i {
+            Some(s) => Self::parse_dim(k, s.trim()),
+            None => Self::parse_name(k),
+        }
+    }
+}
+impl Decision {
+    /// [`Decision::WithDim`]
+    fn parse_dim(k: &str, i: &str) -> Result<Self, Error> {
+        Self::validate_id(k)?;
+        let idx = Self::parse_idx(i)?;
+        Ok(Self::WithDim(k.into(), idx))
+    }
+    /// [`Decision::Name`]
+    fn parse_name(n: &str) -> Result<Self, Error> {
+        Self::validate_id(n)?;
+        Ok(Self::Name(n.into()))
+    }
+    /// [`Index`]
+    fn parse_idx(s: &str) -> Result<Index, Error> {
+        use std::num::IntErrorKind;
+        match s.parse() {
+            Ok(i) => Ok(Index::Num(i)),
+            Err(IntErrorKind::PosOverflow) => Err(Error::out_of_range(s)),
+            _ => {
+                if Self::validate_id(s).is_ok() {
+                    Ok(Index::Name(s.into()))
+                } else {
+                    Err(Error::invalid_choice(s))
+                }
+            }
+        }
+    }
+    fn validate_id(s: &str) -> Result<(), Error> {
+        if is_identifier(s) {
+            Ok(())
+        } else {
+            Err(Error::invalid_identifier(s))
+        }
+    }
+}
+
+/// names of dimensions and choices: a letter or `_`, then alphanumeric characters or `_`
+pub fn is_identifier(s: &str) -> bool {
+    s.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && !s.contains(|c: char| !c.is_alphanumeric() && c != '_')
+}
+
+/// decision for an explicitly named dimension
+#[derive(Debug, Clone, PartialEq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Index {
+    /// by name
+    Name(String),
+    /// by index
+    Num(ChoiceIdx),
+}
+impl std::fmt::Display for Index {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Index::Name(s) => write!(f, "{}", s),
+            Index::Num(n) => write!(f, "{}", n),
+        }
+    }
+}
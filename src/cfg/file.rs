@@ -1,55 +1,515 @@
 //! configuration file
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 use serde::Deserialize;
 use toml::de;
 
+use super::Index;
+use crate::infer::ChoiceIdx;
+use crate::syntax::lexer::Delimiters;
+
 /// contents of a configuration file.
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default)]
 pub struct File {
+    /// config file this one inherits from, relative to this one. see [`File::extend`]
+    pub extends: Option<PathBuf>,
     /// changing defaults. similar to cmd-line args
     pub options: Option<Options>,
     /// variable declarations
-    pub variables: Option<HashMap<String, String>>,
+    pub variables: Option<HashMap<String, Variable>>,
     /// dimension declarations
-    pub dimensions: Option<HashMap<String, Choices>>,
+    pub dimensions: Option<Dimensions>,
     /// source -> destination map
-    pub paths: Option<HashMap<PathBuf, PathBuf>>,
+    pub paths: Option<Paths>,
+    /// how the sources are loaded
+    pub files: Option<Files>,
+    /// named decision bundles, selected with `--profile`
+    pub profiles: Option<HashMap<String, Profile>>,
+    /// name -> condition the decisions must satisfy, same syntax as the guards e.g. `!(os=windows && init=systemd)`
+    pub constraints: Option<HashMap<String, String>>,
+    /// delimiters of the sources
+    pub syntax: Option<Syntax>,
+    /// pattern -> comment of the headers written by `--headers`
+    pub headers: Option<HashMap<String, HeaderDecl>>,
+    /// name -> template rendered by `flan test`
+    pub tests: Option<HashMap<String, TestDecl>>,
+    /// commands run before and after writing
+    pub hooks: Option<Hooks>,
+    /// `variables.NAME`, `dimensions.NAME`, `aliases.NAME`, `constraints.NAME`, `paths.SOURCE` or `syntax` -> the file it was inherited from
+    #[serde(skip)]
+    pub origins: HashMap<String, PathBuf>,
 }
-impl File {
-    pub fn from_str(s: &str) -> Result<Self, de::Error> {
+impl FromStr for File {
+    type Err = de::Error;
+    fn from_str(s: &str) -> Result<Self, de::Error> {
         toml::from_str(s)
     }
-
+}
+impl File {
     pub fn dimensions(&self) -> impl Iterator<Item = (&String, &Choices)> + '_ {
-        self.dimensions.iter().flatten()
+        self.dimensions.iter().flat_map(|d| d.map.iter())
     }
-    pub fn variables(&self) -> impl Iterator<Item = (&String, &String)> + '_ {
+    pub fn aliases(&self) -> impl Iterator<Item = (&String, &String)> + '_ {
+        self.dimensions.iter().flat_map(|d| d.aliases.iter())
+    }
+    pub fn variables(&self) -> impl Iterator<Item = (&String, &Variable)> + '_ {
         self.variables.iter().flatten()
     }
     pub fn paths(&self) -> impl Iterator<Item = (&PathBuf, &PathBuf)> + '_ {
-        self.paths.iter().flatten()
+        self.paths.iter().flat_map(|p| p.map.iter())
     }
-    pub fn variables_cloned(&self) -> impl Iterator<Item = (String, String)> + '_ {
+    pub fn variables_cloned(&self) -> impl Iterator<Item = (String, Variable)> + '_ {
         self.variables.clone().into_iter().flatten()
     }
     pub fn dimensions_cloned(&self) -> impl Iterator<Item = (String, Choices)> + '_ {
-        self.dimensions.clone().into_iter().flatten()
+        self.dimensions.clone().into_iter().flat_map(|d| d.map)
+    }
+    /// merges `base`, the file at `base_path` this one extends: the values of `self` take precedence,
+    /// the patterns of `include`, `ignore` and `[files]` are added to the ones of `base`, and the delimiters of `[syntax]`
+    /// are merged one by one.
+    /// The inherited values are recorded in [`File::origins`].
+    pub fn extend(mut self, base: File, base_path: &Path) -> Self {
+        let mut base_origins = base.origins;
+        let origins = &mut self.origins;
+        let mut origin = |key: String| {
+            let path = base_origins.remove(&key).unwrap_or_else(|| base_path.into());
+            origins.insert(key, path);
+        };
+        let variables = merge(self.variables.unwrap_or_default(), base.variables.unwrap_or_default(), |n| {
+            origin(format!("variables.{}", n))
+        });
+        let (dims, base_dims) = (self.dimensions.unwrap_or_default(), base.dimensions.unwrap_or_default());
+        let dimensions = Dimensions {
+            aliases: merge(dims.aliases, base_dims.aliases, |n| origin(format!("aliases.{}", n))),
+            map: merge(dims.map, base_dims.map, |n| origin(format!("dimensions.{}", n))),
+        };
+        let (paths, base_paths) = (self.paths.unwrap_or_default(), base.paths.unwrap_or_default());
+        let paths = Paths {
+            include: [base_paths.include, paths.include].concat(),
+            ignore: [base_paths.ignore, paths.ignore].concat(),
+            map: merge(paths.map, base_paths.map, |s| origin(format!("paths.{}", s.display()))),
+            overrides: merge(paths.overrides, base_paths.overrides, |s| origin(format!("paths.{}", s.display()))),
+            targets: merge(paths.targets, base_paths.targets, |s| origin(format!("paths.{}", s.display()))),
+        };
+        let (files, base_files) = (self.files.unwrap_or_default(), base.files.unwrap_or_default());
+        let files = Files {
+            binary: [base_files.binary, files.binary].concat(),
+            text: [base_files.text, files.text].concat(),
+            lossy: [base_files.lossy, files.lossy].concat(),
+        };
+        let options = match (self.options, base.options) {
+            (Some(o), Some(b)) => Some(o.or(b)),
+            (o, b) => o.or(b),
+        };
+        let syntax = match (self.syntax, base.syntax) {
+            (Some(s), Some(b)) => Some(Syntax {
+                delimiters: s.delimiters.or(b.delimiters),
+                files: merge(s.files, b.files, |_| ()),
+            }),
+            (None, Some(b)) => {
+                origin(String::from("syntax"));
+                Some(b)
+            }
+            (s, None) => s,
+        };
+        let (hooks, base_hooks) = (self.hooks.unwrap_or_default(), base.hooks.unwrap_or_default());
+        if hooks.pre.is_empty() && !base_hooks.pre.is_empty() {
+            origin(String::from("hooks.pre"));
+        }
+        if hooks.post.is_empty() && !base_hooks.post.is_empty() {
+            origin(String::from("hooks.post"));
+        }
+        let hooks = Some(Hooks { pre: [base_hooks.pre, hooks.pre].concat(), post: [base_hooks.post, hooks.post].concat() });
+        File {
+            extends: self.extends,
+            options,
+            variables: Some(variables),
+            dimensions: Some(dimensions),
+            paths: Some(paths),
+            files: Some(files),
+            profiles: Some(merge(self.profiles.unwrap_or_default(), base.profiles.unwrap_or_default(), |_| ())),
+            constraints: Some(merge(self.constraints.unwrap_or_default(), base.constraints.unwrap_or_default(), |n| {
+                origin(format!("constraints.{}", n))
+            })),
+            syntax,
+            headers: Some(merge(self.headers.unwrap_or_default(), base.headers.unwrap_or_default(), |_| ())),
+            tests: Some(merge(self.tests.unwrap_or_default(), base.tests.unwrap_or_default(), |_| ())),
+            hooks,
+            origins: self.origins,
+        }
     }
 }
 
-impl Default for File {
-    fn default() -> Self {
-        File {
-            options: None,
-            variables: None,
-            dimensions: None,
-            paths: None,
+/// the entries of `child` and the ones of `base` it doesn't have, `inherited` is called with the latter
+fn merge<K: Eq + Hash, V>(mut child: HashMap<K, V>, base: HashMap<K, V>, mut inherited: impl FnMut(&K)) -> HashMap<K, V> {
+    use std::collections::hash_map::Entry;
+    for (k, v) in base {
+        if let Entry::Vacant(e) = child.entry(k) {
+            inherited(e.key());
+            e.insert(v);
         }
     }
+    child
+}
+
+/// offsets of the first key or table header of `src` for `key` or one of its sub-keys,
+/// e.g. `os` in `os = [..]` under `[dimensions]`, or `[dimensions.os.choices.linux]` for `["dimensions", "os"]`.
+/// Inline tables aren't looked into.
+pub fn locate_key(src: &str, key: &[&str]) -> Option<(usize, usize)> {
+    let mut table = Vec::new();
+    let mut offset = 0;
+    for line in src.split_inclusive('\n') {
+        let start = offset + line.len() - line.trim_start().len();
+        offset += line.len();
+        let line = line.trim();
+        if line.starts_with('[') {
+            let header = line.trim_start_matches('[');
+            let end = find_unquoted(header, ']').unwrap_or(header.len());
+            table = split_key(&header[..end]);
+            if table.starts_with(key) {
+                return Some((start, start + line.len() - header.len() + end + 1));
+            }
+        } else if let Some(eq) = find_unquoted(line, '=').filter(|_| !line.starts_with('#')) {
+            let k = line[..eq].trim_end();
+            let full: Vec<_> = table.iter().cloned().chain(split_key(k)).collect();
+            if full.starts_with(key) {
+                return Some((start, start + k.len()));
+            }
+        }
+    }
+    None
+}
+/// `a."b.c"` -> `["a", "b.c"]`
+fn split_key(s: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = s;
+    while let Some(i) = find_unquoted(rest, '.') {
+        parts.push(rest[..i].trim().trim_matches('"'));
+        rest = &rest[i + 1..];
+    }
+    parts.push(rest.trim().trim_matches('"'));
+    parts
+}
+fn find_unquoted(s: &str, c: char) -> Option<usize> {
+    let mut quoted = false;
+    for (i, ch) in s.char_indices() {
+        match ch {
+            '"' | '\'' => quoted = !quoted,
+            _ if ch == c && !quoted => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `[hooks]` section, shell commands run in order around the write phase, e.g.
+/// ```toml
+/// [hooks]
+/// pre = ["make clean"]
+/// post = ["chmod +x out/run.sh"]
+/// ```
+/// the hooks of an extended file run before the ones of the file extending it.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct Hooks {
+    /// run before writing, nothing is written if one fails
+    #[serde(default)]
+    pub pre: Vec<String>,
+    /// run once everything is written
+    #[serde(default)]
+    pub post: Vec<String>,
+}
+
+/// `[paths]` section.
+/// `include` and `ignore` are reserved, i.e. they can't be used as source paths.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(from = "PathsDecl")]
+pub struct Paths {
+    /// only load the files of directories matching one of these patterns. see [`crate::utils::glob`]
+    pub include: Vec<String>,
+    /// skip the files and sub-directories of directories matching one of these patterns
+    pub ignore: Vec<String>,
+    /// source -> destination map
+    pub map: HashMap<PathBuf, PathBuf>,
+    /// source -> the decisions and variables pinned for it, see [`PathOverride`]
+    pub overrides: HashMap<PathBuf, PathOverride>,
+    /// source -> the targets after the first one, see [`PathTarget`]
+    pub targets: HashMap<PathBuf, Vec<PathOverride>>,
+}
+
+/// a source of `[paths]` given as a table, e.g.
+/// ```toml
+/// [paths."src/app.conf"]
+/// destination = "out/app.conf"   # optional, for a file of a mapped directory
+/// decisions = ["debug=0"]
+/// variables = { env = "production" }
+/// ```
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct PathOverride {
+    pub destination: Option<PathBuf>,
+    /// take precedence over the decisions of the command line for this source
+    #[serde(default)]
+    pub decisions: Vec<String>,
+    /// take precedence over `[variables]` for this source
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+}
+
+/// a destination of a source of `[paths]` given as a list, e.g.
+/// ```toml
+/// [paths]
+/// "src/config" = [
+///     { dest = "config.linux", decisions = ["linux"] },
+///     { dest = "config.windows", decisions = ["windows"] },
+/// ]
+/// ```
+/// the first target is the destination of the source with a [`PathOverride`], the source is written again for the others.
+#[derive(Deserialize, Debug, Clone)]
+pub struct PathTarget {
+    pub dest: PathBuf,
+    #[serde(default)]
+    pub decisions: Vec<String>,
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+}
+impl From<PathTarget> for PathOverride {
+    fn from(t: PathTarget) -> Self {
+        PathOverride { destination: Some(t.dest), decisions: t.decisions, variables: t.variables }
+    }
+}
+
+/// see [`Paths`]
+#[derive(Deserialize)]
+struct PathsDecl {
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    ignore: Vec<String>,
+    #[serde(flatten)]
+    map: HashMap<PathBuf, PathDecl>,
+}
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PathDecl {
+    Destination(PathBuf),
+    Targets(Vec<PathTarget>),
+    Override(PathOverride),
+}
+impl From<PathsDecl> for Paths {
+    fn from(decl: PathsDecl) -> Self {
+        let mut map = HashMap::new();
+        let mut overrides = HashMap::new();
+        let mut targets = HashMap::new();
+        for (src, path) in decl.map {
+            match path {
+                PathDecl::Destination(dst) => {
+                    map.insert(src, dst);
+                }
+                PathDecl::Override(o) => {
+                    if let Some(dst) = &o.destination {
+                        map.insert(src.clone(), dst.clone());
+                    }
+                    overrides.insert(src, o);
+                }
+                PathDecl::Targets(ts) => {
+                    let mut ts = ts.into_iter().map(PathOverride::from);
+                    if let Some(first) = ts.next() {
+                        // @SAFETY unwrap(): the targets have a destination
+                        map.insert(src.clone(), first.destination.clone().unwrap());
+                        overrides.insert(src.clone(), first);
+                        targets.insert(src, ts.collect());
+                    }
+                }
+            }
+        }
+        Paths { include: decl.include, ignore: decl.ignore, map, overrides, targets }
+    }
+}
+
+/// `[files]` section, patterns of the sources which are always loaded as binary or text.
+/// see [`crate::sourcemap::FileKind`]
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct Files {
+    /// copied without being parsed, even if they are valid UTF-8
+    #[serde(default)]
+    pub binary: Vec<String>,
+    /// always parsed, loading them fails if they aren't valid UTF-8
+    #[serde(default)]
+    pub text: Vec<String>,
+    /// parsed even with invalid UTF-8 bytes, see [`crate::sourcemap::FileKind::Lossy`]
+    #[serde(default)]
+    pub lossy: Vec<String>,
+}
+
+/// `[syntax]` section, the characters replacing `#`, `{`, `}` and `$` in the sources.
+/// see [`crate::syntax::lexer::Delimiters`]
+/// ```toml
+/// [syntax]
+/// sigil = "@"
+/// [syntax.files."*.sh"]
+/// sigil = "^"
+/// open = "["
+/// close = "]"
+/// ```
+/// `files` is reserved.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct Syntax {
+    /// delimiters of all the sources
+    #[serde(flatten)]
+    pub delimiters: DelimitersDecl,
+    /// pattern -> delimiters of the matching sources, the unset ones are the ones of all the sources.
+    /// see [`crate::utils::glob`]
+    #[serde(default)]
+    pub files: HashMap<String, DelimitersDecl>,
+}
+/// the delimiters of [`Syntax`], the unset ones are the defaults
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct DelimitersDecl {
+    pub sigil: Option<char>,
+    pub open: Option<char>,
+    pub close: Option<char>,
+    pub var: Option<char>,
+}
+impl DelimitersDecl {
+    /// the delimiters of `self`, or else of `base`
+    pub fn or(self, base: DelimitersDecl) -> DelimitersDecl {
+        DelimitersDecl {
+            sigil: self.sigil.or(base.sigil),
+            open: self.open.or(base.open),
+            close: self.close.or(base.close),
+            var: self.var.or(base.var),
+        }
+    }
+    /// the delimiters of `self`, or else of `base`
+    pub fn or_delimiters(self, base: Delimiters) -> Delimiters {
+        Delimiters {
+            sigil: self.sigil.unwrap_or(base.sigil),
+            open: self.open.unwrap_or(base.open),
+            close: self.close.unwrap_or(base.close),
+            var: self.var.unwrap_or(base.var),
+        }
+    }
+}
+
+/// `[headers]` section, how the headers of `--headers` are commented in the sources matching each pattern.
+/// ```toml
+/// [headers]
+/// "*.conf" = "#"           # line comment
+/// "*.css" = ["/*", "*/"]   # block comment
+/// "vendor/**" = false      # no header
+/// ```
+/// see [`super::Headers`]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum HeaderDecl {
+    /// `false` writes no header, `true` the one of the extension
+    Enabled(bool),
+    Line(String),
+    Block(String, String),
+}
+
+/// a `[tests.NAME]` table, a template and the output it has to render to with its decisions and variables
+/// ```toml
+/// [tests.bashrc-linux]
+/// template = "src/bashrc"
+/// decisions = ["linux", "shell=zsh"]
+/// variables = { user = "ci" }
+/// expected_file = "tests/bashrc.linux"   # or `expected = "..."`
+/// ```
+/// The decisions given to `flan` don't apply, the variables override the ones of the config file. see [`crate::driver::run_test`]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TestDecl {
+    pub template: PathBuf,
+    #[serde(default)]
+    pub decisions: Vec<String>,
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+    /// the output
+    pub expected: Option<String>,
+    /// the file with the output, exclusive with `expected`
+    pub expected_file: Option<PathBuf>,
+}
+
+/// decisions and variables overrides, the contents of a `--decisions-file` or a `[profiles.NAME]` table
+/// ```toml
+/// os = "linux"
+/// debug = 1
+/// [variables]
+/// pkg = "apt"
+/// ```
+/// `variables` is reserved, i.e. it can't be used as a dimension name. see [`super::Config::with_profile`]
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Profile {
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+    /// dimension -> choice name or index
+    #[serde(flatten)]
+    pub decisions: HashMap<String, Index>,
+}
+impl FromStr for Profile {
+    type Err = de::Error;
+    fn from_str(s: &str) -> Result<Self, de::Error> {
+        toml::from_str(s)
+    }
+}
+
+/// value of a variable declaration
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Variable {
+    Value(String),
+    /// read from the environment when the program starts, e.g.
+    /// ```toml
+    /// [variables]
+    /// user = { env = "USER", default = "nobody" }
+    /// ```
+    Env(EnvVar),
+    /// output of a command, run by the shell when the program starts, e.g.
+    /// ```toml
+    /// [variables]
+    /// version = { cmd = "git describe --tags" }
+    /// ```
+    Cmd(CmdVar),
+}
+/// see [`Variable::Env`]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct EnvVar {
+    /// name of the environment variable
+    pub env: String,
+    /// value if the environment variable isn't set
+    pub default: Option<String>,
+}
+
+/// see [`Variable::Cmd`]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct CmdVar {
+    /// shell command, its output without the trailing newline is the value
+    pub cmd: String,
+}
+
+/// `[dimensions]` section.
+/// `aliases` is reserved, i.e. it can't be used as a dimension name.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct Dimensions {
+    /// alternate names of dimensions: alias -> dimension, e.g. `platform = "os"`
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    /// dimension declarations
+    #[serde(flatten)]
+    pub map: HashMap<String, Choices>,
 }
+
 /// default values for command-line optional arguments.
+/// `ignore-unset` of `[options]`
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(untagged)]
+pub enum IgnoreUnset {
+    Bool(bool),
+    Unset(super::Unset),
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "kebab-case")]
 pub struct Options {
@@ -59,22 +519,46 @@ pub struct Options {
     ///
     /// [`error::ErrorFlags`]: ../error/struct.ErrorFlags.html
     pub verbosity: Option<u8>,
-    /// ignore unset variables
-    pub ignore_unset: Option<bool>,
+    /// ignore unset variables, `true` or what they are written as e.g. `"verbatim"`, see [`super::Unset`]
+    pub ignore_unset: Option<IgnoreUnset>,
     /// prefix for all the relative source files
     pub in_prefix: Option<PathBuf>,
     /// prefix for all the relative destination files
     pub out_prefix: Option<PathBuf>,
+    /// maximum number of files opened at the same time by the output
+    pub max_open_files: Option<usize>,
+    /// capacity in bytes of the buffers used to read the sources and write the destinations
+    pub io_buffer_size: Option<usize>,
+    /// write the headers of `[headers]`
+    pub headers: Option<bool>,
 }
 impl Options {
+    /// the options of `self`, and the ones of `base` it doesn't set
+    pub fn or(self, base: Options) -> Options {
+        Options {
+            force: self.force.or(base.force),
+            verbosity: self.verbosity.or(base.verbosity),
+            ignore_unset: self.ignore_unset.or(base.ignore_unset),
+            in_prefix: self.in_prefix.or(base.in_prefix),
+            out_prefix: self.out_prefix.or(base.out_prefix),
+            max_open_files: self.max_open_files.or(base.max_open_files),
+            io_buffer_size: self.io_buffer_size.or(base.io_buffer_size),
+            headers: self.headers.or(base.headers),
+        }
+    }
     pub fn force(&self) -> Option<bool> {
         self.force
     }
     pub fn verbosity(&self) -> Option<u8> {
         self.verbosity
     }
-    pub fn ignore_unset(&self) -> Option<bool> {
-        self.ignore_unset
+    /// how the unset variables are written, if they are ignored
+    pub fn ignore_unset(&self) -> Option<super::Unset> {
+        match self.ignore_unset {
+            Some(IgnoreUnset::Bool(true)) => Some(super::UNSET_DEFAULT),
+            Some(IgnoreUnset::Unset(u)) => Some(u),
+            Some(IgnoreUnset::Bool(false)) | None => None,
+        }
     }
     pub fn in_prefix(&self) -> Option<&PathBuf> {
         self.in_prefix.as_ref()
@@ -82,6 +566,15 @@ impl Options {
     pub fn out_prefix(&self) -> Option<&PathBuf> {
         self.out_prefix.as_ref()
     }
+    pub fn max_open_files(&self) -> Option<usize> {
+        self.max_open_files
+    }
+    pub fn io_buffer_size(&self) -> Option<usize> {
+        self.io_buffer_size
+    }
+    pub fn headers(&self) -> Option<bool> {
+        self.headers
+    }
 }
 
 /// dimension Declarations.  
@@ -89,10 +582,132 @@ impl Options {
 #[derive(Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum Choices {
-    Size(u8),
+    Size(u16),
     Names(Vec<String>),
+    /// named choices which can also set variables, e.g.
+    /// ```toml
+    /// [dimensions.os.choices.linux]
+    /// vars = { pkg = "apt" }
+    /// ```
+    /// or a list of names with a documentation, e.g. `os = { choices = ["linux", "mac"], doc = "target os" }`
+    Table {
+        choices: ChoiceTable,
+        /// shown by `--query-dimensions` and the errors about the decisions
+        #[serde(default)]
+        doc: Option<String>,
+    },
+    /// decided by the other decisions, `choose` if `when` holds and `else` otherwise, e.g.
+    /// ```toml
+    /// libc = { when = { os = "linux" }, choose = "glibc", else = "msvcrt" }
+    /// ```
+    Derived(Derived),
 }
+
+/// a dimension decided from the others, see [`Choices::Derived`]
+#[derive(Deserialize, Debug, Clone)]
+pub struct Derived {
+    pub when: When,
+    /// the first choice
+    pub choose: String,
+    /// the second choice
+    #[serde(rename = "else")]
+    pub otherwise: String,
+    /// see [`Choices::doc`]
+    #[serde(default)]
+    pub doc: Option<String>,
+}
+
+/// condition of a [`Derived`] dimension
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum When {
+    /// the syntax of the guards, e.g. `"os=linux && arch!=arm"`
+    Cond(String),
+    /// `dimension = "choice"` pairs which all hold, e.g. `{ os = "linux" }`
+    Pairs(HashMap<String, String>),
+}
+impl When {
+    /// the condition in the syntax of the guards, the pairs are sorted
+    pub fn to_cond(&self) -> String {
+        match self {
+            When::Cond(c) => c.clone(),
+            When::Pairs(ps) => {
+                let mut ps: Vec<_> = ps.iter().map(|(dn, ch)| format!("{}={}", dn, ch)).collect();
+                ps.sort();
+                ps.join(" && ")
+            }
+        }
+    }
+}
+
+/// named choices in declaration order, a table of [`Choice`]s or a list of names
+#[derive(Debug, Clone, Default)]
+pub struct ChoiceTable(pub Vec<(String, Choice)>);
+
+/// a choice of a [`ChoiceTable`]
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Choice {
+    /// variables set when this choice is selected
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+}
+
+impl ChoiceTable {
+    pub fn names(&self) -> Vec<String> {
+        self.0.iter().map(|(n, _)| n.clone()).collect()
+    }
+    pub fn get(&self, idx: usize) -> Option<&(String, Choice)> {
+        self.0.get(idx)
+    }
+}
+
+impl<'de> Deserialize<'de> for ChoiceTable {
+    /// a `HashMap` would lose the order, which gives the choices' indices
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        use serde::de::{MapAccess, SeqAccess, Visitor};
+        struct TableVisitor;
+        impl<'de> Visitor<'de> for TableVisitor {
+            type Value = ChoiceTable;
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a table or a list of choices")
+            }
+            fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<Self::Value, S::Error> {
+                let mut choices = Vec::new();
+                while let Some(name) = seq.next_element()? {
+                    choices.push((name, Choice::default()));
+                }
+                Ok(ChoiceTable(choices))
+            }
+            fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<Self::Value, M::Error> {
+                let mut choices = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    choices.push(entry);
+                }
+                Ok(ChoiceTable(choices))
+            }
+        }
+        d.deserialize_any(TableVisitor)
+    }
+}
+
 impl Choices {
+    /// names of the choices, if they are named
+    pub fn names(&self) -> Option<Vec<String>> {
+        match self {
+            Choices::Size(_) => None,
+            Choices::Names(ns) => Some(ns.clone()),
+            Choices::Table { choices, .. } => Some(choices.names()),
+            Choices::Derived(d) => Some(vec![d.choose.clone(), d.otherwise.clone()]),
+        }
+    }
+    /// the `doc` of the dimension, see [`Choices::Table`]
+    pub fn doc(&self) -> Option<&str> {
+        match self {
+            Choices::Table { doc, .. } => doc.as_deref(),
+            Choices::Derived(d) => d.doc.as_deref(),
+            Choices::Size(_) | Choices::Names(_) => None,
+        }
+    }
     pub fn valid(&self) -> bool {
         fn has_dup(xs: &Vec<String>) -> bool {
             use std::collections::HashSet;
@@ -107,8 +722,10 @@ impl Choices {
             false
         }
         match self {
-            Choices::Size(i) => *i <= i8::MAX as u8,
-            Choices::Names(ns) => !has_dup(ns),
+            Choices::Size(_) => true,
+            Choices::Names(ns) => ns.len() <= ChoiceIdx::MAX_CHOICES && !has_dup(ns),
+            Choices::Table { choices, .. } => choices.0.len() <= ChoiceIdx::MAX_CHOICES && !has_dup(&choices.names()),
+            Choices::Derived(d) => d.choose != d.otherwise,
         }
     }
 }
@@ -0,0 +1,57 @@
+//! lockfile: the decisions and variables of a run, loaded by the next ones.
+//!
+//! ```toml
+//! [decisions]
+//! os = "linux"
+//! size = 1
+//!
+//! [variables]
+//! pkg = "apt"
+//! ```
+//! the command line decisions take precedence over the lockfile, which takes precedence over the config file.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{fmt, fs};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Error, Index};
+
+/// contents of a lockfile
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Lock {
+    /// choice name, or index if the dimension has no names
+    #[serde(default)]
+    pub decisions: BTreeMap<String, Index>,
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+}
+impl FromStr for Lock {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Error> {
+        toml::from_str(s).map_err(Error::TOML)
+    }
+}
+impl fmt::Display for Lock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the tables only hold strings and integers
+        f.write_str(&toml::to_string(self).map_err(|_| fmt::Error)?)
+    }
+}
+
+/// the lockfile of a config file: its path with a `.lock` suffix, i.e. `.flan.lock` by default.
+pub fn lock_path<P: AsRef<Path>>(config_path: Option<P>) -> PathBuf {
+    let mut path = config_path.map_or(".flan".into(), |p| p.as_ref().as_os_str().to_owned());
+    path.push(".lock");
+    path.into()
+}
+
+/// opens a lockfile and parses it, returns `None` if it doesn't exist.
+pub fn path_to_lock<P: AsRef<Path>>(path: P) -> Result<Option<Lock>, Error> {
+    match fs::read_to_string(&path) {
+        Ok(buf) => Lock::from_str(&buf).map(Some).map_err(|e| e.at(path.as_ref())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::IO(e)),
+    }
+}
@@ -0,0 +1,71 @@
+//! decision matrix: several named sets of decisions rendered in a single run.
+//!
+//! ```toml
+//! [debug]
+//! decisions = ["debug", "os=linux"]
+//!
+//! [release]
+//! decisions = ["release", "os=linux"]
+//! out-prefix = "dist/rel" # defaults to `<out-prefix>/release`
+//! ```
+//! the same can be written in JSON if the file has a `.json` extension.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use super::{decisions, Error, Index};
+
+/// contents of a matrix file
+#[derive(Debug, Clone, Default)]
+pub struct Matrix {
+    /// variants sorted by name
+    pub variants: BTreeMap<String, Variant>,
+}
+
+/// one set of decisions of a [`Matrix`]
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Variant {
+    /// same syntax as [`super::opts::Opt::decisions`]
+    pub decisions: Vec<String>,
+    /// destination prefix of this variant
+    pub out_prefix: Option<PathBuf>,
+}
+impl Variant {
+    /// see [`decisions::parse_decisions`]
+    pub fn parse_decisions(&self) -> Result<(HashSet<String>, HashMap<String, Index>), Error> {
+        decisions::parse_decisions(&self.decisions)
+    }
+    /// `out-prefix` if given, otherwise `<prefix>/<name>`.
+    pub fn out_prefix(&self, name: &str, prefix: Option<&PathBuf>) -> PathBuf {
+        match (&self.out_prefix, prefix) {
+            (Some(p), _) => p.clone(),
+            (None, Some(prefix)) => prefix.join(name),
+            (None, None) => PathBuf::from(name),
+        }
+    }
+}
+
+impl Matrix {
+    pub fn from_toml(s: &str) -> Result<Self, Error> {
+        let variants = toml::from_str(s).map_err(Error::TOML)?;
+        Ok(Matrix { variants })
+    }
+    pub fn from_json(s: &str) -> Result<Self, Error> {
+        let variants = serde_json::from_str(s).map_err(Error::JSON)?;
+        Ok(Matrix { variants })
+    }
+}
+
+/// opens a matrix file and parses it, as JSON if the extension is `.json` and as TOML otherwise.
+pub fn path_to_matrix<P: AsRef<Path>>(path: P) -> Result<Matrix, Error> {
+    let path = path.as_ref();
+    let buf = fs::read_to_string(path).map_err(Error::IO)?;
+    if path.extension().is_some_and(|e| e == "json") {
+        Matrix::from_json(&buf)
+    } else {
+        Matrix::from_toml(&buf)
+    }
+}
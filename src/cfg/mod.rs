@@ -2,16 +2,33 @@
 //!
 //! @TODO: more precise error handling
 pub mod file;
+pub mod lock;
+pub mod decisions;
+pub mod matrix;
+#[cfg(feature = "cli")]
 pub mod opts;
 
 #[doc(inline)]
-pub use file::{Choices, File};
+pub use decisions::{parse_decisions, Decision, Index};
+#[doc(inline)]
+pub use file::{Choice, ChoiceTable, Choices, CmdVar, DelimitersDecl, Derived, Dimensions, EnvVar, File, Files, HeaderDecl, Hooks, PathOverride, PathTarget, Paths, Profile, TestDecl, Variable, When};
+#[doc(inline)]
+pub use lock::Lock;
+#[cfg(feature = "cli")]
 pub use opts::StructOpt;
 #[doc(inline)]
-pub use opts::{Decision, Index, Opt};
+#[cfg(feature = "cli")]
+pub use opts::{DebugCommand, Opt, SubCommand};
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use crate::error::WarnSet;
+use crate::infer::Origin;
+use crate::error::codes::{self, Code};
+use crate::sourcemap::{span_in, BytePos, Destination, FileKind, SourceInfo, Span, SrcFile, SrcMap};
+use crate::syntax::lexer::Delimiters;
+use crate::utils::glob::glob_match;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::{fmt, fs, io};
 use toml::de;
 
@@ -27,15 +44,84 @@ pub const FORCE_DEFAULT: bool = false;
 pub const COMMAND_DEFAULT: Command = Command::Default;
 /// see [`Flags::ignore_unset`]
 pub const IGNORE_UNSET_DEFAULT: bool = false;
+pub const UNSET_DEFAULT: Unset = Unset::Empty;
+/// see [`Flags::max_open_files`]
+pub const MAX_OPEN_FILES_DEFAULT: usize = 64;
+/// see [`Flags::symlinks`]
+pub const SYMLINKS_DEFAULT: Symlinks = Symlinks::Follow;
+/// see [`Flags::headers`]
+pub const HEADERS_DEFAULT: bool = false;
+/// see [`Flags::io_buffer_size`]
+pub const IO_BUFFER_SIZE_DEFAULT: usize = 64 * 1024;
+/// see [`Flags::timings`]
+pub const TIMINGS_DEFAULT: Timings = Timings::Default;
+/// see [`Flags::query_scope`]
+pub const QUERY_SCOPE_DEFAULT: QueryScope = QueryScope::All;
+/// see [`Flags::query_format`]
+pub const QUERY_FORMAT_DEFAULT: QueryFormat = QueryFormat::Human;
+/// see [`Flags::graph_format`]
+pub const GRAPH_FORMAT_DEFAULT: GraphFormat = GraphFormat::Dot;
+/// see [`Snapshots::limit`]
+pub const SNAPSHOTS_LIMIT_DEFAULT: usize = 64;
+/// see [`Flags::newline`]
+pub const NEWLINE_DEFAULT: Newline = Newline::Preserve;
+/// see [`ErrorFlags::message_format`]
+pub const MESSAGE_FORMAT_DEFAULT: MessageFormat = MessageFormat::Human;
 
 #[derive(Debug, Clone)]
 /// start configuration.
 pub struct Config {
     pub variables: HashMap<String, String>,
+    /// variables read from the environment, see [`Variable::Env`]
+    pub env_variables: HashMap<String, EnvVar>,
+    /// variables set by the output of a command, see [`Variable::Cmd`]
+    pub cmd_variables: HashMap<String, CmdVar>,
+    /// run the commands of [`Config::cmd_variables`], unset by `--no-exec`
+    pub exec: bool,
+    /// command -> output, `None` if it failed. Shared by the clones so each command only runs once.
+    pub cmd_outputs: Arc<Mutex<HashMap<String, Option<String>>>>,
     pub dimensions: HashMap<String, Choices>,
-    pub paths: HashMap<PathBuf, PathBuf>,
+    /// alias -> dimension, see [`Dimensions::aliases`]
+    pub aliases: HashMap<String, String>,
+    /// source -> destination, sorted so the sources are loaded (and reported) in the same order by every run
+    pub paths: BTreeMap<PathBuf, PathBuf>,
+    /// source -> its pinned decisions and variables, see [`PathOverride`]
+    pub overrides: BTreeMap<PathBuf, PathOverride>,
+    /// source -> its other destinations, see [`PathTarget`]
+    pub targets: BTreeMap<PathBuf, Vec<PathOverride>>,
+    /// see [`PathFilters`]
+    pub filters: PathFilters,
+    /// see [`FileKinds`]
+    pub file_kinds: FileKinds,
     pub decisions_name: HashSet<String>,
     pub decisions_pair: HashMap<String, Index>,
+    /// decisions of the lockfile or of a [`Profile`] for undeclared dimensions, they only apply if nothing
+    /// else decides them. see [`Config::with_lock`]
+    pub locked: HashMap<String, Index>,
+    /// dimension -> where its decision comes from, when it isn't the command line. see [`crate::driver::make_env`]
+    pub decision_origins: HashMap<String, Origin>,
+    /// the config file, if one was loaded. see [`cfgfile_path`]
+    pub path: Option<PathBuf>,
+    /// the config files the inherited declarations come from, see [`File::origins`]
+    pub origins: HashMap<String, PathBuf>,
+    /// the config files loaded in the [`SrcMap`] to point at their declarations, see [`Config::load_into`]
+    pub sources: HashMap<PathBuf, SrcFile>,
+    /// `[profiles]` of the config file, see [`Config::select_profile`]
+    pub profiles: HashMap<String, Profile>,
+    /// variables set by [`Config::with_profile`], the lockfile doesn't override them
+    pub profile_variables: HashSet<String>,
+    /// `[constraints]`, see [`File::constraints`]
+    pub constraints: HashMap<String, String>,
+    /// see [`Syntax`]
+    pub syntax: Syntax,
+    /// see [`Headers`]
+    pub headers: Headers,
+    /// `[tests]`, sorted so `flan test` runs them in the same order
+    pub tests: BTreeMap<String, TestDecl>,
+    /// `[hooks]`, see [`crate::driver::run_hooks`]
+    pub hooks: Hooks,
+    /// run [`Config::hooks`], unset by `--no-hooks`
+    pub run_hooks: bool,
 }
 impl Config {
     pub fn new(
@@ -43,52 +129,407 @@ impl Config {
         decisions_pair: HashMap<String, Index>,
         file: File,
     ) -> Self {
-        let variables = file.variables.unwrap_or(HashMap::new());
-        let dimensions = file.dimensions.unwrap_or(HashMap::new());
-        let paths = file.paths.unwrap_or(HashMap::new());
+        let mut variables = HashMap::new();
+        let mut env_variables = HashMap::new();
+        let mut cmd_variables = HashMap::new();
+        for (name, var) in file.variables.unwrap_or_default() {
+            match var {
+                Variable::Value(v) => {
+                    variables.insert(name, v);
+                }
+                Variable::Env(e) => {
+                    env_variables.insert(name, e);
+                }
+                Variable::Cmd(c) => {
+                    cmd_variables.insert(name, c);
+                }
+            }
+        }
+        let Dimensions { map: dimensions, aliases } = file.dimensions.unwrap_or_default();
+        let Paths { map: paths, include, ignore, overrides, targets } = file.paths.unwrap_or_default();
+        let Files { binary, text, lossy } = file.files.unwrap_or_default();
+        let syntax = file.syntax.unwrap_or_default();
         Config {
             variables,
+            env_variables,
+            cmd_variables,
+            exec: true,
+            cmd_outputs: Arc::new(Mutex::new(HashMap::new())),
             dimensions,
-            paths,
+            aliases,
+            paths: paths.into_iter().collect(),
+            overrides: overrides.into_iter().collect(),
+            targets: targets.into_iter().collect(),
+            filters: PathFilters { include, ignore, ignore_files: false },
+            file_kinds: FileKinds { binary, text, lossy, lossy_by_default: false },
             decisions_name,
             decisions_pair,
+            locked: HashMap::new(),
+            decision_origins: HashMap::new(),
+            path: None,
+            origins: file.origins,
+            sources: HashMap::new(),
+            profiles: file.profiles.unwrap_or_default(),
+            profile_variables: HashSet::new(),
+            constraints: file.constraints.unwrap_or_default(),
+            syntax: Syntax::new(syntax.delimiters, syntax.files),
+            headers: Headers::new(file.headers.unwrap_or_default()),
+            tests: file.tests.unwrap_or_default().into_iter().collect(),
+            hooks: file.hooks.unwrap_or_default(),
+            run_hooks: true,
+        }
+    }
+    /// the variables of `lock` override the values of the config file, and its decisions are used for the
+    /// declared dimensions the command line doesn't decide.
+    pub fn with_lock(self, lock: Lock) -> Self {
+        // the variables set by the environment or a command are read again, even if set by an older lockfile
+        let skip = |n: &String| self.profile_variables.contains(n) || self.env_variables.contains_key(n) || self.cmd_variables.contains_key(n);
+        let variables: Vec<_> = lock.variables.into_iter().filter(|(n, _)| !skip(n)).collect();
+        self.with_decisions(lock.decisions, variables, Origin::Lock)
+    }
+    /// same as [`Config::with_lock`], a profile is applied before the lockfile so it takes precedence over it.
+    /// The first profile applied takes precedence over the next ones.
+    pub fn with_profile(self, profile: Profile) -> Self {
+        self.apply_profile(profile, Origin::Profile)
+    }
+    /// same as [`Config::with_profile`] with the contents of a `--decisions-file`
+    pub fn with_decisions_file(self, profile: Profile) -> Self {
+        self.apply_profile(profile, Origin::DecisionsFile)
+    }
+    fn apply_profile(mut self, profile: Profile, origin: Origin) -> Self {
+        let variables: Vec<_> = profile.variables.into_iter().filter(|(n, _)| !self.profile_variables.contains(n)).collect();
+        self.profile_variables.extend(variables.iter().map(|(n, _)| n.clone()));
+        self.with_decisions(profile.decisions, variables, origin)
+    }
+    /// note telling which config file declares `key`, e.g. `variables.user`. see [`Config::origins`]
+    pub fn declared_in(&self, key: &str) -> String {
+        match self.origins.get(key).or(self.path.as_ref()) {
+            Some(path) => format!("declared in `{}`.", path.display()),
+            None => String::from("declared in the config file."),
+        }
+    }
+    /// loads the config file and the ones it extends in `map`, see [`Config::span`]
+    pub fn load_into(&mut self, map: &SrcMap) {
+        for path in self.path.iter().chain(self.origins.values()) {
+            if !self.sources.contains_key(path) {
+                if let Ok(f) = map.load_file_as(path.clone(), Destination::InMemory, FileKind::Text) {
+                    self.sources.insert(path.clone(), f);
+                }
+            }
+        }
+    }
+    /// the declaration of `key` in the config file, e.g. `dimensions.os`. see [`file::locate_key`]
+    pub fn span(&self, key: &str) -> Option<Span> {
+        let file = self.sources.get(self.origins.get(key).or(self.path.as_ref())?)?;
+        let src = match &file.src {
+            SourceInfo::Source(s) => s,
+            _ => return None,
+        };
+        let keys = match key.split_once('.') {
+            Some(("aliases", name)) => vec!["dimensions", "aliases", name],
+            Some((section, name)) => vec![section, name],
+            None => vec![key],
+        };
+        let (lo, hi) = file::locate_key(src, &keys)?;
+        Some(span_in(file.id, BytePos::from(lo), BytePos::from(hi)))
+    }
+    /// [`Config::with_profile`] with `[profiles.NAME]` of the config file, `--profile NAME`
+    pub fn select_profile(self, name: &str) -> Result<Self, Error> {
+        match self.profiles.get(name) {
+            Some(profile) => {
+                let profile = profile.clone();
+                Ok(self.with_profile(profile))
+            }
+            None => {
+                let mut names: Vec<_> = self.profiles.keys().map(|n| format!("`{}`", n)).collect();
+                names.sort();
+                Err(Error::unknown_profile(name, &names))
+            }
+        }
+    }
+    fn with_decisions<D, V>(mut self, decisions: D, variables: V, origin: Origin) -> Self
+    where
+        D: IntoIterator<Item = (String, Index)>,
+        V: IntoIterator<Item = (String, String)>,
+    {
+        for (name, value) in variables {
+            self.env_variables.remove(&name);
+            self.cmd_variables.remove(&name);
+            self.variables.insert(name, value);
+        }
+        for (dn, idx) in decisions {
+            match self.dimensions.get(&dn) {
+                Some(chs) => {
+                    let by_name = chs.names().is_some_and(|ns| ns.iter().any(|n| self.decisions_name.contains(n)));
+                    let aliased = self.aliases.iter().any(|(a, d)| d == &dn && self.decisions_pair.contains_key(a));
+                    if !by_name && !aliased && !self.decisions_pair.contains_key(&dn) {
+                        self.decision_origins.insert(dn.clone(), origin);
+                        self.decisions_pair.insert(dn, idx);
+                    }
+                }
+                None if !self.locked.contains_key(&dn) && !self.decisions_pair.contains_key(&dn) => {
+                    self.decision_origins.insert(dn.clone(), origin);
+                    self.locked.insert(dn, idx);
+                }
+                None => {}
+            }
         }
+        self
     }
 }
+/// `include` and `ignore` patterns of `[paths]`, applied to the contents of the mapped directories.
+#[derive(Debug, Clone, Default)]
+pub struct PathFilters {
+    pub include: Vec<String>,
+    pub ignore: Vec<String>,
+    /// `--ignore-files`, also skip the entries matched by the `.gitignore` and `.flanignore` of the walked directories.
+    /// see [`crate::utils::ignore`]
+    pub ignore_files: bool,
+}
+impl PathFilters {
+    /// `rel` is relative to the mapped directory. Directories are included unless ignored
+    pub fn included(&self, rel: &Path, is_dir: bool) -> bool {
+        if self.ignore.iter().any(|p| glob_match(p, rel)) {
+            return false;
+        }
+        is_dir || self.include.is_empty() || self.include.iter().any(|p| glob_match(p, rel))
+    }
+}
+/// `binary`, `text` and `lossy` patterns of `[files]`, matched like the [`PathFilters`].
+#[derive(Debug, Clone, Default)]
+pub struct FileKinds {
+    pub binary: Vec<String>,
+    pub text: Vec<String>,
+    pub lossy: Vec<String>,
+    /// `--lossy-decode`, the files matching none of the patterns are [`FileKind::Lossy`]
+    pub lossy_by_default: bool,
+}
+impl FileKinds {
+    /// `rel` is relative to the mapped directory, or the source path of a mapped file.
+    /// `binary` is checked first, then `text`.
+    pub fn kind(&self, rel: &Path) -> FileKind {
+        if self.binary.iter().any(|p| glob_match(p, rel)) {
+            FileKind::Binary
+        } else if self.text.iter().any(|p| glob_match(p, rel)) {
+            FileKind::Text
+        } else if self.lossy_by_default || self.lossy.iter().any(|p| glob_match(p, rel)) {
+            FileKind::Lossy
+        } else {
+            FileKind::Auto
+        }
+    }
+}
+
+/// delimiters of the sources, `[syntax]` of the config file. see [`file::Syntax`]
+#[derive(Debug, Clone, Default)]
+pub struct Syntax {
+    /// delimiters of the sources matching none of the patterns
+    pub delimiters: Delimiters,
+    /// pattern -> delimiters, sorted by pattern. Matched like the [`FileKinds`], against the source paths
+    pub files: Vec<(String, Delimiters)>,
+}
+impl Syntax {
+    pub fn new(delimiters: DelimitersDecl, files: HashMap<String, DelimitersDecl>) -> Self {
+        let delimiters = delimiters.or_delimiters(Delimiters::default());
+        let mut files: Vec<_> = files.into_iter().map(|(p, d)| (p, d.or_delimiters(delimiters))).collect();
+        files.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Syntax { delimiters, files }
+    }
+    /// the delimiters of the first pattern matching `path`
+    pub fn delimiters_of(&self, path: &Path) -> Delimiters {
+        self.files.iter().find(|(p, _)| glob_match(p, path)).map_or(self.delimiters, |(_, d)| *d)
+    }
+}
+
+/// how the headers of `--headers` are commented, `[headers]` of the config file. see [`file::HeaderDecl`]
+#[derive(Debug, Clone, Default)]
+pub struct Headers {
+    /// pattern -> comment, sorted by pattern. Matched like the [`FileKinds`], against the source paths
+    pub files: Vec<(String, HeaderDecl)>,
+}
+/// the delimiters of a comment, e.g. `//` or `/*` and `*/`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    pub open: String,
+    pub close: Option<String>,
+}
+impl Headers {
+    /// the comments of the extensions (or the names of the dot-files) no pattern matches
+    pub const COMMENTS: &'static [(&'static str, &'static str, Option<&'static str>)] = &[
+        ("bash", "#", None),
+        ("bashrc", "#", None),
+        ("c", "//", None),
+        ("cfg", "#", None),
+        ("conf", "#", None),
+        ("cpp", "//", None),
+        ("css", "/*", Some("*/")),
+        ("fish", "#", None),
+        ("gitconfig", "#", None),
+        ("go", "//", None),
+        ("h", "//", None),
+        ("hs", "--", None),
+        ("html", "<!--", Some("-->")),
+        ("ini", ";", None),
+        ("java", "//", None),
+        ("js", "//", None),
+        ("lua", "--", None),
+        ("md", "<!--", Some("-->")),
+        ("nix", "#", None),
+        ("profile", "#", None),
+        ("py", "#", None),
+        ("rb", "#", None),
+        ("rs", "//", None),
+        ("scss", "//", None),
+        ("sh", "#", None),
+        ("sql", "--", None),
+        ("toml", "#", None),
+        ("ts", "//", None),
+        ("vim", "\"", None),
+        ("vimrc", "\"", None),
+        ("xml", "<!--", Some("-->")),
+        ("yaml", "#", None),
+        ("yml", "#", None),
+        ("zsh", "#", None),
+        ("zshrc", "#", None),
+    ];
+    pub fn new(files: HashMap<String, HeaderDecl>) -> Self {
+        let mut files: Vec<_> = files.into_iter().collect();
+        files.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Headers { files }
+    }
+    /// the comment of the header of the source at `path`, `None` if a pattern matching it is `false`.
+    /// Otherwise the comment of the first pattern matching it, or of its extension, see [`Self::COMMENTS`]
+    pub fn comment_of(&self, path: &Path) -> Option<Comment> {
+        let mut matching = self.files.iter().filter(|(p, _)| glob_match(p, path)).map(|(_, d)| d);
+        if matching.clone().any(|d| *d == HeaderDecl::Enabled(false)) {
+            return None;
+        }
+        match matching.find(|d| !matches!(d, HeaderDecl::Enabled(_))) {
+            Some(HeaderDecl::Line(open)) => Some(Comment { open: open.clone(), close: None }),
+            Some(HeaderDecl::Block(open, close)) => Some(Comment { open: open.clone(), close: Some(close.clone()) }),
+            _ => {
+                let name = path.file_name()?.to_str()?;
+                let ext = Path::new(name).extension().and_then(|e| e.to_str()).unwrap_or_else(|| name.trim_start_matches('.'));
+                let (_, open, close) = Self::COMMENTS.iter().find(|(e, ..)| *e == ext)?;
+                Some(Comment { open: open.to_string(), close: close.map(String::from) })
+            }
+        }
+    }
+}
+impl Comment {
+    /// `text` in a comment
+    pub fn wrap(&self, text: &str) -> String {
+        match &self.close {
+            Some(close) => format!("{} {} {}", self.open, text, close),
+            None => format!("{} {}", self.open, text),
+        }
+    }
+}
+
 #[derive(Debug, Hash, PartialEq, Clone)]
 pub struct Flags {
     /// see [`ErrorFlags`]
     pub eflags: ErrorFlags,
     /// `--force`
     pub force: bool,
-    /// `--dry-run` or `--query-dimensions`
+    /// `--if-changed`, existing destinations are compared with [`crate::output::same_contents`]
+    pub if_changed: bool,
+    /// the subcommand, or the flag selecting the mode
     pub command: Command,
-    /// `--stdin`
-    pub stdin: Option<PathBuf>,
-    /// `--in-prefix`
+    /// `--stdin`, written to `OUT` or to stdout
+    pub stdin: Option<Destination>,
+    /// `--stdout`
+    pub stdout: bool,
+    /// `--in-prefix`, the first one if given several times
     pub in_prefix: Option<PathBuf>,
+    /// the next `--in-prefix`: layers over [`Flags::in_prefix`], a file of a layer replaces the file with the same
+    /// path relative to the prefix in the layers under it. see [`crate::driver::load_sources`]
+    pub overlays: Vec<PathBuf>,
     /// `--out-prefix`
     pub out_prefix: Option<PathBuf>,
+    /// `flan escape`, `flan unescape` or their flags
+    pub escape: Option<PathBuf>,
+    /// `flan debug lex` or `flan debug ast`
+    pub debug: Option<PathBuf>,
+    /// the files of `flan fmt`, the sources if empty
+    pub fmt: Vec<PathBuf>,
+    /// the tests run by `flan test`, all of them if empty
+    pub tests: Vec<String>,
+    /// `flan matrix FILE`
+    pub snapshots: Option<Snapshots>,
+    /// `flan explain` or `--explain`
+    pub explain: Option<String>,
+    /// `--timings`
+    pub timings: Timings,
+    /// `--metrics`, see [`crate::driver::metrics::Metrics::write`]
+    pub metrics: Option<MetricsFormat>,
+    /// `--metrics-file`, stderr if `None`
+    pub metrics_file: Option<PathBuf>,
+    /// `--query-dimensions=per-file`
+    pub query_scope: QueryScope,
+    /// `--query-format`
+    pub query_format: QueryFormat,
+    /// `flan graph --format`
+    pub graph_format: GraphFormat,
+    /// `--max-open-files`
+    pub max_open_files: usize,
+    /// `--io-buffer-size`, at least 1
+    pub io_buffer_size: usize,
+    /// `--headers`, see [`Headers`]
+    pub headers: bool,
+    /// `--progress` without `--silence`, see [`crate::driver::progress`]
+    pub progress: bool,
+    /// `--origin-map`, see [`crate::output::origins`]
+    pub origin_map: Option<PathBuf>,
+    /// `--out-archive`, see [`crate::output::archive`]
+    pub out_archive: Option<PathBuf>,
+    /// `--offline`, see [`crate::driver::fetch`]
+    pub offline: bool,
+    /// `--cache-dir`, see [`crate::driver::fetch`]
+    pub cache_dir: PathBuf,
+    /// `--symlinks`
+    pub symlinks: Symlinks,
+    /// `--newline`, the line endings of the written text files
+    pub newline: Newline,
+    /// `--matrix`
+    pub matrix: Option<PathBuf>,
+    /// `--all-variants`, the matrix is every combination of choices, see [`crate::driver::all_variants`]
+    pub all_variants: bool,
+    /// `--interactive`
+    pub interactive: bool,
+    /// lockfile to write with `--lock`, see [`lock::lock_path`]
+    pub lock_file: Option<PathBuf>,
 }
 
 impl Flags {
     /// cmd-line opts take precedence over config file. Otherwise use default values
+    #[cfg(feature = "cli")]
     pub fn new(opt: &Opt, config: Option<&file::Options>) -> Self {
         let report_level = Self::make_flag(
             opt.report_level(),
             config.and_then(file::Options::verbosity),
             VERBOSITY_DEFAULT,
         );
-        let ignore_unset = Self::make_bflag(
-            opt.ignore_unset,
-            config.and_then(file::Options::ignore_unset),
-            IGNORE_UNSET_DEFAULT,
-        );
+        // structopt only accepts the names of `Unset`
+        let unset = opt.ignore_unset.as_ref().map(|m| m.as_deref().and_then(Unset::from_name).unwrap_or(UNSET_DEFAULT));
+        let (ignore_unset, unset) = match unset.or_else(|| config.and_then(file::Options::ignore_unset)) {
+            Some(unset) => (true, unset),
+            None => (IGNORE_UNSET_DEFAULT, UNSET_DEFAULT),
+        };
+        let mut denied: WarnSet = opt.deny.iter().collect();
+        if opt.strict_decisions {
+            denied.insert(crate::error::WarnKind::UnusedDecision);
+        }
         let eflags = ErrorFlags {
             report_level,
             warn_as_error: opt.warn_error(),
+            allowed: opt.allow.iter().collect(),
+            denied,
             no_extra: opt.no_extra(),
             ignore_unset,
+            unset,
+            partial: opt.partial,
+            message_format: MessageFormat::from_opt(opt),
         };
 
         let force = Self::make_bflag(
@@ -96,36 +537,161 @@ impl Flags {
             config.and_then(file::Options::force),
             FORCE_DEFAULT,
         );
-        let command = Command::from_opt(&opt);
+        let command = Command::from_opt(opt);
 
         let in_prefix = opt
             .in_prefix
-            .as_ref()
+            .first()
             .or(config.and_then(file::Options::in_prefix))
             .cloned();
+        let overlays = opt.in_prefix.iter().skip(1).cloned().collect();
         let out_prefix = opt
             .out_prefix
             .as_ref()
             .or(config.and_then(file::Options::out_prefix))
             .cloned();
-        let stdin = opt.stdin.clone().map(|o| o.unwrap_or("<stdout>".into()));
+        let stdin = opt.stdin.clone().map(|o| match o {
+            Some(out) if !opt.stdout => Destination::Path(out),
+            _ => Destination::Stdout,
+        });
+        let escape = opt.escape().map(|(f, _)| f.clone());
+        let debug = opt.debug().map(|(f, _)| f.clone());
+        let fmt = match &opt.subcommand {
+            Some(SubCommand::Fmt { files, .. }) => files.clone(),
+            _ => Vec::new(),
+        };
+        let tests = match &opt.subcommand {
+            Some(SubCommand::Test { names }) => names.clone(),
+            _ => Vec::new(),
+        };
+        let timings = Timings::from_opt(opt);
+        let query_scope = QueryScope::from_opt(opt);
+        let query_format = QueryFormat::from_opt(opt);
+        let max_open_files = Self::make_flag(
+            opt.max_open_files,
+            config.and_then(file::Options::max_open_files),
+            MAX_OPEN_FILES_DEFAULT,
+        );
+        let io_buffer_size = Self::make_flag(
+            opt.io_buffer_size,
+            config.and_then(file::Options::io_buffer_size),
+            IO_BUFFER_SIZE_DEFAULT,
+        )
+        .max(1);
 
         Flags {
             eflags,
             stdin,
+            stdout: opt.stdout,
             in_prefix,
+            overlays,
             out_prefix,
             force,
+            if_changed: opt.if_changed,
             command,
+            escape,
+            debug,
+            fmt,
+            tests,
+            snapshots: Snapshots::from_opt(opt),
+            explain: opt.explain().cloned(),
+            timings,
+            metrics: MetricsFormat::from_opt(opt),
+            metrics_file: opt.metrics_file.clone(),
+            query_scope,
+            query_format,
+            graph_format: GraphFormat::from_opt(opt),
+            max_open_files,
+            io_buffer_size,
+            headers: Self::make_bflag(opt.headers, config.and_then(file::Options::headers), HEADERS_DEFAULT),
+            progress: opt.progress && !opt.silence,
+            origin_map: opt.origin_map.clone(),
+            out_archive: opt.out_archive.clone(),
+            offline: opt.offline,
+            cache_dir: opt.cache_dir.clone().unwrap_or_else(|| PathBuf::from(crate::driver::fetch::CACHE_DIR_DEFAULT)),
+            symlinks: Symlinks::from_opt(opt),
+            newline: Newline::from_opt(opt),
+            matrix: opt.matrix().cloned(),
+            all_variants: opt.all_variants(),
+            interactive: opt.interactive,
+            lock_file: Some(lock::lock_path(opt.config_file.as_ref())).filter(|_| opt.lock),
         }
     }
+    /// `-i` names a single file to process instead of a prefix for `[paths]`
+    pub fn single_file(&self) -> Option<&PathBuf> {
+        self.in_prefix.as_ref().filter(|p| self.overlays.is_empty() && p.is_file())
+    }
+    /// the prefixes of the sources of `[paths]`, from the base layer to the top one, see [`Flags::overlays`]
+    pub fn layers(&self) -> Vec<Option<&PathBuf>> {
+        std::iter::once(self.in_prefix.as_ref()).chain(self.overlays.iter().map(Some)).collect()
+    }
+    /// the destination is stdout, so nothing else should be printed there
+    pub fn to_stdout(&self) -> bool {
+        self.stdout
+            || self.stdin.is_some()
+            || (self.single_file().is_some() && self.out_prefix.is_none())
+    }
+    #[cfg(feature = "cli")]
     fn make_flag<T>(opt: Option<T>, cfg: Option<T>, default: T) -> T {
         opt.or(cfg).unwrap_or(default)
     }
+    #[cfg(feature = "cli")]
     fn make_bflag(opt: bool, cfg: Option<bool>, default: bool) -> bool {
         opt || cfg.unwrap_or(default)
     }
 }
+/// the flags of `flan` without arguments nor config file, see [`Flags::new`]
+impl Default for Flags {
+    fn default() -> Self {
+        Flags {
+            eflags: ErrorFlags {
+                report_level: VERBOSITY_DEFAULT,
+                warn_as_error: WARN_DEFAULT,
+                allowed: WarnSet::default(),
+                denied: WarnSet::default(),
+                no_extra: NO_EXTRA_DEFAULT,
+                ignore_unset: IGNORE_UNSET_DEFAULT,
+                unset: UNSET_DEFAULT,
+                partial: false,
+                message_format: MESSAGE_FORMAT_DEFAULT,
+            },
+            stdin: None,
+            stdout: false,
+            in_prefix: None,
+            overlays: Vec::new(),
+            out_prefix: None,
+            force: FORCE_DEFAULT,
+            if_changed: false,
+            command: COMMAND_DEFAULT,
+            escape: None,
+            debug: None,
+            fmt: Vec::new(),
+            tests: Vec::new(),
+            snapshots: None,
+            explain: None,
+            timings: TIMINGS_DEFAULT,
+            metrics: None,
+            metrics_file: None,
+            query_scope: QUERY_SCOPE_DEFAULT,
+            query_format: QUERY_FORMAT_DEFAULT,
+            graph_format: GRAPH_FORMAT_DEFAULT,
+            max_open_files: MAX_OPEN_FILES_DEFAULT,
+            io_buffer_size: IO_BUFFER_SIZE_DEFAULT,
+            headers: HEADERS_DEFAULT,
+            progress: false,
+            origin_map: None,
+            out_archive: None,
+            offline: false,
+            cache_dir: PathBuf::from(crate::driver::fetch::CACHE_DIR_DEFAULT),
+            symlinks: SYMLINKS_DEFAULT,
+            newline: NEWLINE_DEFAULT,
+            matrix: None,
+            all_variants: false,
+            interactive: false,
+            lock_file: None,
+        }
+    }
+}
 /// flags related to error reporting
 #[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Debug, Hash)]
 pub struct ErrorFlags {
@@ -134,35 +700,113 @@ pub struct ErrorFlags {
     pub report_level: u8,
     /// treat warnings as errors (fail before copying)
     pub warn_as_error: bool,
+    /// warnings which aren't reported, `--allow`
+    pub allowed: WarnSet,
+    /// warnings which are reported as errors, `--deny`
+    pub denied: WarnSet,
     /// do not print extra notes & suggestions
     pub no_extra: bool,
-    /// don't error on undeclared variables: maps to empty string.
+    /// don't error on undeclared variables, they are written as [`Self::unset`].
     pub ignore_unset: bool,
+    /// what the undeclared variables are written as with `--ignore-unset`
+    pub unset: Unset,
+    /// `--partial`: undecided dimensions and unset variables aren't errors, they are kept in the outputs.
+    /// see [`crate::infer::specialize`]
+    pub partial: bool,
+    /// how diagnostics are rendered
+    pub message_format: MessageFormat,
 }
 impl Default for ErrorFlags {
     fn default() -> Self {
         ErrorFlags {
             report_level: 5,
             warn_as_error: false,
+            allowed: WarnSet::default(),
+            denied: WarnSet::default(),
             no_extra: false,
             ignore_unset: false,
+            unset: UNSET_DEFAULT,
+            partial: false,
+            message_format: MESSAGE_FORMAT_DEFAULT,
         }
     }
 }
 
 #[derive(Debug, Hash, PartialEq, Clone, Copy)]
 pub enum Command {
+    /// `flan build`, or no subcommand
     Default,
-    /// `--dry-run`
+    /// `flan build --dry-run` or `--dry-run`
     DryRun,
-    /// `--query-dimensions`
+    /// `flan query` or `--query-dimensions`
     Query,
+    /// `flan query --variables` or `--query-variables`
+    QueryVars,
+    /// `flan graph`, see [`crate::driver::graph`]
+    Graph,
+    /// `flan check`, see [`crate::driver::check_declarations`]
+    Check,
+    /// `flan clean [--dry-run]`, see [`crate::driver::clean`]
+    Clean { dry_run: bool },
+    /// `flan escape` or `--escape`
+    Escape,
+    /// `flan unescape` or `--unescape`
+    Unescape,
+    /// `flan fmt [--check]`, see [`crate::driver::fmt`]
+    Fmt { check: bool },
+    /// `flan test`, see [`crate::driver::run_test`]
+    Test,
+    /// `flan matrix FILE`, see [`Snapshots`]
+    Snapshots,
+    /// `flan debug lex`
+    DebugLex,
+    /// `flan debug ast`
+    DebugAst,
+    /// `flan build --matrix`, `--matrix` or `--all-variants`
+    Matrix,
+    /// `flan explain` or `--explain`
+    Explain,
+    /// `flan lsp`, see [`crate::lsp::serve`]
+    Lsp,
 }
 impl Command {
+    #[cfg(feature = "cli")]
     pub fn from_opt(opt: &Opt) -> Self {
-        if opt.query_dims {
+        if opt.explain().is_some() {
+            Command::Explain
+        } else if let Some((_, unescape)) = opt.escape() {
+            if unescape {
+                Command::Unescape
+            } else {
+                Command::Escape
+            }
+        } else if let Some((_, ast)) = opt.debug() {
+            if ast {
+                Command::DebugAst
+            } else {
+                Command::DebugLex
+            }
+        } else if opt.matrix().is_some() || opt.all_variants() {
+            Command::Matrix
+        } else if opt.query_variables() {
+            Command::QueryVars
+        } else if opt.query().is_some() {
             Command::Query
-        } else if opt.dry_run {
+        } else if let Some(SubCommand::Graph { .. }) = opt.subcommand {
+            Command::Graph
+        } else if let Some(SubCommand::Check { .. }) = opt.subcommand {
+            Command::Check
+        } else if let Some(SubCommand::Clean { dry_run, .. }) = opt.subcommand {
+            Command::Clean { dry_run }
+        } else if let Some(SubCommand::Fmt { check, .. }) = opt.subcommand {
+            Command::Fmt { check }
+        } else if let Some(SubCommand::Matrix { .. }) = opt.subcommand {
+            Command::Snapshots
+        } else if let Some(SubCommand::Test { .. }) = opt.subcommand {
+            Command::Test
+        } else if let Some(SubCommand::Lsp { .. }) = opt.subcommand {
+            Command::Lsp
+        } else if opt.dry_run() {
             Command::DryRun
         } else {
             Command::Default
@@ -170,27 +814,278 @@ impl Command {
     }
 }
 
+/// `flan matrix FILE --out DIR`: FILE rendered for each combination of choices, see [`crate::driver::file_variants`]
+#[derive(Debug, Hash, PartialEq, Clone)]
+pub struct Snapshots {
+    pub template: PathBuf,
+    pub out: PathBuf,
+    /// `--limit`, the most combinations rendered
+    pub limit: usize,
+}
+impl Snapshots {
+    #[cfg(feature = "cli")]
+    pub fn from_opt(opt: &Opt) -> Option<Self> {
+        match &opt.subcommand {
+            Some(SubCommand::Matrix { file, out, limit }) => Some(Snapshots {
+                template: file.clone(),
+                out: out.clone(),
+                limit: limit.unwrap_or(SNAPSHOTS_LIMIT_DEFAULT),
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Clone, Copy)]
+pub enum Timings {
+    Default,
+    /// `--timings=verbose`
+    Verbose,
+}
+impl Timings {
+    #[cfg(feature = "cli")]
+    pub fn from_opt(opt: &Opt) -> Self {
+        match &opt.timings {
+            // structopt only accepts `verbose`
+            Some(Some(_)) => Timings::Verbose,
+            _ => TIMINGS_DEFAULT,
+        }
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Clone, Copy)]
+pub enum MetricsFormat {
+    /// `--metrics=json`, a single object
+    Json,
+    /// `--metrics=csv`, a row per file and one for the whole run
+    Csv,
+}
+impl MetricsFormat {
+    #[cfg(feature = "cli")]
+    pub fn from_opt(opt: &Opt) -> Option<Self> {
+        match opt.metrics.as_deref() {
+            // structopt only accepts `json` and `csv`
+            Some("json") => Some(MetricsFormat::Json),
+            Some(_) => Some(MetricsFormat::Csv),
+            None => None,
+        }
+    }
+}
+
+/// how the directory walker treats symbolic links, see [`crate::driver::load_sources`]
+#[derive(Debug, Hash, PartialEq, Clone, Copy)]
+pub enum Symlinks {
+    /// walk/load their target, unless it is a directory being walked
+    Follow,
+    /// `--symlinks=copy-link`, recreate them at the destination, see [`crate::sourcemap::SourceInfo::Symlink`]
+    CopyLink,
+    /// `--symlinks=skip`
+    Skip,
+}
+impl Symlinks {
+    #[cfg(feature = "cli")]
+    pub fn from_opt(opt: &Opt) -> Self {
+        match opt.symlinks.as_deref() {
+            // structopt only accepts `follow`, `copy-link` and `skip`
+            Some("copy-link") => Symlinks::CopyLink,
+            Some("skip") => Symlinks::Skip,
+            Some(_) => Symlinks::Follow,
+            None => SYMLINKS_DEFAULT,
+        }
+    }
+}
+
+/// what an unset variable is written as with `--ignore-unset`
+#[derive(Debug, Hash, PartialEq, PartialOrd, Eq, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Unset {
+    /// nothing, `--ignore-unset` or `--ignore-unset=empty`
+    Empty,
+    /// its text in the source e.g. `#$name#`, so the output is still a template. `--ignore-unset=verbatim`
+    Verbatim,
+    /// `${name}` e.g. for `envsubst`, `--ignore-unset=placeholder`
+    Placeholder,
+}
+impl Unset {
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "empty" => Some(Unset::Empty),
+            "verbatim" => Some(Unset::Verbatim),
+            "placeholder" => Some(Unset::Placeholder),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
+pub enum Newline {
+    /// the line endings of the sources are written unchanged
+    Preserve,
+    /// `--newline=lf`, `\r\n` is written `\n`
+    Lf,
+    /// `--newline=crlf`, a `\n` without `\r` is written `\r\n`
+    Crlf,
+}
+impl Newline {
+    #[cfg(feature = "cli")]
+    pub fn from_opt(opt: &Opt) -> Self {
+        match opt.newline.as_deref() {
+            // structopt only accepts `lf`, `crlf` and `preserve`
+            Some("lf") => Newline::Lf,
+            Some("crlf") => Newline::Crlf,
+            Some(_) => Newline::Preserve,
+            None => NEWLINE_DEFAULT,
+        }
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Clone, Copy)]
+pub enum QueryScope {
+    /// the dimensions of all the files together
+    All,
+    /// `--query-dimensions=per-file`, see [`crate::driver::DimUse`]
+    PerFile,
+}
+impl QueryScope {
+    #[cfg(feature = "cli")]
+    pub fn from_opt(opt: &Opt) -> Self {
+        match opt.query() {
+            Some((true, _)) => QueryScope::PerFile,
+            _ => QUERY_SCOPE_DEFAULT,
+        }
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Clone, Copy)]
+pub enum QueryFormat {
+    /// see [`crate::driver::pp_dim_provenance`]
+    Human,
+    /// `--query-format=json`, see [`crate::driver::DimQuery`]
+    Json,
+    /// `--query-format=toml`
+    Toml,
+}
+impl QueryFormat {
+    #[cfg(feature = "cli")]
+    pub fn from_opt(opt: &Opt) -> Self {
+        match opt.query().and_then(|(_, format)| format) {
+            // structopt only accepts `human`, `json` and `toml`
+            Some("json") => QueryFormat::Json,
+            Some("toml") => QueryFormat::Toml,
+            Some(_) => QueryFormat::Human,
+            None => QUERY_FORMAT_DEFAULT,
+        }
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Clone, Copy)]
+pub enum GraphFormat {
+    /// for graphviz, see [`crate::driver::pp_graph`]
+    Dot,
+    /// `flan graph --format=json`, see [`crate::driver::Graph`]
+    Json,
+}
+impl GraphFormat {
+    #[cfg(feature = "cli")]
+    pub fn from_opt(opt: &Opt) -> Self {
+        match &opt.subcommand {
+            // structopt only accepts `dot` and `json`
+            Some(SubCommand::Graph { format: Some(f), .. }) if f == "json" => GraphFormat::Json,
+            Some(SubCommand::Graph { format: Some(_), .. }) => GraphFormat::Dot,
+            _ => GRAPH_FORMAT_DEFAULT,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Debug, Hash)]
+pub enum MessageFormat {
+    /// rendered with the source line, see [`crate::error::Error::render`]
+    Human,
+    /// `--message-format=json`, see [`crate::error::Error::render_json`]
+    Json,
+}
+impl MessageFormat {
+    #[cfg(feature = "cli")]
+    pub fn from_opt(opt: &Opt) -> Self {
+        match opt.message_format.as_deref() {
+            // structopt only accepts `human` and `json`
+            Some("json") => MessageFormat::Json,
+            Some(_) => MessageFormat::Human,
+            None => MESSAGE_FORMAT_DEFAULT,
+        }
+    }
+}
+
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Hash)]
 /// config-related parsing error kind. [`Error::Cfg`]
 pub enum ErrorKind {
     OutOfRange,
     InvalidChoice,
     InvalidIdentifier,
+    UnknownProfile,
+    /// a config file of the `extends` chain couldn't be loaded, or the chain is a cycle
+    Extends,
 }
 /// config error
 #[derive(Debug)]
 pub enum Error {
     IO(io::Error),
     TOML(de::Error),
+    JSON(serde_json::Error),
+    /// a TOML error of the file at `path`, see [`Error::at`]
+    Syntax { path: PathBuf, error: de::Error },
     Cfg { msg: String, kind: ErrorKind },
 }
 impl Error {
+    /// the [`Error::TOML`] errors of the file at `path` become [`Error::Syntax`]
+    pub fn at(self, path: &Path) -> Self {
+        match self {
+            Error::TOML(error) => Error::Syntax { path: path.into(), error },
+            e => e,
+        }
+    }
+    /// the diagnostic code it is reported with
+    pub fn code(&self) -> Code {
+        match self {
+            Error::IO(_) => codes::LOAD_FAILED,
+            Error::TOML(_) | Error::JSON(_) | Error::Syntax { .. } => codes::INVALID_CONFIG,
+            Error::Cfg { kind: ErrorKind::Extends, .. } => codes::INVALID_CONFIG,
+            Error::Cfg { .. } => codes::INVALID_ARGUMENTS,
+        }
+    }
     pub fn out_of_range(lexeme: &str) -> Self {
         Error::Cfg {
             kind: ErrorKind::OutOfRange,
             msg: format!("Numeric choice `{}` is out of range.\n note: consulte --help for a more detailed explanation.", lexeme)
         }
     }
+    pub fn unknown_profile(name: &str, names: &[String]) -> Self {
+        let declared = match names {
+            [] => String::from("the config file has no `[profiles]`"),
+            _ => format!("the profiles are {}", names.join(", ")),
+        };
+        Error::Cfg {
+            kind: ErrorKind::UnknownProfile,
+            msg: format!("unknown profile `{}`.\n note: {}.", name, declared),
+        }
+    }
+    /// `e` happened loading `base`, which `path` extends
+    pub fn extended(base: &Path, path: &Path, e: Error) -> Self {
+        match e {
+            Error::Cfg { .. } | Error::Syntax { .. } => e,
+            e => Error::Cfg {
+                kind: ErrorKind::Extends,
+                msg: format!("in `{}`, extended by `{}`: {}", base.display(), path.display(), e),
+            },
+        }
+    }
+    pub fn extends_cycle(chain: &[PathBuf]) -> Self {
+        let files: Vec<_> = chain.iter().map(|p| format!("`{}`", p.display())).collect();
+        Error::Cfg {
+            kind: ErrorKind::Extends,
+            msg: format!("config file `{}` extends itself.\n note: through {}.", chain[0].display(), files.join(" -> ")),
+        }
+    }
     pub fn invalid_choice(lexeme: &str) -> Self {
         Error::Cfg {
             kind: ErrorKind::InvalidChoice,
@@ -209,39 +1104,58 @@ impl fmt::Display for Error {
         match self {
             Error::IO(e) => e.fmt(f),
             Error::TOML(e) => e.fmt(f),
+            Error::JSON(e) => e.fmt(f),
+            Error::Syntax { path, error } => write!(f, "`{}`: {}", path.display(), error),
             Error::Cfg { msg, .. } => write!(f, "{}", msg),
         }
     }
 }
 
-/// opens config file and parses it.
+/// opens config file and parses it, with the config files it `extends`. see [`File::extend`]
 /// get the `.flan` file named in the current working directory if path is `None`;
 /// or returns [`File::default()`] if `.flan` doesn't exist.
 pub fn path_to_cfgfile<P: AsRef<Path>>(config_path: Option<P>) -> Result<File, Error> {
-    let default = Path::new(".flan");
-    let path = match config_path {
-        Some(ref path) => Some(path.as_ref()),
-        None => {
-            if default.exists() {
-                Some(default)
-            } else {
-                None
-            }
-        }
-    };
-    match path {
-        Some(path) => {
-            use std::io::Read;
-            let mut buf = String::new();
-            let mut file = fs::File::open(path).map_err(Error::IO)?;
-            file.read_to_string(&mut buf).map_err(Error::IO)?;
-            string_to_cfgfile(&buf).map_err(Error::TOML)
-        }
+    match cfgfile_path(config_path) {
+        Some(path) => load_cfgfile(&path, &mut Vec::new()),
         None => Ok(File::default()),
     }
 }
+/// `chain` holds the files extended by `path`, to report cycles
+fn load_cfgfile(path: &Path, chain: &mut Vec<PathBuf>) -> Result<File, Error> {
+    let buf = fs::read_to_string(path).map_err(Error::IO)?;
+    let file = string_to_cfgfile(&buf).map_err(|e| Error::TOML(e).at(path))?;
+    let base = match &file.extends {
+        Some(base) => path.parent().unwrap_or_else(|| Path::new("")).join(base),
+        None => return Ok(file),
+    };
+    let canonical = |p: &Path| fs::canonicalize(p).unwrap_or_else(|_| p.into());
+    chain.push(path.into());
+    if let Some(i) = chain.iter().position(|p| canonical(p) == canonical(&base)) {
+        let mut cycle = chain[i..].to_vec();
+        cycle.push(base);
+        return Err(Error::extends_cycle(&cycle));
+    }
+    let base_file = load_cfgfile(&base, chain).map_err(|e| Error::extended(&base, path, e))?;
+    chain.pop();
+    Ok(file.extend(base_file, &base))
+}
+
+/// opens a `--decisions-file` and parses it, see [`Profile`]
+pub fn path_to_profile<P: AsRef<Path>>(path: P) -> Result<Profile, Error> {
+    let buf = fs::read_to_string(&path).map_err(Error::IO)?;
+    buf.parse::<Profile>().map_err(|e| Error::TOML(e).at(path.as_ref()))
+}
+
+/// the config file given, or `.flan` if it exists
+pub fn cfgfile_path<P: AsRef<Path>>(config_path: Option<P>) -> Option<PathBuf> {
+    let default = Path::new(".flan");
+    match config_path {
+        Some(ref path) => Some(path.as_ref().into()),
+        None => Some(default.into()).filter(|_| default.exists()),
+    }
+}
 
 /// parse config string
-pub fn string_to_cfgfile(s: &String) -> Result<File, de::Error> {
-    File::from_str(s.as_ref())
+pub fn string_to_cfgfile(s: &str) -> Result<File, de::Error> {
+    s.parse::<File>()
 }
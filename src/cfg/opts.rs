@@ -2,69 +2,397 @@
 use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 
+use super::decisions::{parse_decisions, Index};
 use super::Error;
+use crate::error::WarnKind;
+use structopt::clap::{self, ArgGroup};
 pub use structopt::StructOpt;
 
+/// the options of all the subcommands are global, they can be given before or after the subcommand.
+/// The flags selecting a mode (`--dry-run`, `-q`, `--explain`...) are kept for compatibility,
+/// they belong to the `mode` group so only one of them can be given, see [`Opt::check`].
 #[derive(StructOpt, Clone, PartialEq, Eq, Debug)]
-#[structopt(version = "0.1", rename_all = "kebab-case")]
+#[structopt(version = "0.1", about = "A dot-file manager, inspired by Sheng Chen's `Variational Typing`.", rename_all = "kebab-case", group = ArgGroup::with_name("mode"), group = ArgGroup::with_name("query"))]
 pub struct Opt {
-    #[structopt(long)]
+    #[structopt(subcommand)]
+    pub subcommand: Option<SubCommand>,
+    #[structopt(long, global = true)]
     /// overwrite existing destination files
     pub force: bool,
-    #[structopt(long)]
-    /// run without substituting the files.
+    #[structopt(long = "if-changed", global = true)]
+    /// only replace the existing destination files whose content changed, the others keep their modification time.
+    pub if_changed: bool,
+    #[structopt(long, group = "mode")]
+    /// run without substituting the files, same as `flan build --dry-run`.
     pub dry_run: bool,
-    #[structopt(long)]
+    #[structopt(long, global = true)]
     /// ignore all warnings
     pub no_warn: bool,
-    #[structopt(short = "z", long)]
+    #[structopt(short = "z", long, global = true)]
     /// silence all errors and warnings
     pub silence: bool,
-    #[structopt(short, long)]
+    #[structopt(short, long, global = true)]
     /// explain what is being done
     pub verbose: bool,
-    #[structopt(long = "Werror")]
+    #[structopt(long, global = true)]
+    /// show the files parsed and written on the terminal
+    pub progress: bool,
+    #[structopt(long = "Werror", global = true)]
     /// make all warnings into errors
     pub warn_error: bool,
-    #[structopt(long = "ignore-unset")]
-    /// ignore unset variables: don't fail.
-    pub ignore_unset: bool,
-    #[structopt(short = "q", long = "query-dimensions")]
-    /// list all dimensions
-    pub query_dims: bool,
-    #[structopt(name = "PATH", short = "c", long = "config")]
+    #[structopt(name = "WARNING", long = "allow", number_of_values = 1, parse(try_from_str = WarnKind::from_name), global = true)]
+    /// don't report this kind of warning, e.g. `--allow redundant-decision`. Can be repeated
+    pub allow: Vec<WarnKind>,
+    #[structopt(name = "DENIED", long = "deny", number_of_values = 1, parse(try_from_str = WarnKind::from_name), global = true)]
+    /// report this kind of warning as an error, e.g. `--deny empty-choice`. Can be repeated
+    pub deny: Vec<WarnKind>,
+    #[structopt(long = "strict-decisions", global = true)]
+    /// make the decisions which match no dimension an error, same as `--deny unused-decision`
+    pub strict_decisions: bool,
+    #[structopt(name = "UNSET", long = "ignore-unset", require_equals = true, possible_values = &["empty", "verbatim", "placeholder"], global = true)]
+    /// ignore unset variables: don't fail, write them as nothing (`empty`, the default), as in the source
+    /// (`verbatim`), or as `${name}` (`placeholder`)
+    pub ignore_unset: Option<Option<String>>,
+    #[structopt(long, global = true)]
+    /// only substitute the decided dimensions and the set variables, the others are kept: the outputs are templates.
+    pub partial: bool,
+    #[structopt(long, global = true)]
+    /// write a comment first in each output, naming its source and the decisions it depends on. see `[headers]`
+    pub headers: bool,
+    #[structopt(name = "LEVEL", long = "timings", possible_values = &["verbose"], global = true)]
+    /// report timings at the end of the run, `--timings=verbose` also lists the slowest files
+    pub timings: Option<Option<String>>,
+    #[structopt(name = "METRICS", long = "metrics", possible_values = &["json", "csv"], global = true)]
+    /// write the metrics of the run (counts, timings and bytes of each phase and file) as `json` or `csv` to stderr
+    pub metrics: Option<String>,
+    #[structopt(name = "METRICS_FILE", long = "metrics-file", requires = "METRICS", global = true)]
+    /// write the `--metrics` to this file instead of stderr
+    pub metrics_file: Option<PathBuf>,
+    #[structopt(name = "FORMAT", long = "message-format", possible_values = &["human", "json"], global = true)]
+    /// how to print the diagnostics: `human` or `json` (one object per line)
+    pub message_format: Option<String>,
+    #[structopt(name = "N", long = "max-open-files", global = true)]
+    /// maximum number of files opened at the same time while writing the output.
+    pub max_open_files: Option<usize>,
+    #[structopt(name = "BYTES", long = "io-buffer-size", global = true)]
+    /// capacity of the buffers used to read each source and write each destination.
+    pub io_buffer_size: Option<usize>,
+    #[structopt(name = "ARCHIVE", long = "out-archive", global = true)]
+    /// write the destinations as the entries of ARCHIVE (`.tar` or `.zip`) instead of files.
+    /// The entries are named by the destinations relative to the output prefix
+    pub out_archive: Option<PathBuf>,
+    #[structopt(name = "MAP", long = "origin-map", global = true)]
+    /// write to MAP (as JSON) which source bytes or substitutions produced each region of the outputs
+    pub origin_map: Option<PathBuf>,
+    #[structopt(name = "query-dims", short = "q", long = "query-dimensions", possible_values = &["per-file"], require_equals = true, groups = &["mode", "query"])]
+    /// list all dimensions, `--query-dimensions=per-file` also lists the files and lines using each of them.
+    /// Same as `flan query [--per-file]`
+    pub query_dims: Option<Option<String>>,
+    #[structopt(name = "query-vars", long = "query-variables", possible_values = &["per-file"], require_equals = true, groups = &["mode", "query"])]
+    /// list all variables used by the sources, whether they are declared and their value,
+    /// `--query-variables=per-file` also lists the files and lines using each of them.
+    /// Same as `flan query --variables [--per-file]`
+    pub query_vars: Option<Option<String>>,
+    #[structopt(long = "query-format", requires = "query", possible_values = &["human", "json", "toml"])]
+    /// print the dimensions or variables listed by `--query-dimensions` or `--query-variables` as `human` readable text, `json` or `toml`
+    pub query_format: Option<String>,
+    #[structopt(long, conflicts_with_all = &["query-dims", "query-vars", "MATRIX", "[OUT]"], global = true)]
+    /// ask on the terminal for the decisions of the dimensions left undecided
+    pub interactive: bool,
+    #[structopt(long, global = true)]
+    /// write the decisions and variables used to the lockfile (`.flan.lock`, or the config file path + `.lock`)
+    pub lock: bool,
+    #[structopt(long = "no-lock", global = true)]
+    /// don't load the decisions and variables of the lockfile
+    pub no_lock: bool,
+    #[structopt(long = "offline", global = true)]
+    /// don't download the sources of `[paths]` given as URLs, read them from the cache only.
+    pub offline: bool,
+    #[structopt(name = "CACHE", long = "cache-dir", global = true)]
+    /// where the sources given as URLs are cached, `.flan-cache` by default.
+    pub cache_dir: Option<PathBuf>,
+    #[structopt(long = "no-exec", global = true)]
+    /// don't run the commands of the variables set by a command (`{ cmd = ".." }`), they are left unset
+    pub no_exec: bool,
+    #[structopt(long = "no-hooks", global = true)]
+    /// don't run the commands of `[hooks]` before and after writing
+    pub no_hooks: bool,
+    #[structopt(name = "POLICY", long = "symlinks", possible_values = &["follow", "copy-link", "skip"], global = true)]
+    /// what to do with the symbolic links of the walked directories: `follow` them, `copy-link` them as links, or `skip` them
+    pub symlinks: Option<String>,
+    #[structopt(name = "EOL", long = "newline", possible_values = &["lf", "crlf", "preserve"], global = true)]
+    /// line endings of the written files: `lf`, `crlf`, or `preserve` those of the sources (the default)
+    pub newline: Option<String>,
+    #[structopt(long = "lossy-decode", global = true)]
+    /// parse the sources with invalid UTF-8 bytes instead of copying them, the invalid bytes are written unchanged
+    pub lossy_decode: bool,
+    #[structopt(long = "ignore-files", global = true)]
+    /// skip the files of the walked directories matched by their `.gitignore` or `.flanignore`
+    pub ignore_files: bool,
+    #[structopt(name = "CODE", long = "explain", group = "mode")]
+    /// same as `flan explain CODE`
+    pub explain: Option<String>,
+    #[structopt(name = "FILE", long = "escape", group = "mode")]
+    /// same as `flan escape FILE`
+    pub escape: Option<PathBuf>,
+    #[structopt(name = "UNESCAPE_FILE", long = "unescape", group = "mode")]
+    /// same as `flan unescape FILE`
+    pub unescape: Option<PathBuf>,
+    #[structopt(name = "MATRIX", long = "matrix", conflicts_with = "[OUT]", group = "mode")]
+    /// same as `flan build --matrix MATRIX`
+    pub matrix: Option<PathBuf>,
+    #[structopt(long, conflicts_with = "[OUT]", group = "mode")]
+    /// same as `flan build --all-variants`
+    pub all_variants: bool,
+    #[structopt(name = "DECISIONS_FILE", long = "decisions-file", global = true)]
+    /// read decisions and variables from a TOML file, e.g. `os = "linux"`. The DECISIONS take precedence
+    pub decisions_file: Option<PathBuf>,
+    #[structopt(name = "PROFILE", long = "profile", global = true)]
+    /// apply the decisions and variables of `[profiles.PROFILE]`, after the DECISIONS and the `--decisions-file`
+    pub profile: Option<String>,
+    #[structopt(name = "PATH", short = "c", long = "config", global = true)]
     /// use this config file instead
     pub config_file: Option<PathBuf>,
-    #[structopt(name = "[OUT]", long = "stdin")]
-    /// read from stdin, write to OUT. If no OUT given writes to stdout
+    #[structopt(name = "[OUT]", long = "stdin", require_equals = true, global = true)]
+    /// read a single template from stdin (ignoring `[paths]`), write it to `--stdin=OUT`, or to stdout if no OUT given
     pub stdin: Option<Option<PathBuf>>,
-    #[structopt(name = "OUTPATH", short = "o", long = "out-prefix", parse(from_os_str))]
-    /// destination path
+    #[structopt(long, conflicts_with_all = &["OUTPATH", "MATRIX"], global = true)]
+    /// write the processed file to stdout, whatever its destination. Requires a single file
+    /// given with `-i` (or `--stdin`)
+    pub stdout: bool,
+    #[structopt(name = "OUTPATH", short = "o", long = "out-prefix", parse(from_os_str), global = true)]
+    /// destination path, or destination file if INPATH is a file
     pub out_prefix: Option<PathBuf>,
-    #[structopt(name = "INPATH", short = "i", long = "in-prefix", parse(from_os_str))]
-    /// source path
-    pub in_prefix: Option<PathBuf>,
+    #[structopt(name = "INPATH", short = "i", long = "in-prefix", parse(from_os_str), number_of_values = 1, global = true)]
+    /// source path. If it is a file, only that file is processed (ignoring `[paths]`) and written
+    /// to OUTPATH, or to stdout if no OUTPATH is given.
+    /// Given several times, the directories are layers: the files of the later ones replace the files
+    /// with the same path in the earlier ones
+    pub in_prefix: Vec<PathBuf>,
     #[structopt(name = "DECISIONS")]
     /// Can be Choice or Dimension_name=Index pairs. An Index is either a
-    /// a choice name or a natural smaller than 128. Valid names contain `_` or alphanumeric chars but
+    /// a choice name or a natural smaller than 65535. Valid names contain `_` or alphanumeric chars but
     /// cannot start with a digit
     pub decisions: Vec<String>,
 }
+
+// `flan SUBCOMMAND`, without one `flan` builds. see [`super::Command`]
+// (not a doc comment: structopt would print it as the `about` of `flan --help`)
+#[derive(StructOpt, Clone, PartialEq, Eq, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub enum SubCommand {
+    /// substitute the sources and write them to their destinations, the default
+    Build {
+        #[structopt(long)]
+        /// run without substituting the files.
+        dry_run: bool,
+        #[structopt(name = "MATRIX", long = "matrix", conflicts_with_all = &["dry-run", "[OUT]"])]
+        /// render every decision set of the MATRIX file (TOML or JSON) into its own output prefix
+        matrix: Option<PathBuf>,
+        #[structopt(long, conflicts_with_all = &["dry-run", "[OUT]", "MATRIX"])]
+        /// render every combination of the choices of the dimensions, each into `<out-prefix>/<choices>`
+        all_variants: bool,
+        #[structopt(name = "DECISIONS")]
+        /// see `flan --help`
+        decisions: Vec<String>,
+    },
+    /// list all dimensions with their choices and decision
+    Query {
+        #[structopt(long = "per-file")]
+        /// also list the files and lines using each dimension
+        per_file: bool,
+        #[structopt(long)]
+        /// list the variables used by the sources instead, whether they are declared and their value
+        variables: bool,
+        #[structopt(name = "QUERY_FORMAT", long = "format", value_name = "FORMAT", possible_values = &["human", "json", "toml"])]
+        /// print the dimensions as `human` readable text, `json` or `toml`
+        format: Option<String>,
+        #[structopt(name = "DECISIONS")]
+        /// see `flan --help`
+        decisions: Vec<String>,
+    },
+    /// print the graph of the files using each dimension and variable, with the choices of the dimensions
+    Graph {
+        #[structopt(name = "GRAPH_FORMAT", long = "format", value_name = "FORMAT", possible_values = &["dot", "json"])]
+        /// print the graph for graphviz (`dot`) or as `json`
+        format: Option<String>,
+        #[structopt(name = "DECISIONS")]
+        /// see `flan --help`
+        decisions: Vec<String>,
+    },
+    /// parse and check the sources with the decisions, without writing anything. Unlike `build --dry-run`,
+    /// every declared dimension needs a decision, even if no source uses it
+    Check {
+        #[structopt(name = "DECISIONS")]
+        /// see `flan --help`
+        decisions: Vec<String>,
+    },
+    /// remove the destinations of the sources, if they are what `build` would write
+    Clean {
+        #[structopt(long)]
+        /// only list the destinations which would be removed
+        dry_run: bool,
+        #[structopt(name = "DECISIONS")]
+        /// see `flan --help`
+        decisions: Vec<String>,
+    },
+    /// describe the diagnostic CODE (e.g. `F0203`) with examples
+    Explain {
+        #[structopt(name = "CODE")]
+        code: String,
+    },
+    /// escape FILE so it can be used as a template. Writes to stdout
+    Escape {
+        #[structopt(name = "FILE")]
+        file: PathBuf,
+    },
+    /// inverse of `flan escape`
+    Unescape {
+        #[structopt(name = "FILE")]
+        file: PathBuf,
+    },
+    /// format the templates: canonical escapes, no whitespace trimmed around the delimiters, and the terms
+    /// written over several lines laid out as indented blocks. Formats the sources of the config file without FILEs
+    Fmt {
+        #[structopt(long)]
+        /// only list the files which aren't formatted, and fail if there are some
+        check: bool,
+        #[structopt(name = "FILE")]
+        files: Vec<PathBuf>,
+    },
+    /// render the templates of the `[tests]` of the config file and compare them with their expected outputs,
+    /// only the tests named TEST if some are given
+    Test {
+        #[structopt(name = "TEST")]
+        names: Vec<String>,
+    },
+    /// render FILE under every combination of the choices of the dimensions it uses, each into a file of DIR
+    /// named by its decisions, e.g. `os=linux,debug=0.txt`, for reviews
+    Matrix {
+        #[structopt(name = "FILE")]
+        file: PathBuf,
+        #[structopt(name = "DIR", long = "out")]
+        out: PathBuf,
+        #[structopt(name = "LIMIT", long = "limit")]
+        /// fail if there are more than LIMIT combinations [default: 64]
+        limit: Option<usize>,
+    },
+    /// print how a template is lexed or parsed, e.g. to see why something is text
+    Debug {
+        #[structopt(subcommand)]
+        what: DebugCommand,
+    },
+    /// serve the diagnostics, hovers and definitions of the templates to an editor, as a language server on
+    /// stdin and stdout
+    Lsp {
+        #[structopt(name = "DECISIONS")]
+        /// see `flan --help`, the hovers show them
+        decisions: Vec<String>,
+    },
+}
+
+#[derive(StructOpt, Clone, PartialEq, Eq, Debug)]
+pub enum DebugCommand {
+    /// print the tokens of FILE with their spans
+    Lex {
+        #[structopt(name = "FILE")]
+        file: PathBuf,
+    },
+    /// print the terms of FILE as a tree, with their names, number of choices and spans
+    Ast {
+        #[structopt(name = "FILE")]
+        file: PathBuf,
+    },
+}
+
 impl Opt {
+    /// rejects a mode flag given with a subcommand, e.g. `flan query --dry-run`.
+    /// clap can't express conflicts between the arguments of different subcommands
+    pub fn check(&self) -> Result<(), clap::Error> {
+        let legacy = [
+            ("--dry-run", self.dry_run),
+            ("--query-dimensions", self.query_dims.is_some()),
+            ("--query-variables", self.query_vars.is_some()),
+            ("--explain", self.explain.is_some()),
+            ("--escape", self.escape.is_some()),
+            ("--unescape", self.unescape.is_some()),
+            ("--matrix", self.matrix.is_some()),
+            ("--all-variants", self.all_variants),
+        ];
+        match (&self.subcommand, legacy.iter().find(|(_, given)| *given)) {
+            (Some(sub), Some((flag, _))) => Err(clap::Error::with_description(
+                &format!("`{}` can't be used with `flan {}`", flag, sub.name()),
+                clap::ErrorKind::ArgumentConflict,
+            )),
+            _ => Ok(()),
+        }
+    }
     pub fn parse_decisions(&self) -> Result<(HashSet<String>, HashMap<String, Index>), Error> {
-        let mut nc = HashSet::new();
-        let mut dc = HashMap::new();
-        for s in &self.decisions {
-            match Decision::from_str(s)? {
-                Decision::Name(s) => {
-                    nc.insert(s);
-                }
-                Decision::WithDim(dname, idx) => {
-                    dc.insert(dname, idx);
-                }
-            }
+        parse_decisions(&self.decisions())
+    }
+    /// the decisions given before the subcommand and after `build`, `query`, `graph`, `check`, `clean` or `lsp`
+    pub fn decisions(&self) -> Vec<&String> {
+        let sub = match &self.subcommand {
+            Some(SubCommand::Build { decisions, .. })
+            | Some(SubCommand::Query { decisions, .. })
+            | Some(SubCommand::Graph { decisions, .. })
+            | Some(SubCommand::Check { decisions })
+            | Some(SubCommand::Clean { decisions, .. })
+            | Some(SubCommand::Lsp { decisions }) => decisions.as_slice(),
+            _ => &[],
+        };
+        self.decisions.iter().chain(sub).collect()
+    }
+    /// `flan query`, `--query-dimensions` or `--query-variables`: (per-file?, format)
+    pub fn query(&self) -> Option<(bool, Option<&str>)> {
+        match &self.subcommand {
+            Some(SubCommand::Query { per_file, format, .. }) => Some((*per_file, format.as_deref())),
+            Some(_) => None,
+            None => self.query_dims.as_ref().or(self.query_vars.as_ref()).map(|scope| (scope.is_some(), self.query_format.as_deref())),
+        }
+    }
+    /// `flan query --variables` or `--query-variables`
+    pub fn query_variables(&self) -> bool {
+        match &self.subcommand {
+            Some(SubCommand::Query { variables, .. }) => *variables,
+            Some(_) => false,
+            None => self.query_vars.is_some(),
+        }
+    }
+    pub fn explain(&self) -> Option<&String> {
+        match &self.subcommand {
+            Some(SubCommand::Explain { code }) => Some(code),
+            _ => self.explain.as_ref(),
+        }
+    }
+    /// the file of `flan escape` or `flan unescape`, and whether it is unescaped
+    pub fn escape(&self) -> Option<(&PathBuf, bool)> {
+        match &self.subcommand {
+            Some(SubCommand::Escape { file }) => Some((file, false)),
+            Some(SubCommand::Unescape { file }) => Some((file, true)),
+            _ => self.escape.as_ref().map(|f| (f, false)).or(self.unescape.as_ref().map(|f| (f, true))),
+        }
+    }
+    /// the file of `flan debug lex` or `flan debug ast`, and whether it is parsed
+    pub fn debug(&self) -> Option<(&PathBuf, bool)> {
+        match &self.subcommand {
+            Some(SubCommand::Debug { what: DebugCommand::Lex { file } }) => Some((file, false)),
+            Some(SubCommand::Debug { what: DebugCommand::Ast { file } }) => Some((file, true)),
+            _ => None,
+        }
+    }
+    pub fn matrix(&self) -> Option<&PathBuf> {
+        match &self.subcommand {
+            Some(SubCommand::Build { matrix, .. }) => matrix.as_ref(),
+            _ => self.matrix.as_ref(),
+        }
+    }
+    /// `flan build --all-variants` or `--all-variants`
+    pub fn all_variants(&self) -> bool {
+        match &self.subcommand {
+            Some(SubCommand::Build { all_variants, .. }) => *all_variants,
+            _ => self.all_variants,
         }
-        Ok((nc, dc))
     }
     pub fn report_level(&self) -> Option<u8> {
         let mut report_level: Option<u8> = None;
@@ -83,84 +411,33 @@ impl Opt {
         self.silence
     }
     pub fn dry_run(&self) -> bool {
-        self.dry_run
+        match &self.subcommand {
+            Some(SubCommand::Build { dry_run, .. }) => *dry_run,
+            _ => self.dry_run,
+        }
     }
     pub fn warn_error(&self) -> bool {
         self.warn_error
     }
 }
 
-#[derive(Hash, Debug, PartialEq, Clone)]
-/// command line passed Decision
-pub enum Decision {
-    /// by name
-    Name(String),
-    /// (dimension name, decision index or name) pair.
-    WithDim(String, Index),
-}
-impl Decision {
-    /// parse one decision
-    pub fn from_str<Str: AsRef<str>>(str: &Str) -> Result<Self, Error> {
-        let mut it = str.as_ref().splitn(2, '=');
-        // splitn will give us at the very least "" as first elem
-        let k = it.next().unwrap().trim();
-        let i = it.next();
-        match i {
-            Some(s) => Self::parse_dim(k, s.trim()),
-            None => Self::parse_name(k),
-        }
-    }
-    /// [`Decision::WithDim`]
-    fn parse_dim(k: &str, i: &str) -> Result<Self, Error> {
-        Self::validate_id(k)?;
-        let idx = Self::parse_idx(i)?;
-        Ok(Self::WithDim(k.into(), idx))
-    }
-    /// [`Decision::Name`]
-    fn parse_name(n: &str) -> Result<Self, Error> {
-        Self::validate_id(n)?;
-        Ok(Self::Name(n.into()))
-    }
-    /// [`Index`]
-    fn parse_idx(s: &str) -> Result<Index, Error> {
-        use std::num::IntErrorKind;
-        return match s.parse() {
-            Ok(i) if i < 128 => Ok(Index::Num(i)),
-            Err(e) if *e.kind() == IntErrorKind::PosOverflow => Err(Error::out_of_range(s)),
-            _ => {
-                if Self::validate_id(s).is_ok() {
-                    Ok(Index::Name(s.into()))
-                } else {
-                    Err(Error::invalid_choice(s))
-                }
-            }
-        };
-    }
-    fn validate_id(s: &str) -> Result<(), Error> {
-        if s.len() > 0
-            && (|c: char| c.is_alphabetic() || c == '_')(s.chars().next().unwrap())
-            && !s.contains(|c: char| !c.is_alphanumeric() && c != '_')
-        {
-            Ok(())
-        } else {
-            Err(Error::invalid_identifier(s))
-        }
-    }
-}
-
-/// decision for an explicitly named dimension
-#[derive(Debug, Clone, PartialEq, Hash)]
-pub enum Index {
-    /// by name
-    Name(String),
-    /// by index
-    Num(u8),
-}
-impl std::fmt::Display for Index {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl SubCommand {
+    /// as typed on the command line
+    pub fn name(&self) -> &'static str {
         match self {
-            Index::Name(s) => write!(f, "{}", s),
-            Index::Num(n) => write!(f, "{}", n),
+            SubCommand::Build { .. } => "build",
+            SubCommand::Query { .. } => "query",
+            SubCommand::Graph { .. } => "graph",
+            SubCommand::Check { .. } => "check",
+            SubCommand::Clean { .. } => "clean",
+            SubCommand::Explain { .. } => "explain",
+            SubCommand::Escape { .. } => "escape",
+            SubCommand::Unescape { .. } => "unescape",
+            SubCommand::Fmt { .. } => "fmt",
+            SubCommand::Test { .. } => "test",
+            SubCommand::Matrix { .. } => "matrix",
+            SubCommand::Debug { .. } => "debug",
+            SubCommand::Lsp { .. } => "lsp",
         }
     }
 }
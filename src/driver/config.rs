@@ -0,0 +1,76 @@
+//! the config and flags of the command line.
+use crate::cfg;
+use crate::error::{ErrorFlags, Handler};
+use crate::sourcemap::{span_in, BytePos, Destination, FileKind, SourceInfo, SrcMap};
+
+/// build a new Config and Flags, from arguments and config file
+#[cfg(feature = "cli")]
+pub fn mk_cfgflags() -> Result<(cfg::Flags, cfg::Config), cfg::Error> {
+    use cfg::StructOpt;
+    let opt = cfg::Opt::from_iter_safe(std::env::args_os()).and_then(|opt| opt.check().map(|_| opt)).unwrap_or_else(|e| exit_clap(e));
+    let file = cfg::path_to_cfgfile(opt.config_file.as_ref())?;
+    // @TODO finer grained error reporting. 
+    let decisions = opt.parse_decisions()?;
+    let flags = cfg::Flags::new(&opt, file.options.as_ref());
+    let mut config = cfg::Config::new(decisions.0, decisions.1, file);
+    config.exec = !opt.no_exec;
+    config.run_hooks = !opt.no_hooks;
+    config.file_kinds.lossy_by_default = opt.lossy_decode;
+    config.filters.ignore_files = opt.ignore_files;
+    config.path = cfg::cfgfile_path(opt.config_file.as_ref());
+    if let Some(path) = opt.decisions_file.as_ref() {
+        config = config.with_decisions_file(cfg::path_to_profile(path)?);
+    }
+    if let Some(name) = opt.profile.as_ref() {
+        config = config.select_profile(name)?;
+    }
+    if !opt.no_lock {
+        if let Some(lock) = cfg::lock::path_to_lock(cfg::lock::lock_path(opt.config_file.as_ref()))? {
+            config = config.with_lock(lock);
+        }
+    }
+    Ok((flags, config))
+}
+
+/// reports an error of [`mk_cfgflags`] and exits, a syntax error points at the config file.
+pub fn exit_cfg_error(e: cfg::Error) -> ! {
+    let source_map = SrcMap::new();
+    let mut handler = Handler::new(ErrorFlags::default(), source_map.clone());
+    let code = e.code();
+    match &e {
+        cfg::Error::Syntax { path, error } => {
+            let msg = error.to_string();
+            let file = source_map.load_file_as(path.clone(), Destination::InMemory, FileKind::Text).ok();
+            let at = file.as_ref().zip(error.line_col()).and_then(|(f, (line, col))| match &f.src {
+                SourceInfo::Source(s) => {
+                    let lo = s.split_inclusive('\n').take(line).map(str::len).sum::<usize>() + col;
+                    let lo = lo.min(s.len().saturating_sub(1));
+                    Some(span_in(f.id, BytePos::from(lo), BytePos::from(lo + 1)))
+                }
+                _ => None,
+            });
+            match at {
+                // the position is already shown
+                Some(at) => handler
+                    .error(msg.rsplit_once(" at line ").map_or(msg.as_str(), |(m, _)| m))
+                    .code(code)
+                    .with_span(at)
+                    .print(),
+                None => handler.error(&e.to_string()).code(code).print(),
+            }
+        }
+        e => handler.error(&e.to_string()).code(code).print(),
+    }
+    code.exit_code().exit()
+}
+
+/// prints the error of the command line and exits with [`ExitCode::Config`],
+/// or with [`ExitCode::Success`] for `--help` and `--version`
+#[cfg(feature = "cli")]
+fn exit_clap(e: structopt::clap::Error) -> ! {
+    if !e.use_stderr() {
+        e.exit();
+    }
+    eprintln!("{}", e.message);
+    crate::error::ExitCode::Config.exit()
+}
@@ -0,0 +1,101 @@
+//! `flan debug`: the tokens and trees of the sources.
+use crate::error::Handler;
+use crate::sourcemap::{SourceInfo, SrcFile};
+use crate::syntax::*;
+
+use super::parse::{file_to_parser_with, source_to_stream_with};
+
+/// `flan debug lex`: the tokens of `file`, see [`pp_tokens`]. `None` if it can't be lexed
+pub fn debug_tokens(h: &mut Handler, file: &SrcFile, delims: Delimiters) -> Option<String> {
+    match &file.src {
+        SourceInfo::Source(s) => source_to_stream_with(h, s, file.id, delims).map(|ts| pp_tokens(&ts, file)),
+        SourceInfo::Binary | SourceInfo::Symlink(_) => None,
+    }
+}
+
+/// `flan debug ast`: the tree of `file`, see [`pp_terms`]. It is printed even if poisoned, so the syntax errors
+/// are reported with what was parsed around them, see [`Parser::parse_partial`]. `None` if it can't be lexed
+pub fn debug_terms(h: &mut Handler, file: &SrcFile, delims: Delimiters) -> Option<String> {
+    let mut p = file_to_parser_with(h, file, delims)?;
+    let (terms, _) = p.parse_partial();
+    Some(pp_terms(&terms, file))
+}
+
+/// a token per line: its kind, span in `file` and text, e.g. `Opend 6:11 "#dim{"`
+pub fn pp_tokens(tokens: &TokenStream, file: &SrcFile) -> String {
+    use std::fmt::Write;
+    let mut s = String::new();
+    for t in tokens {
+        let kind = format!("{:?}", t.node);
+        // @SAFETY unwrap(): writing to a String can't fail
+        writeln!(s, "{:<10} {:<11} {:?}", kind, t.span.to_string(), span_text(file, t.span)).unwrap();
+    }
+    s
+}
+
+/// the terms indented by nesting, with their names, number of choices, conditions and spans in `file`
+pub fn pp_terms(terms: &Terms, file: &SrcFile) -> String {
+    let mut s = String::new();
+    pp_terms_into(&mut s, terms, file, 0);
+    s
+}
+
+fn pp_terms_into(s: &mut String, terms: &Terms, file: &SrcFile, depth: usize) {
+    use std::fmt::Write;
+    let indent = "  ".repeat(depth);
+    // @SAFETY unwrap(): writing to a String can't fail
+    for t in terms {
+        let at = t.span;
+        match &t.node {
+            TermK::Text => writeln!(s, "{}Text {} {:?}", indent, at, span_text(file, t.span)).unwrap(),
+            TermK::Comment => writeln!(s, "{}Comment {}", indent, at).unwrap(),
+            TermK::Value(v) => writeln!(s, "{}Value {} {:?}", indent, at, v).unwrap(),
+            TermK::Var(name, default) => {
+                write!(s, "{}Var {} {}", indent, name, at).unwrap();
+                if let Some(d) = default {
+                    write!(s, " default {:?}", d).unwrap();
+                }
+                s.push('\n');
+            }
+            TermK::Dimension { name, children, labels, default } => {
+                writeln!(s, "{}Dimension {}, {} choices {}", indent, name, children.len(), at).unwrap();
+                for (i, child) in children.iter().enumerate() {
+                    match labels.get(i).and_then(Option::as_ref) {
+                        Some(label) => writeln!(s, "{}  choice {} {}", indent, i, label).unwrap(),
+                        None => writeln!(s, "{}  choice {}", indent, i).unwrap(),
+                    }
+                    pp_terms_into(s, child, file, depth + 2);
+                }
+                if let Some(default) = default {
+                    writeln!(s, "{}  default", indent).unwrap();
+                    pp_terms_into(s, default, file, depth + 2);
+                }
+            }
+            TermK::Guard { cond, then, otherwise } => {
+                writeln!(s, "{}Guard {} {}", indent, cond, at).unwrap();
+                writeln!(s, "{}  then", indent).unwrap();
+                pp_terms_into(s, then, file, depth + 2);
+                if let Some(otherwise) = otherwise {
+                    writeln!(s, "{}  else", indent).unwrap();
+                    pp_terms_into(s, otherwise, file, depth + 2);
+                }
+            }
+            TermK::Def { name, body } => {
+                writeln!(s, "{}Def {} {}", indent, name, at).unwrap();
+                pp_terms_into(s, body, file, depth + 1);
+            }
+            TermK::Use { name, args } => {
+                let args: Vec<String> = args.iter().map(|(n, v)| format!("{}={:?}", n, v)).collect();
+                writeln!(s, "{}Use {}({}) {}", indent, name, args.join(", "), at).unwrap();
+            }
+        }
+    }
+}
+
+/// the source text of `span`, empty if `file` isn't a source
+fn span_text(file: &SrcFile, span: crate::sourcemap::Span) -> &str {
+    match &file.src {
+        SourceInfo::Source(s) => s.get(span.as_range()).unwrap_or_default(),
+        SourceInfo::Binary | SourceInfo::Symlink(_) => "",
+    }
+}
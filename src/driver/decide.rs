@@ -0,0 +1,954 @@
+//! the env of a run: the decisions and variables of the config file and of the command line, checked
+//! against the declarations.
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::cfg::{self, ChoiceTable, Choices, Index};
+use crate::env::{ChoiceIdx, Dim, Env, Origin, Pending};
+use crate::error::{codes, ErrorBuilder, Handler, WarnKind};
+use crate::infer;
+use crate::sourcemap::{BytePos, FileId, SrcFile};
+use crate::syntax::*;
+
+use super::lock::locked_decisions;
+use super::prompt::{prompt_decisions, undecided_dims};
+
+/// helper to make an env from config file (`variables` and `decl_dim`) and cmd line options
+/// (`chs` and `idxs`).
+/// Variables bound by the decided choices (see [`Choices::Table`]) override `variables`.
+pub fn make_env(config: &cfg::Config, handler: Handler) -> Result<Env, Handler> {
+    let mut handler = handler;
+    let err_diff = handler.err_count;
+    check_config(config, &mut handler);
+    if handler.err_count > err_diff {
+        handler.print_all();
+        return Err(handler);
+    }
+    let mut variables = config.variables.clone();
+    variables.extend(env_vars(config, &mut handler));
+    variables.extend(cmd_vars(config, &mut handler));
+    // sorted, the diagnostics and the variables bound twice don't depend on the hashes
+    let mut decl_dim: Vec<_> = config.dimensions.clone().into_iter().collect();
+    decl_dim.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let names = &config.decisions_name;
+
+    let mut dimensions = HashMap::new();
+    // variable -> (value, `dimension=choice` binding it)
+    let mut bound = HashMap::new();
+    let aliases = check_aliases(config, &mut handler);
+    let pairs = &dealias_pairs(&config.decisions_pair, &aliases, &mut handler);
+    // the derived dimensions the decisions don't decide, see [`derive_dims`]
+    let mut derived = Vec::new();
+    for (dn, chs) in decl_dim {
+        let r = match &chs {
+            Choices::Names(chns) => handle_named(&dn, chns.clone(), names, pairs, &mut handler),
+            Choices::Table { choices, .. } => handle_named(&dn, choices.names(), names, pairs, &mut handler),
+            Choices::Size(i) => handle_sized(&dn, *i, pairs, &mut handler),
+            Choices::Derived(d) => match handle_named(&dn, vec![d.choose.clone(), d.otherwise.clone()], names, pairs, &mut handler) {
+                Err(eb) if !eb.is_error() => {
+                    derived.push(dn);
+                    continue;
+                }
+                r => r,
+            },
+        };
+        match r {
+            Ok(dim) => {
+                if let Choices::Table { choices, .. } = &chs {
+                    bind_vars(&dn, choices, dim.decision, &mut bound, &mut handler);
+                }
+                dimensions.insert(dn.into(), dim);
+            }
+            Err(eb) => {
+                let eb = match chs.doc() {
+                    Some(doc) => eb.note(format!("`{}`: {}", dn, doc).as_ref()),
+                    None => eb,
+                };
+                if eb.is_error() {
+                    eb.delay();
+                } else {
+                    eb.print();
+                }
+            }
+        }
+    }
+    if handler.err_count == err_diff {
+        variables.extend(bound.into_iter().map(|(var, (val, _))| (var, val)));
+        variables = expand_vars(&variables, &mut handler);
+    }
+    if handler.err_count == err_diff {
+        // add idxs left to env
+        let variables = variables.into_iter().map(|(k, v)| (k.into(), v)).collect();
+        let mut env = Env::new(variables, dimensions, handler);
+        env.labels = decl_labels(&config.dimensions);
+        env.aliases = aliases.into_iter().map(|(a, dn)| (a.into(), dn.into())).collect();
+        env.pending = pending_decisions(&config.dimensions, names, pairs);
+        // @SPEEDUP don't clone
+        fill_env(pairs.clone(), &mut env);
+        for (dn, dim) in env.dimensions.iter_mut() {
+            if let Some(origin) = config.decision_origins.get(dn.as_str()) {
+                dim.origin = *origin;
+            }
+        }
+        derive_dims(config, derived, &mut env);
+        check_constraints(config, &mut env);
+        if env.handler.err_count > err_diff {
+            env.handler.print_all();
+            return Err(env.handler);
+        }
+        return Ok(env);
+    }
+    handler.print_all();
+    Err(handler)
+}
+
+/// [`make_env`] with the labels of `trees` unified, see [`infer::unify_labels`]
+pub fn labeled_env(config: &cfg::Config, trees: &[(SrcFile, Terms)], handler: Handler) -> Result<Env, Handler> {
+    let mut env = make_env(config, handler)?;
+    if infer::unify_labels(trees.iter().map(|t| &t.1), &mut env) {
+        env.handler.print_all();
+        return Err(env.handler);
+    }
+    Ok(env)
+}
+
+/// [`labeled_env`] with the decisions taken after parsing, which are added to `config`: the lockfile's for the
+/// dimensions left undecided, then the ones `--interactive` asks on the terminal. The decisions can bind variables
+/// so the env is made again for them.
+pub fn decided_env<H: Fn() -> Handler>(
+    flags: &cfg::Flags,
+    config: &mut cfg::Config,
+    trees: &[(SrcFile, Terms)],
+    mk_handler: H,
+) -> Result<Env, Handler> {
+    let mut env = labeled_env(config, trees, mk_handler())?;
+    let locked = locked_decisions(config, trees.iter().map(|t| &t.1), &env);
+    if !locked.is_empty() {
+        config.decisions_pair.extend(locked);
+        env = labeled_env(config, trees, mk_handler())?;
+    }
+    if flags.interactive {
+        let undecided = undecided_dims(trees.iter().map(|t| &t.1), &env, &config.dimensions);
+        if !undecided.is_empty() {
+            // stdout can be the destination
+            match prompt_decisions(&undecided, &mut io::stdin().lock(), &mut io::stderr()) {
+                Ok(decisions) => config.decisions_pair.extend(decisions),
+                Err(e) => {
+                    env.handler.error(format!("couldn't read the decisions:\n  {}", e).as_ref()).code(codes::LOAD_FAILED).print();
+                    return Err(env.handler);
+                }
+            }
+            env = labeled_env(config, trees, mk_handler())?;
+        }
+    }
+    Ok(env)
+}
+
+/// reports all the invalid declarations of the config file for [`make_env`]: dimensions with duplicate choices
+/// or more than [`ChoiceIdx::MAX_CHOICES`] of them, dimension, alias, choice and variable names which can't be used in the sources,
+/// and empty paths.
+/// reports the delimiters of `[syntax]` which can't be lexed, see [`Delimiters::validate`].
+/// They have to be checked before parsing the sources
+pub fn check_syntax(config: &cfg::Config, handler: &mut Handler) {
+    let syntax = &config.syntax;
+    let mut report = |msg: String| handler.error(&msg).code(codes::INVALID_DECLARATION).declared_at(config, "syntax").print();
+    // the patterns inherit the invalid delimiters, they'd only repeat the error
+    if let Err(e) = syntax.delimiters.validate() {
+        return report(format!("invalid delimiters: {}", e));
+    }
+    for (pattern, delims) in syntax.files.iter() {
+        if let Err(e) = delims.validate() {
+            report(format!("invalid delimiters for `{}`: {}", pattern, e));
+        }
+    }
+}
+
+fn check_config(config: &cfg::Config, handler: &mut Handler) {
+    use crate::syntax::lexer::Lexer;
+    let mut dims: Vec<_> = config.dimensions.iter().collect();
+    dims.sort_by_key(|(dn, _)| *dn);
+    for (dn, chs) in dims {
+        let key = format!("dimensions.{}", dn);
+        if !cfg::decisions::is_identifier(dn) {
+            handler
+                .error(format!("dimension name `{}` isn't an identifier.", dn).as_ref())
+                .code(codes::INVALID_DECLARATION)
+                .declared_at(config, &key)
+                .delay();
+        }
+        if !chs.valid() {
+            handler
+                .error(format!("dimension `{}` has duplicate choices, or more than {} of them.", dn, ChoiceIdx::MAX_CHOICES).as_ref())
+                .code(codes::INVALID_DECLARATION)
+                .declared_at(config, &key)
+                .delay();
+        }
+        for name in chs.names().unwrap_or_default().iter().filter(|n| !cfg::decisions::is_identifier(n)) {
+            handler
+                .error(format!("choice `{}` of dimension `{}` isn't an identifier.", name, dn).as_ref())
+                .code(codes::INVALID_DECLARATION)
+                .declared_at(config, &key)
+                .delay();
+        }
+    }
+    let mut aliases: Vec<_> = config.aliases.keys().filter(|a| !cfg::decisions::is_identifier(a)).collect();
+    aliases.sort();
+    for alias in aliases {
+        handler
+            .error(format!("alias `{}` isn't an identifier.", alias).as_ref())
+            .code(codes::INVALID_DECLARATION)
+            .declared_at(config, &format!("aliases.{}", alias))
+            .delay();
+    }
+    let mut vars: Vec<_> = config
+        .variables
+        .keys()
+        .chain(config.env_variables.keys())
+        .chain(config.cmd_variables.keys())
+        .filter(|v| v.is_empty() || !v.chars().all(Lexer::is_varsymbol) || v.contains(":-"))
+        .collect();
+    vars.sort();
+    for name in vars {
+        let eb = handler
+            .error(format!("variable name `{}` can't be used as `#${}#`.", name, name).as_ref())
+            .code(codes::INVALID_DECLARATION)
+            .declared_at(config, &format!("variables.{}", name));
+        match name.split_once(":-") {
+            Some((var, default)) => eb.note(&format!("`#${}#` is the variable `{}` with the default `{}`.", name, var, default)).delay(),
+            None => eb.delay(),
+        }
+    }
+    let paths = config.paths.iter().filter(|(src, dst)| src.as_os_str().is_empty() || dst.as_os_str().is_empty());
+    for (src, dst) in paths {
+        handler
+            .error(format!("path `{}` = `{}` has an empty source or destination.", src.display(), dst.display()).as_ref())
+            .code(codes::INVALID_DECLARATION)
+            .declared_at(config, &format!("paths.{}", src.display()))
+            .delay();
+    }
+    let mut derived: Vec<_> = config.dimensions.iter().filter_map(|(dn, chs)| match chs {
+        Choices::Derived(d) => Some((dn, cond::parse_cond(&d.when.to_cond(), FileId::NONE, BytePos(0)))),
+        _ => None,
+    }).collect();
+    derived.sort_by_key(|(dn, _)| *dn);
+    for (dn, cond) in derived {
+        if let Err(e) = cond {
+            handler
+                .error(format!("the `when` of derived dimension `{}` isn't a valid condition.", dn).as_ref())
+                .code(codes::INVALID_DECLARATION)
+                .declared_at(config, &format!("dimensions.{}", dn))
+                .note(&e.msg)
+                .delay();
+        }
+    }
+    for (name, cond) in constraints(config) {
+        if let Err(e) = cond {
+            handler
+                .error(format!("constraint `{}` isn't a valid condition.", name).as_ref())
+                .code(codes::INVALID_DECLARATION)
+                .declared_at(config, &format!("constraints.{}", name))
+                .note(&e.msg)
+                .delay();
+        }
+    }
+}
+
+/// decides the `derived` dimensions of the config for [`make_env`], see [`Choices::Derived`]. A derived dimension
+/// can depend on other derived ones, in the order of their dependencies. A cycle is an error, and a dimension
+/// depending on an undecided one stays undecided.
+fn derive_dims(config: &cfg::Config, derived: Vec<String>, env: &mut Env) {
+    let mut pending: Vec<_> = derived
+        .into_iter()
+        .filter_map(|dn| match config.dimensions.get(&dn) {
+            Some(Choices::Derived(d)) => cond::parse_cond(&d.when.to_cond(), FileId::NONE, BytePos(0)).ok().map(|c| (dn, c)),
+            _ => None,
+        })
+        .collect();
+    pending.sort_by(|a, b| a.0.cmp(&b.0));
+    while !pending.is_empty() {
+        let waiting: HashSet<Name> = pending.iter().map(|(dn, _)| Name::from(dn)).collect();
+        let (ready, rest): (Vec<_>, Vec<_>) = pending.into_iter().partition(|(_, c)| {
+            c.comparisons().iter().all(|cmp| match cmp {
+                cond::Cond::Is { dim, .. } => !waiting.contains(env.canonical(dim)),
+                _ => true,
+            })
+        });
+        if ready.is_empty() {
+            let names: Vec<_> = rest.iter().map(|(dn, _)| format!("`{}`", dn)).collect();
+            env.handler
+                .error(format!("derived dimensions {} depend on each other.", names.join(", ")).as_ref())
+                .code(codes::INVALID_DECLARATION)
+                .declared_at(config, &format!("dimensions.{}", rest[0].0))
+                .delay();
+            return;
+        }
+        for (dn, c) in ready {
+            let undecided = c.comparisons().into_iter().find_map(|cmp| match cmp {
+                cond::Cond::Is { dim, .. } if env.get_dimension(dim).is_none() => Some(*dim),
+                _ => None,
+            });
+            if let Some(dim) = undecided {
+                env.handler
+                    .note(format!("no decision found for derived dimension `{}`.", dn).as_ref())
+                    .code(codes::NO_DECISION)
+                    .note(format!("it depends on `{}`, which isn't decided.", dim).as_ref())
+                    .print();
+                continue;
+            }
+            let decision = ChoiceIdx(if infer::eval_cond(&c, env) { 0 } else { 1 });
+            env.dimensions.insert(Name::from(&dn), Dim { choices: Some(2), decision, origin: Origin::Derived });
+        }
+        pending = rest;
+    }
+}
+
+/// the `[constraints]` parsed, sorted by name
+pub(super) fn constraints(config: &cfg::Config) -> Vec<(&String, Result<cond::Cond, cond::CondError>)> {
+    let mut cs: Vec<_> = config.constraints.iter().map(|(n, c)| (n, cond::parse_cond(c, FileId::NONE, BytePos(0)))).collect();
+    cs.sort_by_key(|(n, _)| *n);
+    cs
+}
+
+/// reports the `[constraints]` the decisions of `env` don't satisfy. The undeclared dimensions are decided by
+/// their `dimension=choice` pairs, the constraints with an undecided dimension are skipped.
+pub fn check_constraints(config: &cfg::Config, env: &mut Env) {
+    // the index and the name of the choice of `dn`, only the name is known for a pending pair
+    let decision = |dn: &Name| -> Option<(Option<ChoiceIdx>, Option<Name>)> {
+        match env.get_dimension(dn) {
+            Some(d) => Some((Some(d.decision), env.labels.get(dn).and_then(|ls| ls.get(d.decision.as_usize()).copied().flatten()))),
+            None => env.pending.pairs.get(dn).map(|n| (None, Some(*n))),
+        }
+    };
+    let mut violated = Vec::new();
+    for (name, cond) in constraints(config) {
+        let cond = match cond {
+            Ok(c) => c,
+            // reported by `check_config`
+            Err(_) => continue,
+        };
+        let is = |dim: &Name, choice: &cond::Choice| -> Option<bool> {
+            let (i, n) = decision(env.canonical(dim))?;
+            match choice {
+                cond::Choice::Num(c) => i.map(|i| i == *c),
+                cond::Choice::Name(c) => n.map(|n| n == *c),
+            }
+        };
+        let cmps = cond.comparisons();
+        if cmps.iter().any(|c| matches!(c, cond::Cond::Is { dim, choice, .. } if is(dim, choice).is_none()))
+            || cond.eval(&|dim: &Name, choice: &cond::Choice| is(dim, choice).unwrap_or(false))
+        {
+            continue;
+        }
+        let mut dims: Vec<_> = cmps.iter().filter_map(|c| match c {
+            cond::Cond::Is { dim, .. } => Some(*env.canonical(dim)),
+            _ => None,
+        }).collect();
+        dims.sort();
+        dims.dedup();
+        let decided: Vec<_> = dims
+            .iter()
+            .map(|dn| match decision(dn) {
+                Some((_, Some(n))) => format!("`{}={}`", dn, n),
+                Some((Some(i), None)) => format!("`{}={}`", dn, i),
+                // the constraints with an undecided dimension were skipped
+                _ => unreachable!(),
+            })
+            .collect();
+        violated.push((name, decided));
+    }
+    for (name, decided) in violated {
+        env.handler
+            .error(format!("the decisions violate constraint `{}`.", name).as_ref())
+            .code(codes::VIOLATED_CONSTRAINT)
+            .declared_at(config, &format!("constraints.{}", name))
+            .note(format!("`{}` doesn't hold with {}.", config.constraints[name], decided.join(", ")).as_ref())
+            .delay();
+    }
+}
+
+/// reads the variables of `[variables]` which come from the environment, see [`cfg::Variable::Env`].
+/// Unset environment variables without a default are reported, and left out.
+fn env_vars(config: &cfg::Config, handler: &mut Handler) -> HashMap<String, String> {
+    let mut names: Vec<_> = config.env_variables.keys().collect();
+    names.sort();
+    let mut values = HashMap::new();
+    for name in names {
+        let var = &config.env_variables[name];
+        match (std::env::var(&var.env), &var.default) {
+            (Ok(v), _) => {
+                values.insert(name.clone(), v);
+            }
+            (Err(std::env::VarError::NotPresent), Some(d)) => {
+                values.insert(name.clone(), d.clone());
+            }
+            (Err(std::env::VarError::NotPresent), None) => handler
+                .error(format!("environment variable `{}` of variable `{}` is not set.", var.env, name).as_ref())
+                .code(codes::UNSET_ENV_VARIABLE)
+                .declared_at(config, &format!("variables.{}", name))
+                .note(format!("give it a default: `{} = {{ env = \"{}\", default = \"...\" }}`.", name, var.env).as_ref())
+                .delay(),
+            (Err(std::env::VarError::NotUnicode(_)), _) => handler
+                .error(format!("environment variable `{}` of variable `{}` is not valid unicode.", var.env, name).as_ref())
+                .code(codes::INVALID_UNICODE)
+                .delay(),
+        }
+    }
+    values
+}
+
+/// runs the commands of the variables set by a command, see [`cfg::Variable::Cmd`].
+/// Each command only runs once, see [`cfg::Config::cmd_outputs`]. Failed commands are reported, and left out.
+fn cmd_vars(config: &cfg::Config, handler: &mut Handler) -> HashMap<String, String> {
+    let mut names: Vec<_> = config.cmd_variables.keys().collect();
+    names.sort();
+    let mut values = HashMap::new();
+    // @SAFETY unwrap(): the lock is only held here, which doesn't panic
+    let mut outputs = config.cmd_outputs.lock().unwrap();
+    for name in names {
+        let cmd = &config.cmd_variables[name].cmd;
+        if !config.exec {
+            handler
+                .warn(WarnKind::CommandNotRun, format!("variable `{}` is left unset, its command `{}` isn't run.", name, cmd).as_ref())
+                .note("because of `--no-exec`.")
+                .print();
+            continue;
+        }
+        let output = outputs
+            .entry(cmd.clone())
+            .or_insert_with(|| run_cmd(name, cmd, handler));
+        if let Some(v) = output {
+            values.insert(name.clone(), v.clone());
+        }
+    }
+    values
+}
+/// output of `cmd` without its trailing newlines, see [`cmd_vars`]
+fn run_cmd(name: &str, cmd: &str, handler: &mut Handler) -> Option<String> {
+    use std::process::Command;
+    let output = if cfg!(windows) {
+        Command::new("cmd").arg("/C").arg(cmd).output()
+    } else {
+        Command::new("sh").arg("-c").arg(cmd).output()
+    };
+    let output = match output {
+        Ok(o) => o,
+        Err(e) => {
+            handler
+                .error(format!("couldn't run the command `{}` of variable `{}`: {}.", cmd, name, e).as_ref())
+                .code(codes::FAILED_COMMAND)
+                .delay();
+            return None;
+        }
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let eb = handler
+            .error(format!("the command `{}` of variable `{}` failed ({}).", cmd, name, output.status).as_ref())
+            .code(codes::FAILED_COMMAND);
+        match stderr.trim() {
+            "" => eb.delay(),
+            s => eb.note(s).delay(),
+        }
+        return None;
+    }
+    match String::from_utf8(output.stdout) {
+        Ok(s) => Some(s.trim_end_matches(['\n', '\r']).into()),
+        Err(_) => {
+            handler
+                .error(format!("the output of the command `{}` of variable `{}` is not valid unicode.", cmd, name).as_ref())
+                .code(codes::INVALID_UNICODE)
+                .delay();
+            None
+        }
+    }
+}
+
+/// expands the references to other variables (`#$name#` or `#$name:-default#`) in the values of `variables`.
+/// Undeclared variables and cycles are reported, and the variables involved are left out.
+pub fn expand_vars(variables: &HashMap<String, String>, handler: &mut Handler) -> HashMap<String, String> {
+    // variable -> expanded value, `None` if it failed
+    let mut done = HashMap::new();
+    let mut names: Vec<_> = variables.keys().collect();
+    names.sort();
+    for name in names {
+        expand_var(name, variables, &mut done, &mut Vec::new(), handler);
+    }
+    done.into_iter().filter_map(|(k, v)| Some((k, v?))).collect()
+}
+/// expands one variable for [`expand_vars`], `stack` holds the variables being expanded.
+fn expand_var(
+    name: &str,
+    variables: &HashMap<String, String>,
+    done: &mut HashMap<String, Option<String>>,
+    stack: &mut Vec<String>,
+    handler: &mut Handler,
+) -> Option<String> {
+    if let Some(v) = done.get(name) {
+        return v.clone();
+    }
+    if let Some(i) = stack.iter().position(|n| n == name) {
+        let mut cycle = stack[i..].iter().map(|n| format!("`{}`", n)).collect::<Vec<_>>();
+        cycle.push(format!("`{}`", name));
+        handler
+            .error(format!("variable `{}` references itself.", name).as_ref())
+            .code(codes::RECURSIVE_VARIABLE)
+            .note(format!("through {}.", cycle.join(" -> ")).as_ref())
+            .delay();
+        // the other variables of the cycle fail without reporting it again
+        for n in &stack[i..] {
+            done.insert(n.clone(), None);
+        }
+        return None;
+    }
+    stack.push(name.into());
+    let mut rest = variables[name].as_str();
+    let mut value = String::with_capacity(rest.len());
+    let mut ok = true;
+    while let Some(i) = rest.find("#$") {
+        let (before, after) = (&rest[..i], &rest[i + 2..]);
+        value.push_str(before);
+        let (var, default, len) = match var_reference(after) {
+            Some(r) => r,
+            None => {
+                // not a reference, kept as is
+                value.push_str("#$");
+                rest = after;
+                continue;
+            }
+        };
+        rest = &after[len..];
+        let expanded = match (variables.contains_key(var), default) {
+            (true, _) => expand_var(var, variables, done, stack, handler),
+            (false, Some(d)) => Some(d.into()),
+            (false, None) => {
+                handler
+                    .error(format!("variable `{}` references the undeclared variable `{}`.", name, var).as_ref())
+                    .code(codes::RECURSIVE_VARIABLE)
+                    .delay();
+                None
+            }
+        };
+        match expanded {
+            Some(v) => value.push_str(&v),
+            None => ok = false,
+        }
+    }
+    value.push_str(rest);
+    stack.pop();
+    // a cycle going through this variable may already have failed it
+    let value = if ok && !done.contains_key(name) { Some(value) } else { None };
+    done.insert(name.into(), value.clone());
+    value
+}
+/// parses `name#` or `name:-default#` (after a `#$`), returns the name, default, and length of the reference.
+fn var_reference(s: &str) -> Option<(&str, Option<&str>, usize)> {
+    use crate::syntax::lexer::Lexer;
+    let mut end = 0;
+    for (i, c) in s.char_indices() {
+        end = i;
+        if c == '#' || s[i..].starts_with(":-") || !Lexer::is_varsymbol(c) {
+            break;
+        }
+        end = i + c.len_utf8();
+    }
+    let (name, rest) = s.split_at(end);
+    if name.is_empty() {
+        return None;
+    }
+    if rest.starts_with('#') {
+        return Some((name, None, end + 1));
+    }
+    let default = rest.strip_prefix(":-")?;
+    let len = default.find(['#', '\n'])?;
+    if default[len..].starts_with('#') {
+        Some((name, Some(&default[..len]), end + 2 + len + 1))
+    } else {
+        None
+    }
+}
+/// the variables referenced by `value`, see [`expand_vars`]
+fn var_references(value: &str) -> Vec<&str> {
+    let mut refs = Vec::new();
+    let mut rest = value;
+    while let Some(i) = rest.find("#$") {
+        rest = &rest[i + 2..];
+        if let Some((var, _, len)) = var_reference(rest) {
+            refs.push(var);
+            rest = &rest[len..];
+        }
+    }
+    refs
+}
+
+/// `flan check`: the declared dimensions are decided, even those no source uses.
+/// Their declarations are checked by [`make_env`].
+pub fn check_declarations(config: &cfg::Config, env: &mut Env) {
+    let mut dims: Vec<_> = config.dimensions.iter().collect();
+    dims.sort_by_key(|(dn, _)| *dn);
+    for (dn, _) in dims {
+        if env.get_dimension(&Name::from(dn)).is_none() {
+            env.handler
+                .error(format!("dimension `{}` has no decision.", dn).as_ref())
+                .code(codes::NO_DECISION)
+                .print();
+        }
+    }
+}
+
+/// warns about the dimensions and variables of the config file which none of the checked sources use,
+/// see [`Env::used_dims`]. The variables referenced by the values of used variables are used too.
+/// The unused decisions are reported too, see [`check_unused_decisions`]
+pub fn check_unused(config: &cfg::Config, env: &mut Env) {
+    check_unused_decisions(config, env);
+    let mut used_vars = env.used_vars.clone();
+    let mut todo: Vec<Name> = used_vars.iter().copied().collect();
+    while let Some(name) = todo.pop() {
+        for var in config.variables.get(name.as_str()).map_or(Vec::new(), |v| var_references(v)) {
+            let var = Name::from(var);
+            if used_vars.insert(var) {
+                todo.push(var);
+            }
+        }
+    }
+    let mut dims: Vec<_> = config.dimensions.keys().filter(|d| !env.used_dims.contains(&Name::from(*d))).collect();
+    dims.sort();
+    for dn in dims {
+        env.handler
+            .warn(WarnKind::UnusedDimension, format!("dimension `{}` is never used.", dn).as_ref())
+            .declared_at(config, &format!("dimensions.{}", dn))
+            .print();
+    }
+    let mut vars: Vec<_> = config
+        .variables
+        .keys()
+        .chain(config.env_variables.keys())
+        .chain(config.cmd_variables.keys())
+        .filter(|v| !used_vars.contains(&Name::from(*v)))
+        .collect();
+    vars.sort();
+    for name in vars {
+        env.handler
+            .warn(WarnKind::UnusedVariable, format!("variable `{}` is never used.", name).as_ref())
+            .declared_at(config, &format!("variables.{}", name))
+            .print();
+    }
+}
+
+/// checks `trees` against `env`, except the sources with an override in `files` which are checked against their
+/// own env by [`path_envs`](super::path_envs). If they check, so do the declarations with `flan check`, see
+/// [`check_declarations`], and the unused ones, see [`check_unused`]. `on_checked` is called with the time each
+/// source took. The errors are printed, returns whether there are any.
+pub fn check_trees<F: FnMut(&SrcFile, Duration)>(
+    flags: &cfg::Flags,
+    config: &cfg::Config,
+    trees: &[(SrcFile, Terms)],
+    files: &HashMap<PathBuf, Env>,
+    env: &mut Env,
+    mut on_checked: F,
+) -> bool {
+    let err = trees.iter().filter(|(f, _)| !files.contains_key(&f.path)).fold(false, |acc, (f, tree)| {
+        let start = Instant::now();
+        let err = infer::check(tree, env).0;
+        on_checked(f, start.elapsed());
+        err || acc
+    });
+    if !err {
+        if flags.command == cfg::Command::Check {
+            check_declarations(config, env);
+        }
+        check_unused(config, env);
+    }
+    env.handler.print_all();
+    err || env.handler.err_count > 0
+}
+
+/// warns about the decisions of the command line matching no dimension once the sources are checked:
+/// a `dimension=choice` pair of a dimension neither declared nor used, or a name none of their choices has.
+/// They are errors with `--strict-decisions`.
+pub fn check_unused_decisions(config: &cfg::Config, env: &mut Env) {
+    let mut pairs: Vec<_> = config
+        .decisions_pair
+        .iter()
+        .filter(|(dn, _)| {
+            let dn = config.aliases.get(*dn).unwrap_or(dn);
+            !config.dimensions.contains_key(dn) && !env.used_dims.contains(&Name::from(dn.as_str()))
+        })
+        .collect();
+    pairs.sort_by_key(|(dn, _)| *dn);
+    let dims: Vec<String> = config.dimensions.keys().chain(config.aliases.keys()).cloned().chain(env.used_dims.iter().map(Name::to_string)).collect();
+    for (dn, idx) in pairs {
+        env.handler
+            .warn(WarnKind::UnusedDecision, format!("decision `{}={}` matches no dimension.", dn, idx).as_ref())
+            .note(format!("dimension `{}` isn't declared, and no source uses it.", dn).as_ref())
+            .suggest_closest(dn, dims.iter().map(String::as_str))
+            .print();
+    }
+    let mut names: Vec<_> = config
+        .decisions_name
+        .iter()
+        .filter(|n| !env.labels.values().flatten().flatten().any(|l| l.as_str() == n.as_str()))
+        .collect();
+    names.sort();
+    let labels: Vec<String> = env.labels.values().flatten().flatten().map(Name::to_string).collect();
+    for n in names {
+        env.handler
+            .warn(WarnKind::UnusedDecision, format!("decision `{}` matches no dimension.", n).as_ref())
+            .note("it is none of the choice names of the config file or the labels of the sources.")
+            .suggest_closest(n, labels.iter().map(String::as_str))
+            .print();
+    }
+}
+
+/// the valid aliases of the config file for [`make_env`].
+/// An alias can't be a declared dimension, nor refer to another alias.
+fn check_aliases(config: &cfg::Config, handler: &mut Handler) -> HashMap<String, String> {
+    let mut aliases: Vec<_> = config.aliases.iter().collect();
+    aliases.sort();
+    let mut valid = HashMap::new();
+    for (alias, dn) in aliases {
+        if config.dimensions.contains_key(alias) {
+            handler
+                .error(format!("alias `{}` of dimension `{}` is also a declared dimension.", alias, dn).as_ref())
+                .code(codes::INVALID_ALIAS)
+                .declared_at(config, &format!("aliases.{}", alias))
+                .delay();
+        } else if config.aliases.contains_key(dn) {
+            handler
+                .error(format!("alias `{}` refers to `{}`, which is an alias itself.", alias, dn).as_ref())
+                .code(codes::INVALID_ALIAS)
+                .declared_at(config, &format!("aliases.{}", alias))
+                .note(format!("use the dimension `{}` refers to instead.", dn).as_ref())
+                .delay();
+        } else {
+            valid.insert(alias.clone(), dn.clone());
+        }
+    }
+    valid
+}
+
+/// renames the dimensions of the `dimension=index` pairs given by an alias for [`make_env`].
+/// it is an error to decide the same dimension through several of its names.
+fn dealias_pairs(
+    pairs: &HashMap<String, Index>,
+    aliases: &HashMap<String, String>,
+    handler: &mut Handler,
+) -> HashMap<String, Index> {
+    let mut sorted: Vec<_> = pairs.iter().collect();
+    sorted.sort_by(|a, b| a.0.cmp(b.0));
+    // dimension -> (name used, decision)
+    let mut dealiased: HashMap<String, (&String, &Index)> = HashMap::new();
+    for (name, idx) in sorted {
+        let dn = aliases.get(name).unwrap_or(name);
+        match dealiased.get(dn) {
+            Some((other, oidx)) => handler
+                .error(format!("decisions `{}={}` and `{}={}` are both for dimension `{}`.", other, oidx, name, idx, dn).as_ref())
+                .code(codes::INVALID_ALIAS)
+                .delay(),
+            None => {
+                dealiased.insert(dn.clone(), (name, idx));
+            }
+        }
+    }
+    dealiased.into_iter().map(|(dn, (_, idx))| (dn, idx.clone())).collect()
+}
+
+/// choice names of the declared dimensions, see [`Env::labels`]
+fn decl_labels(decl_dim: &HashMap<String, Choices>) -> HashMap<Name, Vec<Option<Name>>> {
+    decl_dim
+        .iter()
+        .filter_map(|(dn, chs)| Some((dn.into(), chs.names()?.into_iter().map(|n| Some(n.into())).collect())))
+        .collect()
+}
+
+/// the named decisions [`make_env`] couldn't use, see [`Pending`]
+fn pending_decisions(decl_dim: &HashMap<String, Choices>, names: &HashSet<String>, pairs: &HashMap<String, Index>) -> Pending {
+    let declared: HashSet<String> = decl_dim.values().filter_map(Choices::names).flatten().collect();
+    Pending {
+        names: names.difference(&declared).map(Name::from).collect(),
+        pairs: pairs
+            .iter()
+            .filter(|(dn, _)| !decl_dim.contains_key(*dn))
+            .filter_map(|(dn, idx)| match idx {
+                Index::Name(n) => Some((dn.into(), n.into())),
+                Index::Num(_) => None,
+            })
+            .collect(),
+    }
+}
+
+/// collects the variables bound by the decided choice of a [`Choices::Table`] for [`make_env`].
+/// it is an error for two decided choices to bind the same variable.
+fn bind_vars(
+    dn: &str,
+    choices: &ChoiceTable,
+    decision: ChoiceIdx,
+    bound: &mut HashMap<String, (String, String)>,
+    handler: &mut Handler,
+) {
+    let (chn, choice) = match choices.get(decision.as_usize()) {
+        Some(c) => c,
+        None => return,
+    };
+    let by = format!("{}={}", dn, chn);
+    let mut vars: Vec<_> = choice.vars.iter().collect();
+    vars.sort();
+    for (var, val) in vars {
+        match bound.get(var) {
+            Some((_, other)) => handler
+                .error(format!("variable `{}` is bound by both `{}` and `{}`.", var, other, by).as_ref())
+                .code(codes::VARIABLE_BOUND_TWICE)
+                .delay(),
+            None => {
+                bound.insert(var.clone(), (val.clone(), by.clone()));
+            }
+        }
+    }
+}
+
+/// handle named choices of declared dimension for [`make_env`]
+fn handle_named<'a>(
+    dn: &str,
+    chns: Vec<String>,
+    names: &HashSet<String>,        // standalone decision names
+    pairs: &HashMap<String, Index>, // `dimension=decision` pairs
+    handler: &'a mut Handler,
+) -> Result<Dim, ErrorBuilder<'a>> {
+    use std::fmt::Write;
+    // we keep this binding for error reporting
+    let idx = pairs.get(dn);
+    let mut ni = maybe_idx(idx, &chns);
+    let mut origin = Origin::Pair;
+    // list of valid decisions for the current dimension
+    let mut found = Vec::new();
+    // conflict between `names` and `pairs => ni`
+    let mut conflict = false;
+
+    for (p, chn) in chns.iter().enumerate() {
+        if names.contains(chn) {
+            // if the decision we found in the `pairs` is different than the one we found in `names`
+            // we have a conflict.
+            if ni.is_some_and(|(n, _)| n != chn) {
+                conflict = true
+            }
+            // if there is both a standalone and pair for the same decision, it's redundant
+            if ni.is_some_and(|(n, _)| n == chn) {
+                handler
+                    .warn(
+                        WarnKind::RedundantDecision,
+                        format!(
+                            "decisions `{}` and `{}={}` are redundant.",
+                            chn,
+                            &dn,
+                            idx.unwrap()
+                        )
+                        .as_ref(),
+                    )
+                    .print();
+            }
+            if ni.is_none() {
+                // @SAFETY unwrap(): `check_config` reports the dimensions with too many choices
+                ni = Some((chn, ChoiceIdx::new(p).unwrap()));
+                origin = Origin::Name;
+            }
+        } else {
+            if ni.is_none_or(|(n, _)| n != chn) {
+                continue;
+            }
+        }
+        found.push(chn);
+    }
+    // @SAFETY: write! does not fail on Strings
+    #[allow(unused_must_use)]
+    if conflict || found.len() > 1 {
+        // if conflicting decisions
+        let mut msg = String::from("the following choices are conflicting: ");
+        let mut it = found.iter();
+        if conflict { // @SAFETY unwrap(): conflict = true, implies that `ni.is_some` (which implies `idx.is_some`)
+            write!(&mut msg, "{}={}", &dn, idx.unwrap());
+        } else { // @SAFETY unwrap(): found.len() > 1
+            write!(&mut msg, "{}", it.next().unwrap());  
+        }
+        for &i in it {
+            write!(&mut msg, ", {}", i);
+        }
+        Err(handler.error(msg.as_ref()).code(codes::CONFLICTING_DECISIONS))
+    } else if let (Some(idx), None) = (idx, ni) {
+        // a pair which isn't one of the choices
+        let eb = handler
+            .error(format!("no choice `{}` in dimension `{}`.", idx, dn).as_ref())
+            .code(codes::UNKNOWN_CHOICE)
+            .note(format!("the choices of `{}` are: {}.", dn, chns.join(", ")).as_ref());
+        Err(match idx {
+            Index::Name(n) => eb.suggest_closest(n, chns.iter().map(String::as_str)),
+            Index::Num(_) => eb,
+        })
+    } else if !conflict && found.is_empty() {
+        // if no decision for declared dimension
+        // @NOTE should this be a warning instead?
+        Err(handler.note(format!("no decision found for declared dimension `{}`.", dn).as_ref()).code(codes::NO_DECISION))
+    } else {
+        // !conflict && found.len() == 1
+        Ok(Dim {
+            choices: ChoiceIdx::count(chns.len()),
+            // @DOC: unwrap safety
+            decision: ni.unwrap().1,
+            origin,
+        })
+    }
+}
+
+/// handle Sized dimension declaration for [`make_env`]
+fn handle_sized<'a>(
+    dn: &str,
+    size: u16,
+    decisions: &HashMap<String, Index>,
+    handler: &'a mut Handler,
+) -> Result<Dim, ErrorBuilder<'a>> {
+    match decisions.get(dn) {
+        Some(Index::Num(i)) => {
+            if i.0 < size {
+                Ok(Dim {choices: Some(size), decision: *i, origin: Origin::Pair})
+            } else {
+                // @TODO note: dimensions declared here: 
+                Err(handler.error(format!("index greater than declared dimension size for decision `{}`=`{}`", dn, i).as_ref()).code(codes::UNKNOWN_CHOICE))
+            }
+        }
+        Some(Index::Name(n)) =>
+            // @TODO note: dimensions declared here: 
+            Err(handler.error(format!("dimension `{}` declared with size `{}`, but a decision name `{}` was given instead of an index.", dn, size, n).as_ref()).code(codes::UNKNOWN_CHOICE)),
+        None =>
+            Err(handler.note(format!("no decision found for dimension `{}`.", dn).as_ref()).code(codes::NO_DECISION)),
+    }
+}
+
+/// tries to get the name and index pair from an [`Index`] and a list of choices
+/// returns `None` if the named decision isn't in choices, or index is out of bounds of choices.
+pub fn maybe_idx<'a>(i: Option<&'a Index>, choices: &'a [String]) -> Option<(&'a String, ChoiceIdx)> {
+    match i? {
+        Index::Name(n) => {
+            let i = choices.iter().position(|s| n == s)?;
+            Some((n, ChoiceIdx::new(i)?))
+        }
+        Index::Num(i) => {
+            let n = choices.get(i.as_usize())?;
+            Some((n, *i))
+        }
+    }
+}
+
+/// fill the env with the remaining decisions
+pub fn fill_env(decisions: HashMap<String, Index>, env: &mut Env) {
+    for (dn, idx) in decisions.into_iter() {
+        match idx {
+            Index::Num(i) => match env.get_dimension(&dn.as_str().into()) {
+                Some(Dim { .. }) => {}
+                None => {
+                    env.dimensions.insert(dn.into(), Dim::new(i));
+                }
+            },
+            Index::Name(_) => {}
+        };
+    }
+}
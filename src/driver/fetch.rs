@@ -0,0 +1,92 @@
+//! remote sources: the sources of `[paths]` given as `http://` or `https://` URLs are downloaded with `curl`
+//! before parsing, and kept in the cache directory (`--cache-dir`, `.flan-cache` by default).
+//!
+//! Each run asks for the source again, only downloading it if it changed since it was cached (`curl --time-cond`),
+//! and falls back to the cached copy if the download fails. `--offline` only reads the cache. The sources are
+//! loaded in memory under their URL, which the diagnostics point at, see [`crate::sourcemap::SourceOrigin::Remote`].
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// default of `--cache-dir`
+pub const CACHE_DIR_DEFAULT: &str = ".flan-cache";
+
+/// whether the source `path` of `[paths]` is an `http://` or `https://` URL
+pub fn is_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|s| s.starts_with("http://") || s.starts_with("https://"))
+}
+
+/// the cached copy of `url` in `cache_dir`: a hash of the URL and its last segment, e.g. `9c1f..-app.conf`
+pub fn cache_path(cache_dir: &Path, url: &str) -> PathBuf {
+    // FNV-1a, stable across runs and platforms
+    let hash = url.bytes().fold(0xcbf29ce484222325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+    let name: String = url
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    cache_dir.join(format!("{:016x}-{}", hash, name))
+}
+
+/// where the contents returned by [`fetch`] come from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fetched {
+    /// downloaded, and cached
+    Downloaded,
+    /// the cached copy, the source didn't change since
+    NotModified,
+    /// the cached copy, with `--offline`
+    Offline,
+    /// the cached copy, the download failed for this reason
+    Stale(String),
+}
+
+/// the contents of `url`, downloaded and cached or only read from the cache if `offline`
+pub fn fetch(url: &str, cache_dir: &Path, offline: bool) -> Result<(String, Fetched), String> {
+    let cached = cache_path(cache_dir, url);
+    let read_cache = || fs::read(&cached).map_err(|e| format!("couldn't read the cached copy at `{}`: {}", cached.display(), e));
+    let (bytes, fetched) = if offline {
+        let bytes = fs::read(&cached).map_err(|e| format!("no cached copy at `{}` with `--offline`: {}", cached.display(), e))?;
+        (bytes, Fetched::Offline)
+    } else {
+        let since = Some(cached.as_path()).filter(|c| c.is_file());
+        match download(url, since) {
+            Ok(Some(bytes)) => {
+                fs::create_dir_all(cache_dir)
+                    .and_then(|_| fs::write(&cached, &bytes))
+                    .map_err(|e| format!("couldn't cache it at `{}`: {}", cached.display(), e))?;
+                (bytes, Fetched::Downloaded)
+            }
+            Ok(None) => (read_cache()?, Fetched::NotModified),
+            Err(e) if since.is_some() => (read_cache()?, Fetched::Stale(e)),
+            Err(e) => return Err(e),
+        }
+    };
+    let contents = String::from_utf8(bytes).map_err(|_| String::from("it isn't valid UTF-8."))?;
+    Ok((contents, fetched))
+}
+
+/// `curl --fail`, following the redirections but only to http(s). `None` if the source didn't change since the
+/// modification time of the file `since`, see `curl --time-cond`
+fn download(url: &str, since: Option<&Path>) -> Result<Option<Vec<u8>>, String> {
+    let mut curl = Command::new("curl");
+    curl.args(["--fail", "--silent", "--show-error", "--location", "--proto", "=http,https", "--proto-redir", "=http,https"])
+        // the status is appended to the body, to tell a `304 Not Modified` from an empty source
+        .args(["--write-out", "\n%{http_code}"]);
+    if let Some(since) = since {
+        curl.arg("--time-cond").arg(since);
+    }
+    let output = curl.arg(url).output().map_err(|e| format!("couldn't run `curl`: {}", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("`curl` failed ({}): {}", output.status, stderr.trim()));
+    }
+    let mut body = output.stdout;
+    let status = body.iter().rposition(|&b| b == b'\n').unwrap_or(0);
+    let not_modified = &body[status..] == b"\n304";
+    body.truncate(status);
+    Ok(Some(body).filter(|_| !not_modified))
+}
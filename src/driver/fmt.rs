@@ -0,0 +1,62 @@
+//! `flan escape`, `flan fmt` and `--partial`: the sources rewritten as sources.
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use crate::env::Env;
+use crate::sourcemap::{span_in, BytePos, SourceInfo, SrcFile};
+use crate::syntax::*;
+use crate::{cfg, infer};
+
+/// reads a plain file and (un)escapes it. see [`crate::syntax::escape`]
+pub fn escape_file(path: &Path, unescape: bool) -> io::Result<String> {
+    use crate::syntax::escape;
+    let src = fs::read_to_string(path)?;
+    Ok(if unescape {
+        escape::unescape(&src)
+    } else {
+        escape::escape(&src)
+    })
+}
+
+/* fmt */
+
+/// `flan fmt`: rewrites each source of `trees` as [`printer::format`] lays it out, unless `check`.
+/// Returns the sources which weren't formatted
+pub fn fmt(trees: &[(SrcFile, Terms)], syntax: &cfg::Syntax, check: bool) -> io::Result<Vec<PathBuf>> {
+    let mut unformatted = Vec::new();
+    for (file, terms) in trees {
+        let src = match &file.src {
+            // stdin and the remote sources can't be rewritten
+            SourceInfo::Source(s) if file.origin == crate::sourcemap::SourceOrigin::Path => s,
+            _ => continue,
+        };
+        let formatted = printer::format(terms, src, syntax.delimiters_of(&file.path));
+        if &formatted != src {
+            if !check {
+                fs::write(&file.path, formatted)?;
+            }
+            unformatted.push(file.path.clone());
+        }
+    }
+    Ok(unformatted)
+}
+
+/* partial */
+
+/// `--partial`: replaces each tree by the template left with the decisions of `env` (see [`infer::specialize`]),
+/// as a single value written as is.
+pub fn specialize_trees(trees: Vec<(SrcFile, Terms)>, env: &Env, syntax: &cfg::Syntax) -> Vec<(SrcFile, Terms)> {
+    trees
+        .into_iter()
+        .map(|(file, tree)| {
+            let template = match &file.src {
+                SourceInfo::Source(s) => {
+                    printer::print_with(&infer::specialize(&tree, env), s, syntax.delimiters_of(&file.path))
+                }
+                SourceInfo::Binary | SourceInfo::Symlink(_) => return (file, tree),
+            };
+            let tree = vec![Term::value(template, span_in(file.id, BytePos(0), file.end))];
+            (file, tree)
+        })
+        .collect()
+}
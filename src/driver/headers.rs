@@ -0,0 +1,77 @@
+//! `--headers`: the comments written at the top of the destinations.
+use std::collections::HashMap;
+
+use crate::cfg::{self, Choices};
+use crate::env::Env;
+use crate::infer;
+use crate::sourcemap::{span_in, BytePos, SourceInfo, SrcFile};
+use crate::syntax::*;
+
+use super::lock::decision_index;
+
+/// `--headers`: each tree writes first the [`header`] of its source, after its `#!` line if it starts with one.
+/// The sources without a comment, see [`cfg::Headers::comment_of`], are unchanged.
+pub fn inject_headers(trees: Vec<(SrcFile, Terms)>, env: &Env, config: &cfg::Config) -> Vec<(SrcFile, Terms)> {
+    trees
+        .into_iter()
+        .map(|(file, mut tree)| {
+            let (src, comment) = match (&file.src, config.headers.comment_of(&file.path)) {
+                (SourceInfo::Source(s), Some(c)) => (s, c),
+                _ => return (file, tree),
+            };
+            let newline = if src.contains("\r\n") { "\r\n" } else { "\n" };
+            let text = |t: &Term| &src[t.span.as_range()];
+            let at = match tree.first() {
+                Some(t) if t.node == TermK::Text && text(t).starts_with("#!") => {
+                    // the header can't be written before the end of the line
+                    let line = tree.iter().position(|t| t.node == TermK::Text && text(t).contains('\n'));
+                    let i = match line {
+                        Some(i) => i,
+                        None => return (file, tree),
+                    };
+                    // @SAFETY unwrap(): found by `position`
+                    let end = tree[i].span.lo + BytePos::from(text(&tree[i]).find('\n').unwrap() + 1);
+                    let rest = Term { node: TermK::Text, span: span_in(file.id, end, tree[i].span.hi) };
+                    tree[i].span.hi = end;
+                    if !rest.span.is_empty() {
+                        tree.insert(i + 1, rest);
+                    }
+                    i + 1
+                }
+                _ => 0,
+            };
+            let header = comment.wrap(&header(&file, &tree, env, &config.dimensions)) + newline;
+            let pos = tree.get(at).map_or(file.end, |t| t.span.lo);
+            tree.insert(at, Term::value(header, span_in(file.id, pos, pos)));
+            (file, tree)
+        })
+        .collect()
+}
+
+/// `GENERATED by flan from SOURCE (decisions: dim=choice, ..)`, the decisions of the dimensions used by `terms`
+pub fn header(file: &SrcFile, terms: &Terms, env: &Env, declared_dims: &HashMap<String, Choices>) -> String {
+    let mut dims = infer::traverse(terms, Vec::new(), &|t: &Term, mut dims: Vec<Name>| {
+        match &t.node {
+            TermK::Dimension { name, .. } => dims.push(*env.canonical(name)),
+            TermK::Guard { cond, .. } => {
+                for c in cond.comparisons() {
+                    if let Cond::Is { dim, .. } = c {
+                        dims.push(*env.canonical(dim));
+                    }
+                }
+            }
+            _ => {}
+        }
+        dims
+    });
+    dims.sort();
+    dims.dedup();
+    let decisions: Vec<_> = dims
+        .iter()
+        .filter_map(|dn| env.get_dimension(dn).map(|dim| format!("{}={}", dn, decision_index(env, declared_dims, dn, dim))))
+        .collect();
+    match decisions.is_empty() {
+        true => format!("GENERATED by flan from {}", file.path.display()),
+        false => format!("GENERATED by flan from {} (decisions: {})", file.path.display(), decisions.join(", ")),
+    }
+}
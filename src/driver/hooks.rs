@@ -0,0 +1,57 @@
+//! the `[hooks]` commands run before and after writing.
+use crate::cfg;
+use crate::error::{codes, Handler};
+
+/// when the commands of `[hooks]` run, see [`run_hooks`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookStage {
+    Pre,
+    Post,
+}
+impl HookStage {
+    fn key(self) -> &'static str {
+        match self {
+            HookStage::Pre => "pre",
+            HookStage::Post => "post",
+        }
+    }
+}
+
+/// runs the `pre` or `post` commands of [`cfg::Config::hooks`] in order with the shell, unless `--no-hooks`.
+/// Their output goes to stderr since stdout can be a destination. The first failure is reported and the next
+/// hooks aren't run, returns whether they all succeeded.
+pub fn run_hooks(config: &cfg::Config, stage: HookStage, handler: &mut Handler) -> bool {
+    use std::process::{Command, Stdio};
+    if !config.run_hooks {
+        return true;
+    }
+    let cmds = match stage {
+        HookStage::Pre => &config.hooks.pre,
+        HookStage::Post => &config.hooks.post,
+    };
+    let key = format!("hooks.{}", stage.key());
+    for cmd in cmds {
+        let mut command = if cfg!(windows) {
+            let mut c = Command::new("cmd");
+            c.arg("/C");
+            c
+        } else {
+            let mut c = Command::new("sh");
+            c.arg("-c");
+            c
+        };
+        let status = command.arg(cmd).stdin(Stdio::null()).stdout(Stdio::from(std::io::stderr())).status();
+        let msg = match status {
+            Ok(s) if s.success() => continue,
+            Ok(s) => format!("the `{}` hook `{}` failed ({}).", stage.key(), cmd, s),
+            Err(e) => format!("couldn't run the `{}` hook `{}`: {}.", stage.key(), cmd, e),
+        };
+        let eb = handler.error(&msg).code(codes::HOOK_FAILED).declared_at(config, &key);
+        match stage {
+            HookStage::Pre => eb.note("nothing is written.").print(),
+            HookStage::Post => eb.note("the destinations are written, the next hooks aren't run.").print(),
+        }
+        return false;
+    }
+    true
+}
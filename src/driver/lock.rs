@@ -0,0 +1,51 @@
+//! `--lock`: the decisions of a run written for the next ones.
+use std::collections::HashMap;
+use std::path::Path;
+use std::{fs, io};
+
+use crate::cfg::{self, Choices, Index};
+use crate::env::{Dim, Env, Origin};
+use crate::syntax::*;
+
+use super::prompt::undecided_dims;
+
+/// the decisions of [`cfg::Config::locked`] for the dimensions of `trees` left undecided in `env`.
+pub fn locked_decisions<'a, It: Iterator<Item = &'a Terms>>(
+    config: &cfg::Config,
+    trees: It,
+    env: &Env,
+) -> HashMap<String, Index> {
+    undecided_dims(trees, env, &config.dimensions)
+        .into_iter()
+        .filter_map(|d| Some((d.name.to_string(), config.locked.get(d.name.as_str())?.clone())))
+        .collect()
+}
+
+/// the decisions of `env`, by choice name if the dimension has names, and the variables set by value in `config`.
+/// The values aren't expanded, and the variables set by the environment or a command aren't locked: the next runs
+/// read them again.
+pub fn env_to_lock(env: &Env, config: &cfg::Config) -> cfg::Lock {
+    let mut lock = cfg::Lock::default();
+    // the derived dimensions are derived again by the next runs
+    for (dn, dim) in env.dimensions.iter().filter(|(_, d)| d.origin != Origin::Derived) {
+        lock.decisions.insert(dn.to_string(), decision_index(env, &config.dimensions, dn, dim));
+    }
+    lock.variables = config.variables.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    lock
+}
+
+/// the name of the choice of `dim`, declared or from the labels, or else its index
+pub(super) fn decision_index(env: &Env, declared_dims: &HashMap<String, Choices>, dn: &Name, dim: &Dim) -> Index {
+    let names = match declared_dims.get(dn.as_str()) {
+        Some(ch) => ch.names(),
+        None => env.labels.get(dn).and_then(|ls| ls.iter().map(|l| l.map(String::from)).collect()),
+    };
+    match names.as_ref().and_then(|ns| ns.get(dim.decision.as_usize())) {
+        Some(n) => Index::Name(n.clone()),
+        None => Index::Num(dim.decision),
+    }
+}
+
+pub fn write_lock(path: &Path, lock: &cfg::Lock) -> io::Result<()> {
+    fs::write(path, lock.to_string())
+}
@@ -0,0 +1,205 @@
+//! `--matrix` and `--all-variants`: the sources written once per variant.
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::{self, Choices};
+use crate::cfg::matrix::{Matrix, Variant};
+use crate::env::Env;
+use crate::error::{codes, Handler};
+use crate::infer;
+use crate::output::{Output, Throttle};
+use crate::sourcemap::{Destination, SrcFile};
+use crate::syntax::*;
+
+use super::decide::{constraints, labeled_env};
+use super::write::write_files_to;
+
+/// the config of a matrix [`Variant`]: its decisions replace the ones given on the command line.
+pub fn variant_config(config: &cfg::Config, variant: &Variant) -> Result<cfg::Config, cfg::Error> {
+    let (names, pairs) = variant.parse_decisions()?;
+    let mut config = config.clone();
+    config.decisions_name = names;
+    config.decisions_pair = pairs;
+    Ok(config)
+}
+
+/// a matrix of every combination of the choices of the dimensions used by `trees`, decided or not.
+/// The choices are named by their declared names or labels, otherwise by their index, and a variant is named
+/// by its choices joined with `-` in the order of the dimension names, e.g. `linux-debug`. Without dimensions,
+/// the only variant is `default`. The combinations violating `[constraints]` are left out, see [`check_constraints`](super::check_constraints),
+/// and the derived dimensions aren't enumerated.
+pub fn all_variants(trees: &[(SrcFile, Terms)], env: &Env, config: &cfg::Config) -> Matrix {
+    let mut used: BTreeMap<Name, usize> = BTreeMap::new();
+    for (_, terms) in trees {
+        used = infer::traverse(terms, used, &|t: &Term, mut used: BTreeMap<Name, usize>| {
+            if let TermK::Dimension { name, children, .. } = &t.node {
+                let n = used.entry(*env.canonical(name)).or_default();
+                *n = (*n).max(children.len());
+            }
+            used
+        });
+    }
+    // (dimension, index, choice) of the combinations so far
+    let mut combos = vec![Vec::<(Name, usize, String)>::new()];
+    // the derived dimensions follow the others
+    let used = used.into_iter().filter(|(dn, _)| !matches!(config.dimensions.get(dn.as_str()), Some(Choices::Derived(_))));
+    for (dn, size) in used {
+        let choices: Vec<String> = match config.dimensions.get(dn.as_str()) {
+            Some(Choices::Size(n)) => (0..*n as usize).map(|i| i.to_string()).collect(),
+            Some(ch) => ch.names().unwrap_or_default(),
+            // labels are only used if they name all the choices
+            None => env.labels.get(&dn)
+                .and_then(|ls| ls.iter().map(|l| l.map(String::from)).collect())
+                .unwrap_or_else(|| (0..size).map(|i| i.to_string()).collect()),
+        };
+        combos = combos
+            .into_iter()
+            .flat_map(|combo| {
+                choices.iter().enumerate().map(move |(i, ch)| {
+                    let mut combo = combo.clone();
+                    combo.push((dn, i, ch.clone()));
+                    combo
+                })
+            })
+            .collect();
+    }
+    let constraints: Vec<_> = constraints(config).into_iter().filter_map(|(_, c)| c.ok()).collect();
+    let variants = combos
+        .into_iter()
+        .filter(|combo| {
+            let decided = |dim: &Name| combo.iter().find(|(dn, _, _)| dn == env.canonical(dim));
+            let is = |dim: &Name, choice: &cond::Choice| {
+                decided(dim).is_some_and(|(_, i, ch)| match choice {
+                    cond::Choice::Num(n) => n.as_usize() == *i,
+                    cond::Choice::Name(n) => n == ch,
+                })
+            };
+            // like `check_constraints`, the ones over dimensions the sources don't use are skipped
+            constraints.iter().all(|c| {
+                let used = c.comparisons().iter().all(|cmp| matches!(cmp, cond::Cond::Is { dim, .. } if decided(dim).is_some()));
+                !used || c.eval(&is)
+            })
+        })
+        .map(|combo| {
+            let names: Vec<_> = combo.iter().map(|(_, _, ch)| ch.as_str()).collect();
+            let name = if names.is_empty() { "default".into() } else { names.join("-") };
+            let decisions = combo.iter().map(|(dn, _, ch)| format!("{}={}", dn, ch)).collect();
+            (name, Variant { decisions, out_prefix: None })
+        })
+        .collect();
+    Matrix { variants }
+}
+
+/// moves a destination under `variant_prefix`, replacing `out_prefix` if it was applied.
+/// returns `None` for absolute destinations, as they would be shared by all the variants.
+pub fn variant_destination(dest: &Path, out_prefix: Option<&PathBuf>, variant_prefix: &Path) -> Option<PathBuf> {
+    let rel = match out_prefix {
+        Some(prefix) => dest.strip_prefix(prefix).unwrap_or(dest),
+        None => dest,
+    };
+    if rel.is_absolute() {
+        None
+    } else {
+        Some(variant_prefix.join(rel))
+    }
+}
+
+/// why a variant of [`render_matrix`] isn't written
+#[derive(Debug)]
+pub enum VariantError {
+    /// the decisions of the variant are invalid, see [`variant_config`]
+    Decisions(cfg::Error),
+    /// the sources don't check with its decisions, the handler printed why
+    Diagnostics(Handler),
+    /// writing or copying failed
+    IO(io::Error),
+}
+
+/// [`render_variant`] failed for `variant`
+#[derive(Debug)]
+pub struct MatrixError {
+    pub variant: String,
+    pub error: VariantError,
+    /// number of files written and copied by the previous variants
+    pub written: usize,
+}
+
+/// writes each variant of `matrix` with its decisions replacing the ones of `config`, see [`render_variant`].
+/// Stops at the first variant failing, returns the number of files written and copied.
+pub fn render_matrix<H: Fn() -> Handler>(
+    flags: &cfg::Flags,
+    config: &cfg::Config,
+    matrix: &Matrix,
+    (trees, bins): (&[(SrcFile, Terms)], &[SrcFile]),
+    mk_handler: H,
+    out: &Output,
+    throttle: &Throttle,
+) -> Result<(usize, usize), MatrixError> {
+    let (mut written, mut copied) = (0, 0);
+    for (name, variant) in matrix.variants.iter() {
+        let rendered = variant_config(config, variant)
+            .map_err(VariantError::Decisions)
+            .and_then(|config| render_variant(flags, &config, (name, variant), (trees, bins), mk_handler(), out, throttle));
+        match rendered {
+            Ok((w, c)) => {
+                written += w;
+                copied += c;
+            }
+            Err(error) => return Err(MatrixError { variant: name.clone(), error, written: written + copied }),
+        }
+    }
+    Ok((written, copied))
+}
+
+/// checks and writes the already parsed `trees`, and copies `bins`, with the decisions of `variant`.
+/// returns the number of files written and copied.
+pub fn render_variant(
+    flags: &cfg::Flags,
+    config: &cfg::Config,
+    (name, variant): (&str, &Variant),
+    (trees, bins): (&[(SrcFile, Terms)], &[SrcFile]),
+    handler: Handler,
+    out: &Output,
+    throttle: &Throttle,
+) -> Result<(usize, usize), VariantError> {
+    let mut env = labeled_env(config, trees, handler).map_err(VariantError::Diagnostics)?;
+    if trees.iter()
+            .fold(false, |acc, (_, tree)| infer::check(tree, &mut env).0 || acc) {
+        env.handler.print_all();
+        return Err(VariantError::Diagnostics(env.handler));
+    }
+
+    let prefix = variant.out_prefix(name, flags.out_prefix.as_ref());
+    let mut dest = |f: &SrcFile| match f.destination.path().map(|d| variant_destination(d, flags.out_prefix.as_ref(), &prefix)) {
+        // stdout is shared by the variants
+        None => Ok(f.destination.clone()),
+        Some(Some(d)) => Ok(Destination::Path(d)),
+        Some(None) => {
+            env.handler
+                .error(format!("destination `{}` is absolute and can't be placed under variant `{}`.", f.destination, name).as_ref())
+                .code(codes::ABSOLUTE_DESTINATION)
+                .note(format!("the variant writes to `{}`.", prefix.display()).as_ref())
+                .delay();
+            Err(())
+        }
+    };
+    let tree_dests: Vec<_> = trees.iter().map(|(f, _)| dest(f)).collect();
+    let bin_dests: Vec<_> = bins.iter().map(dest).collect();
+    if env.handler.err_count > 0 {
+        env.handler.print_all();
+        return Err(VariantError::Diagnostics(env.handler));
+    }
+    // @SAFETY unwrap(): we returned on any `Err(())`
+    let tree_dests: Vec<_> = tree_dests.into_iter().map(Result::unwrap).collect();
+    let bin_dests: Vec<_> = bin_dests.into_iter().map(Result::unwrap).collect();
+
+    let written = write_files_to(flags, trees, &tree_dests, &env, out, throttle, |_, _, _| {}).map_err(VariantError::IO)?;
+    let mut copied = 0;
+    for (bin, dest) in bins.iter().zip(bin_dests.iter()) {
+        let _permit = throttle.acquire();
+        out.copy_bin_to(flags, bin.clone(), dest).map_err(VariantError::IO)?;
+        copied += 1;
+    }
+    Ok((written, copied))
+}
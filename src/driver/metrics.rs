@@ -0,0 +1,266 @@
+//! counts, timings and sizes of a run, printed at its end or written with `--metrics` for the build systems.
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+use crate::cfg::{self, MetricsFormat};
+use crate::error::codes;
+use crate::sourcemap::SrcFile;
+use crate::utils::{peak_rss, pp_bytes};
+
+/// number of files listed for each phase by `--timings=verbose`
+pub const SLOWEST_N: usize = 5;
+
+/// the metrics of a source file, the phases it didn't go through are `None`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FileMetrics {
+    /// size of the source
+    pub bytes: usize,
+    pub parse: Option<Duration>,
+    pub check: Option<Duration>,
+    pub write: Option<Duration>,
+    /// bytes written to the destination
+    pub written: usize,
+}
+
+pub struct Metrics {
+    /// processed file count
+    pub proc_f: isize,
+    /// copied file count
+    pub copy_f: isize,
+    /// total file count (in paths)
+    pub total_f: isize,
+
+    /// start time of the program
+    start: Instant,
+    /// frontend duration
+    pub front: Duration,
+    /// typechecking/inference duration
+    pub infer: Duration,
+    /// backend duration
+    pub end: Duration,
+
+    /// total time
+    pub total: Duration,
+
+    /// bytes held by the source map
+    pub mem_src: usize,
+    /// bytes held by the parsed trees
+    pub mem_trees: usize,
+    /// bytes written to the destinations, see `--io-buffer-size`
+    pub bytes_w: usize,
+
+    /// `--timings=verbose`
+    verbose: bool,
+    /// source path -> its metrics
+    pub files: BTreeMap<PathBuf, FileMetrics>,
+}
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            proc_f: -1,
+            copy_f: -1,
+            total_f: 0,
+            start: Instant::now(),
+            front: Duration::ZERO,
+            infer: Duration::ZERO,
+            end:   Duration::ZERO,
+            total: Duration::ZERO,
+            mem_src: 0,
+            mem_trees: 0,
+            bytes_w: 0,
+            verbose: false,
+            files: BTreeMap::new(),
+        }
+    }
+    pub fn total_files(&mut self, total_files: isize) {
+        self.total_f = total_files;
+    }
+    pub fn processed(&mut self, processed: isize) {
+        self.proc_f = processed
+    }
+    pub fn copied(&mut self, copied: isize) {
+        self.copy_f = copied;
+    }
+    pub fn front(&mut self, start: Instant) {
+        self.front = start.elapsed();
+    }
+    pub fn infer(&mut self, start: Instant) {
+        self.infer = start.elapsed();
+    }
+    pub fn end(&mut self, start: Instant) {
+        self.end = start.elapsed();
+    }
+    pub fn verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+    pub fn mem_sources(&mut self, size: usize) {
+        self.mem_src = size;
+    }
+    pub fn mem_trees(&mut self, size: usize) {
+        self.mem_trees = size;
+    }
+    pub fn record_parse(&mut self, file: &SrcFile, time: Duration) {
+        let m = self.files.entry(file.path.clone()).or_default();
+        m.bytes = file.size();
+        m.parse = Some(time);
+    }
+    pub fn record_check(&mut self, path: &Path, time: Duration) {
+        self.files.entry(path.to_path_buf()).or_default().check = Some(time);
+    }
+    pub fn record_write(&mut self, path: &Path, time: Duration, bytes: usize) {
+        let m = self.files.entry(path.to_path_buf()).or_default();
+        m.write = Some(time);
+        m.written = bytes;
+        self.bytes_w += bytes;
+    }
+    /// ends the run: prints the report if `human`, and writes the `--metrics`
+    pub fn finish(&mut self, flags: &cfg::Flags, human: bool) {
+        self.total = self.start.elapsed();
+        if human {
+            self.report();
+        }
+        let format = match flags.metrics {
+            Some(f) => f,
+            None => return,
+        };
+        let written = match &flags.metrics_file {
+            Some(path) => std::fs::File::create(path).and_then(|mut f| self.write(format, &mut f)),
+            None => self.write(format, &mut io::stderr().lock()),
+        };
+        if let Err(e) = written {
+            emit_error!(code = codes::LOAD_FAILED; "couldn't write the metrics:\n  {}", e);
+        }
+    }
+    pub fn report(&mut self) {
+        self.total = self.start.elapsed();
+        println!("\n");
+        self.report_files();
+        self.report_time();
+        self.report_mem();
+        if self.verbose {
+            self.report_slowest();
+        }
+    }
+    pub fn report_files(&self) {
+        let any = self.proc_f >= 0 || self.copy_f >= 0;
+        if self.total_f >= 0 {
+            print!("{}", self.total_f);
+            if any {
+                let p = isize::max(self.proc_f, 0);
+                let b = isize::max(self.copy_f, 0);
+                print!("[{}+{}]", p, b);
+            }
+            println!(" file{}.", if self.total_f > 1 { "s" } else { "" });
+        }
+    }
+    pub fn report_time(&self) {
+        println!("Total time: {}ms.", self.total.as_millis());
+        if !self.front.is_zero() {
+            println!(" ` front:  {}ms", self.front.as_millis());
+        }
+        if !self.infer.is_zero() {
+            println!(" ` infer:  {}ms", self.infer.as_millis());
+        }
+        if !self.end.is_zero() {
+            print!(" ` output: {}ms", self.end.as_millis());
+            if self.bytes_w > 0 {
+                let rate = self.bytes_w as f64 / self.end.as_secs_f64();
+                print!(", {} written ({}/s)", pp_bytes(self.bytes_w), pp_bytes(rate as usize));
+            }
+            println!();
+        }
+    }
+    pub fn report_slowest(&self) {
+        println!("Slowest files:");
+        self.report_phase("parse", |m| m.parse);
+        self.report_phase("check", |m| m.check);
+        self.report_phase("write", |m| m.write);
+    }
+    /// the [`SLOWEST_N`] slowest files of a phase, slowest first
+    fn report_phase(&self, phase: &str, time: impl Fn(&FileMetrics) -> Option<Duration>) {
+        let mut slowest: Vec<_> = self.files.iter().filter_map(|(p, m)| Some((time(m)?, p))).collect();
+        if slowest.is_empty() {
+            return;
+        }
+        slowest.sort_by_key(|(time, _)| std::cmp::Reverse(*time));
+        println!(" ` {}:", phase);
+        for (time, path) in slowest.iter().take(SLOWEST_N) {
+            println!("   {:>10.3}ms {}", ms(*time), path.display());
+        }
+    }
+    pub fn report_mem(&self) {
+        match peak_rss() {
+            Some(rss) => println!("Peak memory: {}.", pp_bytes(rss)),
+            None => println!("Memory:"),
+        }
+        println!(" ` sources: {}", pp_bytes(self.mem_src));
+        println!(" ` trees:   {}", pp_bytes(self.mem_trees));
+    }
+    /// `--metrics`: as JSON, a single object with the counts, the times (in milliseconds) and the sizes of the run,
+    /// and its `per_file` metrics. As CSV, a row per file then the row of the whole run, without path:
+    /// the parse, check and write times of the latter are the front, infer and output phases.
+    pub fn write(&self, format: MetricsFormat, to: &mut dyn Write) -> io::Result<()> {
+        // the unknown counts are negative
+        let count = |n: isize| if n < 0 { None } else { Some(n) };
+        match format {
+            MetricsFormat::Json => {
+                let files: Vec<_> = self
+                    .files
+                    .iter()
+                    .map(|(p, m)| {
+                        json!({
+                            "path": p,
+                            "bytes": m.bytes,
+                            "parse_ms": m.parse.map(ms),
+                            "check_ms": m.check.map(ms),
+                            "write_ms": m.write.map(ms),
+                            "written": m.written,
+                        })
+                    })
+                    .collect();
+                let metrics = json!({
+                    "files": { "total": count(self.total_f), "processed": count(self.proc_f), "copied": count(self.copy_f) },
+                    "time_ms": { "total": ms(self.total), "front": ms(self.front), "infer": ms(self.infer), "output": ms(self.end) },
+                    "bytes": { "written": self.bytes_w, "sources": self.mem_src, "trees": self.mem_trees, "peak_rss": peak_rss() },
+                    "per_file": files,
+                });
+                serde_json::to_writer(&mut *to, &metrics)?;
+                writeln!(to)
+            }
+            MetricsFormat::Csv => {
+                let opt = |t: Option<Duration>| t.map_or(String::new(), |t| format!("{:.3}", ms(t)));
+                writeln!(to, "path,bytes,parse_ms,check_ms,write_ms,written")?;
+                for (p, m) in self.files.iter() {
+                    let (parse, check, write) = (opt(m.parse), opt(m.check), opt(m.write));
+                    writeln!(to, "{},{},{},{},{},{}", csv_field(p), m.bytes, parse, check, write, m.written)?;
+                }
+                let bytes: usize = self.files.values().map(|m| m.bytes).sum();
+                let (front, infer, end) = (ms(self.front), ms(self.infer), ms(self.end));
+                writeln!(to, ",{},{:.3},{:.3},{:.3},{}", bytes, front, infer, end, self.bytes_w)
+            }
+        }
+    }
+}
+
+fn ms(time: Duration) -> f64 {
+    time.as_secs_f64() * 1000.0
+}
+
+/// quoted if it contains a `,`, a `"` or a newline
+fn csv_field(path: &Path) -> String {
+    let s = path.to_string_lossy();
+    if s.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.into_owned()
+    }
+}
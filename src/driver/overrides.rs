@@ -0,0 +1,165 @@
+//! the `[paths]` overrides and targets: the sources written with their own decisions.
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::env::{Env, Origin};
+use crate::error::{codes, Handler};
+use crate::output::{Output, Throttle};
+use crate::sourcemap::{Destination, SrcFile};
+use crate::syntax::*;
+use crate::{cfg, infer};
+
+use super::decide::make_env;
+use super::sources::{mk_path, normalize_lossy};
+use super::write::write_files_to;
+
+/// the config of a source with a `[paths]` override: its decisions replace the ones of `config` for the
+/// dimensions they decide, and its variables take precedence over all the others. see [`cfg::PathOverride`]
+pub fn override_config(config: &cfg::Config, o: &cfg::PathOverride) -> Result<cfg::Config, cfg::Error> {
+    let (names, pairs) = cfg::parse_decisions(&o.decisions)?;
+    let canonical = |dn: &String| config.aliases.get(dn).cloned().unwrap_or_else(|| dn.clone());
+    let mut decided: HashSet<String> = pairs.keys().map(canonical).collect();
+    for (dn, chs) in config.dimensions.iter() {
+        if chs.names().is_some_and(|ns| ns.iter().any(|n| names.contains(n))) {
+            decided.insert(dn.clone());
+        }
+    }
+    let mut overridden = config.clone();
+    overridden.decisions_pair.retain(|dn, _| !decided.contains(&canonical(dn)));
+    overridden.locked.retain(|dn, _| !decided.contains(dn));
+    overridden.decision_origins.extend(decided.iter().map(|dn| (dn.clone(), Origin::PathOverride)));
+    for dn in decided.iter() {
+        if let Some(ns) = config.dimensions.get(dn).and_then(|chs| chs.names()) {
+            overridden.decisions_name.retain(|n| !ns.contains(n));
+        }
+    }
+    overridden.decisions_name.extend(names);
+    overridden.decisions_pair.extend(pairs);
+    for (var, value) in o.variables.iter() {
+        overridden.env_variables.remove(var);
+        overridden.cmd_variables.remove(var);
+        overridden.variables.insert(var.clone(), value.clone());
+    }
+    Ok(overridden)
+}
+
+/// the index of the source `key` of `[paths]` in `trees`, if it's loaded from one of the layers
+fn path_tree(flags: &cfg::Flags, key: &Path, trees: &[(SrcFile, Terms)]) -> Option<usize> {
+    let paths: Vec<_> = flags.layers().into_iter().map(|l| normalize_lossy(&mk_path(l, key.to_path_buf()))).collect();
+    trees.iter().position(|(f, _)| paths.contains(&normalize_lossy(&f.path)))
+}
+
+/// the env of [`override_config`] checked against `tree` like the env of all the sources. The errors are printed.
+fn override_env(config: &cfg::Config, key: &Path, o: &cfg::PathOverride, tree: &Terms, mut handler: Handler) -> Result<Env, Handler> {
+    let config = match override_config(config, o) {
+        Ok(c) => c,
+        Err(e) => {
+            let key = format!("paths.{}", key.display());
+            handler
+                .error(format!("invalid decisions of `{}`:\n  {}", key, e).as_ref())
+                .code(codes::INVALID_DECLARATION)
+                .declared_at(config, &key)
+                .print();
+            return Err(handler);
+        }
+    };
+    let mut env = make_env(&config, handler)?;
+    if infer::unify_labels(std::iter::once(tree), &mut env) || infer::check(tree, &mut env).0 {
+        env.handler.print_all();
+        return Err(env.handler);
+    }
+    Ok(env)
+}
+
+/// the envs of the sources with a `[paths]` override, by source path, see [`override_env`] and [`FileEnvs`](crate::env::FileEnvs).
+pub fn path_envs<H: Fn() -> Handler>(
+    flags: &cfg::Flags,
+    config: &cfg::Config,
+    trees: &[(SrcFile, Terms)],
+    mk_handler: H,
+) -> Result<HashMap<PathBuf, Env>, Handler> {
+    let mut envs = HashMap::new();
+    for (key, o) in config.overrides.iter() {
+        if let Some(i) = path_tree(flags, key, trees) {
+            let (source, tree) = &trees[i];
+            envs.insert(source.path.clone(), override_env(config, key, o, tree, mk_handler())?);
+        }
+    }
+    Ok(envs)
+}
+
+/// a source of `[paths]` written again to another destination, see [`cfg::PathTarget`]
+#[derive(Debug)]
+pub struct Target {
+    /// index of the source in the trees
+    pub index: usize,
+    pub destination: Destination,
+    pub env: Env,
+}
+
+/// the targets after the first one of the sources of `[paths]` given as a list, see [`override_env`].
+/// A target writing to the destination of a source or of another target is an error.
+pub fn path_targets<H: Fn() -> Handler>(
+    flags: &cfg::Flags,
+    config: &cfg::Config,
+    trees: &[(SrcFile, Terms)],
+    mk_handler: H,
+) -> Result<Vec<Target>, Handler> {
+    let mut targets = Vec::new();
+    let mut dests: HashSet<PathBuf> = trees.iter().filter_map(|(f, _)| f.destination.path().map(normalize_lossy)).collect();
+    for (key, ts) in config.targets.iter() {
+        let index = match path_tree(flags, key, trees) {
+            Some(i) => i,
+            None => continue,
+        };
+        for o in ts {
+            // @SAFETY unwrap(): see [`cfg::PathTarget`]
+            let dest = mk_path(flags.out_prefix.as_ref(), o.destination.clone().unwrap());
+            if !dests.insert(normalize_lossy(&dest)) {
+                let mut h = mk_handler();
+                let key = format!("paths.{}", key.display());
+                h.error(format!("`{}` is written twice to `{}`.", key, dest.display()).as_ref())
+                    .code(codes::DUPLICATE_DESTINATION)
+                    .declared_at(config, &key)
+                    .print();
+                return Err(h);
+            }
+            let env = override_env(config, key, o, &trees[index].1, mk_handler())?;
+            targets.push(Target { index, destination: Destination::Path(dest), env });
+        }
+    }
+    Ok(targets)
+}
+
+/// the envs of [`path_envs`] and the targets of [`path_targets`], their dimensions and variables are used by `env`
+pub fn override_envs<H: Fn() -> Handler>(
+    flags: &cfg::Flags,
+    config: &cfg::Config,
+    trees: &[(SrcFile, Terms)],
+    env: &mut Env,
+    mk_handler: H,
+) -> Result<(HashMap<PathBuf, Env>, Vec<Target>), Handler> {
+    let files = path_envs(flags, config, trees, &mk_handler)?;
+    let targets = path_targets(flags, config, trees, &mk_handler)?;
+    files.values().chain(targets.iter().map(|t| &t.env)).for_each(|e| env.extend_used(e));
+    Ok((files, targets))
+}
+
+/// writes the sources of `targets` again, see [`write_files_to`]. returns the number of files written.
+pub fn write_targets<F: FnMut(&SrcFile, Duration, usize)>(
+    flags: &cfg::Flags,
+    trees: &[(SrcFile, Terms)],
+    targets: &[Target],
+    out: &Output,
+    throttle: &Throttle,
+    mut on_written: F,
+) -> io::Result<usize> {
+    let mut count = 0;
+    for t in targets {
+        let dests = [t.destination.clone()];
+        count += write_files_to(flags, std::slice::from_ref(&trees[t.index]), &dests, &t.env, out, throttle, &mut on_written)?;
+    }
+    Ok(count)
+}
@@ -0,0 +1,100 @@
+//! lexing and parsing the sources.
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::cfg;
+use crate::error::Handler;
+use crate::sourcemap::{FileId, SrcFile};
+use crate::syntax::*;
+
+/// Does not fail, only report. Caller should check if all sources passed yielded Terms.  
+/// All the syntax errors of a file are reported, its poisoned tree is left out (see [`Parser::parse_partial`])
+pub fn parse_sources(
+    sources: Vec<SrcFile>,
+    h: &mut Handler,
+) -> (Vec<(SrcFile, Terms)>, Vec<SrcFile>) {
+    parse_sources_timed(sources, h, &cfg::Syntax::default(), |_, _| {})
+}
+
+/// same as [`parse_sources`] with the delimiters of `syntax`, checked by [`check_syntax`](super::check_syntax).
+/// Calls `on_parsed` with the time spent on each source file
+pub fn parse_sources_timed<F: FnMut(&SrcFile, Duration)>(
+    sources: Vec<SrcFile>,
+    h: &mut Handler,
+    syntax: &cfg::Syntax,
+    mut on_parsed: F,
+) -> (Vec<(SrcFile, Terms)>, Vec<SrcFile>) {
+    let mut bins = vec![];
+    let mut trees = vec![];
+    for f in sources {
+        if f.is_binary() {
+            bins.push(f);
+            continue;
+        }
+        let start = Instant::now();
+        let tree = match file_to_parser_with(h, &f, syntax.delimiters_of(&f.path)) {
+            Some(mut p) => p.parse().ok(),
+            None => None,
+        };
+        on_parsed(&f, start.elapsed());
+        match tree {
+            Some(tree) => trees.push((f, tree)),
+            None => h.print_all(),
+        }
+    }
+    (trees, bins)
+}
+
+/// transform a source into a [`TokenStream`]
+pub fn source_to_stream(h: &mut Handler, src: &str) -> Option<TokenStream> {
+    source_to_stream_in(h, src, FileId::NONE)
+}
+
+/// same as [`source_to_stream`] but the spans are in `file`, see [`crate::sourcemap::File::id`]
+pub fn source_to_stream_in(h: &mut Handler, src: &str, file: FileId) -> Option<TokenStream> {
+    source_to_stream_with(h, src, file, Delimiters::default())
+}
+
+/// same as [`source_to_stream_in`] with other delimiters, see [`Lexer::with_delimiters`]
+pub fn source_to_stream_with(h: &mut Handler, src: &str, file: FileId, delims: Delimiters) -> Option<TokenStream> {
+    // @REFACTOR
+    let mut vd = VecDeque::new();
+    let mut lexer = Lexer::with_delimiters(h, src, file, delims);
+    loop {
+        let t = lexer.next_token();
+        vd.push_back(t);
+        if lexer.failed() {
+            return None;
+        }
+        if t.is_eof() {
+            break;
+        }
+    }
+    Some(vd)
+}
+
+pub fn string_to_parser<'a>(h: &'a mut Handler, str: String) -> Option<Parser<'a>> {
+    string_to_parser_in(h, str, FileId::NONE)
+}
+
+/// see [`source_to_stream_in`]
+pub fn string_to_parser_in<'a>(h: &'a mut Handler, str: String, file: FileId) -> Option<Parser<'a>> {
+    source_to_stream_in(h, str.as_ref(), file).map(move |ts| Parser::new(h, str, ts))
+}
+
+/// the spans of the parsed terms are in `source`, see [`SrcFile::id`].
+/// The parser borrows the source instead of copying it.
+pub fn file_to_parser<'a>(h: &'a mut Handler, source: &'a SrcFile) -> Option<Parser<'a>> {
+    file_to_parser_with(h, source, Delimiters::default())
+}
+
+/// same as [`file_to_parser`] with other delimiters, see [`Lexer::with_delimiters`]
+pub fn file_to_parser_with<'a>(h: &'a mut Handler, source: &'a SrcFile, delims: Delimiters) -> Option<Parser<'a>> {
+    use crate::sourcemap::SourceInfo;
+    match &source.src {
+        SourceInfo::Source(s) => {
+            source_to_stream_with(h, s, source.id, delims).map(move |ts| Parser::new(h, s.as_str(), ts))
+        }
+        SourceInfo::Binary | SourceInfo::Symlink(_) => None,
+    }
+}
@@ -0,0 +1,69 @@
+//! progress of the phases over the files on the terminal, see `--progress`.
+//!
+//! A single line is redrawn on stdout, e.g. `[write] 1200/4000 src/nvim/init.lua`, and cleared at the end of
+//! each phase. It is silent unless stdout is a terminal, which the destination isn't.
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::cfg;
+
+/// the line is redrawn at most this often
+const REDRAW: Duration = Duration::from_millis(50);
+/// characters of the file name shown, its end is kept
+const MAX_PATH: usize = 60;
+
+pub struct Progress {
+    enabled: bool,
+    phase: &'static str,
+    total: usize,
+    done: usize,
+    /// last time the line was drawn
+    drawn: Option<Instant>,
+}
+impl Progress {
+    /// `--progress`, silent if stdout isn't a terminal or is the destination
+    pub fn new(flags: &cfg::Flags) -> Self {
+        let enabled = flags.progress && !flags.to_stdout() && io::stdout().is_terminal();
+        Progress { enabled, phase: "", total: 0, done: 0, drawn: None }
+    }
+    /// never draws anything
+    pub fn silent() -> Self {
+        Progress { enabled: false, phase: "", total: 0, done: 0, drawn: None }
+    }
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+    /// a phase over `total` files starts
+    pub fn start(&mut self, phase: &'static str, total: usize) {
+        self.phase = phase;
+        self.total = total;
+        self.done = 0;
+        self.drawn = None;
+    }
+    /// `path` is done
+    pub fn step(&mut self, path: &Path) {
+        self.done += 1;
+        if !self.enabled || (self.drawn.is_some_and(|t| t.elapsed() < REDRAW) && self.done < self.total) {
+            return;
+        }
+        self.drawn = Some(Instant::now());
+        let path = path.to_string_lossy();
+        let skip = path.chars().count().saturating_sub(MAX_PATH);
+        let path: String = path.chars().skip(skip).collect();
+        let ellipsis = if skip > 0 { "..." } else { "" };
+        // the line is only feedback, a failed write isn't worth reporting
+        let mut out = io::stdout().lock();
+        let _ = write!(out, "\r\x1b[2K[{}] {}/{} {}{}", self.phase, self.done, self.total, ellipsis, path);
+        let _ = out.flush();
+    }
+    /// clears the line of the phase
+    pub fn finish(&mut self) {
+        if self.enabled && self.drawn.is_some() {
+            let mut out = io::stdout().lock();
+            let _ = write!(out, "\r\x1b[2K");
+            let _ = out.flush();
+        }
+        self.drawn = None;
+    }
+}
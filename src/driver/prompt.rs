@@ -0,0 +1,108 @@
+//! `--interactive`: the decisions asked on the terminal.
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Write};
+
+use crate::cfg::{self, Choices, Index};
+use crate::env::{ChoiceIdx, Env};
+use crate::infer;
+use crate::syntax::*;
+
+/// a dimension left without a decision, see [`undecided_dims`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Undecided {
+    pub name: Name,
+    /// names of the choices, from the config file or the labels
+    pub choices: Option<Vec<String>>,
+    /// number of choices, the largest use if the dimension isn't declared
+    pub size: usize,
+    /// one of its uses has a default branch, so any index is valid
+    pub default: bool,
+}
+
+/// the dimensions used by `trees` without a decision in `env`, sorted by name.
+pub fn undecided_dims<'a, It: Iterator<Item = &'a Terms>>(
+    trees: It,
+    env: &Env,
+    declared_dims: &HashMap<String, Choices>,
+) -> Vec<Undecided> {
+    let mut used: BTreeMap<Name, (usize, bool)> = BTreeMap::new();
+    for terms in trees {
+        used = infer::traverse(terms, used, &|t: &Term, mut used: BTreeMap<Name, (usize, bool)>| {
+            if let TermK::Dimension { name, children, default, .. } = &t.node {
+                if env.get_dimension(name).is_none() {
+                    let u = used.entry(*env.canonical(name)).or_default();
+                    *u = (usize::max(u.0, children.len()), u.1 || default.is_some());
+                }
+            }
+            used
+        });
+    }
+    used.into_iter()
+        .map(|(name, (size, default))| {
+            let declared = declared_dims.get(name.as_str());
+            let choices = match declared {
+                Some(ch) => ch.names(),
+                // labels are only used if they name all the choices
+                None => env.labels.get(&name).and_then(|ls| ls.iter().map(|l| l.map(String::from)).collect()),
+            };
+            let size = match (declared, &choices) {
+                (Some(Choices::Size(n)), _) => *n as usize,
+                (_, Some(names)) => usize::max(size, names.len()),
+                _ => size,
+            };
+            Undecided { name, choices, size, default: default && declared.is_none() }
+        })
+        .collect()
+}
+
+/// asks on `output` for the decision of each of the `undecided` dimensions, and reads the answers from `input`.
+/// An answer is a choice name or an index, invalid answers are asked again.
+/// Returns `dimension=index` pairs, see [`cfg::Config::decisions_pair`].
+pub fn prompt_decisions<R: BufRead, W: Write>(
+    undecided: &[Undecided],
+    input: &mut R,
+    output: &mut W,
+) -> io::Result<HashMap<String, Index>> {
+    let mut decisions = HashMap::new();
+    for dim in undecided {
+        writeln!(output, "no decision for dimension `{}`, choose one of:", dim.name)?;
+        match &dim.choices {
+            Some(names) => {
+                for (i, n) in names.iter().enumerate() {
+                    writeln!(output, "  {}: {}", i, n)?;
+                }
+            }
+            None => writeln!(output, "  an index smaller than {}", dim.size)?,
+        }
+        if dim.default {
+            writeln!(output, "  or a larger index for the default branch")?;
+        }
+        loop {
+            write!(output, "{}> ", dim.name)?;
+            output.flush()?;
+            let mut answer = String::new();
+            if input.read_line(&mut answer)? == 0 {
+                let msg = format!("no decision given for dimension `{}`.", dim.name);
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, msg));
+            }
+            match prompt_answer(dim, answer.trim()) {
+                Some(i) => {
+                    decisions.insert(dim.name.to_string(), Index::Num(i));
+                    break;
+                }
+                None => writeln!(output, "`{}` isn't a choice of dimension `{}`.", answer.trim(), dim.name)?,
+            }
+        }
+    }
+    Ok(decisions)
+}
+
+/// index of the choice `answer` of `dim`, see [`prompt_decisions`]
+fn prompt_answer(dim: &Undecided, answer: &str) -> Option<ChoiceIdx> {
+    let names = dim.choices.as_ref();
+    match format!("{}={}", dim.name, answer).parse::<cfg::Decision>().ok()? {
+        cfg::Decision::WithDim(_, Index::Num(i)) if i.as_usize() < dim.size || dim.default => Some(i),
+        cfg::Decision::WithDim(_, Index::Name(n)) => ChoiceIdx::new(names?.iter().position(|c| *c == n)?),
+        _ => None,
+    }
+}
@@ -0,0 +1,417 @@
+//! `flan query` and `flan graph`: the dimensions and variables used by the sources.
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use crate::cfg::{self, Choices};
+use crate::env::{ChoiceIdx, Dim, Env};
+use crate::infer;
+use crate::sourcemap::{BytePos, Span, SrcFile};
+use crate::syntax::*;
+
+/// wrapper around [`infer::collect`].
+/// see [`cfg::opts::Opt::query_dims`]
+pub fn collect_dims<'a, It: Iterator<Item = &'a Terms>>(
+    trees: &mut It,
+    env: &mut Env,
+    declared_dims: &HashMap<String, Choices>,
+) -> Vec<(Name, Choices)> {
+    let mut map = HashMap::new();
+    for terms in trees {
+        infer::check_collect(terms, &mut map, env);
+    }
+    // @NOTE is checking conflict between declared_dims here needed?
+    map.into_iter()
+        .map(|(k, v)| match declared_dims.get(k.as_str()) {
+            Some(v) => (k, v.clone()),
+            None => (k, Choices::Size(v)),
+        })
+        .collect()
+}
+
+/// a dimension listed by `--query-format=json|toml`, see [`query_dims`]
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct DimQuery {
+    pub name: Name,
+    /// names of the choices, from the config file or the labels
+    pub choices: Option<Vec<String>>,
+    /// see [`Choices::doc`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub doc: Option<String>,
+    /// number of choices
+    pub size: usize,
+    /// declared in the config file, otherwise the size is inferred
+    pub declared: bool,
+    /// source files using the dimension
+    pub files: Vec<PathBuf>,
+    /// the decided choice
+    pub decision: Option<ChoiceIdx>,
+    /// name of the decided choice
+    pub decision_name: Option<String>,
+    /// see [`Origin`](crate::env::Origin)
+    pub origin: Option<String>,
+    /// where the dimension is used, only with [`cfg::QueryScope::PerFile`]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub uses: Vec<DimUse>,
+}
+
+/// the uses of a dimension in a file, see [`DimQuery::uses`]
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct DimUse {
+    pub file: PathBuf,
+    /// line numbers (starting at 1) of the uses
+    pub lines: Vec<usize>,
+}
+
+/// the dimensions used by `trees`, sorted by name, with their choices, the files using them and their decision.
+/// see [`collect_dims`]
+pub fn query_dims(
+    trees: &[(SrcFile, Terms)],
+    env: &mut Env,
+    declared_dims: &HashMap<String, Choices>,
+    scope: cfg::QueryScope,
+) -> Vec<DimQuery> {
+    let dims = collect_dims(&mut trees.iter().map(|t| &t.1), env, declared_dims);
+    let mut files: HashMap<Name, Vec<PathBuf>> = HashMap::new();
+    let mut uses: HashMap<Name, Vec<DimUse>> = HashMap::new();
+    for (f, terms) in trees {
+        let used = infer::traverse(terms, HashMap::new(), &|t: &Term, mut used: HashMap<Name, Vec<BytePos>>| {
+            if let TermK::Dimension { name, .. } = &t.node {
+                used.entry(*env.canonical(name)).or_default().push(t.span.lo);
+            }
+            used
+        });
+        for (name, positions) in used {
+            files.entry(name).or_default().push(f.path.clone());
+            if scope == cfg::QueryScope::PerFile {
+                let mut lines: Vec<_> = positions.into_iter().filter_map(|p| f.get_line_num(p)).map(|i| i + 1).collect();
+                lines.sort();
+                lines.dedup();
+                uses.entry(name).or_default().push(DimUse { file: f.path.clone(), lines });
+            }
+        }
+    }
+    let mut query: Vec<_> = dims
+        .into_iter()
+        .map(|(name, ch)| {
+            let decided = env.get_dimension(&name);
+            let names = choice_names(&name, &ch, env);
+            DimQuery {
+                size: match &ch {
+                    Choices::Size(n) => *n as usize,
+                    _ => names.as_ref().map_or(0, Vec::len),
+                },
+                declared: declared_dims.contains_key(name.as_str()),
+                doc: declared_dims.get(name.as_str()).and_then(Choices::doc).map(String::from),
+                files: files.remove(&name).unwrap_or_default(),
+                decision: decided.map(|d| d.decision),
+                decision_name: decided.and_then(|d| names.as_ref()?.get(d.decision.as_usize()).cloned()),
+                origin: decided.map(|d| d.origin.to_string()),
+                uses: uses.remove(&name).unwrap_or_default(),
+                choices: names,
+                name,
+            }
+        })
+        .collect();
+    query.sort_by_key(|q| q.name);
+    query
+}
+
+/// a variable listed by `--query-variables`, see [`query_vars`]
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct VarQuery {
+    pub name: Name,
+    /// declared in `[variables]`, rather than only given on the command line
+    pub declared: bool,
+    /// its value, if it is set
+    pub value: Option<String>,
+    /// source files using the variable
+    pub files: Vec<PathBuf>,
+    /// where the variable is used, only with [`cfg::QueryScope::PerFile`]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub uses: Vec<DimUse>,
+}
+
+/// the variables used by `trees`, sorted by name, with their value and the files using them.
+/// see [`infer::collect_vars`]
+pub fn query_vars(trees: &[(SrcFile, Terms)], env: &Env, config: &cfg::Config, scope: cfg::QueryScope) -> Vec<VarQuery> {
+    let declared = |n: &str| config.variables.contains_key(n) || config.env_variables.contains_key(n) || config.cmd_variables.contains_key(n);
+    let mut query: BTreeMap<Name, VarQuery> = BTreeMap::new();
+    for (f, terms) in trees {
+        let mut used = HashMap::new();
+        infer::collect_vars(terms, &mut used);
+        for (name, spans) in used {
+            let var = query.entry(name).or_insert_with(|| VarQuery {
+                declared: declared(name.as_str()),
+                value: env.get_var(&name).cloned(),
+                files: vec![],
+                uses: vec![],
+                name,
+            });
+            var.files.push(f.path.clone());
+            if scope == cfg::QueryScope::PerFile {
+                let mut lines: Vec<_> = spans.into_iter().filter_map(|s| f.get_line_num(s.lo)).map(|i| i + 1).collect();
+                lines.sort();
+                lines.dedup();
+                var.uses.push(DimUse { file: f.path.clone(), lines });
+            }
+        }
+    }
+    query.into_values().collect()
+}
+
+/// the names of the choices, from the config file or the labels. The labels are only used if they name all the choices
+fn choice_names(name: &Name, ch: &Choices, env: &Env) -> Option<Vec<String>> {
+    ch.names().or_else(|| env.labels.get(name)?.iter().map(|l| l.map(String::from)).collect())
+}
+
+/* graph */
+
+/// the dimensions and variables of the sources and of the config file, and where they are used. see [`graph`]
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Graph {
+    /// the sources, even those without dimensions nor variables
+    pub files: Vec<PathBuf>,
+    pub dimensions: Vec<GraphDim>,
+    pub variables: Vec<GraphVar>,
+}
+
+/// a dimension of a [`Graph`]
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct GraphDim {
+    pub name: Name,
+    /// names of the choices, see [`DimQuery::choices`]
+    pub choices: Option<Vec<String>>,
+    pub size: usize,
+    /// declared in the config file, otherwise the size is inferred
+    pub declared: bool,
+    /// its dimension terms and the comparisons of the guards
+    pub uses: Vec<GraphUse>,
+}
+
+/// a variable of a [`Graph`]
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct GraphVar {
+    pub name: Name,
+    /// declared in the config file, rather than only given on the command line or by the environment
+    pub declared: bool,
+    pub uses: Vec<GraphUse>,
+}
+
+/// the uses of a dimension or variable in a file
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct GraphUse {
+    pub file: PathBuf,
+    /// byte ranges of the uses in the file
+    pub spans: Vec<(usize, usize)>,
+}
+
+/// the graph of the files using each dimension (through its aliases too) and each variable, sorted by name.
+/// The declarations of the config file no source uses are in it without uses. see [`collect_dims`]
+pub fn graph(trees: &[(SrcFile, Terms)], env: &mut Env, config: &cfg::Config) -> Graph {
+    let mut dims: BTreeMap<_, _> = collect_dims(&mut trees.iter().map(|t| &t.1), env, &config.dimensions).into_iter().collect();
+    for (name, ch) in config.dimensions.iter() {
+        dims.entry(Name::from(name.as_str())).or_insert_with(|| ch.clone());
+    }
+    type Uses = BTreeMap<Name, Vec<GraphUse>>;
+    let (mut dim_uses, mut var_uses) = (Uses::new(), Uses::new());
+    for (f, terms) in trees {
+        let (used_dims, used_vars) = infer::traverse(terms, (Uses::new(), Uses::new()), &|t: &Term, (mut ds, mut vs): (Uses, Uses)| {
+            let push = |uses: &mut Uses, name: Name, span: Span| {
+                let spans = uses.entry(name).or_insert_with(|| vec![GraphUse { file: f.path.clone(), spans: vec![] }]);
+                spans[0].spans.push((span.lo.as_usize(), span.hi.as_usize()));
+            };
+            match &t.node {
+                // @SAFETY unwrap(): dimension terms always have an opening delimiter
+                TermK::Dimension { name, .. } => push(&mut ds, *env.canonical(name), t.opend_span().unwrap()),
+                TermK::Var(name, _) => push(&mut vs, *name, t.span),
+                TermK::Guard { cond, .. } => {
+                    for c in cond.comparisons() {
+                        if let cond::Cond::Is { dim, span, .. } = c {
+                            push(&mut ds, *env.canonical(dim), *span);
+                        }
+                    }
+                }
+                _ => {}
+            }
+            (ds, vs)
+        });
+        for (uses, used) in [(&mut dim_uses, used_dims), (&mut var_uses, used_vars)] {
+            for (name, u) in used {
+                uses.entry(name).or_default().extend(u);
+            }
+        }
+    }
+    // the dimensions only compared by guards without a declaration aren't known, their uses are left out
+    let dimensions = dims
+        .into_iter()
+        .map(|(name, ch)| {
+            let choices = choice_names(&name, &ch, env);
+            GraphDim {
+                size: match &ch {
+                    Choices::Size(n) => *n as usize,
+                    _ => choices.as_ref().map_or(0, Vec::len),
+                },
+                declared: config.dimensions.contains_key(name.as_str()),
+                uses: dim_uses.remove(&name).unwrap_or_default(),
+                choices,
+                name,
+            }
+        })
+        .collect();
+    let mut vars: BTreeMap<Name, Vec<GraphUse>> = config.variables.keys().map(|n| (Name::from(n.as_str()), vec![])).collect();
+    vars.extend(var_uses);
+    let variables = vars
+        .into_iter()
+        .map(|(name, uses)| GraphVar { declared: config.variables.contains_key(name.as_str()), name, uses })
+        .collect();
+    let mut files: Vec<_> = trees.iter().map(|(f, _)| f.path.clone()).collect();
+    files.sort();
+    Graph { files, dimensions, variables }
+}
+
+/// prints the graph in the `flan graph --format`. For graphviz, the files are boxes with an edge to each dimension
+/// (an ellipse with its choices) and variable (a diamond) they use
+pub fn pp_graph(graph: &Graph, format: cfg::GraphFormat) -> String {
+    // @SAFETY write does not fail on `String`
+    #![allow(unused_must_use)]
+    use std::fmt::Write;
+    // @SAFETY unwrap(): a `Graph` is always representable
+    if format == cfg::GraphFormat::Json {
+        return serde_json::to_string_pretty(graph).unwrap() + "\n";
+    }
+    let esc = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+    let mut buf = String::from("digraph flan {\n    rankdir=LR;\n");
+    for f in &graph.files {
+        let f = esc(&f.to_string_lossy());
+        writeln!(buf, "    \"file:{}\" [shape=box, label=\"{}\"];", f, f);
+    }
+    for d in &graph.dimensions {
+        let choices = match &d.choices {
+            Some(names) => names.join(" | "),
+            None => format!("size {}", d.size),
+        };
+        let style = if d.declared { "" } else { ", style=dashed" };
+        // the choices on a second line
+        writeln!(buf, "    \"dim:{}\" [shape=ellipse, label=\"{}\\n{}\"{}];", d.name, d.name, esc(&choices), style);
+    }
+    for v in &graph.variables {
+        let style = if v.declared { "" } else { ", style=dashed" };
+        writeln!(buf, "    \"var:{}\" [shape=diamond, label=\"{}\"{}];", v.name, v.name, style);
+    }
+    let uses = graph.dimensions.iter().map(|d| (format!("dim:{}", d.name), &d.uses));
+    for (node, uses) in uses.chain(graph.variables.iter().map(|v| (format!("var:{}", v.name), &v.uses))) {
+        for u in uses {
+            let file = esc(&u.file.to_string_lossy());
+            writeln!(buf, "    \"file:{}\" -> \"{}\" [label=\"{}\"];", file, node, u.spans.len());
+        }
+    }
+    buf.push_str("}\n");
+    buf
+}
+
+/// prints the dimensions in the `--query-format`, the human format is the same as [`pp_dim_provenance`]
+pub fn pp_query(query: &[DimQuery], format: cfg::QueryFormat) -> String {
+    #[derive(serde::Serialize)]
+    struct Query<'a> {
+        dimensions: &'a [DimQuery],
+    }
+    let query = Query { dimensions: query };
+    match format {
+        // @SAFETY unwrap(): a `DimQuery` is always representable
+        cfg::QueryFormat::Json => serde_json::to_string_pretty(&query).unwrap(),
+        cfg::QueryFormat::Toml => toml::to_string(&query).unwrap(),
+        cfg::QueryFormat::Human => query.dimensions.iter().map(pp_dim_query).collect(),
+    }
+}
+
+/// prints the variables in the `--query-format`, see [`pp_query`]
+pub fn pp_query_vars(query: &[VarQuery], format: cfg::QueryFormat) -> String {
+    #[derive(serde::Serialize)]
+    struct Query<'a> {
+        variables: &'a [VarQuery],
+    }
+    let query = Query { variables: query };
+    match format {
+        // @SAFETY unwrap(): a `VarQuery` is always representable
+        cfg::QueryFormat::Json => serde_json::to_string_pretty(&query).unwrap(),
+        cfg::QueryFormat::Toml => toml::to_string(&query).unwrap(),
+        cfg::QueryFormat::Human => query.variables.iter().map(pp_var_query).collect(),
+    }
+}
+
+/// e.g. `var name = "value"`, or `var name (undeclared) -> unset`
+fn pp_var_query(v: &VarQuery) -> String {
+    let mut buf = format!("var {}", v.name);
+    if !v.declared {
+        buf.push_str(" (undeclared)");
+    }
+    match &v.value {
+        Some(value) => buf.push_str(&format!(" = {:?}", value)),
+        None => buf.push_str(" -> unset"),
+    }
+    buf.push('\n');
+    for u in &v.uses {
+        let lines: Vec<_> = u.lines.iter().map(usize::to_string).collect();
+        buf.push_str(&format!("  {}:{}\n", u.file.display(), lines.join(",")));
+    }
+    buf
+}
+
+/// see [`pp_dim_provenance`]
+fn pp_dim_query(d: &DimQuery) -> String {
+    let mut buf = match &d.choices {
+        Some(names) => format!("dim {} = {:?}", d.name, names),
+        None => format!("dim {} = size {}", d.name, d.size),
+    };
+    if !d.declared {
+        buf.push_str(" (inferred)");
+    }
+    match (&d.decision_name, d.decision, &d.origin) {
+        (Some(n), _, Some(o)) => buf.push_str(&format!(" -> {} ({})", n, o)),
+        (None, Some(i), Some(o)) => buf.push_str(&format!(" -> {} ({})", i, o)),
+        _ => buf.push_str(" -> undecided"),
+    }
+    if let Some(doc) = &d.doc {
+        buf.push_str(&format!("  # {}", doc));
+    }
+    buf.push('\n');
+    for u in &d.uses {
+        let lines: Vec<_> = u.lines.iter().map(usize::to_string).collect();
+        buf.push_str(&format!("  {}:{}\n", u.file.display(), lines.join(",")));
+    }
+    buf
+}
+
+pub fn pp_dim(dim: &Name, ch: &Choices) -> String {
+    // @SAFETY write does not fail on `String`
+    #![allow(unused_must_use)]
+    use std::fmt::Write;
+    let mut buf = format!("dim {} = ", dim);
+    match ch {
+        Choices::Size(n) => write!(buf, "size {}", n),
+        Choices::Names(v) => write!(buf, "{:?}", v),
+        Choices::Table { choices, .. } => write!(buf, "{:?}", choices.names()),
+        Choices::Derived(d) => write!(buf, "{:?} when `{}`", [&d.choose, &d.otherwise], d.when.to_cond()),
+    };
+    buf
+}
+
+/// [`pp_dim`] annotated with where the choices and the decision come from.
+/// `declared` is whether the dimension is declared in the config file, otherwise its size is inferred.
+pub fn pp_dim_provenance(dim: &Name, ch: &Choices, declared: bool, decided: Option<&Dim>) -> String {
+    // @SAFETY write does not fail on `String`
+    #![allow(unused_must_use)]
+    use std::fmt::Write;
+    let mut buf = pp_dim(dim, ch);
+    if !declared {
+        write!(buf, " (inferred)");
+    }
+    let names = ch.names().unwrap_or_default();
+    match decided {
+        Some(d) if d.decision.as_usize() < names.len() =>
+            write!(buf, " -> {} ({})", names[d.decision.as_usize()], d.origin),
+        Some(d) => write!(buf, " -> {} ({})", d.decision, d.origin),
+        None => write!(buf, " -> undecided"),
+    };
+    buf
+}
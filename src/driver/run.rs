@@ -0,0 +1,194 @@
+//! [`run`]: a build from the library, without exiting.
+use std::io;
+
+use crate::cfg::matrix::path_to_matrix;
+use crate::env::FileEnvs;
+use crate::error::{ExitCode, Handler, MemorySink, WarnKind};
+use crate::output::{Output, Throttle};
+use crate::sourcemap::{SrcFile, SrcMap};
+use crate::cfg;
+
+use super::decide::{check_syntax, check_trees, decided_env, labeled_env};
+use super::fmt::specialize_trees;
+use super::headers::inject_headers;
+use super::hooks::{run_hooks, HookStage};
+use super::lock::{env_to_lock, write_lock};
+use super::matrix::{all_variants, render_matrix, VariantError};
+use super::overrides::{override_envs, write_targets};
+use super::parse::parse_sources_timed;
+use super::sources::{check_destinations, check_overlapping_paths, load_sources_with};
+use super::write::{check_output_flags, copy_bins, write_files, write_origin_map};
+
+/// summary of a successful [`run`]
+#[derive(Debug, Default)]
+pub struct Report {
+    /// number of source files loaded
+    pub files: usize,
+    /// number of files written, 0 on a dry run
+    pub written: usize,
+    /// number of binary files copied, 0 on a dry run
+    pub copied: usize,
+    /// number of bytes written to the destinations of the processed files
+    pub bytes: usize,
+    /// the warnings and notes emitted
+    pub diagnostics: Vec<crate::error::Error>,
+}
+
+/// why a [`run`] failed
+#[derive(Debug)]
+pub enum RunError {
+    /// only [`cfg::Command::Default`], [`cfg::Command::DryRun`], [`cfg::Command::Check`] and [`cfg::Command::Matrix`]
+    /// can be run
+    Unsupported(cfg::Command),
+    /// the matrix file can't be loaded, or the decisions of one of its variants are invalid
+    Config(cfg::Error),
+    /// the sources or decisions are invalid or a hook failed, the diagnostics explain why
+    Diagnostics(Vec<crate::error::Error>),
+    /// writing or copying failed
+    IO(io::Error),
+    /// copying failed after some files were written
+    Partial(io::Error),
+}
+impl RunError {
+    /// the exit status of `flan` for this error
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            RunError::Unsupported(_) | RunError::Config(_) => ExitCode::Config,
+            RunError::Diagnostics(errs) => errs
+                .iter()
+                .find(|e| e.level().as_u8() < crate::error::Level::Warning.as_u8())
+                .and_then(|e| e.code())
+                .map_or(ExitCode::Failure, |c| c.exit_code()),
+            RunError::IO(_) => ExitCode::IO,
+            RunError::Partial(_) => ExitCode::Partial,
+        }
+    }
+}
+impl std::fmt::Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunError::Unsupported(cmd) => write!(f, "command `{:?}` can't be run as a library.", cmd),
+            RunError::Config(e) => write!(f, "{}", e),
+            RunError::Diagnostics(errs) => {
+                let n = errs.iter().filter(|e| e.level().as_u8() < crate::error::Level::Warning.as_u8()).count();
+                write!(f, "aborted due to {} error{}.", n, if n == 1 { "" } else { "s" })
+            }
+            RunError::IO(e) => write!(f, "{}", e),
+            RunError::Partial(e) => write!(f, "{}, after writing some destinations", e),
+        }
+    }
+}
+impl std::error::Error for RunError {}
+
+/// loads, parses, checks and writes the sources like the `flan` binary, but collects the diagnostics
+/// instead of printing them and returns instead of exiting. The lockfile is written with `--lock`, and
+/// `--interactive` asks the decisions on the terminal, like the binary.
+pub fn run(flags: &cfg::Flags, config: &cfg::Config) -> Result<Report, RunError> {
+    match flags.command {
+        cfg::Command::Default | cfg::Command::DryRun | cfg::Command::Check | cfg::Command::Matrix => {}
+        cmd => return Err(RunError::Unsupported(cmd)),
+    }
+    let matrix = flags.matrix.as_ref().map(path_to_matrix).transpose().map_err(RunError::Config)?;
+    let sink = MemorySink::new();
+    let failed = |_: Handler| RunError::Diagnostics(sink.errors());
+    // like the binary, the files that can't be loaded are reported but don't abort
+    let mut hl = Handler::with_sink(flags.eflags, SrcMap::new(), Box::new(sink.clone()));
+    check_output_flags(flags, &mut hl);
+    if hl.err_count > 0 {
+        return Err(failed(hl));
+    }
+    let sources = load_sources_with(flags, config.paths.iter(), &config.filters, &config.file_kinds, &mut hl);
+    let source_map = hl.sources;
+    let mut config = config.clone();
+    config.load_into(&source_map);
+    let mk_handler = || Handler::with_sink(flags.eflags, source_map.clone(), Box::new(sink.clone()));
+    let mut report = Report { files: sources.len(), ..Report::default() };
+    let mut h = mk_handler();
+    if sources.is_empty() {
+        h.warn(WarnKind::NoPaths, "no paths given")
+            .note("see `[paths]` section in the configuration file")
+            .print();
+        report.diagnostics = sink.errors();
+        return Ok(report);
+    }
+    check_overlapping_paths(flags, config.paths.iter(), &mut h);
+    check_destinations(&sources, &mut h);
+    check_syntax(&config, &mut h);
+    let (trees, bins) = parse_sources_timed(sources, &mut h, &config.syntax, |_, _| {});
+    if h.err_count > 0 {
+        h.print_all();
+        return Err(failed(h));
+    }
+
+    if flags.command == cfg::Command::Matrix {
+        if !run_hooks(&config, HookStage::Pre, &mut h) {
+            return Err(failed(h));
+        }
+        // the command is only set by a matrix file or `--all-variants`
+        let matrix = match matrix {
+            Some(m) => m,
+            None => all_variants(&trees, &labeled_env(&config, &trees, mk_handler()).map_err(failed)?, &config),
+        };
+        let throttle = Throttle::new(flags.max_open_files);
+        let out = Output::new(flags).map_err(RunError::IO)?;
+        let (written, copied) = render_matrix(flags, &config, &matrix, (&trees, &bins), mk_handler, &out, &throttle)
+            .map_err(|e| match e.error {
+                VariantError::Decisions(err) => RunError::Config(err),
+                VariantError::Diagnostics(h) => failed(h),
+                VariantError::IO(err) => io_error(e.written > 0, err),
+            })?;
+        out.finish().map_err(|e| io_error(written + copied > 0, e))?;
+        if !run_hooks(&config, HookStage::Post, &mut h) {
+            return Err(failed(h));
+        }
+        report.written = written;
+        report.copied = copied;
+        report.diagnostics = sink.errors();
+        return Ok(report);
+    }
+
+    let mut env = decided_env(flags, &mut config, &trees, mk_handler).map_err(failed)?;
+    let (file_envs, targets) = override_envs(flags, &config, &trees, &mut env, mk_handler).map_err(failed)?;
+    if check_trees(flags, &config, &trees, &file_envs, &mut env, |_, _| {}) {
+        return Err(failed(env.handler));
+    }
+    let trees = if flags.eflags.partial { specialize_trees(trees, &env, &config.syntax) } else { trees };
+    let trees = if flags.headers && !flags.eflags.partial { inject_headers(trees, &env, &config) } else { trees };
+
+    if flags.command == cfg::Command::Default {
+        if !run_hooks(&config, HookStage::Pre, &mut h) {
+            return Err(failed(h));
+        }
+        let lock = flags.lock_file.as_ref().map(|_| env_to_lock(&env, &config));
+        let env = FileEnvs { base: env, files: file_envs };
+        let throttle = Throttle::new(flags.max_open_files);
+        let out = Output::new(flags).map_err(RunError::IO)?;
+        let bytes = &mut report.bytes;
+        let mut record = |_: &SrcFile, _, n| *bytes += n;
+        report.written = write_files(flags, &trees, &env, &out, &throttle, &mut record).map_err(RunError::IO)?;
+        report.written += write_targets(flags, &trees, &targets, &out, &throttle, &mut record).map_err(|e| io_error(report.written > 0, e))?;
+        let written = report.written > 0;
+        if let Some(map) = &flags.origin_map {
+            write_origin_map(map, &trees, &env).map_err(|e| io_error(written, e))?;
+        }
+        report.copied = copy_bins(flags, bins, &out, &throttle).map_err(|e| io_error(written, e))?;
+        out.finish().map_err(|e| io_error(written, e))?;
+        if let (Some(path), Some(lock)) = (flags.lock_file.as_ref(), lock) {
+            write_lock(path, &lock).map_err(RunError::Partial)?;
+        }
+        if !run_hooks(&config, HookStage::Post, &mut h) {
+            return Err(failed(h));
+        }
+    }
+    report.diagnostics = sink.errors();
+    Ok(report)
+}
+
+/// some destinations were written before `e`, or none
+fn io_error(written: bool, e: io::Error) -> RunError {
+    if written {
+        RunError::Partial(e)
+    } else {
+        RunError::IO(e)
+    }
+}
@@ -0,0 +1,128 @@
+//! [`Session`]: the config, env and sources kept between renders, for the editors and the tests.
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::cfg::matrix::Matrix;
+use crate::env::Env;
+use crate::error::{Handler, MemorySink};
+use crate::output::{write_terms, ReadCtx, WriteCtx};
+use crate::sourcemap::{Destination, SrcFile, SrcMap};
+use crate::syntax::*;
+use crate::{cfg, infer};
+
+use super::decide::{check_syntax, check_unused, make_env};
+use super::matrix::all_variants;
+use super::parse::file_to_parser_with;
+use super::run::RunError;
+
+/// the flags, config, source map and handler of a run bundled together, for embedding and testing.
+/// The diagnostics are collected instead of printed, see [`Session::diagnostics`].
+pub struct Session {
+    pub flags: cfg::Flags,
+    pub config: cfg::Config,
+    pub sources: Arc<SrcMap>,
+    /// reports on [`Self::sources`], [`Self::check`] moves it into the env and starts a new one
+    pub handler: Handler,
+    sink: MemorySink,
+}
+impl Session {
+    /// an empty source map, the sources of `config` aren't loaded
+    pub fn from_config(flags: cfg::Flags, config: cfg::Config) -> Self {
+        let sources = SrcMap::new();
+        let sink = MemorySink::new();
+        let handler = Handler::with_sink(flags.eflags, sources.clone(), Box::new(sink.clone()));
+        Session { flags, config, sources, handler, sink }
+    }
+    /// the default flags and an empty config, i.e. `flan` without arguments nor config file
+    pub fn in_memory() -> Self {
+        let flags = cfg::Flags::default();
+        let config = cfg::Config::new(HashSet::new(), HashMap::new(), cfg::File::default());
+        Self::from_config(flags, config)
+    }
+    fn new_handler(&self) -> Handler {
+        Handler::with_sink(self.flags.eflags, self.sources.clone(), Box::new(self.sink.clone()))
+    }
+    /// the diagnostics emitted so far, in the order they were printed
+    pub fn diagnostics(&self) -> Vec<crate::error::Error> {
+        self.sink.errors()
+    }
+    /// [`Self::diagnostics`] rendered in the `--message-format`
+    pub fn rendered_diagnostics(&self) -> Vec<String> {
+        let format = self.flags.eflags.message_format;
+        let render = |e: &crate::error::Error| e.render_as(format, self.sources.lookup_span(e.span()).map(|(f, _)| f));
+        self.diagnostics().iter().map(render).collect()
+    }
+    /// adds `src` to the source map as `<string>` and parses it, the errors go to [`Self::diagnostics`].
+    pub fn parse_str(&mut self, src: &str) -> Option<(SrcFile, Terms)> {
+        self.parse_source(PathBuf::from("<string>"), src)
+    }
+    /// [`Self::parse_str`] of the contents of `path`, with its `[syntax]` delimiters
+    pub fn parse_source(&mut self, path: PathBuf, src: &str) -> Option<(SrcFile, Terms)> {
+        let file = self.sources.load_source(path, Destination::InMemory, src.into());
+        // the parser fails on any error of its handler, the earlier sources mustn't count
+        let mut h = self.new_handler();
+        check_syntax(&self.config, &mut h);
+        if h.err_count > 0 {
+            self.handler.err_count += h.err_count;
+            return None;
+        }
+        let tree = match file_to_parser_with(&mut h, &file, self.config.syntax.delimiters_of(&file.path)) {
+            Some(mut p) => p.parse().ok(),
+            None => None,
+        };
+        h.print_all();
+        self.handler.err_count += h.err_count;
+        tree.map(|tree| (file, tree))
+    }
+    /// makes the env of the config and checks `trees` against it, like [`run`](super::run()).
+    pub fn check(&mut self, trees: &[&Terms]) -> Result<Env, RunError> {
+        let handler = self.new_handler();
+        let handler = std::mem::replace(&mut self.handler, handler);
+        let mut env = make_env(&self.config, handler).map_err(|_| RunError::Diagnostics(self.diagnostics()))?;
+        let mut err = infer::unify_labels(trees.iter().copied(), &mut env);
+        if !err {
+            for tree in trees {
+                err = infer::check(tree, &mut env).0 || err;
+            }
+        }
+        if !err {
+            check_unused(&self.config, &mut env);
+        }
+        env.handler.print_all();
+        if err || env.handler.err_count > 0 {
+            return Err(RunError::Diagnostics(self.diagnostics()));
+        }
+        Ok(env)
+    }
+    /// makes the env of the config and the matrix of all the variants of `trees`, see [`all_variants`].
+    pub fn all_variants(&mut self, trees: &[(SrcFile, Terms)]) -> Result<Matrix, RunError> {
+        let handler = self.new_handler();
+        let handler = std::mem::replace(&mut self.handler, handler);
+        let mut env = make_env(&self.config, handler).map_err(|_| RunError::Diagnostics(self.diagnostics()))?;
+        if infer::unify_labels(trees.iter().map(|t| &t.1), &mut env) {
+            env.handler.print_all();
+            return Err(RunError::Diagnostics(self.diagnostics()));
+        }
+        Ok(all_variants(trees, &env, &self.config))
+    }
+    /// parses, checks and writes `src` in memory.
+    pub fn render_to_string(&mut self, src: &str) -> Result<String, RunError> {
+        self.render_source(PathBuf::from("<string>"), src)
+    }
+    /// [`Self::render_to_string`] of the contents of `path`, see [`Self::parse_source`]
+    pub fn render_source(&mut self, path: PathBuf, src: &str) -> Result<String, RunError> {
+        let (_, tree) = self.parse_source(path, src).ok_or_else(|| RunError::Diagnostics(self.diagnostics()))?;
+        let env = self.check(&[&tree])?;
+        let tree = infer::resolve(&tree, &env);
+        let mut from = io::Cursor::new(src.as_bytes());
+        let mut out = Vec::new();
+        let mut to = WriteCtx::new(&mut out).newline(self.flags.newline).vectored(true);
+        write_terms(&mut ReadCtx::new(&mut from, 0usize), &mut to, &tree)
+            .and_then(|_| to.flush())
+            .map_err(RunError::IO)?;
+        // @SAFETY unwrap(): only the text of `src` and utf-8 values are written
+        Ok(String::from_utf8(out).unwrap())
+    }
+}
@@ -0,0 +1,80 @@
+//! `flan test --snapshots`: the variants of a source rendered in memory.
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::cfg;
+use crate::cfg::matrix::{Matrix, Variant};
+
+use super::matrix::variant_config;
+use super::run::RunError;
+use super::session::Session;
+
+/// the variants of the template `path` whose contents are `src`, over the dimensions it uses, see [`all_variants`](super::all_variants).
+/// The errors are the rendered diagnostics
+pub fn file_variants(flags: &cfg::Flags, config: &cfg::Config, path: &Path, src: &str) -> Result<Matrix, Vec<String>> {
+    let mut s = Session::from_config(flags.clone(), config.clone());
+    let tree = s.parse_source(path.to_path_buf(), src).ok_or_else(|| s.rendered_diagnostics())?;
+    s.all_variants(&[tree]).map_err(|_| s.rendered_diagnostics())
+}
+
+/// the name of the snapshot of `variant`: its decisions joined with `,`, e.g. `os=linux,debug=0`, or `default`
+pub fn snapshot_name(variant: &Variant) -> String {
+    if variant.decisions.is_empty() {
+        String::from("default")
+    } else {
+        variant.decisions.join(",")
+    }
+}
+
+/// renders `src` in memory with the decisions of `variant`, see [`file_variants`]
+pub fn snapshot(flags: &cfg::Flags, config: &cfg::Config, path: &Path, src: &str, variant: &Variant) -> Result<String, Vec<String>> {
+    let config = variant_config(config, variant).map_err(|e| vec![e.to_string()])?;
+    let mut s = Session::from_config(flags.clone(), config);
+    match s.render_source(path.to_path_buf(), src) {
+        Ok(out) => Ok(out),
+        Err(RunError::IO(e)) | Err(RunError::Partial(e)) => Err(vec![e.to_string()]),
+        Err(_) => Err(s.rendered_diagnostics()),
+    }
+}
+
+/// renders `src` with `decisions` and `vars` only, without reading a config file nor touching the filesystem.
+/// see [`Session::render_to_string`]
+pub fn render(src: &str, decisions: &[cfg::Decision], vars: &HashMap<String, String>) -> Result<String, Vec<crate::error::Error>> {
+    let mut s = Session::in_memory();
+    s.config.variables = vars.clone();
+    for d in decisions {
+        match d {
+            cfg::Decision::Name(n) => {
+                s.config.decisions_name.insert(n.clone());
+            }
+            cfg::Decision::WithDim(dn, i) => {
+                s.config.decisions_pair.insert(dn.clone(), i.clone());
+            }
+        }
+    }
+    s.render_to_string(src).map_err(|e| match e {
+        RunError::Diagnostics(errs) => errs,
+        // nothing is written to or copied on the filesystem
+        _ => s.diagnostics(),
+    })
+}
+
+/// the decisions of a variant and its output, see [`render_variants`]
+pub type RenderedVariant = (Vec<cfg::Decision>, Result<String, Vec<crate::error::Error>>);
+
+/// renders `src` with `vars` for each variant of [`all_variants`](super::all_variants), lazily. Fails if `src` can't be parsed.
+pub fn render_variants<'a>(
+    src: &'a str,
+    vars: &'a HashMap<String, String>,
+) -> Result<impl Iterator<Item = RenderedVariant> + 'a, Vec<crate::error::Error>> {
+    let mut s = Session::in_memory();
+    s.config.variables = vars.clone();
+    let tree = s.parse_str(src).ok_or_else(|| s.diagnostics())?;
+    let matrix = s.all_variants(&[tree]).map_err(|_| s.diagnostics())?;
+    Ok(matrix.variants.into_values().map(move |v| {
+        // @SAFETY unwrap(): all the decisions are `dim=choice`
+        let decisions: Vec<_> = v.decisions.iter().map(|d| d.parse::<cfg::Decision>().unwrap()).collect();
+        let out = render(src, &decisions, vars);
+        (decisions, out)
+    }))
+}
@@ -0,0 +1,312 @@
+//! loading the sources of `[paths]` in the source map.
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::cfg;
+use crate::error::{codes, Handler, WarnKind};
+use crate::sourcemap::{Destination, SrcFile, SrcMap};
+use crate::utils::ignore::{is_ignored, IgnoreFile};
+
+use super::fetch;
+
+/// `path` under `prefix`, without the `.` of `path`, e.g. `l2/./a.txt` is `l2/a.txt`
+pub(super) fn mk_path(prefix: Option<&PathBuf>, path: PathBuf) -> PathBuf {
+    match prefix {
+        Some(prefix) => prefix.join(path).components().collect(),
+        None => path,
+    }
+}
+
+/// load all the sources in the source map and returns them in a `Vec`.
+/// `paths` are ignored in [`cfg::Flags::single_file`] mode, `kinds` tells how each file is loaded.
+/// errors are printed to stderr, see [`load_sources_with`] to report them elsewhere.
+pub fn load_sources<'a, It: Iterator<Item = (&'a PathBuf, &'a PathBuf)>>(
+    flags: &cfg::Flags,
+    paths: It,
+    filters: &cfg::PathFilters,
+    kinds: &cfg::FileKinds,
+) -> (Arc<SrcMap>, Vec<SrcFile>) {
+    let mut h = Handler::new(flags.eflags, SrcMap::new());
+    let sources = load_sources_with(flags, paths, filters, kinds, &mut h);
+    (h.sources, sources)
+}
+
+/// [`load_sources`] in the source map of `h`, reporting the files that can't be loaded to `h`.
+pub fn load_sources_with<'a, It: Iterator<Item = (&'a PathBuf, &'a PathBuf)>>(
+    flags: &cfg::Flags,
+    paths: It,
+    filters: &cfg::PathFilters,
+    kinds: &cfg::FileKinds,
+    h: &mut Handler,
+) -> Vec<SrcFile> {
+    let source_map = h.sources.clone();
+    let mut sources = vec![];
+    // the layer of each source, see [`merge_layers`]
+    let mut layer_of = Vec::new();
+    let inp = flags.in_prefix.as_ref();
+    let outp = flags.out_prefix.as_ref();
+
+    if flags.stdin.is_some() {
+        // @IMPROVEMENT error handling
+        let dest = match flags.stdin.clone().unwrap() {
+            Destination::Path(out) => Destination::Path(mk_path(outp, out)),
+            dest => dest,
+        };
+        match source_map.load_stdin(dest) {
+            Err(e) => h.error(format!("couldn't load `{}`:\n  {}", "<stdin>", e).as_ref()).code(codes::LOAD_FAILED).print(),
+            Ok(f) => sources.push(f.clone()),
+        };
+    } else if let Some(src) = flags.single_file() {
+        let dst = match outp {
+            _ if flags.stdout => Destination::Stdout,
+            Some(o) if o.is_dir() => o.join(src.file_name().unwrap_or_default()).into(),
+            Some(o) => o.clone().into(),
+            None => Destination::Stdout,
+        };
+        match source_map.load_file_as(src.clone(), dst, kinds.kind(src)) {
+            Err(e) => h.error(format!("couldn't load `{}`:\n  {}", src.display(), e).as_ref()).code(codes::LOAD_FAILED).print(),
+            Ok(f) => sources.push(f.clone()),
+        }
+    } else {
+        let mut walk = Walk {
+            filters,
+            kinds,
+            symlinks: flags.symlinks,
+            source_map: &source_map,
+            sources: &mut sources,
+            h,
+            ancestors: Vec::new(),
+            ignores: Vec::new(),
+            offline: flags.offline,
+            cache_dir: &flags.cache_dir,
+        };
+        if flags.overlays.is_empty() {
+            load_files(paths, inp, outp, &mut walk);
+        } else {
+            let paths: Vec<_> = paths.collect();
+            let layers = flags.layers();
+            for (i, layer) in layers.iter().enumerate() {
+                let exists = |l: &Option<&PathBuf>, src: &PathBuf| !fetch::is_url(src) && mk_path(*l, src.clone()).exists();
+                // a source missing from every layer is reported by the base one
+                let here = paths.iter().copied().filter(|(src, _)| exists(layer, src) || (i == 0 && !layers.iter().any(|l| exists(l, src))));
+                load_files(here, *layer, outp, &mut walk);
+                layer_of.resize(walk.sources.len(), i);
+            }
+        }
+    }
+    if !flags.overlays.is_empty() {
+        sources = merge_layers(flags, sources, layer_of, h);
+    }
+    sources
+}
+
+/// keeps the file of the top layer of each destination, in the order the destinations are first loaded, see
+/// [`cfg::Flags::overlays`]. With `--verbose`, tells the layer of each file and the layers it replaces.
+fn merge_layers(flags: &cfg::Flags, sources: Vec<SrcFile>, layer_of: Vec<usize>, h: &mut Handler) -> Vec<SrcFile> {
+    let layers = flags.layers();
+    let name = |i: usize| format!("`{}`", layers[i].map_or_else(|| String::from("."), |p| p.display().to_string()));
+    let mut index: HashMap<Destination, usize> = HashMap::new();
+    // (file, its layer, the layers it replaces)
+    let mut merged: Vec<(SrcFile, usize, Vec<usize>)> = Vec::new();
+    for (file, layer) in sources.into_iter().zip(layer_of) {
+        match index.get(&file.destination) {
+            Some(&i) => {
+                let (old, old_layer, replaced) = &mut merged[i];
+                replaced.push(*old_layer);
+                *old = file;
+                *old_layer = layer;
+            }
+            None => {
+                index.insert(file.destination.clone(), merged.len());
+                merged.push((file, layer, Vec::new()));
+            }
+        }
+    }
+    for (file, layer, replaced) in merged.iter() {
+        let eb = h.more(format!("`{}` is from layer {}.", file.path.display(), name(*layer)).as_ref());
+        match replaced.as_slice() {
+            [] => eb.print(),
+            ls => eb.note(format!("it replaces the file of {}.", ls.iter().map(|&l| name(l)).collect::<Vec<_>>().join(", ")).as_ref()).print(),
+        }
+    }
+    merged.into_iter().map(|(file, ..)| file).collect()
+}
+
+fn load_files<'a, It: Iterator<Item = (&'a PathBuf, &'a PathBuf)>>(
+    paths: It, 
+    inp: Option<&PathBuf>, 
+    outp: Option<&PathBuf>, 
+    walk: &mut Walk,
+) {
+    for (src_, dst_) in paths {
+        let dst = mk_path(outp, dst_.clone());
+        if fetch::is_url(src_) {
+            let url = src_.to_string_lossy();
+            match fetch::fetch(&url, walk.cache_dir, walk.offline) {
+                Err(e) => walk.h.error(format!("couldn't fetch `{}`:\n  {}", url, e).as_ref()).code(codes::FETCH_FAILED).print(),
+                Ok((src, fetched)) => {
+                    if let fetch::Fetched::Stale(e) = fetched {
+                        walk.h.warn(WarnKind::StaleCache, format!("couldn't fetch `{}`, using its cached copy.", url).as_ref())
+                            .note(&e)
+                            .print();
+                    }
+                    walk.sources.push(walk.source_map.load_remote(src_.clone(), dst.into(), src))
+                }
+            }
+            continue;
+        }
+        let src = mk_path(inp, src_.clone());
+        if src.is_dir() {
+            walk_dir(&src, &dst, Path::new(""), walk);
+        } else {
+            match walk.source_map.load_file_as(src, dst.into(), walk.kinds.kind(src_)) {
+                // @IMPROVEMENT error handling
+                Err(e) => walk.h.error(format!("couldn't load `{}`:\n  {}", src_.to_string_lossy(), e).as_ref()).code(codes::LOAD_FAILED).print(),
+                Ok(f) => walk.sources.push(f.clone()),
+            }
+        }
+    }
+}
+
+/// normalizes `path`, see [`crate::utils::path::normalize_path`].
+/// missing paths can't be normalized, but we can at least ignore `.`
+pub(super) fn normalize_lossy(path: &Path) -> PathBuf {
+    crate::utils::path::normalize_path(path)
+        .unwrap_or_else(|_| path.components().filter(|c| *c != std::path::Component::CurDir).collect())
+}
+
+/// warns about `[paths]` sources nested in one another (after normalization), e.g. `conf` and `conf/app`,
+/// since the files they have in common would be loaded twice, with different destinations.
+pub fn check_overlapping_paths<'a, It: Iterator<Item = (&'a PathBuf, &'a PathBuf)>>(
+    flags: &cfg::Flags,
+    paths: It,
+    h: &mut Handler,
+) {
+    if flags.single_file().is_some() {
+        return;
+    }
+    let inp = flags.in_prefix.as_ref();
+    let mut srcs: Vec<_> = paths
+        .map(|(src, dst)| (normalize_lossy(&mk_path(inp, src.clone())), src, dst))
+        .collect();
+    // ancestors are sorted right before their descendants
+    srcs.sort();
+    let mut ancestors: Vec<&(PathBuf, &PathBuf, &PathBuf)> = Vec::new();
+    for entry in srcs.iter() {
+        while ancestors.last().is_some_and(|a| !entry.0.starts_with(&a.0)) {
+            ancestors.pop();
+        }
+        if let Some((_, asrc, adst)) = ancestors.last() {
+            let (path, src, dst) = entry;
+            let twice = if path.is_dir() {
+                format!("files in `{}` are loaded twice.", src.display())
+            } else {
+                format!("`{}` is loaded twice.", src.display())
+            };
+            h.warn(WarnKind::OverlappingPaths, format!("Paths `{}` and `{}` overlap.", asrc.display(), src.display()).as_ref())
+                .note(format!("`{}` is mapped to `{}`.", asrc.display(), adst.display()).as_ref())
+                .note(format!("`{}` is mapped to `{}`.", src.display(), dst.display()).as_ref())
+                .note(&twice)
+                .print();
+        }
+        ancestors.push(entry);
+    }
+}
+
+/// reports the sources of [`load_sources`] written to the same destination (after normalization),
+/// e.g. `a -> out/f` and `b -> out/./f`, since one would overwrite the other.
+pub fn check_destinations(sources: &[SrcFile], h: &mut Handler) {
+    let mut dests: Vec<_> = sources.iter().filter_map(|f| Some((normalize_lossy(f.destination.path()?), &f.path))).collect();
+    dests.sort();
+    for pair in dests.windows(2) {
+        let ((dst, first), (dst2, src)) = (&pair[0], &pair[1]);
+        if dst == dst2 {
+            h.error(format!("`{}` and `{}` are written to the same destination.", first.display(), src.display()).as_ref())
+                .code(codes::DUPLICATE_DESTINATION)
+                .note(format!("both are written to `{}`.", dst.display()).as_ref())
+                .print();
+        }
+    }
+}
+
+/// recursively loads the files of `src/rel` to `dst/rel`, skipping the entries rejected by `filters`.
+/// what [`walk_dir`] needs besides the paths
+struct Walk<'a> {
+    filters: &'a cfg::PathFilters,
+    kinds: &'a cfg::FileKinds,
+    symlinks: cfg::Symlinks,
+    source_map: &'a Arc<SrcMap>,
+    sources: &'a mut Vec<SrcFile>,
+    /// where the files that can't be loaded are reported
+    h: &'a mut Handler,
+    /// canonical paths of the directories being walked, a symlink to one of them is a cycle
+    ancestors: Vec<PathBuf>,
+    /// ignore files of the directories being walked, if [`cfg::PathFilters::ignore_files`]
+    ignores: Vec<IgnoreFile>,
+    /// see [`cfg::Flags::offline`]
+    offline: bool,
+    cache_dir: &'a Path,
+}
+
+fn walk_dir(src: &Path, dst: &Path, rel: &Path, walk: &mut Walk) {
+    let dir = src.join(rel);
+    let canonical = dir.canonicalize().unwrap_or_else(|_| dir.clone());
+    if let Some(ancestor) = walk.ancestors.iter().find(|a| **a == canonical) {
+        let msg = format!("skipping `{}`: it links to `{}`, which is already walked.", dir.display(), ancestor.display());
+        return walk.h.error(msg.as_ref()).code(codes::SYMLINK_CYCLE).print();
+    }
+    walk.ancestors.push(canonical);
+    walk_entries(src, dst, rel, walk);
+    walk.ancestors.pop();
+}
+
+fn walk_entries(src: &Path, dst: &Path, rel: &Path, walk: &mut Walk) {
+    let dir = src.join(rel);
+    let mut entries = match dir.read_dir().and_then(|rd| rd.map(|e| Ok(e?.file_name())).collect::<io::Result<Vec<_>>>()) {
+        Ok(entries) => entries,
+        Err(e) => return walk.h.error(format!("couldn't load directory `{}`:\n  {}", dir.display(), e).as_ref()).code(codes::LOAD_FAILED).print(),
+    };
+    entries.sort();
+    let ignores = walk.ignores.len();
+    if walk.filters.ignore_files {
+        match IgnoreFile::read(src, rel) {
+            Ok(file) if !file.is_empty() => walk.ignores.push(file),
+            Ok(_) => (),
+            Err(e) => walk.h.error(format!("couldn't load the ignore files of `{}`:\n  {}", dir.display(), e).as_ref()).code(codes::LOAD_FAILED).print(),
+        }
+    }
+    for name in entries {
+        // flan's own ignore file isn't a source
+        if walk.filters.ignore_files && name == ".flanignore" {
+            continue;
+        }
+        let rel = rel.join(name);
+        let path = src.join(&rel);
+        let is_link = path.symlink_metadata().is_ok_and(|m| m.file_type().is_symlink());
+        let follow = !is_link || walk.symlinks == cfg::Symlinks::Follow;
+        let is_dir = follow && path.is_dir();
+        if !walk.filters.included(&rel, is_dir)
+            || (!follow && walk.symlinks == cfg::Symlinks::Skip)
+            || is_ignored(&walk.ignores, &rel, is_dir)
+        {
+            continue;
+        }
+        if is_dir {
+            walk_dir(src, dst, &rel, walk);
+            continue;
+        }
+        let loaded = if follow {
+            walk.source_map.load_file_as(path, dst.join(&rel).into(), walk.kinds.kind(&rel))
+        } else {
+            walk.source_map.load_symlink(path, dst.join(&rel).into())
+        };
+        match loaded {
+            // @IMPROVEMENT error handling
+            Err(e) => walk.h.error(format!("couldn't load `{}`:\n  {}", src.join(&rel).display(), e).as_ref()).code(codes::LOAD_FAILED).print(),
+            Ok(f) => walk.sources.push(f.clone()),
+        }
+    }
+    walk.ignores.truncate(ignores);
+}
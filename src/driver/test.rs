@@ -0,0 +1,60 @@
+//! `flan test`: the `[tests.NAME]` templates rendered and compared.
+use std::fs;
+use std::path::Path;
+
+use crate::cfg;
+
+use super::run::RunError;
+use super::session::Session;
+
+/// the outcome of a `[tests.NAME]` of the config file, see [`run_test`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum TestOutcome {
+    Passed,
+    /// the diff of the output against the expected output, see [`crate::utils::pp_diff`]
+    Failed(String),
+    /// the template doesn't render, or a file couldn't be read. The diagnostics are rendered in the `--message-format`
+    Error(Vec<String>),
+}
+
+/// renders the template of the test `name` in memory with the declarations of `config` and the decisions and
+/// variables of the test, and compares it with the expected output.
+pub fn run_test(flags: &cfg::Flags, config: &cfg::Config, name: &str, test: &cfg::TestDecl) -> TestOutcome {
+    let read = |path: &Path| {
+        fs::read_to_string(path).map_err(|e| TestOutcome::Error(vec![format!("couldn't load `{}`:\n  {}", path.display(), e)]))
+    };
+    let expected = match (&test.expected, &test.expected_file) {
+        (Some(e), None) => e.clone(),
+        (None, Some(path)) => match read(path) {
+            Ok(e) => e,
+            Err(e) => return e,
+        },
+        _ => return TestOutcome::Error(vec![format!("`tests.{}` needs either `expected` or `expected_file`.", name)]),
+    };
+    let src = match read(&test.template) {
+        Ok(src) => src,
+        Err(e) => return e,
+    };
+    let mut config = config.clone();
+    match cfg::parse_decisions(&test.decisions) {
+        Ok((names, pairs)) => {
+            config.decisions_name = names;
+            config.decisions_pair = pairs;
+            config.locked.clear();
+            config.decision_origins.clear();
+        }
+        Err(e) => return TestOutcome::Error(vec![format!("invalid decisions of `tests.{}`:\n  {}", name, e)]),
+    }
+    for (var, value) in test.variables.iter() {
+        config.env_variables.remove(var);
+        config.cmd_variables.remove(var);
+        config.variables.insert(var.clone(), value.clone());
+    }
+    let mut s = Session::from_config(flags.clone(), config);
+    match s.render_source(test.template.clone(), &src) {
+        Ok(out) if out == expected => TestOutcome::Passed,
+        Ok(out) => TestOutcome::Failed(crate::utils::pp_diff(&expected, &out)),
+        Err(RunError::IO(e)) | Err(RunError::Partial(e)) => TestOutcome::Error(vec![e.to_string()]),
+        Err(_) => TestOutcome::Error(s.rendered_diagnostics()),
+    }
+}
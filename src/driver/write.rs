@@ -0,0 +1,249 @@
+//! writing the destinations, and `flan clean` removing them.
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::env::{Env, EnvOf};
+use crate::error::{codes, Handler};
+use crate::output::{archive, create_parent, origins, same_contents, write_terms, Output, ReadCtx, Throttle, WriteCtx};
+use crate::sourcemap::{Destination, SrcFile};
+use crate::syntax::*;
+use crate::{cfg, infer};
+
+/// reports the output flags which can't be used: `--stdout` without a single input file, and an `--out-archive`
+/// which isn't a `.tar` or `.zip` archive.
+pub fn check_output_flags(flags: &cfg::Flags, h: &mut Handler) {
+    if flags.stdout && flags.stdin.is_none() && flags.single_file().is_none() {
+        h.error("`--stdout` needs a single input file, given with `-i FILE` or `--stdin`.")
+            .code(codes::INVALID_ARGUMENTS)
+            .print();
+    }
+    if let Some(path) = flags.out_archive.as_ref().filter(|p| archive::Format::from_path(p).is_none()) {
+        h.error(format!("`--out-archive {}` isn't a `.tar` or `.zip` archive.", path.display()).as_ref())
+            .code(codes::INVALID_ARGUMENTS)
+            .print();
+    }
+}
+
+/// processes and writes to the destination file, returns the number of bytes written.  
+/// With [`cfg::Flags::if_changed`], nothing is written (and 0 returned) if the destination already has this content.  
+/// `terms` are [`infer::resolve`]d once, then written as a straight copy of their texts and values.  
+pub fn write(flags: &cfg::Flags, file: SrcFile, terms: &Terms, env: &Env) -> io::Result<usize> {
+    let dest = file.destination.clone();
+    write_to(flags, file, &dest, terms, env)
+}
+
+/// reader of the text of `file`
+fn open_source<'a>(flags: &cfg::Flags, file: &'a SrcFile) -> io::Result<Box<dyn io::BufRead + 'a>> {
+    use crate::sourcemap::{SourceInfo, SourceOrigin};
+    if file.is_stdin() || file.origin == SourceOrigin::Remote {
+        let src = match &file.src {
+            SourceInfo::Source(s) => io::Cursor::new(s.as_bytes()),
+            SourceInfo::Binary | SourceInfo::Symlink(_) => panic!("cannot read form binary input in `{}`", file.path.display()),
+        };
+        Ok(Box::new(io::BufReader::with_capacity(flags.io_buffer_size, src)))
+    } else {
+        // @FIXME Why? if the Source is already loaded in memory, why don't we re-use it? much faster than reading from disk.
+        Ok(Box::new(io::BufReader::with_capacity(flags.io_buffer_size, fs::File::open(&file.path)?)))
+    }
+}
+
+/// the output of the resolved `terms` in memory
+fn render_file(flags: &cfg::Flags, file: &SrcFile, terms: &Terms) -> io::Result<Vec<u8>> {
+    let mut reader = open_source(flags, file)?;
+    let mut rdr = ReadCtx::new(&mut reader, 0usize);
+    let mut out = Vec::new();
+    let mut wtr = WriteCtx::new(&mut out).newline(flags.newline).vectored(true);
+    write_terms(&mut rdr, &mut wtr, terms)?;
+    wtr.flush()?;
+    Ok(out)
+}
+
+/// same as [`write`] but to `dest` instead of the file's destination.
+/// the source and the destination are buffered by [`cfg::Flags::io_buffer_size`] bytes.
+pub fn write_to(flags: &cfg::Flags, file: SrcFile, dest: &Destination, terms: &Terms, env: &Env) -> io::Result<usize> {
+    write_resolved(flags, file, dest, &infer::resolve(terms, env))
+}
+
+/// [`write_to`] of the already resolved `terms`
+fn write_resolved(flags: &cfg::Flags, file: SrcFile, dest: &Destination, terms: &Terms) -> io::Result<usize> {
+    if let Some(dest) = dest.path().filter(|d| flags.if_changed && d.is_file()) {
+        // rendered in memory first, so an unchanged destination isn't touched at all
+        let out = render_file(flags, &file, terms)?;
+        let mut old = io::BufReader::with_capacity(flags.io_buffer_size, fs::File::open(dest)?);
+        if same_contents(&mut out.as_slice(), &mut old)? {
+            return Ok(0);
+        }
+        fs::write(dest, &out)?;
+        return Ok(out.len());
+    }
+    if let Some(dest) = dest.path().filter(|d| !flags.force && d.exists()) {
+        let msg = format!(
+            "error: file `{}` already exists. [use --force or --if-changed to overwrite]",
+            dest.display()
+        );
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, msg));
+    }
+    let mut reader = open_source(flags, &file)?;
+    let mut rdr = ReadCtx::new(&mut reader, 0usize);
+    let mut out_f: Box<dyn io::Write> = match dest {
+        Destination::Path(dest) => {
+            create_parent(dest)?;
+            Box::new(io::BufWriter::with_capacity(flags.io_buffer_size, fs::File::create(dest)?))
+        }
+        Destination::Stdout => Box::new(io::BufWriter::with_capacity(flags.io_buffer_size, io::stdout())),
+        Destination::InMemory => Box::new(io::sink()),
+    };
+    let mut wtr = WriteCtx::new(&mut out_f).newline(flags.newline).vectored(true);
+    write_terms(&mut rdr, &mut wtr, terms)?;
+    wtr.flush()?;
+    Ok(wtr.written())
+}
+
+/// [`write_to`], or adds the output to the archive of `out`. The text entries have the default permissions.
+pub fn write_out(flags: &cfg::Flags, out: &Output, file: SrcFile, dest: &Destination, terms: &Terms, env: &Env) -> io::Result<usize> {
+    let terms = infer::resolve(terms, env);
+    match out.entry(flags, dest) {
+        Some((archive, name)) => {
+            let bytes = render_file(flags, &file, &terms)?;
+            archive.add(name, archive::Entry::File(&bytes, 0o644))?;
+            Ok(bytes.len())
+        }
+        None => write_resolved(flags, file, dest, &terms),
+    }
+}
+
+/// writes all the trees, in batches of at most [`Throttle::batch_size`] files in flight.
+/// calls `on_written` with the time spent on each file and the bytes written, and returns the number of files written.
+pub fn write_files<E: EnvOf, F: FnMut(&SrcFile, Duration, usize)>(
+    flags: &cfg::Flags,
+    trees: &[(SrcFile, Terms)],
+    env: &E,
+    out: &Output,
+    throttle: &Throttle,
+    on_written: F,
+) -> io::Result<usize> {
+    let dests: Vec<_> = trees.iter().map(|(f, _)| f.destination.clone()).collect();
+    write_files_to(flags, trees, &dests, env, out, throttle, on_written)
+}
+
+/// same as [`write_files`], but each tree is written to the destination at the same index in `dests`.
+pub fn write_files_to<E: EnvOf, F: FnMut(&SrcFile, Duration, usize)>(
+    flags: &cfg::Flags,
+    trees: &[(SrcFile, Terms)],
+    dests: &[Destination],
+    env: &E,
+    out: &Output,
+    throttle: &Throttle,
+    mut on_written: F,
+) -> io::Result<usize> {
+    assert_eq!(trees.len(), dests.len());
+    let mut count = 0;
+    let batch_size = throttle.batch_size();
+    for (batch, dests) in trees.chunks(batch_size).zip(dests.chunks(batch_size)) {
+        let written = std::thread::scope(|s| {
+            let workers: Vec<_> = batch
+                .iter()
+                .zip(dests)
+                .map(|((source, tree), dest)| {
+                    s.spawn(move || {
+                        let _permit = throttle.acquire();
+                        let start = Instant::now();
+                        let env = env.env_of(source);
+                        write_out(flags, out, source.clone(), dest, tree, env).map(|bytes| (start.elapsed(), bytes))
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|w| w.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+                .collect::<Vec<_>>()
+        });
+        for ((source, _), written) in batch.iter().zip(written) {
+            let (time, bytes) = written?;
+            on_written(source, time, bytes);
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// `--origin-map`: writes the [`origins::map`] of the outputs of `trees` to `path`, as JSON.
+pub fn write_origin_map<E: EnvOf>(path: &Path, trees: &[(SrcFile, Terms)], env: &E) -> io::Result<()> {
+    create_parent(path)?;
+    let mut out = io::BufWriter::new(fs::File::create(path)?);
+    serde_json::to_writer_pretty(&mut out, &origins::map(trees, env))?;
+    writeln!(out)?;
+    out.flush()
+}
+
+/// copies the binary files one at a time, sharing the file descriptors of `throttle` with [`write_files`].
+/// returns the number of files copied.
+pub fn copy_bins(flags: &cfg::Flags, bins: Vec<SrcFile>, out: &Output, throttle: &Throttle) -> io::Result<usize> {
+    let mut count = 0;
+    for bin in bins {
+        let _permit = throttle.acquire();
+        let dest = bin.destination.clone();
+        out.copy_bin_to(flags, bin, &dest)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[doc(inline)]
+pub use crate::output::{copy_bin, copy_bin_to};
+
+/// `flan clean`: removes the existing destinations of the sources, only lists them with `--dry-run`.
+/// A destination which isn't what would be written (or copied) is an error, and nothing is removed,
+/// unless `--force` is given. Returns the destinations removed, or which would be with `--dry-run`.
+pub fn clean(
+    flags: &cfg::Flags,
+    trees: &[(SrcFile, Terms)],
+    bins: &[SrcFile],
+    env: &Env,
+    handler: &mut Handler,
+) -> io::Result<Vec<PathBuf>> {
+    use crate::sourcemap::SourceInfo;
+    let err_count = handler.err_count;
+    let mut found = Vec::new();
+    let files = trees.iter().map(|(f, t)| (f, Some(t))).chain(bins.iter().map(|f| (f, None)));
+    for (file, terms) in files {
+        let dest = match file.destination.path() {
+            Some(dest) => dest,
+            None => continue,
+        };
+        // `exists` would follow links, and flan never writes directories
+        match dest.symlink_metadata() {
+            Ok(meta) if !meta.is_dir() => {}
+            _ => continue,
+        }
+        let generated = flags.force
+            || match (&file.src, terms) {
+                (SourceInfo::Symlink(target), _) => fs::read_link(dest).is_ok_and(|t| &t == target),
+                (_, Some(terms)) => dest.is_file() && same_contents(&mut render_file(flags, file, &infer::resolve(terms, env))?.as_slice(), &mut open_dest(flags, dest)?)?,
+                (_, None) => dest.is_file() && same_contents(&mut open_dest(flags, &file.path)?, &mut open_dest(flags, dest)?)?,
+            };
+        if generated {
+            found.push(dest.to_path_buf());
+        } else {
+            handler
+                .error(format!("`{}` isn't what `{}` would generate, it is kept.", dest.display(), file.path.display()).as_ref())
+                .code(codes::MODIFIED_DESTINATION)
+                .suggest("use `--force` to remove it anyway.")
+                .print();
+        }
+    }
+    if handler.err_count > err_count {
+        return Ok(Vec::new());
+    }
+    if flags.command != (cfg::Command::Clean { dry_run: true }) {
+        for dest in found.iter() {
+            fs::remove_file(dest)?;
+        }
+    }
+    Ok(found)
+}
+fn open_dest(flags: &cfg::Flags, path: &Path) -> io::Result<io::BufReader<fs::File>> {
+    Ok(io::BufReader::with_capacity(flags.io_buffer_size, fs::File::open(path)?))
+}
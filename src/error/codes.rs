@@ -0,0 +1,350 @@
+//! registry of the diagnostic codes, see `flan --explain`.
+//!
+//! codes are stable: a code is never reused for another diagnostic, they are grouped by
+//! * `F00xx` syntax
+//! * `F01xx` variables
+//! * `F02xx` dimensions and decisions
+//! * `F03xx` paths and files
+//! * `F04xx` command line
+//! * `F05xx` lints
+use std::fmt;
+
+use super::ExitCode;
+
+/// stable code of a diagnostic, printed as `F0203`
+#[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
+pub struct Code(u16);
+
+pub const UNCLOSED_DELIMITER: Code = Code(1);
+pub const ILLEGAL_CHARACTER: Code = Code(2);
+pub const UNEXPECTED_TOKEN: Code = Code(3);
+pub const DUPLICATE_LABEL: Code = Code(4);
+pub const INVALID_CONDITION: Code = Code(5);
+pub const TOO_MANY_CHOICES: Code = Code(6);
+pub const INVALID_BLOCK: Code = Code(7);
+pub const UNKNOWN_BLOCK: Code = Code(8);
+pub const DUPLICATE_BLOCK: Code = Code(9);
+pub const RECURSIVE_BLOCK: Code = Code(10);
+
+pub const UNDECLARED_VARIABLE: Code = Code(101);
+pub const RECURSIVE_VARIABLE: Code = Code(102);
+pub const VARIABLE_BOUND_TWICE: Code = Code(103);
+pub const UNSET_ENV_VARIABLE: Code = Code(104);
+pub const INVALID_UNICODE: Code = Code(105);
+pub const FAILED_COMMAND: Code = Code(106);
+pub const COMMAND_NOT_RUN: Code = Code(107);
+
+pub const UNKNOWN_DIMENSION: Code = Code(201);
+pub const SIZE_CONFLICT: Code = Code(202);
+pub const CONFLICTING_DECISIONS: Code = Code(203);
+pub const UNKNOWN_CHOICE: Code = Code(204);
+pub const NO_DECISION: Code = Code(205);
+pub const LABEL_CONFLICT: Code = Code(206);
+pub const REDUNDANT_DECISIONS: Code = Code(207);
+pub const INVALID_ALIAS: Code = Code(208);
+pub const INVALID_DECLARATION: Code = Code(209);
+pub const VIOLATED_CONSTRAINT: Code = Code(210);
+pub const UNUSED_DECISION: Code = Code(211);
+
+pub const OVERLAPPING_PATHS: Code = Code(301);
+pub const ABSOLUTE_DESTINATION: Code = Code(302);
+pub const NO_PATHS: Code = Code(303);
+pub const LOAD_FAILED: Code = Code(304);
+pub const DUPLICATE_DESTINATION: Code = Code(305);
+pub const SYMLINK_CYCLE: Code = Code(306);
+pub const MODIFIED_DESTINATION: Code = Code(307);
+pub const INVALID_CONFIG: Code = Code(308);
+pub const FETCH_FAILED: Code = Code(309);
+pub const HOOK_FAILED: Code = Code(310);
+pub const STALE_CACHE: Code = Code(311);
+
+pub const INVALID_ARGUMENTS: Code = Code(401);
+
+pub const EMPTY_BRANCH: Code = Code(501);
+pub const UNUSED_DIMENSION: Code = Code(502);
+pub const UNUSED_VARIABLE: Code = Code(503);
+
+/// (code, title, explanation)
+static REGISTRY: &[(Code, &str, &str)] = &[
+    (UNCLOSED_DELIMITER, "unclosed delimiter", "\
+A variable, dimension or guard was opened but never closed.
+
+    #$name          <- missing `#`
+    #os{a##b        <- missing `}#`
+
+variables end with `#`, dimensions and guards with `}#`."),
+    (ILLEGAL_CHARACTER, "illegal character in a variable name", "\
+Variable names contain alphanumeric characters and some symbols like `_`, `-` or `.`, but no
+whitespace nor `#`, `$`, `(`, `)`...
+
+    #$user(name)#   <- `(` isn't allowed
+    #$user_name#"),
+    (UNEXPECTED_TOKEN, "unexpected token", "\
+A dimension delimiter was found outside of a dimension, or in the wrong place.
+
+    a##b            <- `##` outside of a dimension, escape it with `\\##`
+    #os{a##!b##c}#  <- the default branch `##!` has to be the last one"),
+    (DUPLICATE_LABEL, "duplicate label", "\
+Two choices of a dimension have the same label.
+
+    #os{linux: a##linux: b}#
+    #os{linux: a##mac: b}#"),
+    (INVALID_CONDITION, "invalid condition", "\
+The condition of a guard couldn't be parsed. Conditions compare decisions with `=` and `!=`,
+and combine them with `!`, `&&`, `||` and parentheses.
+
+    #when(os==linux){..}#
+    #when(os=linux && !(arch=arm)){..}#"),
+    (TOO_MANY_CHOICES, "too many choices", "\
+A dimension of a source has more than 65535 choices, the most a dimension can have. The choices can be
+split between nested dimensions.
+"),
+    (INVALID_BLOCK, "invalid named block", "\
+The name of a named block, or the arguments of one of its uses, couldn't be parsed. Named blocks are named
+like dimensions, and the arguments bind variables of the block.
+
+    #def(my header){..}#        <- no whitespace in names
+    #use(header, title)#        <- arguments are `variable=value`
+    #use(header, title=Intro)#"),
+    (UNKNOWN_BLOCK, "unknown named block", "\
+A named block is used, but none of the sources defines it.
+
+    #use(header)#       <- needs a `#def(header){..}#` in one of the sources"),
+    (DUPLICATE_BLOCK, "duplicate named block", "\
+Two named blocks have the same name, in the same source or in different ones."),
+    (RECURSIVE_BLOCK, "recursive named block", "\
+A named block uses itself, directly or through the other blocks it uses.
+
+    #def(a){ #use(b)# }#
+    #def(b){ #use(a)# }#     <- `a` -> `b` -> `a`"),
+    (UNDECLARED_VARIABLE, "undeclared variable", "\
+A variable is used but not declared in `[variables]`, nor bound by a decided choice.
+
+    #$user#             <- error if `user` isn't declared
+    #$user:-nobody#     <- uses `nobody` instead
+
+`--ignore-unset` replaces the undeclared variables by nothing, `--ignore-unset=verbatim` keeps them as they are
+written and `--ignore-unset=placeholder` writes `${user}`."),
+    (RECURSIVE_VARIABLE, "invalid variable reference", "\
+A variable of the config file references itself, or a variable that isn't declared.
+
+    [variables]
+    a = \"#$b#\"
+    b = \"#$a#\"         <- `a` -> `b` -> `a`"),
+    (VARIABLE_BOUND_TWICE, "variable bound twice", "\
+Two decided choices set the same variable.
+
+    [dimensions.os.choices.linux]
+    vars = { pkg = \"apt\" }
+    [dimensions.distro.choices.arch]
+    vars = { pkg = \"pacman\" }
+
+    flan linux arch     <- `pkg` is bound by both `os=linux` and `distro=arch`"),
+    (UNSET_ENV_VARIABLE, "unset environment variable", "\
+A variable is read from an environment variable which isn't set, and has no default.
+
+    [variables]
+    user = { env = \"USER\" }
+    user = { env = \"USER\", default = \"nobody\" }"),
+    (INVALID_UNICODE, "invalid unicode", "\
+The value of an environment variable, or the output of a command, isn't valid unicode."),
+    (FAILED_COMMAND, "failed command", "\
+The command of a variable couldn't be run, or exited with an error.
+
+    [variables]
+    version = { cmd = \"git describe --tags\" }   <- fails outside of a git repository"),
+    (COMMAND_NOT_RUN, "command not run", "\
+`--no-exec` was given, so the commands of the variables aren't run and these variables are left unset."),
+    (UNKNOWN_DIMENSION, "unknown dimension", "\
+A dimension is used without a decision.
+
+    #os{a##b}#
+
+    flan os=1           <- decides `os`
+    flan linux          <- decides `os` if `linux` is one of its choice names"),
+    (SIZE_CONFLICT, "conflicting number of choices", "\
+The uses of a dimension, or its declaration, don't have the same number of choices.
+
+    #os{a##b}# #os{a##b##c}#
+
+a use with a default branch `##!` can have fewer choices."),
+    (CONFLICTING_DECISIONS, "conflicting decisions", "\
+A dimension is decided more than once, with different choices.
+
+    [dimensions]
+    os = [\"linux\", \"mac\"]
+
+    flan linux mac
+    flan os=mac linux"),
+    (UNKNOWN_CHOICE, "unknown choice", "\
+A decision or a condition refers to a choice the dimension doesn't have.
+
+    [dimensions]
+    os = [\"linux\", \"mac\"]
+    size = 2
+
+    flan os=windows     <- not a choice name of `os`
+    flan size=2         <- indices start at 0
+    #when(os=bsd){..}#"),
+    (NO_DECISION, "no decision", "\
+A declared dimension isn't decided. It is only an error if one of the sources uses it, or with `flan check`.
+`--interactive` asks for the missing decisions."),
+    (LABEL_CONFLICT, "conflicting labels", "\
+The label of a choice conflicts with its name in the config file, or with the labels of another use.
+
+    #os{linux: a##mac: b}#
+    #os{mac: a##linux: b}#"),
+    (REDUNDANT_DECISIONS, "redundant decisions", "\
+The same decision is given twice, by its name and by a `dimension=choice` pair.
+
+    flan linux os=linux"),
+    (INVALID_ALIAS, "invalid alias", "\
+An alias of `[dimensions.aliases]` is also a declared dimension, or refers to another alias.
+A dimension also can't be decided through several of its names.
+
+    [dimensions.aliases]
+    platform = \"os\"
+    target = \"platform\"  <- use `target = \"os\"`"),
+    (INVALID_DECLARATION, "invalid declaration", "\
+A declaration of the config file can't be used:
+* a dimension has the same choice name twice, or more than 65535 choices
+* the name of a dimension, alias or choice isn't an identifier: a letter or `_`, then letters, digits or `_`
+* the name of a variable has characters which can't be in `#$name#`
+* a path has an empty source or destination
+
+    [dimensions]
+    os = [\"linux\", \"mac\", \"linux\"]
+    arch = [\"x86-64\", \"arm\"]     <- use `x86_64`"),
+    (VIOLATED_CONSTRAINT, "violated constraint", "\
+The decisions don't satisfy a condition of `[constraints]` of the config file, which uses the syntax of the
+guards. Change one of the decisions listed in the note.
+
+    [constraints]
+    no-systemd-on-windows = \"!(os=windows && init=systemd)\"
+
+    $ flan os=windows init=systemd"),
+    (UNUSED_DECISION, "unused decision", "\
+A decision given on the command line matches no dimension: the dimension of a `dimension=choice`
+pair is neither declared nor used by the sources, or a standalone name is none of their choices.
+It is usually a typo, `--strict-decisions` (same as `--deny unused-decision`) makes it an error.
+
+    flan linxu          <- instead of `linux`
+    flan dist=debian    <- no source uses `dist`"),
+    (OVERLAPPING_PATHS, "overlapping paths", "\
+Two source paths of `[paths]` overlap, so some files are processed twice."),
+    (ABSOLUTE_DESTINATION, "absolute destination", "\
+A destination is absolute, so it can't be placed under the prefix of a matrix variant."),
+    (NO_PATHS, "no paths", "\
+There isn't anything to process: give paths in `[paths]`, or a file with `-i`."),
+    (LOAD_FAILED, "couldn't read or write a file", "\
+A file, directory, config file or lockfile couldn't be read or written. The message has the reason."),
+    (DUPLICATE_DESTINATION, "duplicate destination", "\
+Two sources are written to the same destination file, so one would overwrite the other.
+
+    [paths]
+    \"a/gitconfig\" = \"~/.gitconfig\"
+    \"b/gitconfig\" = \"~/./.gitconfig\"   <- same destination after normalization
+
+a directory source can also collide with a file source mapped inside its destination."),
+    (SYMLINK_CYCLE, "symlink cycle", "\
+A symbolic link of a walked directory leads to one of the directories being walked, so following it
+would never end. The link is skipped, `--symlinks=copy-link` recreates the links instead of following them.
+
+    src/
+      conf/
+        loop -> ..      <- `src/conf/loop` is `src`"),
+    (MODIFIED_DESTINATION, "modified destination", "\
+`flan clean` only removes the destinations which are what `flan build` would write with the same
+decisions (or copy, for binary files), so changes made after the build aren't lost.
+Nothing is removed if one of them differs, `flan clean --force` removes them anyway."),
+    (INVALID_CONFIG, "invalid config file", "\
+The config file, a file it `extends`, a `--decisions-file` or the lockfile isn't valid TOML, or one of its
+values doesn't have the expected type.
+
+    [dimensions]
+    os = [\"linux\", \"mac\"
+    size = \"2\"         <- the size of a dimension is a number"),
+    (FETCH_FAILED, "couldn't fetch a remote source", "\
+A source of `[paths]` given as an URL couldn't be downloaded and isn't in the cache (`--cache-dir`,
+`.flan-cache` by default) yet, or with `--offline` it isn't in the cache. The message has the reason.
+
+    [paths]
+    \"https://example.com/shared/app.conf\" = \"app.conf\"   <- downloaded with `curl`"),
+    (HOOK_FAILED, "failed hook", "\
+A command of `[hooks]` couldn't be run, or exited with an error. The next hooks aren't run, and nothing
+is written if it's a `pre` hook. `--no-hooks` doesn't run any.
+
+    [hooks]
+    pre = [\"make clean\"]
+    post = [\"chmod +x out/run.sh\"]   <- fails if `out/run.sh` isn't written"),
+    (STALE_CACHE, "stale cached source", "\
+A source of `[paths]` given as an URL couldn't be downloaded, but an earlier run cached it: the cached copy
+is used, it may be out of date. The note has the reason. Can be silenced with `--allow stale-cache`."),
+    (INVALID_ARGUMENTS, "invalid arguments", "\
+The command line arguments, or the decisions of a matrix variant, are invalid.
+
+    flan --stdout       <- `--stdout` needs a single file, given with `-i FILE` or `--stdin`
+    flan --explain E1   <- codes look like `F0203`"),
+    (EMPTY_BRANCH, "empty branch", "\
+A choice of a dimension or a branch of a guard is empty, which is usually caused by a misplaced
+or a trailing `##`. Can be silenced with `--allow empty-branch`.
+
+    #os{a####b}#
+    #os{a##\\~##b}#      <- `\\~` marks an intentionally empty choice
+    #os{a##}#           <- a second, empty, choice
+    #when(os=linux){a##}#"),
+    (UNUSED_DIMENSION, "unused dimension", "\
+A dimension is declared in the config file, but none of the sources use it, neither in a
+dimension nor in the condition of a guard. It may be misspelled, or left over."),
+    (UNUSED_VARIABLE, "unused variable", "\
+A variable is declared in `[variables]`, but none of the sources use it, nor the values of
+the variables they use.
+
+    [variables]
+    user = \"foo\"
+    email = \"#$user#@bar.com\"  <- `user` is used if `email` is"),
+];
+
+impl Code {
+    /// parses `F0203`, only registered codes are valid
+    pub fn lookup(s: &str) -> Option<Code> {
+        let n = s.strip_prefix('F').or_else(|| s.strip_prefix('f'))?.parse::<u16>().ok()?;
+        REGISTRY.iter().map(|(c, ..)| *c).find(|c| c.0 == n)
+    }
+    /// short description
+    pub fn title(&self) -> &'static str {
+        self.entry().1
+    }
+    /// longer description with examples, see `--explain`
+    pub fn explanation(&self) -> &'static str {
+        self.entry().2
+    }
+    /// how `flan` exits when this is the first error
+    pub fn exit_code(&self) -> ExitCode {
+        match *self {
+            LOAD_FAILED | MODIFIED_DESTINATION | FETCH_FAILED | HOOK_FAILED => ExitCode::IO,
+            Code(0..=99) => ExitCode::Parse,
+            Code(300..=499) => ExitCode::Config,
+            _ => ExitCode::Type,
+        }
+    }
+    /// all the codes, in order
+    pub fn all() -> impl Iterator<Item = Code> {
+        REGISTRY.iter().map(|(c, ..)| *c)
+    }
+    fn entry(&self) -> &'static (Code, &'static str, &'static str) {
+        // @SAFETY unwrap(): the constants are all in the registry
+        REGISTRY.iter().find(|(c, ..)| c == self).unwrap()
+    }
+}
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "F{:04}", self.0)
+    }
+}
+
+/// text printed by `flan --explain CODE`
+pub fn explain(s: &str) -> Option<String> {
+    let code = Code::lookup(s)?;
+    Some(format!("{}: {}\n\n{}\n", code, code.title(), code.explanation()))
+}
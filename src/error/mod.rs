@@ -0,0 +1,910 @@
+//! Code for emitting and handling errors
+//!
+//! @DESIGN The goal is that if an error occurs we continue parsing the rest of the files
+//! but I'm stil not sure whether copying should continue, stop or a rollback should occur.
+pub mod codes;
+
+use std::sync::{Arc, Mutex};
+
+#[doc(inline)]
+pub use codes::Code;
+pub use crate::cfg::{ErrorFlags, MessageFormat};
+use crate::sourcemap::{BytePos, Loc, Span, SrcFile, SrcMap};
+
+#[macro_export]
+macro_rules! emit_error {
+    (code = $code:expr; $($arg:tt)*) => ({
+        $crate::error::Error::_emit($crate::error::Level::Error, Some($code), format_args!("{}\n", format_args!($($arg)*)));
+    });
+    ($($arg:tt)*) => ({
+        $crate::error::Error::_emit($crate::error::Level::Error, None, format_args!("{}\n", format_args!($($arg)*)));
+    })
+}
+
+#[derive(Clone, PartialEq, PartialOrd, Eq, Debug, Hash)]
+pub struct Error {
+    level: Level,
+    /// see [`codes`]
+    code: Option<Code>,
+    msg: String,
+    /// error location
+    span: Span,
+    /// extra error messages
+    extra: Vec<String>,
+    /// message right under the error location
+    at_span: String,
+    /// secondary locations, rendered under the error location
+    labels: Vec<Label>,
+}
+
+/// secondary location of an [`Error`] with its message, e.g. where a conflicting definition is
+#[derive(Clone)]
+pub struct Label {
+    pub span: Span,
+    pub msg: String,
+    /// file containing `span`, looked up when the error is created
+    src: Option<SrcFile>,
+}
+impl Label {
+    pub fn new(span: Span, msg: String, src: Option<SrcFile>) -> Self {
+        Label { span, msg, src }
+    }
+    pub fn src(&self) -> Option<&SrcFile> {
+        self.src.as_ref()
+    }
+}
+// the file is determined by the span
+impl PartialEq for Label {
+    fn eq(&self, other: &Self) -> bool {
+        (self.span, &self.msg) == (other.span, &other.msg)
+    }
+}
+impl Eq for Label {}
+impl PartialOrd for Label {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        (self.span, &self.msg).partial_cmp(&(other.span, &other.msg))
+    }
+}
+impl std::hash::Hash for Label {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (self.span, &self.msg).hash(state)
+    }
+}
+impl std::fmt::Debug for Label {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Label")
+            .field("span", &self.span)
+            .field("msg", &self.msg)
+            .field("src", &self.src.as_ref().map(|s| &s.path))
+            .finish()
+    }
+}
+
+/// exit status of `flan`, so scripts can tell why it failed. see [`Code::exit_code`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum ExitCode {
+    Success = 0,
+    /// an error without a more precise class
+    Failure = 1,
+    /// invalid arguments, config file, lockfile or matrix
+    Config = 2,
+    /// a source couldn't be parsed
+    Parse = 3,
+    /// the sources don't check with the decisions: undeclared variables, unknown dimensions...
+    Type = 4,
+    /// a file couldn't be read or written, nothing was written
+    IO = 5,
+    /// some destinations were written before a failure
+    Partial = 6,
+}
+impl ExitCode {
+    pub fn exit(self) -> ! {
+        std::process::exit(self as i32)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Debug, Hash)]
+pub enum Level {
+    Fatal,
+    Error,
+    Warning,
+    Note,
+    More,
+}
+impl Level {
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Level::Fatal)
+    }
+    /// lowercase name, see [`Error::render_json`]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Fatal => "fatal",
+            Level::Error => "error",
+            Level::Warning => "warning",
+            Level::Note => "note",
+            Level::More => "more",
+        }
+    }
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            Level::Fatal => 1,
+            Level::Error => 2,
+            Level::Warning => 3,
+            Level::Note => 4,
+            Level::More => 5,
+        }
+    }
+}
+/// categories of warnings, which can be silenced with `--allow` or made into errors with `--deny`
+#[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Debug, Hash)]
+pub enum WarnKind {
+    /// a variable set by a command isn't set because of `--no-exec`
+    CommandNotRun,
+    /// the same decision given by its name and a `dimension=choice` pair
+    RedundantDecision,
+    /// a decision matching no dimension, see [`crate::driver::check_unused_decisions`]
+    UnusedDecision,
+    /// paths of `[paths]` loading the same files twice
+    OverlappingPaths,
+    /// nothing to process
+    NoPaths,
+    /// a remote source couldn't be downloaded, its cached copy is used, see [`crate::driver::fetch`]
+    StaleCache,
+    /// lint: a dimension choice or a guard branch without any terms, usually caused by a misplaced `##`
+    EmptyBranch,
+    /// lint: a dimension of the config file which no source uses
+    UnusedDimension,
+    /// lint: a variable of the config file which no source uses
+    UnusedVariable,
+}
+impl WarnKind {
+    pub const ALL: [WarnKind; 9] = [
+        WarnKind::CommandNotRun,
+        WarnKind::RedundantDecision,
+        WarnKind::UnusedDecision,
+        WarnKind::OverlappingPaths,
+        WarnKind::NoPaths,
+        WarnKind::StaleCache,
+        WarnKind::EmptyBranch,
+        WarnKind::UnusedDimension,
+        WarnKind::UnusedVariable,
+    ];
+    pub fn name(&self) -> &'static str {
+        match self {
+            WarnKind::CommandNotRun => "command-not-run",
+            WarnKind::RedundantDecision => "redundant-decision",
+            WarnKind::UnusedDecision => "unused-decision",
+            WarnKind::OverlappingPaths => "overlapping-paths",
+            WarnKind::NoPaths => "no-paths",
+            WarnKind::StaleCache => "stale-cache",
+            WarnKind::EmptyBranch => "empty-branch",
+            WarnKind::UnusedDimension => "unused-dimension",
+            WarnKind::UnusedVariable => "unused-variable",
+        }
+    }
+    pub fn code(&self) -> Code {
+        match self {
+            WarnKind::CommandNotRun => codes::COMMAND_NOT_RUN,
+            WarnKind::RedundantDecision => codes::REDUNDANT_DECISIONS,
+            WarnKind::UnusedDecision => codes::UNUSED_DECISION,
+            WarnKind::OverlappingPaths => codes::OVERLAPPING_PATHS,
+            WarnKind::NoPaths => codes::NO_PATHS,
+            WarnKind::StaleCache => codes::STALE_CACHE,
+            WarnKind::EmptyBranch => codes::EMPTY_BRANCH,
+            WarnKind::UnusedDimension => codes::UNUSED_DIMENSION,
+            WarnKind::UnusedVariable => codes::UNUSED_VARIABLE,
+        }
+    }
+    /// whether it warns about code that is valid but likely a mistake
+    pub fn is_lint(&self) -> bool {
+        matches!(self, WarnKind::EmptyBranch | WarnKind::UnusedDimension | WarnKind::UnusedVariable)
+    }
+    /// parses the names given to `--allow` and `--deny`
+    pub fn from_name(s: &str) -> Result<Self, String> {
+        // the former name of `empty-branch`
+        if s == "empty-choice" {
+            return Ok(WarnKind::EmptyBranch);
+        }
+        Self::ALL.iter().copied().find(|k| k.name() == s).ok_or_else(|| {
+            let names: Vec<_> = Self::ALL.iter().map(WarnKind::name).collect();
+            format!("unknown warning `{}`, expected one of: {}", s, names.join(", "))
+        })
+    }
+    fn bit(&self) -> u32 {
+        1 << (*self as u32)
+    }
+}
+impl std::fmt::Display for WarnKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// set of [`WarnKind`]s, see [`ErrorFlags::allowed`]
+#[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Debug, Hash, Default)]
+pub struct WarnSet(u32);
+impl WarnSet {
+    pub fn insert(&mut self, kind: WarnKind) {
+        self.0 |= kind.bit();
+    }
+    pub fn contains(&self, kind: WarnKind) -> bool {
+        self.0 & kind.bit() != 0
+    }
+}
+impl<'a> std::iter::FromIterator<&'a WarnKind> for WarnSet {
+    fn from_iter<I: IntoIterator<Item = &'a WarnKind>>(kinds: I) -> Self {
+        let mut set = WarnSet::default();
+        kinds.into_iter().for_each(|k| set.insert(*k));
+        set
+    }
+}
+
+impl std::fmt::Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Level::Fatal => "FATAL ERROR",
+                Level::Error => "error",
+                Level::Warning => "warning",
+                Level::Note => "note",
+                Level::More => "",
+            }
+        )
+    }
+}
+
+impl Error {
+    pub fn _emit(level: Level, code: Option<Code>, args: std::fmt::Arguments) {
+        use std::io::{self, Write};
+        let mut err = Self::with_msg(level, std::fmt::format(args));
+        err.code = code;
+        // there is nowhere left to report a failed write, `eprint!` would panic instead
+        let _ = io::stderr().write_all(err.render(None).as_bytes());
+    }
+    pub fn is_fatal(&self) -> bool {
+        self.level.is_fatal()
+    }
+    pub fn level(&self) -> Level {
+        self.level
+    }
+    pub fn code(&self) -> Option<Code> {
+        self.code
+    }
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+    pub fn span(&self) -> Span {
+        self.span
+    }
+    /// extra notes and suggestions
+    pub fn extra(&self) -> &[String] {
+        &self.extra
+    }
+    pub fn at_span(&self) -> &str {
+        &self.at_span
+    }
+    /// see [`Label`]
+    pub fn labels(&self) -> &[Label] {
+        &self.labels
+    }
+    /// a general error without any specific location
+    pub fn error_general(msg: String) -> Self {
+        Self::with_msg(Level::Error, msg)
+    }
+    /// an error at `span`, like [`Self::fatal_span`]
+    pub fn error_span(span: Span, msg: String) -> Self {
+        Self::with_msg_span(Level::Error, msg, span)
+    }
+    pub fn warn_general(msg: String) -> Self {
+        Self::with_msg(Level::Warning, msg)
+    }
+    pub fn warn(span: Span, msg: String) -> Self {
+        Self::with_msg_span(Level::Warning, msg, span)
+    }
+    pub fn note_general(msg: String) -> Self {
+        Self::with_msg(Level::Note, msg)
+    }
+    pub fn note(span: Span, msg: String) -> Self {
+        Self::with_msg_span(Level::Note, msg, span)
+    }
+    pub fn fatal_unexpected() -> Self {
+        Self::with_msg(Level::Fatal, String::from("Unexpected fatal error."))
+    }
+    pub fn fatal_span(msg: String, span: Span) -> Self {
+        Self::with_msg_span(Level::Fatal, msg, span)
+    }
+    pub fn fatal(msg: String) -> Self {
+        Self::with_msg(Level::Fatal, msg)
+    }
+    fn with_msg(level: Level, msg: String) -> Self {
+        Self::with_msg_span(level, msg, Span::NIL)
+    }
+    fn with_msg_span(level: Level, msg: String, span: Span) -> Self {
+        Error {
+            level,
+            code: None,
+            msg,
+            span,
+            extra: Vec::new(),
+            at_span: String::from(""),
+            labels: Vec::new(),
+        }
+    }
+    pub fn with_code(mut self, code: Code) -> Self {
+        self.code = Some(code);
+        self
+    }
+    pub fn add_label(&mut self, label: Label) -> &mut Self {
+        self.labels.push(label);
+        self
+    }
+    /// add extra messages
+    pub fn add_msg(&mut self, msg: String) -> &mut Self {
+        self.extra.push(msg);
+        self
+    }
+    pub fn render(&self, src: Option<SrcFile>) -> String {
+        // @SAFETY: write does not fail on Strings
+        #![allow(unused_must_use)]
+        use std::fmt::Write;
+
+        // `--verbose` messages have no level to show
+        let mut buf = match (self.level, self.code) {
+            (Level::More, _) => format!("{}\n", self.msg),
+            (_, Some(code)) => format!("{}[{}]: {}\n", self.level, code, self.msg),
+            (_, None) => format!("{}: {}\n", self.level, self.msg),
+        };
+        // the line numbers of the labels are aligned with the ones of the error
+        let label_width = self
+            .labels
+            .iter()
+            .filter_map(|l| Some((l.src.as_ref()?.get_line_num(l.span.lo)? + 1).to_string().len()))
+            .max();
+        let mut alignment = label_width.map_or(3, |w| w + 1);
+
+        if let Some(src) = src.as_ref() {
+            write!(buf, "in {}", src.path.display());
+            if !self.span.is_nil() {
+                let line_ = src.lookup_line(self.span.lo);
+                assert!(line_.is_some());
+                let loc = line_.unwrap();
+                let lnum = (loc.index + 1).to_string();
+                // columns are in characters, the span in bytes
+                let start = loc.column(self.span.lo);
+                let end = loc.column(self.span.hi);
+                // the last byte of the span can be on another line
+                let last = match self.span.len() {
+                    0 => None,
+                    _ => src.lookup_line(self.span.hi - BytePos::from(1usize)),
+                };
+
+                let last = last.filter(|l| l.span.lo > loc.span.lo);
+                let width = last.as_ref().map_or(lnum.len(), |l| (l.index + 1).to_string().len());
+                alignment = usize::max(width, label_width.unwrap_or(0)) + 1;
+                writeln!(buf, ":{}:{}", lnum, start + 1);
+
+                writeln!(buf, "{}", Self::align_left("|", alignment));
+
+                match last {
+                    Some(last) => {
+                        self.render_lines(&mut buf, src, &loc, last, alignment);
+                    }
+                    None => {
+                        writeln!(buf, "{:>width$} | {}", lnum, loc.line, width = alignment - 1);
+
+                        // highlight span
+                        write!(buf, "{} ", Self::align_left("|", alignment));
+                        write!(buf, "{}", Self::align_left("", start));
+                        write!(buf, "{}", "^".repeat(end - start));
+                        writeln!(buf, " {}", self.at_span);
+                    }
+                }
+
+                writeln!(buf, "{}", Self::align_left("|", alignment));
+            } else {
+                writeln!(buf);
+            }
+        }
+        for label in self.labels.iter() {
+            Self::render_label(&mut buf, label, src.as_ref().filter(|_| !self.span.is_nil()), alignment);
+        }
+        for m in self.extra.iter() {
+            writeln!(buf, "{} {}", Self::align_left("*", alignment), m);
+        }
+        buf
+    }
+    /// snippet of a span crossing lines for [`Error::render`], from the `first` line to the `last` one.
+    /// `_^` marks the start and the end of the span, `|` the lines in between.
+    /// Only the first and last lines of long spans are shown.
+    fn render_lines(&self, buf: &mut String, src: &SrcFile, first: &Loc, last: Loc, alignment: usize) {
+        // @SAFETY: write does not fail on Strings
+        #![allow(unused_must_use)]
+        use std::fmt::Write;
+        const CONTEXT: usize = 2;
+        let lnum = first.index + 1;
+        let gutter = |n: usize| format!("{:>width$} |", n, width = alignment - 1);
+
+        writeln!(buf, "{}   {}", gutter(lnum), first.line);
+        let start = first.column(self.span.lo);
+        writeln!(buf, "{}  {}^", Self::align_left("|", alignment), "_".repeat(start + 1));
+        let last_num = last.index + 1;
+        for n in lnum + 1..last_num {
+            if n > lnum + CONTEXT && n + CONTEXT <= last_num {
+                if n == lnum + CONTEXT + 1 {
+                    writeln!(buf, "{} | ...", Self::align_left("|", alignment));
+                }
+                continue;
+            }
+            // @SAFETY unwrap(): the lines between `first` and `last` exist
+            writeln!(buf, "{} | {}", gutter(n), src.get_loc(n - 1).unwrap());
+        }
+        writeln!(buf, "{} | {}", gutter(last_num), last.line);
+        let end = last.column(self.span.hi - BytePos::from(1usize));
+        writeln!(buf, "{} |{}^ {}", Self::align_left("|", alignment), "_".repeat(end + 1), self.at_span);
+    }
+    /// snippet of a [`Label`] for [`Error::render`], `-` marks its span on its first line.
+    /// The file is only given if it isn't the one of the error's snippet.
+    fn render_label(buf: &mut String, label: &Label, primary: Option<&SrcFile>, alignment: usize) {
+        // @SAFETY: write does not fail on Strings
+        #![allow(unused_must_use)]
+        use std::fmt::Write;
+        let (src, loc) = match label.src.as_ref().and_then(|s| Some((s, s.lookup_line(label.span.lo)?))) {
+            Some(l) => l,
+            // not in the source map, only the message is left
+            None => {
+                writeln!(buf, "{} {}", Self::align_left("-", alignment), label.msg);
+                return;
+            }
+        };
+        // the label is cut at the end of its first line
+        let (start, end) = (loc.column(label.span.lo), loc.column(label.span.hi));
+        let lnum = loc.index + 1;
+        if primary.is_none_or(|p| !Arc::ptr_eq(p, src)) {
+            writeln!(buf, "in {}:{}:{}", src.path.display(), lnum, start + 1);
+            writeln!(buf, "{}", Self::align_left("|", alignment));
+        }
+        writeln!(buf, "{:>width$} | {}", lnum, loc.line, width = alignment - 1);
+        writeln!(
+            buf,
+            "{} {}{} {}",
+            Self::align_left("|", alignment),
+            " ".repeat(start),
+            "-".repeat(usize::max(end - start, 1)),
+            label.msg
+        );
+        writeln!(buf, "{}", Self::align_left("|", alignment));
+    }
+    /// renders the error as a single line JSON object:
+    /// ```json
+    /// {"level":"error","code":"F0203","message":"..","file":"a.txt","span":{"lo":4,"hi":9},"line":1,"column":5,"label":"..","notes":[".."],
+    ///  "labels":[{"message":"..","file":"b.txt","span":{"lo":0,"hi":2},"line":3,"column":1}]}
+    /// ```
+    /// `span` is in bytes from the start of the file, `column` in characters from the start of the line. `file`, `span`, `line` and `column` are `null` if the error
+    /// (or the label) has no location, `code` if it has no [`Code`].
+    pub fn render_json(&self, src: Option<SrcFile>) -> String {
+        use serde_json::{json, Value};
+        /// `file`, `span`, `line` and `column` of `span`
+        fn location(src: Option<&SrcFile>, span: Span) -> (Value, Value, Value, Value) {
+            let (mut file, mut jspan, mut line, mut column) = (Value::Null, Value::Null, Value::Null, Value::Null);
+            if let Some(src) = src.filter(|_| !span.is_nil()) {
+                file = json!(src.path.display().to_string());
+                let lo = span.lo.as_usize();
+                jspan = json!({ "lo": lo, "hi": lo + span.len() });
+                if let Some(loc) = src.lookup_loc(span) {
+                    line = json!(loc.line);
+                    column = json!(loc.col_start);
+                }
+            }
+            (file, jspan, line, column)
+        }
+        let (file, span, line, column) = location(src.as_ref(), self.span);
+        let labels: Vec<_> = self
+            .labels
+            .iter()
+            .map(|l| {
+                let (file, span, line, column) = location(l.src.as_ref(), l.span);
+                json!({ "message": l.msg, "file": file, "span": span, "line": line, "column": column })
+            })
+            .collect();
+        json!({
+            "level": self.level.name(),
+            "code": self.code.map(|c| c.to_string()),
+            "message": self.msg,
+            "file": file,
+            "span": span,
+            "line": line,
+            "column": column,
+            "label": self.at_span,
+            "notes": self.extra,
+            "labels": labels,
+        })
+        .to_string()
+    }
+    /// see [`MessageFormat`]
+    pub fn render_as(&self, format: MessageFormat, src: Option<SrcFile>) -> String {
+        match format {
+            MessageFormat::Human => self.render(src),
+            MessageFormat::Json => self.render_json(src),
+        }
+    }
+    fn align_left(txt: &str, size: usize) -> String {
+        let mut buf = String::with_capacity(size + txt.len());
+        buf.push_str(" ".repeat(size).as_ref());
+        buf.push_str(txt);
+        buf
+    }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.span != Span::MEMPTY {
+            writeln!(f, " > filename:line_n:offset {}", self.span)?
+        }
+        if self.is_fatal() {
+            return writeln!(f, "aborting...");
+        }
+        for m in self.extra.iter() {
+            writeln!(f, "   {}", m)?
+        }
+        Ok(())
+    }
+}
+
+/// where the [`Handler`] emits the errors that pass its [`ErrorFlags::report_level`].
+pub trait Sink: std::fmt::Debug + Send + Sync {
+    /// `src` is the file containing the error's span, if any
+    fn emit(&mut self, err: Error, src: Option<SrcFile>);
+}
+
+/// renders errors to stderr, the default [`Sink`]
+#[derive(Debug)]
+pub struct StderrSink {
+    format: MessageFormat,
+}
+impl StderrSink {
+    pub fn new(format: MessageFormat) -> Self {
+        StderrSink { format }
+    }
+}
+impl Default for StderrSink {
+    fn default() -> Self {
+        Self::new(crate::cfg::MESSAGE_FORMAT_DEFAULT)
+    }
+}
+impl Sink for StderrSink {
+    fn emit(&mut self, err: Error, src: Option<SrcFile>) {
+        eprintln!("{}", err.render_as(self.format, src));
+    }
+}
+
+/// keeps the errors in memory instead of printing them.  
+/// clones share the same errors, so a clone can be given to the [`Handler`] and the other one
+/// used to inspect what was emitted.
+#[derive(Debug, Default, Clone)]
+pub struct MemorySink(Arc<Mutex<Vec<Error>>>);
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// errors emitted so far, in order
+    pub fn errors(&self) -> Vec<Error> {
+        // @SAFETY unwrap(): we never panic while holding the lock
+        self.0.lock().unwrap().clone()
+    }
+}
+impl Sink for MemorySink {
+    fn emit(&mut self, err: Error, _src: Option<SrcFile>) {
+        // @SAFETY unwrap(): see `MemorySink::errors`
+        self.0.lock().unwrap().push(err);
+    }
+}
+
+#[derive(Debug)]
+/// an error handler
+pub struct Handler {
+    pub eflags: ErrorFlags,
+    pub err_count: usize,
+    /// the class of the first error, used by [`Self::abort_now`]
+    pub exit_code: ExitCode,
+    /// errors than haven't been printed yet, these should be emitted
+    /// if we abort (e.g. with a fatal error)
+    pub delayed_err: Vec<Error>,
+    pub sources: Arc<SrcMap>,
+    /// see [`Sink`]
+    sink: Box<dyn Sink>,
+}
+
+impl Handler {
+    /// a handler printing to stderr in the [`ErrorFlags::message_format`], see [`StderrSink`]
+    pub fn new(eflags: ErrorFlags, sources: Arc<SrcMap>) -> Self {
+        Self::with_sink(eflags, sources, Box::new(StderrSink::new(eflags.message_format)))
+    }
+    pub fn with_sink(eflags: ErrorFlags, sources: Arc<SrcMap>, sink: Box<dyn Sink>) -> Self {
+        Handler {
+            eflags,
+            err_count: 0,
+            exit_code: ExitCode::Success,
+            delayed_err: Vec::new(),
+            sources,
+            sink,
+        }
+    }
+    /// prints delayed errors and [`Self::abort_now`]
+    pub fn abort(&mut self) -> ! {
+        self.print_all();
+        self.abort_now()
+    }
+    /// aborts without printing delayed errors
+    pub fn abort_now(&self) -> ! {
+        if self.err_count > 1 {
+            eprintln!("Aborting due to previous errors.");
+        } else if self.err_count == 1 {
+            eprintln!("Aborting due to previous error.");
+        } else {
+            eprintln!("Aborting.");
+        }
+        match self.exit_code {
+            ExitCode::Success => ExitCode::Failure.exit(),
+            code => code.exit(),
+        }
+    }
+    pub fn abort_if_err(&self) {
+        if self.err_count > 0 {
+            self.abort_now();
+        }
+    }
+    /// prints all the delayed errors, in the order they were delayed
+    pub fn print_all(&mut self) {
+        for e in std::mem::take(&mut self.delayed_err) {
+            Self::emit_explicit(&self.eflags, &self.sources, self.sink.as_mut(), e);
+        }
+    }
+    /// delay error reporting for later
+    pub fn delay(&mut self, err: Error) {
+        self.count(&err);
+        self.delayed_err.push(err);
+    }
+    pub fn print(&mut self, err: Error) {
+        self.count(&err);
+        Self::emit_explicit(&self.eflags, &self.sources, self.sink.as_mut(), err)
+    }
+    fn count(&mut self, err: &Error) {
+        if err.level.as_u8() < Level::Warning.as_u8() {
+            if self.err_count == 0 {
+                self.exit_code = err.code.map_or(ExitCode::Failure, |c| c.exit_code());
+            }
+            self.err_count += 1;
+        }
+    }
+    /// exists in order to avoid code duplication between `print` and `print_all` due to
+    /// mutable borrow conflicts of `self`, despite borrowing two different fields
+    /// ```rs
+    /// for e in self.delay_err.iter() { // immutable borrow
+    ///   self.print(e) // mutable borrow
+    /// }
+    /// ```
+    fn emit_explicit(eflags: &ErrorFlags, sources: &SrcMap, sink: &mut dyn Sink, err: Error) {
+        if eflags.report_level >= err.level.as_u8() {
+            let src = sources.lookup_span(err.span).map(|(src, _)| src);
+            sink.emit(err, src);
+        }
+    }
+    pub fn error<'a>(&'a mut self, msg: &str) -> ErrorBuilder<'a> {
+        let no_extra = self.eflags.no_extra;
+        ErrorBuilder {
+            handler: self,
+            level: Level::Error,
+            code: None,
+            messages: vec![String::from(msg)],
+            span: None,
+            at_span: None,
+            labels: Vec::new(),
+            no_extra,
+            kind: None,
+        }
+    }
+    pub fn note<'a>(&'a mut self, msg: &str) -> ErrorBuilder<'a> {
+        let no_extra = self.eflags.no_extra;
+        ErrorBuilder {
+            handler: self,
+            level: Level::Note,
+            code: None,
+            messages: vec![String::from(msg)],
+            span: None,
+            at_span: None,
+            labels: Vec::new(),
+            no_extra,
+            kind: None,
+        }
+    }
+    /// a message only printed with `--verbose`, see [`Level::More`]
+    pub fn more<'a>(&'a mut self, msg: &str) -> ErrorBuilder<'a> {
+        let no_extra = self.eflags.no_extra;
+        ErrorBuilder {
+            handler: self,
+            level: Level::More,
+            code: None,
+            messages: vec![String::from(msg)],
+            span: None,
+            at_span: None,
+            labels: Vec::new(),
+            no_extra,
+            kind: None,
+        }
+    }
+    /// a warning of the given kind, or an error if it's denied, see [`Self::is_allowed`]
+    pub fn warn<'a>(&'a mut self, kind: WarnKind, msg: &str) -> ErrorBuilder<'a> {
+        let no_extra = self.eflags.no_extra;
+        let level = if self.eflags.denied.contains(kind) || (self.eflags.warn_as_error && !self.is_allowed(kind)) {
+            Level::Error
+        } else {
+            Level::Warning
+        };
+        ErrorBuilder {
+            handler: self,
+            level,
+            code: Some(kind.code()),
+            messages: vec![String::from(msg)],
+            span: None,
+            at_span: None,
+            labels: Vec::new(),
+            no_extra,
+            kind: Some(kind),
+        }
+    }
+    /// allowed warnings aren't reported. `--deny` takes precedence over `--allow`, which
+    /// takes precedence over `--Werror`
+    pub fn is_allowed(&self, kind: WarnKind) -> bool {
+        !self.eflags.denied.contains(kind) && self.eflags.allowed.contains(kind)
+    }
+}
+
+/// similar to [`std::str::pattern::Pattern`]
+pub trait Pattern<E> {
+    fn found(&self, e: &E) -> bool;
+}
+impl<E, F> Pattern<E> for F
+where
+    F: Fn(&E) -> bool,
+{
+    fn found(&self, e: &E) -> bool {
+        self(e)
+    }
+}
+impl Pattern<Level> for Level {
+    fn found(&self, e: &Level) -> bool {
+        self == e
+    }
+}
+
+pub struct ErrorBuilder<'a> {
+    handler: &'a mut Handler,
+    level: Level,
+    code: Option<Code>,
+    /// `messages[0] = Error::message`, the rest are extras
+    messages: Vec<String>,
+    span: Option<Span>,
+    at_span: Option<String>,
+    /// secondary spans and their messages, see [`Label`]
+    labels: Vec<(Span, String)>,
+    no_extra: bool,
+    /// see [`Handler::warn`]
+    kind: Option<WarnKind>,
+}
+
+impl<'a> ErrorBuilder<'a> {
+    /// see [`codes`]
+    pub fn code(mut self, code: Code) -> Self {
+        self.code = Some(code);
+        self
+    }
+    /// adds an extra message as note
+    pub fn note(mut self, msg: &str) -> Self {
+        self.add_extra(format!("note: {}", msg));
+        self
+    }
+    /// adds an extra message as suggestion
+    pub fn suggest(mut self, msg: &str) -> Self {
+        self.add_extra(format!("suggestion: {}", msg));
+        self
+    }
+    /// suggests the candidate closest to the misspelled `name`, if there is one. see [`crate::utils::closest`]
+    pub fn suggest_closest<'b, It: IntoIterator<Item = &'b str>>(self, name: &str, candidates: It) -> Self {
+        match crate::utils::closest(name, candidates) {
+            Some(c) => self.suggest(format!("did you mean `{}`?", c).as_ref()),
+            None => self,
+        }
+    }
+    /// should we refine or enlarge the span if they're different?
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+    /// points at the declaration of `key` in the config file, or tells which file declares it. see [`crate::cfg::Config::span`]
+    pub fn declared_at(self, config: &crate::cfg::Config, key: &str) -> Self {
+        match config.span(key) {
+            Some(span) => self.with_span(span),
+            None => self.note(&config.declared_in(key)),
+        }
+    }
+    /// adds a message under the error location
+    pub fn at_span(mut self, msg: &str) -> Self {
+        self.at_span = Some(String::from(msg));
+        self
+    }
+    /// adds a secondary location with a message under it, see [`Label`]
+    pub fn label(mut self, span: Span, msg: &str) -> Self {
+        self.labels.push((span, String::from(msg)));
+        self
+    }
+    /// consumes the builder and prints an error
+    pub fn print(self) {
+        if self.kind.is_some_and(|k| self.handler.is_allowed(k)) {
+            return;
+        }
+        let (e, h) = self.create();
+        h.print(e)
+    }
+    /// consumes the builder and delays error reporting in the handler
+    pub fn delay(self) {
+        if self.kind.is_some_and(|k| self.handler.is_allowed(k)) {
+            return;
+        }
+        let (e, h) = self.create();
+        h.delay(e)
+    }
+
+    fn add_extra(&mut self, msg: String) {
+        if self.no_extra {
+            return;
+        }
+        if self.messages.is_empty() {
+            self.messages.push(String::from(""));
+        }
+        self.messages.push(msg);
+    }
+    /// consume the builder to generate and error and return a Handler ref
+    fn create(mut self) -> (Error, &'a mut Handler) {
+        if let Some(kind) = self.kind {
+            let note = if self.handler.eflags.denied.contains(kind) {
+                format!("`--deny {}` is given.", kind)
+            } else if self.handler.eflags.warn_as_error {
+                format!("`--Werror` is given, `--allow {}` silences it.", kind)
+            } else if kind.is_lint() {
+                format!("`{}` is a lint warning, `--allow {}` silences it.", kind, kind)
+            } else {
+                format!("`--allow {}` silences it.", kind)
+            };
+            self.add_extra(format!("note: {}", note));
+        }
+        let sources = &self.handler.sources;
+        let labels = self
+            .labels
+            .into_iter()
+            .map(|(span, msg)| Label::new(span, msg, sources.lookup_span(span).map(|(src, _)| src)))
+            .collect();
+        let m = match self.messages.len() {
+            0 => String::from(""),
+            1 => self.messages.pop().unwrap(),
+            _ => self.messages.remove(0),
+        };
+
+        (
+            Error {
+                level: self.level,
+                code: self.code,
+                msg: m,
+                extra: self.messages,
+                span: self.span.unwrap_or(Span::NIL),
+                at_span: self.at_span.unwrap_or(String::from("")),
+                labels,
+            },
+            self.handler,
+        )
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self.level, Level::Fatal | Level::Error)
+    }
+}
@@ -1,87 +1,238 @@
 //! Inference/type checking environment.  
 //!
 //! Possible improvements:
-//! * Carry a hashset in the env of decisions left for delayed dimension declarations
 //! * add spans to [`Env::dimensions`] and [`Env::variables`] for better error reporting.
 //!   this might mean a span for every conflicting dimension call, as well as, a mechanism
 //!   to refine delayed_errors.
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 
 use crate::cfg::ErrorFlags;
 use crate::error::Handler;
+use crate::syntax::{Name, Term, TermK, Terms};
+use crate::sourcemap::{Span, SrcFile};
 
 #[derive(Debug)]
 /// typechecking/inference environment  
-/// @TODO: use symbols?
 pub struct Env {
-    pub variables: HashMap<String, String>,
-    pub dimensions: HashMap<String, Dim>,
+    pub variables: HashMap<Name, String>,
+    pub dimensions: HashMap<Name, Dim>,
+    /// choice names of the dimensions, from the config file or from the labels in the sources.
+    /// see [`crate::infer::unify_labels`]
+    pub labels: HashMap<Name, Vec<Option<Name>>>,
+    /// alias -> dimension, see [`crate::cfg::Dimensions::aliases`]
+    pub aliases: HashMap<Name, Name>,
+    /// the use which inferred the number of choices of a dimension, for error reporting
+    pub first_uses: HashMap<Name, Span>,
+    /// named decisions left for the dimensions which aren't declared
+    pub pending: Pending,
+    /// dimensions used by the checked sources, by their canonical name
+    pub used_dims: HashSet<Name>,
+    /// variables used by the checked sources
+    pub used_vars: HashSet<Name>,
+    /// the named blocks of all the sources, their [`TermK::Def`] term by name.
+    /// see [`crate::infer::unify_labels`]
+    pub defs: HashMap<Name, Term>,
     pub handler: Handler,
 }
 
+#[derive(Debug, Default, Clone)]
+/// named decisions that no declared dimension used, they can still decide a dimension by its labels.
+pub struct Pending {
+    /// standalone choice names
+    pub names: HashSet<Name>,
+    /// `dimension=choice` pairs
+    pub pairs: HashMap<Name, Name>,
+}
+
 impl Env {
     pub fn new(
-        variables: HashMap<String, String>,
-        dimensions: HashMap<String, Dim>,
+        variables: HashMap<Name, String>,
+        dimensions: HashMap<Name, Dim>,
         handler: Handler,
     ) -> Self {
         Env {
             variables,
             dimensions,
+            labels: HashMap::new(),
+            aliases: HashMap::new(),
+            first_uses: HashMap::new(),
+            pending: Pending::default(),
+            used_dims: HashSet::new(),
+            used_vars: HashSet::new(),
+            defs: HashMap::new(),
             handler,
         }
     }
-    pub fn get_var(&self, name: &String) -> Option<&String> {
+    pub fn get_var(&self, name: &Name) -> Option<&String> {
         self.variables.get(name)
     }
-    pub fn get_dimension(&self, name: &String) -> Option<&Dim> {
-        self.dimensions.get(name)
+    /// the dimension `name` stands for: itself, or the dimension it is an alias of
+    pub fn canonical<'a>(&'a self, name: &'a Name) -> &'a Name {
+        self.aliases.get(name).unwrap_or(name)
+    }
+    /// the body of the named block `name`
+    pub fn get_def(&self, name: &Name) -> Option<&Terms> {
+        match self.defs.get(name).map(|t| &t.node) {
+            Some(TermK::Def { body, .. }) => Some(body),
+            _ => None,
+        }
+    }
+    pub fn get_dimension(&self, name: &Name) -> Option<&Dim> {
+        self.dimensions.get(self.canonical(name))
     }
-    pub fn get_dimension_mut(&mut self, name: &String) -> Option<&mut Dim> {
-        self.dimensions.get_mut(name)
+    pub fn get_dimension_mut(&mut self, name: &Name) -> Option<&mut Dim> {
+        self.dimensions.get_mut(self.aliases.get(name).unwrap_or(name))
     }
     /// see [`Dim::try_set_dim`]
-    pub fn try_set_dimension(&mut self, name: &String, n: i8) -> Option<bool> {
+    pub fn try_set_dimension(&mut self, name: &Name, n: u16) -> Option<bool> {
         self.get_dimension_mut(name).map(|d| d.try_set_dim(n))
     }
+    /// adds the dimensions and variables used by the sources checked against `other`, see [`FileEnvs`]
+    pub fn extend_used(&mut self, other: &Env) {
+        self.used_dims.extend(other.used_dims.iter().copied());
+        self.used_vars.extend(other.used_vars.iter().copied());
+    }
     pub fn eflags(&self) -> ErrorFlags {
         self.handler.eflags
     }
 }
 
+/// the env each source is written with, see [`FileEnvs`]
+pub trait EnvOf: Sync {
+    fn env_of(&self, file: &SrcFile) -> &Env;
+}
+impl EnvOf for Env {
+    fn env_of(&self, _: &SrcFile) -> &Env {
+        self
+    }
+}
+
+/// the env of all the sources, layered with the envs of the sources pinning decisions or variables
+/// by their path. see [`crate::cfg::PathOverride`]
+#[derive(Debug)]
+pub struct FileEnvs {
+    pub base: Env,
+    /// by source path
+    pub files: HashMap<PathBuf, Env>,
+}
+impl EnvOf for FileEnvs {
+    fn env_of(&self, file: &SrcFile) -> &Env {
+        self.files.get(&file.path).unwrap_or(&self.base)
+    }
+}
+
+/// index of a choice of a dimension, 0-indexed. A dimension has at most [`ChoiceIdx::MAX_CHOICES`] choices.
+#[derive(Clone, Copy, Debug, Hash, Ord, PartialOrd, Eq, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct ChoiceIdx(pub u16);
+impl ChoiceIdx {
+    pub const MAX_CHOICES: usize = u16::MAX as usize;
+    /// `None` if `i` is out of range
+    pub fn new(i: usize) -> Option<Self> {
+        if i < Self::MAX_CHOICES {
+            Some(ChoiceIdx(i as u16))
+        } else {
+            None
+        }
+    }
+    /// the number of choices `n` if a dimension can have that many
+    pub fn count(n: usize) -> Option<u16> {
+        if n <= Self::MAX_CHOICES {
+            Some(n as u16)
+        } else {
+            None
+        }
+    }
+    pub fn as_usize(self) -> usize {
+        usize::from(self.0)
+    }
+}
+impl std::fmt::Display for ChoiceIdx {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl std::str::FromStr for ChoiceIdx {
+    type Err = std::num::IntErrorKind;
+    /// fails with [`std::num::IntErrorKind::PosOverflow`] if it is out of range
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let i = s.parse::<usize>().map_err(|e| *e.kind())?;
+        Self::new(i).ok_or(std::num::IntErrorKind::PosOverflow)
+    }
+}
+
 #[derive(Clone, Copy, Debug, Hash, Ord, PartialOrd, Eq, PartialEq)]
 /// Dimension
 pub struct Dim {
-    /// the total number of choices (alternatives) this dimension holds
-    /// a negative value means it has not yet been inferred.
-    pub choices: i8,
-    /// the currently chosen choice. 0-indexed.
-    pub decision: u8,
+    /// the total number of choices (alternatives) this dimension holds,
+    /// `None` if it has not yet been inferred.
+    pub choices: Option<u16>,
+    /// the currently chosen choice.
+    pub decision: ChoiceIdx,
+    /// where the decision comes from
+    pub origin: Origin,
+}
+
+#[derive(Clone, Copy, Debug, Hash, Ord, PartialOrd, Eq, PartialEq)]
+/// provenance of a [`Dim::decision`], see [`crate::driver::make_env`]
+pub enum Origin {
+    /// a standalone choice name, e.g. `linux`
+    Name,
+    /// a `dimension=index` pair, e.g. `os=linux` or `os=1`
+    Pair,
+    /// a `dimension=index` pair for a dimension that isn't declared in the config file
+    UndeclaredPair,
+    /// computed from the other decisions, see [`crate::cfg::Choices::Derived`]
+    Derived,
+    /// the lockfile, see [`crate::cfg::Config::with_lock`]
+    Lock,
+    /// a `--decisions-file`, see [`crate::cfg::Config::with_decisions_file`]
+    DecisionsFile,
+    /// a `[profiles.NAME]` of the config file selected with `--profile`
+    Profile,
+    /// the decisions of a source in `[paths]`, see [`crate::cfg::PathOverride`]
+    PathOverride,
+}
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Origin::Name => "choice name",
+            Origin::Pair => "dimension=choice pair",
+            Origin::UndeclaredPair => "dimension=choice pair, undeclared dimension",
+            Origin::Derived => "derived from the other decisions",
+            Origin::Lock => "lockfile",
+            Origin::DecisionsFile => "decisions file",
+            Origin::Profile => "profile",
+            Origin::PathOverride => "path override",
+        };
+        write!(f, "{}", s)
+    }
 }
 
 impl Dim {
-    pub fn new(decision: u8) -> Self {
+    /// a dimension that hasn't been declared, see [`Origin::UndeclaredPair`]
+    pub fn new(decision: ChoiceIdx) -> Self {
         Dim {
-            choices: -1,
+            choices: None,
             decision,
+            origin: Origin::UndeclaredPair,
         }
     }
-    /// tries to set the number of choices a dimension holds, returns false if it failed:
-    /// * if it was already set before to a diferent value
-    /// * if `n` is also negative  
+    /// tries to set the number of choices a dimension holds, returns false if it was already set
+    /// before to a diferent value.
     /// @INCOMPLETE `self.decision > n`
-    pub fn try_set_dim(&mut self, n: i8) -> bool {
-        if self.choices != n && self.choices > 0 {
-            false
-        } else if n < 0 {
-            false
-        } else {
-            self.choices = n;
-            true
+    pub fn try_set_dim(&mut self, n: u16) -> bool {
+        match self.choices {
+            Some(c) if c != n && c > 0 => false,
+            _ => {
+                self.choices = Some(n);
+                true
+            }
         }
     }
     /// Whether a dimension's size has already been inferred
     pub fn has_been_inferred(&self) -> bool {
-        self.choices >= 0
+        self.choices.is_some()
     }
 }
@@ -9,54 +9,269 @@ pub mod errors;
 
 // re-exports
 #[doc(inline)]
-pub use env::{Dim, Env};
+pub use env::{ChoiceIdx, Dim, Env, EnvOf, FileEnvs, Origin, Pending};
 #[doc(inline)]
 pub use errors::Error;
 
 use std::collections::HashMap;
 
-use crate::error::{Handler, ErrorBuilder};
+use crate::error::{codes, Handler, ErrorBuilder};
 use crate::sourcemap::Span;
+use crate::syntax::cond::{Choice, Cond};
 use crate::syntax::{Name, TermK, Terms, Term};
 
-//// typecheck and infer (by mutating `env`) choices and dimensions.
+/// typecheck and infer (by mutating `env`) choices and dimensions.
 pub fn check<'a>(terms: &Terms, env: &'a mut Env) -> (bool, &'a mut Env) {
     traverse(terms, (false, env), &check_pass)
 }
 fn check_pass<'a>(term: &Term, (mut err, env): (bool, &'a mut Env)) -> (bool, &'a mut Env) {
     match &term.node {
-        TermK::Text => {},
-        TermK::Var(name) => {
-            if !env.eflags().ignore_unset && !env.variables.contains_key(name) {
+        TermK::Text | TermK::Value(_) | TermK::Def { .. } | TermK::Comment => {},
+        TermK::Use { name, args } => err = check_use(env, name, args, term.span) || err,
+        TermK::Var(name, default) => {
+            env.used_vars.insert(*name);
+            let unset_ok = env.eflags().ignore_unset || env.eflags().partial;
+            // the variables of a named block are checked at each use, which can bind them
+            if default.is_none() && !unset_ok && !env.variables.contains_key(name) && !in_def(env, term.span) {
                 env.handler
                    .error(format!("Undeclared variable `{}`.", name).as_ref())
+                   .code(codes::UNDECLARED_VARIABLE)
                    .with_span(term.span)
                    .print();
                 err = true;
             } 
         },
-        TermK::Dimension { name, children } => match env.dimensions.get_mut(name) {
+        TermK::Dimension { name, children, default, .. } => {
+            let dn = env.aliases.get(name).unwrap_or(name);
+            env.used_dims.insert(*dn);
+            let count = match ChoiceIdx::count(children.len()) {
+                Some(n) => n,
+                None => {
+                    env.handler
+                        .error(format!("dimension `{}` has {} choices.", name, children.len()).as_ref())
+                        .code(codes::TOO_MANY_CHOICES)
+                        .with_span(term.span.subspan(0, name.len() - 1))
+                        .note(format!("a dimension has at most {} choices.", ChoiceIdx::MAX_CHOICES).as_ref())
+                        .print();
+                    return (true, env);
+                }
+            };
+            let partial = env.eflags().partial;
+            match env.dimensions.get_mut(dn) {
                 Some(d) => {
-                    if !d.try_set_dim(children.len() as i8) {
-                        error_size_conflict(&mut env.handler, name, term.span.subspan(0, name.len() - 1)).print();
+                    let inferred = d.has_been_inferred();
+                    let fits = match default {
+                        // the default branch stands for all the remaining choices
+                        Some(_) => d.choices.is_none_or(|c| count <= c),
+                        None => d.try_set_dim(count),
+                    };
+                    if fits && !inferred && d.has_been_inferred() {
+                        // @SAFETY unwrap(): dimension terms always have an opening delimiter
+                        env.first_uses.insert(*dn, term.opend_span().unwrap());
+                    }
+                    if !fits {
+                        let mut eb = error_size_conflict(&mut env.handler, name, term.span.subspan(0, name.len() - 1))
+                            .at_span(format!("with {} choices", children.len()).as_ref());
+                        eb = match env.first_uses.get(dn) {
+                            // @SAFETY unwrap(): `try_set_dim` only fails if the choices are known
+                            Some(first) => eb.label(*first, format!("first used here with {} choices", d.choices.unwrap()).as_ref()),
+                            None => eb.note(format!("`{}` is declared with {} choices.", dn, d.choices.unwrap()).as_ref()),
+                        };
+                        if dn != name {
+                            eb = eb.note(format!("`{}` is an alias of `{}`.", name, dn).as_ref());
+                        }
+                        if children.len() > 1 && children.last().is_some_and(Vec::is_empty) {
+                            eb = eb.note("its last choice is empty, is there a trailing `##`?");
+                        }
+                        eb.print();
                         err = true;
                     } 
                 }
+                None if partial => {}
                 None => {
                     env.handler
                         .error(format!("Unknown dimension `{}`.", name).as_ref())
+                        .code(codes::UNKNOWN_DIMENSION)
                         .with_span(term.opend_span().unwrap())
                         .note("Decision inference is not supported yet. This dimension requires a decision given explicitly.")
                         .note("Postponed dimension declaration (in source files) is not supported yet.")
+                        .suggest_closest(name.as_str(), env.dimensions.keys().chain(env.aliases.keys()).map(Name::as_str))
                         .print();
                     err = true;
                 }
+            }
+        }
+        TermK::Guard { cond, .. } => {
+            for c in cond.comparisons() {
+                if let Cond::Is { dim, .. } = c {
+                    env.used_dims.insert(*env.canonical(dim));
+                    if env.eflags().partial && env.get_dimension(dim).is_none() {
+                        continue;
+                    }
+                }
+                err = check_comparison(&mut env.handler, &env.dimensions, &env.labels, &env.aliases, c) || err;
+            }
         }
     }
     (err, env)
 }
 
-pub type DMap = HashMap<Name, u8>;
+/// whether `span` is in the body of one of the named blocks, see [`unify_labels`]
+fn in_def(env: &Env, span: Span) -> bool {
+    env.defs.values().any(|d| d.span.file == span.file && span.is_inbounds(d.span.lo, d.span.hi) && d.span != span)
+}
+
+/// the named block has to be defined without using itself, `args` have to be variables of its body, and the
+/// other variables of its body have to be declared. Returns whether there was an error.
+fn check_use(env: &mut Env, name: &Name, args: &[(Name, String)], span: Span) -> bool {
+    let body = match env.get_def(name) {
+        Some(body) => body,
+        None => {
+            env.handler
+                .error(format!("Unknown named block `{}`.", name).as_ref())
+                .code(codes::UNKNOWN_BLOCK)
+                .with_span(span)
+                .note(format!("named blocks are defined with `#def({}){{..}}#` in one of the sources.", name).as_ref())
+                .print();
+            return true;
+        }
+    };
+    // (variable, whether it has a default, where)
+    let vars = traverse(body, Vec::new(), &|t: &Term, mut vars: Vec<(Name, bool, Span)>| {
+        if let TermK::Var(n, default) = &t.node {
+            vars.push((*n, default.is_some(), t.span));
+        }
+        vars
+    });
+    let unbound: Vec<_> = args.iter().map(|(n, _)| n).filter(|n| !vars.iter().any(|v| v.0 == **n)).map(Name::as_str).collect();
+    let unset_ok = env.eflags().ignore_unset || env.eflags().partial;
+    let mut undeclared: Vec<(Name, Span)> = Vec::new();
+    for (n, has_default, at) in vars {
+        let bound = has_default || args.iter().any(|(a, _)| *a == n) || env.variables.contains_key(&n);
+        if !bound && !unset_ok && !undeclared.iter().any(|u| u.0 == n) {
+            undeclared.push((n, at));
+        }
+    }
+    let recursive = uses_def(env, name, name, &mut Vec::new());
+    let def = env.defs[name].span;
+    if recursive {
+        env.handler
+            .error(format!("named block `{}` uses itself.", name).as_ref())
+            .code(codes::RECURSIVE_BLOCK)
+            .with_span(span)
+            .label(def, "defined here")
+            .print();
+    }
+    if !unbound.is_empty() {
+        env.handler
+            .error(format!("named block `{}` has no variable {}.", name, unbound.join(", ")).as_ref())
+            .code(codes::INVALID_BLOCK)
+            .with_span(span)
+            .label(def, "defined here")
+            .note("the arguments of a use bind the variables of the block.")
+            .print();
+    }
+    for (n, at) in undeclared.iter() {
+        env.handler
+            .error(format!("Undeclared variable `{}`.", n).as_ref())
+            .code(codes::UNDECLARED_VARIABLE)
+            .with_span(span)
+            .label(*at, format!("used by named block `{}`", name).as_ref())
+            .note(format!("it has to be bound by the use, e.g. `#use({}, {}=..)#`, or declared.", name, n).as_ref())
+            .print();
+    }
+    recursive || !unbound.is_empty() || !undeclared.is_empty()
+}
+/// whether the named block `from` uses `target`, directly or through the blocks it uses
+fn uses_def(env: &Env, from: &Name, target: &Name, seen: &mut Vec<Name>) -> bool {
+    let used = match env.get_def(from) {
+        Some(body) => traverse(body, Vec::new(), &|t: &Term, mut used: Vec<Name>| {
+            if let TermK::Use { name, .. } = &t.node {
+                used.push(*name);
+            }
+            used
+        }),
+        None => return false,
+    };
+    for u in used {
+        if u == *target {
+            return true;
+        }
+        if !seen.contains(&u) {
+            seen.push(u);
+            if uses_def(env, &u, target, seen) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// the dimension of a guard's comparison has to be decided, and the choice has to be one of its choices.
+/// Returns whether there was an error.
+fn check_comparison(
+    handler: &mut Handler,
+    dimensions: &HashMap<Name, Dim>,
+    labels: &HashMap<Name, Vec<Option<Name>>>,
+    aliases: &HashMap<Name, Name>,
+    comparison: &Cond,
+) -> bool {
+    let (dim, choice, span) = match comparison {
+        Cond::Is { dim, choice, span, .. } => (dim, choice, *span),
+        _ => return false,
+    };
+    let dn = aliases.get(dim).unwrap_or(dim);
+    let d = match dimensions.get(dn) {
+        Some(d) => d,
+        None => {
+            handler
+                .error(format!("Unknown dimension `{}` in condition.", dim).as_ref())
+                .code(codes::UNKNOWN_DIMENSION)
+                .with_span(span)
+                .note("the dimensions of a condition need a decision, like the other dimensions.")
+                .suggest_closest(dim.as_str(), dimensions.keys().chain(aliases.keys()).map(Name::as_str))
+                .print();
+            return true;
+        }
+    };
+    let names = labels.get(dn).map(|ls| ls.iter().flatten().map(Name::as_str).collect::<Vec<_>>()).unwrap_or_default();
+    let valid = match choice {
+        Choice::Name(n) => names.contains(&n.as_str()),
+        Choice::Num(i) => d.choices.is_none_or(|c| i.0 < c),
+    };
+    if !valid {
+        let eb = handler
+            .error(format!("no choice `{}` in dimension `{}`.", choice, dim).as_ref())
+            .code(codes::UNKNOWN_CHOICE)
+            .with_span(span);
+        if names.is_empty() {
+            eb.print();
+        } else {
+            let eb = eb.note(format!("the choices of `{}` are: {}.", dim, names.join(", ")).as_ref());
+            match choice {
+                Choice::Name(n) => eb.suggest_closest(n.as_str(), names.iter().copied()).print(),
+                Choice::Num(_) => eb.print(),
+            }
+        }
+    }
+    !valid
+}
+
+/// whether the condition of a guard holds with the decisions of `env`.
+/// Unknown dimensions and choices are never equal, see [`check`].
+pub fn eval_cond(cond: &Cond, env: &Env) -> bool {
+    cond.eval(&|dim: &Name, choice: &Choice| match (env.get_dimension(dim), choice) {
+        (Some(d), Choice::Num(i)) => d.decision == *i,
+        (Some(d), Choice::Name(n)) => env
+            .labels
+            .get(env.canonical(dim))
+            .and_then(|ls| ls.get(d.decision.as_usize()))
+            .is_some_and(|l| l.as_ref() == Some(n)),
+        (None, _) => false,
+    })
+}
+
+pub type DMap = HashMap<Name, u16>;
 
 /// returns all the dimensions used and their size & report conflicts
 /// @REFACTOR merge with [`check`] ?
@@ -72,24 +287,285 @@ pub fn check_collect_pass<'a>(
         return (dims, err, env);
     }
     match &term.node {
-        TermK::Text | TermK::Var(_) => {}
-        TermK::Dimension { name, children } => {
-            match dims.get(name) {
+        TermK::Text | TermK::Var(..) | TermK::Value(_) | TermK::Guard { .. } | TermK::Def { .. } | TermK::Use { .. } | TermK::Comment => {}
+        TermK::Dimension { name, children, .. } => {
+            let name = env.canonical(name);
+            // @SAFETY unwrap(): `check_pass` reports dimensions with too many choices
+            dims.entry(*name).or_insert_with(|| ChoiceIdx::count(children.len()).unwrap());
+        }
+    }
+    (dims, err, env)
+}
+
+pub type VMap = HashMap<Name, Vec<Span>>;
+
+/// returns all the variables used and the spans of their uses, see [`check_collect`]
+pub fn collect_vars<'a>(terms: &Terms, vars: &'a mut VMap) -> &'a mut VMap {
+    traverse(terms, vars, &collect_vars_pass)
+}
+fn collect_vars_pass<'a>(term: &Term, vars: &'a mut VMap) -> &'a mut VMap {
+    if let TermK::Var(name, _) = &term.node {
+        vars.entry(*name).or_default().push(term.span);
+    }
+    vars
+}
+
+/// unifies the labels of the dimensions with the choice names of the config file and of their other uses,
+/// then decides the undeclared dimensions whose labels match a [`Pending`] decision.
+/// The named blocks are registered in [`Env::defs`] along the way.
+/// Has to run on all the trees before [`check`]. Returns whether there were errors.
+pub fn unify_labels<'a, It: IntoIterator<Item = &'a Terms>>(trees: It, env: &mut Env) -> bool {
+    let mut err = false;
+    for terms in trees {
+        err = traverse(terms, (false, &mut *env), &unify_pass).0 || err;
+    }
+    decide_pending(env) || err
+}
+fn unify_pass<'a>(term: &Term, (mut err, env): (bool, &'a mut Env)) -> (bool, &'a mut Env) {
+    let (name, labels) = match &term.node {
+        TermK::Def { name, .. } => return (define(env, name, term) || err, env),
+        TermK::Dimension { name, labels, .. } if !labels.is_empty() => (name, labels),
+        _ => return (err, env),
+    };
+    let dn = *env.canonical(name);
+    let known = env.labels.entry(dn).or_default();
+    for (i, label) in labels.iter().enumerate() {
+        let label = match label {
+            Some(l) => l,
+            None => continue,
+        };
+        let other = known.iter().position(|k| k.as_ref() == Some(label));
+        match known.get(i).cloned().flatten() {
+            Some(k) if &k == label => {}
+            Some(k) => {
+                env.handler
+                    .error(format!("Label `{}` of dimension `{}` conflicts with choice `{}`.", label, name, k).as_ref())
+                    .code(codes::LABEL_CONFLICT)
+                    .with_span(term.opend_span().unwrap())
+                    .note("choice names come from the config file, or from the labels of the other uses of the dimension.")
+                    .print();
+                err = true;
+            }
+            None if other.is_some() => {
+                env.handler
+                    .error(format!("Label `{}` of dimension `{}` is already the name of choice {}.", label, name, other.unwrap()).as_ref())
+                    .code(codes::LABEL_CONFLICT)
+                    .with_span(term.opend_span().unwrap())
+                    .print();
+                err = true;
+            }
+            None => {
+                if known.len() <= i {
+                    known.resize(i + 1, None);
+                }
+                known[i] = Some(*label);
+            }
+        }
+    }
+    (err, env)
+}
+/// registers the named block `name`, it can be used in all the sources. Returns whether it was already defined.
+fn define(env: &mut Env, name: &Name, term: &Term) -> bool {
+    match env.defs.get(name) {
+        Some(first) => {
+            let first = first.span;
+            env.handler
+                .error(format!("named block `{}` is defined twice.", name).as_ref())
+                .code(codes::DUPLICATE_BLOCK)
+                .with_span(term.span.subspan(0, 5 + name.len() as u64))
+                .label(first.subspan(0, 5 + name.len() as u64), "first defined here")
+                .print();
+            true
+        }
+        None => {
+            env.defs.insert(*name, term.clone());
+            false
+        }
+    }
+}
+/// decides the undeclared dimensions from their labels, see [`unify_labels`]
+fn decide_pending(env: &mut Env) -> bool {
+    let mut err = false;
+    let mut undecided: Vec<_> = env.labels.keys().filter(|dn| !env.dimensions.contains_key(*dn)).cloned().collect();
+    undecided.sort();
+    for dn in undecided {
+        let labels = &env.labels[&dn];
+        let position = |n: &Name| labels.iter().position(|l| l.as_ref() == Some(n));
+        let decision = match env.pending.pairs.get(&dn) {
+            Some(n) => match position(n) {
+                Some(i) => Some((i, Origin::Pair)),
                 None => {
-                    dims.insert(name.clone(), children.len() as u8);
+                    env.handler
+                        .error(format!("no choice `{}` in dimension `{}`.", n, dn).as_ref())
+                        .code(codes::UNKNOWN_CHOICE)
+                        .note(format!("the choices of `{}` are: {}.", dn, labels.iter().flatten().map(Name::as_str).collect::<Vec<_>>().join(", ")).as_ref())
+                        .suggest_closest(n.as_str(), labels.iter().flatten().map(Name::as_str))
+                        .print();
+                    err = true;
+                    None
+                }
+            },
+            None => {
+                let found: Vec<_> = labels.iter().flatten().filter(|l| env.pending.names.contains(*l)).collect();
+                match found.as_slice() {
+                    [] => None,
+                    [n] => position(n).map(|i| (i, Origin::Name)),
+                    _ => {
+                        let found: Vec<_> = found.iter().map(|s| s.as_str()).collect();
+                        env.handler
+                            .error(format!("the following choices are conflicting: {}", found.join(", ")).as_ref())
+                            .code(codes::CONFLICTING_DECISIONS)
+                            .print();
+                        err = true;
+                        None
+                    }
                 }
-                _ => {}
             }
+        };
+        // a label past the last index is on a dimension with too many choices, which `check` reports
+        if let Some((decision, origin)) = decision.and_then(|(i, o)| Some((ChoiceIdx::new(i)?, o))) {
+            env.dimensions.insert(dn, Dim { choices: None, decision, origin });
+        }
+    }
+    err
+}
+
+/// what the unset variable `name` at `span` is written as with `--ignore-unset`, see [`crate::cfg::Unset`]
+pub fn unset_value(env: &Env, name: &Name, span: Span) -> String {
+    use crate::cfg::Unset;
+    use crate::sourcemap::SourceInfo;
+    match env.eflags().unset {
+        Unset::Empty => String::new(),
+        Unset::Placeholder => format!("${{{}}}", name),
+        Unset::Verbatim => {
+            let file = env.handler.sources.lookup_span(span).map(|(f, _)| f);
+            let text = file.as_ref().and_then(|f| match &f.src {
+                SourceInfo::Source(s) => s.get(span.lo.as_usize()..span.hi.as_usize()),
+                _ => None,
+            });
+            // the terms made without a source, with the default delimiters
+            text.map_or_else(|| format!("#${}#", name), String::from)
+        }
+    }
+}
+
+/// replaces the dimensions by their chosen child, and the variables by their [`TermK::Value`].
+/// The result only contains [`TermK::Text`] and [`TermK::Value`] terms so writing it is a straight copy.  
+/// `terms` should have been [`check`]ed, unknown dimensions and variables are kept as is.
+pub fn resolve(terms: &Terms, env: &Env) -> Terms {
+    let mut resolved = Vec::with_capacity(terms.len());
+    resolve_into(terms, env, &[], None, &mut resolved);
+    resolved
+}
+/// the terms of a named block are resolved with the variables bound by `args`, and placed `at` its use.
+fn resolve_into(terms: &Terms, env: &Env, args: &[(Name, String)], at: Option<Span>, out: &mut Terms) {
+    for term in terms {
+        let span = at.unwrap_or(term.span);
+        match &term.node {
+            TermK::Text => match out.last_mut() {
+                // contiguous texts are merged
+                Some(last) if last.node == TermK::Text && last.span.hi == term.span.lo => {
+                    last.span.hi = term.span.hi
+                }
+                _ => out.push(term.clone()),
+            },
+            TermK::Value(v) => out.push(Term::value(v.clone(), span)),
+            TermK::Var(name, default) => {
+                let arg = args.iter().find(|(n, _)| n == name).map(|(_, v)| v);
+                match arg.or_else(|| env.get_var(name)).or(default.as_ref()) {
+                    Some(v) => out.push(Term::value(v.clone(), span)),
+                    None if env.eflags().ignore_unset => out.push(Term::value(unset_value(env, name, term.span), span)),
+                    None => out.push(Term { node: term.node.clone(), span }),
+                }
+            }
+            TermK::Dimension { name, children, default, .. } => {
+                let decision = env.get_dimension(name).map(|d| d.decision.as_usize());
+                match decision.and_then(|d| children.get(d).or(default.as_ref())) {
+                    Some(child) => resolve_into(child, env, args, at, out),
+                    None => out.push(Term { node: term.node.clone(), span }),
+                }
+            }
+            TermK::Guard { cond, then, otherwise } => {
+                if eval_cond(cond, env) {
+                    resolve_into(then, env, args, at, out)
+                } else if let Some(otherwise) = otherwise {
+                    resolve_into(otherwise, env, args, at, out)
+                }
+            }
+            TermK::Def { .. } | TermK::Comment => {}
+            TermK::Use { name, args } => match env.get_def(name) {
+                Some(body) => resolve_into(body, env, args, Some(span), out),
+                None => out.push(term.clone()),
+            },
+        }
+    }
+}
+
+/// replaces the decided dimensions by their chosen child, the guards over decided dimensions by their branch,
+/// and the set variables by their [`TermK::Value`], like [`resolve`]. The other terms are kept, so the result
+/// is the template left to decide, see `--partial`. The bodies of the named blocks keep their variables,
+/// the uses can bind them.
+pub fn specialize(terms: &Terms, env: &Env) -> Terms {
+    let mut out = Vec::with_capacity(terms.len());
+    specialize_into(terms, env, true, &mut out);
+    out
+}
+fn specialize_into(terms: &Terms, env: &Env, vars: bool, out: &mut Terms) {
+    let specialized = |ts: &Terms, vars: bool| {
+        let mut out = Vec::with_capacity(ts.len());
+        specialize_into(ts, env, vars, &mut out);
+        out
+    };
+    for term in terms {
+        match &term.node {
+            TermK::Var(name, _) if vars => match env.get_var(name) {
+                Some(v) => out.push(Term::value(v.clone(), term.span)),
+                None => out.push(term.clone()),
+            },
+            TermK::Dimension { name, children, labels, default } => {
+                let decision = env.get_dimension(name).map(|d| d.decision.as_usize());
+                match decision.and_then(|d| children.get(d).or(default.as_ref())) {
+                    Some(child) => specialize_into(child, env, vars, out),
+                    None => out.push(Term::dim(
+                        *name,
+                        children.iter().map(|c| specialized(c, vars)).collect(),
+                        labels.clone(),
+                        default.as_ref().map(|d| specialized(d, vars)),
+                        term.span,
+                    )),
+                }
+            }
+            TermK::Guard { cond, then, otherwise } => {
+                let decided = cond.comparisons().into_iter().all(|c| match c {
+                    Cond::Is { dim, .. } => env.get_dimension(dim).is_some(),
+                    _ => true,
+                });
+                if !decided {
+                    let node = TermK::Guard {
+                        cond: cond.clone(),
+                        then: specialized(then, vars),
+                        otherwise: otherwise.as_ref().map(|o| specialized(o, vars)),
+                    };
+                    out.push(Term { node, span: term.span });
+                } else if eval_cond(cond, env) {
+                    specialize_into(then, env, vars, out)
+                } else if let Some(otherwise) = otherwise {
+                    specialize_into(otherwise, env, vars, out)
+                }
+            }
+            TermK::Def { name, body } => {
+                out.push(Term { node: TermK::Def { name: *name, body: specialized(body, false) }, span: term.span })
+            }
+            TermK::Text | TermK::Value(_) | TermK::Var(..) | TermK::Use { .. } | TermK::Comment => out.push(term.clone()),
         }
     }
-    (dims, err, env)
 }
 
 /// helper for dimension size conflicts errors
-fn error_size_conflict<'a>(handler: &'a mut Handler, name: &String, span: Span) -> ErrorBuilder<'a> {
-    // @TODO get span of declaration or previous use
+fn error_size_conflict<'a>(handler: &'a mut Handler, name: &Name, span: Span) -> ErrorBuilder<'a> {
     handler
         .error(format!("Conflicting number of choices for dimension `{}`.", name).as_ref())
+        .code(codes::SIZE_CONFLICT)
         .with_span(span)
 }
 
@@ -99,11 +575,17 @@ where F : Fn(&Term, T) -> T {
     for term in terms {
         acc = transform(term, acc);
         match &term.node {
-            TermK::Dimension { children , .. } => {
-                for child in children {
+            TermK::Dimension { children, default, .. } => {
+                for child in children.iter().chain(default) {
                     acc = traverse(child, acc,  transform);
                 }
             } 
+            TermK::Guard { then, otherwise, .. } => {
+                for child in std::iter::once(then).chain(otherwise) {
+                    acc = traverse(child, acc, transform);
+                }
+            }
+            TermK::Def { body, .. } => acc = traverse(body, acc, transform),
             _ => {}
         }
     }
@@ -115,11 +597,17 @@ where F : Fn(&mut Term, T) -> T {
     for term in terms {
         acc = transform(term, acc);
         match &mut term.node {
-            TermK::Dimension { children , .. } => {
-                for child in children {
+            TermK::Dimension { children, default, .. } => {
+                for child in children.iter_mut().chain(default) {
                     acc = traverse_mut(child, acc,  transform);
                 }
             } 
+            TermK::Guard { then, otherwise, .. } => {
+                for child in std::iter::once(then).chain(otherwise) {
+                    acc = traverse_mut(child, acc, transform);
+                }
+            }
+            TermK::Def { body, .. } => acc = traverse_mut(body, acc, transform),
             _ => {}
         }
     }
@@ -1,8 +1,4 @@
-#![feature( format_args_nl
-          , new_uninit
-          , option_result_contains
-          , type_ascription
-          )]
+#![forbid(unstable_features)]
 
 #[macro_use]
 pub mod utils;
@@ -13,13 +9,19 @@ pub mod error;
 pub mod cfg;
 
 pub mod driver;
+#[doc(inline)]
+pub use driver::render;
 
 pub mod infer;
 #[doc(inline)]
 pub use infer::env;
 
+pub mod lsp;
+
 pub mod output;
 
+pub mod prelude;
+
 pub mod sourcemap;
 
 pub mod syntax;
@@ -0,0 +1,383 @@
+//! `flan lsp`: a language server for the templates, on stdin and stdout.
+//!
+//! Each open document is parsed and checked alone against the config file whenever it changes, the others aren't
+//! touched. Its diagnostics are published like the ones `flan check` prints, except that the declared dimensions
+//! without a decision aren't errors while editing (see [`crate::cfg::ErrorFlags::partial`]). The hovers show the
+//! choices and the decision of a dimension or the value of a variable, and the definitions of both are their
+//! declarations in the config file.
+//!
+//! The documents are synchronized in full, and the positions are in the UTF-8, UTF-32 or UTF-16 code units the
+//! client supports, in that order of preference.
+pub mod rpc;
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+
+use crate::cfg::{self, Choices};
+use crate::driver::{file_to_parser_with, make_env, pp_dim_provenance};
+use crate::error::{codes, Error, Handler, Level, MemorySink};
+use crate::infer::{self, Env};
+use crate::sourcemap::{BytePos, Destination, File, SrcFile, SrcMap, Span};
+use crate::syntax::cond::Cond;
+use crate::syntax::{Name, Term, TermK, Terms};
+use rpc::*;
+
+/// the code units of the columns of the positions
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Encoding {
+    Utf8,
+    Utf16,
+    Utf32,
+}
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf16 => "utf-16",
+            Encoding::Utf32 => "utf-32",
+        }
+    }
+    fn units(self, c: char) -> usize {
+        match self {
+            Encoding::Utf8 => c.len_utf8(),
+            Encoding::Utf16 => c.len_utf16(),
+            Encoding::Utf32 => 1,
+        }
+    }
+    /// the first of the `positionEncodings` of the client capabilities we prefer, UTF-16 if there is none
+    fn negotiate(capabilities: &Value) -> Self {
+        let offered = capabilities["general"]["positionEncodings"].as_array().cloned().unwrap_or_default();
+        [Encoding::Utf8, Encoding::Utf32]
+            .iter()
+            .copied()
+            .find(|e| offered.iter().any(|o| o.as_str() == Some(e.name())))
+            .unwrap_or(Encoding::Utf16)
+    }
+}
+
+/// an open document, as last parsed and checked
+struct Document {
+    version: Value,
+    file: SrcFile,
+    tree: Option<Terms>,
+    /// `None` if the document or the config file doesn't check
+    env: Option<Env>,
+}
+
+/// the state of `flan lsp` between the messages
+pub struct Server {
+    config: cfg::Config,
+    eflags: cfg::ErrorFlags,
+    /// the files of the config file, the analysis of each document adds it to a fork of it
+    sources: Arc<SrcMap>,
+    documents: HashMap<String, Document>,
+    encoding: Encoding,
+    shutdown: bool,
+}
+
+/// serves the requests read from `from` until the `exit` notification or the end of the input.
+/// Returns whether the client shut the server down first.
+pub fn serve<R: BufRead, W: Write>(flags: &cfg::Flags, config: cfg::Config, from: &mut R, to: &mut W) -> io::Result<bool> {
+    let mut server = Server::new(flags, config);
+    while let Some(msg) = read_message(from)? {
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(reply) => {
+                write_message(to, &reply)?;
+                continue;
+            }
+        };
+        if msg["method"] == "exit" {
+            break;
+        }
+        for reply in server.handle(&msg) {
+            write_message(to, &reply)?;
+        }
+    }
+    Ok(server.shutdown)
+}
+
+impl Server {
+    pub fn new(flags: &cfg::Flags, mut config: cfg::Config) -> Self {
+        let sources = SrcMap::new();
+        config.load_into(&sources);
+        let eflags = cfg::ErrorFlags { partial: true, ..flags.eflags };
+        Server { config, eflags, sources, documents: HashMap::new(), encoding: Encoding::Utf16, shutdown: false }
+    }
+    /// the responses and notifications to `msg`
+    pub fn handle(&mut self, msg: &Value) -> Vec<Value> {
+        let (id, method, params) = (&msg["id"], msg["method"].as_str().unwrap_or_default(), &msg["params"]);
+        let is_request = !id.is_null();
+        if self.shutdown && is_request {
+            return vec![error_response(id, INVALID_REQUEST, "the server is shut down.")];
+        }
+        match method {
+            "initialize" => {
+                self.encoding = Encoding::negotiate(&params["capabilities"]);
+                let capabilities = json!({
+                    "positionEncoding": self.encoding.name(),
+                    "textDocumentSync": { "openClose": true, "change": 1 },
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                });
+                let info = json!({ "name": "flan", "version": env!("CARGO_PKG_VERSION") });
+                vec![response(id, json!({ "capabilities": capabilities, "serverInfo": info }))]
+            }
+            "shutdown" => {
+                self.shutdown = true;
+                vec![response(id, Value::Null)]
+            }
+            "textDocument/didOpen" => {
+                let doc = &params["textDocument"];
+                self.update(doc["uri"].as_str(), doc["version"].clone(), doc["text"].as_str())
+            }
+            "textDocument/didChange" => {
+                let doc = &params["textDocument"];
+                // the whole text, see `textDocumentSync`
+                let text = params["contentChanges"].as_array().and_then(|c| c.last()?["text"].as_str());
+                self.update(doc["uri"].as_str(), doc["version"].clone(), text)
+            }
+            "textDocument/didClose" => match params["textDocument"]["uri"].as_str() {
+                Some(uri) => {
+                    self.documents.remove(uri);
+                    vec![notification("textDocument/publishDiagnostics", json!({ "uri": uri, "diagnostics": [] }))]
+                }
+                None => vec![],
+            },
+            "textDocument/hover" => vec![response(id, self.hover(params).unwrap_or(Value::Null))],
+            "textDocument/definition" => vec![response(id, self.definition(params).unwrap_or(Value::Null))],
+            _ if is_request => vec![error_response(id, METHOD_NOT_FOUND, &format!("`{}` isn't supported.", method))],
+            // e.g. `initialized`
+            _ => vec![],
+        }
+    }
+    /// parses and checks the new `text` of the document, and publishes its diagnostics
+    fn update(&mut self, uri: Option<&str>, version: Value, text: Option<&str>) -> Vec<Value> {
+        let (uri, text) = match (uri, text) {
+            (Some(u), Some(t)) => (u, t),
+            _ => return vec![],
+        };
+        let (doc, errors) = self.analyze(uri, version, text.into());
+        let diagnostics: Vec<_> = errors.iter().map(|e| self.diagnostic(e, &doc.file)).collect();
+        let params = json!({ "uri": uri, "version": doc.version, "diagnostics": diagnostics });
+        self.documents.insert(uri.into(), doc);
+        vec![notification("textDocument/publishDiagnostics", params)]
+    }
+    /// the document and its diagnostics
+    fn analyze(&self, uri: &str, version: Value, text: String) -> (Document, Vec<Error>) {
+        let path = document_path(uri);
+        let sources = self.sources.fork();
+        let file = sources.load_source(path, Destination::InMemory, text);
+        let sink = MemorySink::new();
+        let mut h = Handler::with_sink(self.eflags, sources, Box::new(sink.clone()));
+        let delims = self.config.syntax.delimiters_of(&file.path);
+        let tree = file_to_parser_with(&mut h, &file, delims).and_then(|mut p| p.parse().ok());
+        h.print_all();
+        let env = match &tree {
+            Some(tree) => make_env(&self.config, h).ok().map(|mut env| {
+                if !infer::unify_labels(std::iter::once(tree), &mut env) {
+                    infer::check(tree, &mut env);
+                    undeclared(tree, &mut env, &self.config);
+                }
+                env.handler.print_all();
+                env
+            }),
+            None => None,
+        };
+        // the diagnostics of the config file are `flan check`'s
+        let errors = sink.errors().into_iter().filter(|e| e.span().file == file.id).collect();
+        (Document { version, file, tree, env }, errors)
+    }
+    fn diagnostic(&self, err: &Error, file: &File) -> Value {
+        let severity = match err.level() {
+            Level::Fatal | Level::Error => 1,
+            Level::Warning => 2,
+            Level::Note => 3,
+            Level::More => 4,
+        };
+        let mut message = String::from(err.msg());
+        for extra in err.extra() {
+            message.push('\n');
+            message.push_str(extra);
+        }
+        let related: Vec<_> = err
+            .labels()
+            .iter()
+            .filter_map(|l| {
+                let src = l.src()?;
+                let uri = if src.id == file.id { self.uri_of(file) } else { path_to_uri(&src.path) };
+                Some(json!({ "location": { "uri": uri, "range": self.range(src, l.span) }, "message": l.msg }))
+            })
+            .collect();
+        json!({
+            "range": self.range(file, err.span()),
+            "severity": severity,
+            "code": err.code().map(|c| c.to_string()),
+            "source": "flan",
+            "message": message,
+            "relatedInformation": related,
+        })
+    }
+    /// the choices and decision of the dimension at the position, or the value of the variable
+    fn hover(&self, params: &Value) -> Option<Value> {
+        let (doc, pos) = self.document_at(params)?;
+        let (tree, env) = (doc.tree.as_ref()?, doc.env.as_ref()?);
+        let term = term_at(tree, pos)?;
+        let (text, span) = match &term.node {
+            TermK::Dimension { name, children, .. } => {
+                let dn = env.canonical(name);
+                let (choices, declared) = match self.config.dimensions.get(dn.as_str()) {
+                    Some(ch) => (ch.clone(), true),
+                    None => (Choices::Size(children.len() as u16), false),
+                };
+                let mut text = pp_dim_provenance(dn, &choices, declared, env.get_dimension(dn));
+                if dn != name {
+                    text.push_str(&format!("\n`{}` is an alias of `{}`", name, dn));
+                }
+                // @SAFETY unwrap(): dimension terms always have an opening delimiter
+                (text, term.opend_span().unwrap())
+            }
+            TermK::Var(name, default) => {
+                let text = match (env.get_var(name), default) {
+                    (Some(v), _) => format!("var {} = {:?}", name, v),
+                    (None, Some(d)) => format!("var {} unset, defaults to {:?}", name, d),
+                    (None, None) => format!("var {} unset", name),
+                };
+                (text, term.span)
+            }
+            _ => return None,
+        };
+        let contents = json!({ "kind": "markdown", "value": format!("```\n{}\n```", text) });
+        Some(json!({ "contents": contents, "range": self.range(&doc.file, span) }))
+    }
+    /// the declaration in the config file of the variable or dimension at the position
+    fn definition(&self, params: &Value) -> Option<Value> {
+        let (doc, pos) = self.document_at(params)?;
+        let term = term_at(doc.tree.as_ref()?, pos)?;
+        let key = match &term.node {
+            TermK::Var(name, _) => format!("variables.{}", name),
+            TermK::Dimension { name, .. } if self.config.aliases.contains_key(name.as_str()) => format!("aliases.{}", name),
+            TermK::Dimension { name, .. } => format!("dimensions.{}", name),
+            _ => return None,
+        };
+        let span = self.config.span(&key)?;
+        let file = self.sources.get(span.file)?;
+        Some(json!({ "uri": path_to_uri(&file.path), "range": self.range(&file, span) }))
+    }
+    /// the open document of `params.textDocument` and the offset of `params.position` in it
+    fn document_at(&self, params: &Value) -> Option<(&Document, BytePos)> {
+        let doc = self.documents.get(params["textDocument"]["uri"].as_str()?)?;
+        let (line, character) = (params["position"]["line"].as_u64()?, params["position"]["character"].as_u64()?);
+        let pos = self.offset(&doc.file, line as usize, character as usize)?;
+        Some((doc, pos))
+    }
+    fn uri_of(&self, file: &File) -> String {
+        self.documents
+            .iter()
+            .find(|(_, d)| d.file.path == file.path)
+            .map_or_else(|| path_to_uri(&file.path), |(uri, _)| uri.clone())
+    }
+    /// the position of `pos` in `file`: its line from 0, and its column in [`Self::encoding`] units
+    fn position(&self, file: &File, pos: BytePos) -> Value {
+        match file.lookup_line(pos) {
+            Some(loc) => {
+                let rel = (pos - loc.span.lo).as_usize();
+                let column: usize =
+                    loc.line.char_indices().take_while(|(i, _)| *i < rel).map(|(_, c)| self.encoding.units(c)).sum();
+                json!({ "line": loc.index, "character": column })
+            }
+            None => json!({ "line": 0, "character": 0 }),
+        }
+    }
+    fn range(&self, file: &File, span: Span) -> Value {
+        if span.is_nil() {
+            return json!({ "start": self.position(file, BytePos(0)), "end": self.position(file, BytePos(0)) });
+        }
+        json!({ "start": self.position(file, span.lo), "end": self.position(file, span.hi) })
+    }
+    /// inverse of [`Self::position`], a column past the end of the line is its end
+    fn offset(&self, file: &File, line: usize, character: usize) -> Option<BytePos> {
+        let start = *file.lines.get(line)?;
+        let text = file.get_loc(line)?;
+        let mut units = 0;
+        for (i, c) in text.char_indices() {
+            if units >= character {
+                return Some(start + BytePos::from(i));
+            }
+            units += self.encoding.units(c);
+        }
+        Some(start + BytePos::from(text.len()))
+    }
+}
+
+/// the path of the document relative to the working directory, which is the one of `flan`
+fn document_path(uri: &str) -> PathBuf {
+    let path = uri_to_path(uri).unwrap_or_else(|| PathBuf::from(uri));
+    match std::env::current_dir() {
+        Ok(dir) => path.strip_prefix(&dir).map(Path::to_path_buf).unwrap_or(path),
+        Err(_) => path,
+    }
+}
+
+/// the innermost dimension, variable, guard or named block containing `pos`
+fn term_at(terms: &Terms, pos: BytePos) -> Option<&Term> {
+    let t = terms.iter().find(|t| t.span.lo <= pos && pos < t.span.hi)?;
+    let inner = match &t.node {
+        TermK::Dimension { children, default, .. } => children.iter().chain(default).find_map(|c| term_at(c, pos)),
+        TermK::Guard { then, otherwise, .. } => term_at(then, pos).or_else(|| term_at(otherwise.as_ref()?, pos)),
+        TermK::Def { body, .. } => term_at(body, pos),
+        _ => None,
+    };
+    match &t.node {
+        TermK::Text | TermK::Value(_) | TermK::Comment => inner,
+        _ => inner.or(Some(t)),
+    }
+}
+
+/// the undeclared variables and unknown dimensions, which [`infer::check`] doesn't report with
+/// [`cfg::ErrorFlags::partial`] since they could be decided
+fn undeclared(terms: &Terms, env: &mut Env, config: &cfg::Config) {
+    infer::traverse(terms, env, &|t: &Term, env: &mut Env| {
+        match &t.node {
+            TermK::Var(name, None) if !env.eflags().ignore_unset && env.get_var(name).is_none() => {
+                env.handler
+                    .error(format!("Undeclared variable `{}`.", name).as_ref())
+                    .code(codes::UNDECLARED_VARIABLE)
+                    .with_span(t.span)
+                    .print();
+            }
+            TermK::Dimension { name, .. } if !is_declared(env, config, name) => {
+                env.handler
+                    .error(format!("Unknown dimension `{}`.", name).as_ref())
+                    .code(codes::UNKNOWN_DIMENSION)
+                    // @SAFETY unwrap(): dimension terms always have an opening delimiter
+                    .with_span(t.opend_span().unwrap())
+                    .print();
+            }
+            TermK::Guard { cond, .. } => {
+                for c in cond.comparisons() {
+                    if let Cond::Is { dim, span, .. } = c {
+                        if !is_declared(env, config, dim) {
+                            env.handler
+                                .error(format!("Unknown dimension `{}`.", dim).as_ref())
+                                .code(codes::UNKNOWN_DIMENSION)
+                                .with_span(*span)
+                                .print();
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        env
+    });
+}
+
+/// decided, or declared in the config file
+fn is_declared(env: &Env, config: &cfg::Config, dim: &Name) -> bool {
+    env.get_dimension(dim).is_some() || config.dimensions.contains_key(env.canonical(dim).as_str())
+}
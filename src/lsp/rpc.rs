@@ -0,0 +1,108 @@
+//! the JSON-RPC messages of the language server protocol, each after a `Content-Length` header.
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+/// error codes of the responses
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+
+/// longest body read, the `Content-Length` of a message comes from the client
+pub const MAX_CONTENT_LENGTH: usize = 64 << 20;
+
+/// the next message, `None` at the end of the input. A body which isn't JSON or is longer than
+/// [`MAX_CONTENT_LENGTH`] is skipped, `Err` is then the error response to send back
+pub fn read_message<R: BufRead>(from: &mut R) -> io::Result<Option<Result<Value, Value>>> {
+    let mut len = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if from.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                len = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let len = match len {
+        Some(l) => l,
+        None => return Err(io::Error::new(io::ErrorKind::InvalidData, "a message has no `Content-Length`.")),
+    };
+    if len > MAX_CONTENT_LENGTH {
+        io::copy(&mut from.take(len as u64), &mut io::sink())?;
+        let msg = format!("the message is longer than {} bytes.", MAX_CONTENT_LENGTH);
+        return Ok(Some(Err(error_response(&Value::Null, INVALID_REQUEST, &msg))));
+    }
+    let mut body = vec![0; len];
+    from.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body).map_err(|_| error_response(&Value::Null, PARSE_ERROR, "the message isn't JSON."))))
+}
+
+pub fn write_message<W: Write>(to: &mut W, msg: &Value) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(to, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    to.flush()
+}
+
+pub fn response(id: &Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+pub fn error_response(id: &Value, code: i64, msg: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": msg } })
+}
+
+pub fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+/// the path of a `file://` URI, `None` for the other schemes
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    // the authority is empty or `localhost`
+    let path = path.strip_prefix("localhost").unwrap_or(path);
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(std::str::from_utf8(h).ok()?, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    let path = String::from_utf8(decoded).ok()?;
+    // `/C:/dir` on windows
+    if cfg!(windows) && path.as_bytes().get(2) == Some(&b':') {
+        return Some(PathBuf::from(&path[1..]));
+    }
+    Some(PathBuf::from(path))
+}
+
+/// the `file://` URI of `path`, which is made absolute
+pub fn path_to_uri(path: &Path) -> String {
+    let path = std::env::current_dir().map_or_else(|_| path.to_path_buf(), |d| d.join(path));
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::from(if path.starts_with('/') { "file://" } else { "file:///" });
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' | b':' => uri.push(b as char),
+            _ => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    uri
+}
@@ -1,105 +1,394 @@
-#![feature(type_ascription)]
-#![feature(option_result_contains)]
-#![feature(result_flattening)]
-#![feature(format_args_nl)]
+#![forbid(unstable_features)]
 
 use std::sync::Arc;
 
 use flan::{emit_error};
-use flan::cfg::Command;
+use flan::cfg::{Command, Timings};
+use flan::cfg::matrix::path_to_matrix;
 #[allow(unused_imports)]
-use flan::error::Handler;
-use flan::infer;
+use flan::error::{codes, ExitCode, Handler, WarnKind};
+use flan::infer::FileEnvs;
+use flan::output::{Output, Throttle};
+use flan::sourcemap::{Destination, FileKind, SrcFile, SrcMap};
+use flan::syntax::terms_mem_size;
 
 fn main() {
     use flan::driver::*;
+    use flan::driver::metrics::Metrics;
+    use flan::driver::progress::Progress;
     let mut metrics = Metrics::new();
 
-    let (flags, config) = match mk_cfgflags() {
-        Ok(f) => f,
+    let (flags, mut config) = mk_cfgflags().unwrap_or_else(|e| exit_cfg_error(e));
+    let flags = Arc::new(flags);
+    metrics.verbose(flags.timings == Timings::Verbose);
+
+    if flags.command == Command::Explain {
+        // @SAFETY unwrap(): the command is only set if a code is given
+        let code = flags.explain.as_ref().unwrap();
+        match codes::explain(code) {
+            Some(s) => print!("{}", s),
+            None => {
+                emit_error!(code = codes::INVALID_ARGUMENTS; "`{}` isn't a diagnostic code, they look like `F0203`.", code);
+                ExitCode::Config.exit();
+            }
+        }
+        ExitCode::Success.exit();
+    }
+    if flags.command == Command::Escape || flags.command == Command::Unescape {
+        // @SAFETY unwrap(): the command is only set if one of the paths is given
+        let path = flags.escape.as_ref().unwrap();
+        match escape_file(path, flags.command == Command::Unescape) {
+            Ok(s) => print!("{}", s),
+            Err(e) => {
+                emit_error!(code = codes::LOAD_FAILED; "couldn't load `{}`:\n  {}", path.display(), e);
+                ExitCode::IO.exit();
+            }
+        }
+        ExitCode::Success.exit();
+    }
+    if let Command::Fmt { check } = flags.command {
+        let mut failed = false;
+        let (source_map, sources) = if flags.fmt.is_empty() {
+            load_sources(flags.as_ref(), config.paths.iter(), &config.filters, &config.file_kinds)
+        } else {
+            let map = SrcMap::new();
+            let mut sources = vec![];
+            for path in flags.fmt.iter() {
+                match map.load_file_as(path.clone(), path.clone().into(), config.file_kinds.kind(path)) {
+                    Ok(f) => sources.push(f),
+                    Err(e) => {
+                        emit_error!(code = codes::LOAD_FAILED; "couldn't load `{}`:\n  {}", path.display(), e);
+                        failed = true;
+                    }
+                }
+            }
+            (map, sources)
+        };
+        let mut h = Handler::new(flags.eflags, source_map);
+        check_syntax(&config, &mut h);
+        h.abort_if_err();
+        let sources = sources.into_iter().filter(|f| f.is_source()).collect();
+        let (trees, _) = parse_sources_timed(sources, &mut h, &config.syntax, |_, _| {});
+        let unformatted = match fmt(&trees, &config.syntax, check) {
+            Ok(files) => files,
+            Err(e) => {
+                emit_error!("couldn't format the sources:\n  {}", e);
+                ExitCode::IO.exit();
+            }
+        };
+        if check {
+            unformatted.iter().for_each(|p| println!("{}", p.display()));
+        }
+        h.abort_if_err();
+        if failed {
+            ExitCode::IO.exit();
+        }
+        if check && !unformatted.is_empty() {
+            ExitCode::Failure.exit();
+        }
+        ExitCode::Success.exit();
+    }
+    if flags.command == Command::DebugLex || flags.command == Command::DebugAst {
+        // @SAFETY unwrap(): the command is only set if a path is given
+        let path = flags.debug.as_ref().unwrap();
+        let map = SrcMap::new();
+        let file = match map.load_file_as(path.clone(), Destination::Stdout, FileKind::Text) {
+            Ok(f) => f,
+            Err(e) => {
+                emit_error!(code = codes::LOAD_FAILED; "couldn't load `{}`:\n  {}", path.display(), e);
+                ExitCode::IO.exit();
+            }
+        };
+        let mut h = Handler::new(flags.eflags, map);
+        check_syntax(&config, &mut h);
+        h.abort_if_err();
+        let delims = config.syntax.delimiters_of(path);
+        let out = if flags.command == Command::DebugAst {
+            debug_terms(&mut h, &file, delims)
+        } else {
+            debug_tokens(&mut h, &file, delims)
+        };
+        if let Some(out) = out {
+            print!("{}", out);
+        }
+        h.print_all();
+        h.abort_if_err();
+        ExitCode::Success.exit();
+    }
+
+    if flags.command == Command::Test {
+        let mut h = Handler::new(flags.eflags, SrcMap::new());
+        check_syntax(&config, &mut h);
+        h.abort_if_err();
+        if let Some(name) = flags.tests.iter().find(|n| !config.tests.contains_key(n.as_str())) {
+            emit_error!(code = codes::INVALID_ARGUMENTS; "no test `{}` in `[tests]`.", name);
+            ExitCode::Config.exit();
+        }
+        let (mut passed, mut failed) = (0, 0);
+        for (name, test) in config.tests.iter().filter(|(n, _)| flags.tests.is_empty() || flags.tests.contains(n)) {
+            match run_test(&flags, &config, name, test) {
+                TestOutcome::Passed => {
+                    passed += 1;
+                    println!("test {} ... ok", name);
+                }
+                TestOutcome::Failed(diff) => {
+                    failed += 1;
+                    println!("test {} ... FAILED\n--- expected\n+++ {}\n{}", name, test.template.display(), diff);
+                }
+                TestOutcome::Error(errors) => {
+                    failed += 1;
+                    println!("test {} ... FAILED", name);
+                    errors.iter().for_each(|e| eprintln!("{}", e));
+                }
+            }
+        }
+        println!("\n{} passed, {} failed.", passed, failed);
+        if failed > 0 {
+            ExitCode::Failure.exit();
+        }
+        ExitCode::Success.exit();
+    }
+    if let (Command::Snapshots, Some(snap)) = (flags.command, &flags.snapshots) {
+        let mut h = Handler::new(flags.eflags, SrcMap::new());
+        check_syntax(&config, &mut h);
+        h.abort_if_err();
+        let src = match std::fs::read_to_string(&snap.template) {
+            Ok(src) => src,
+            Err(e) => {
+                emit_error!(code = codes::LOAD_FAILED; "couldn't load `{}`:\n  {}", snap.template.display(), e);
+                ExitCode::IO.exit();
+            }
+        };
+        let matrix = match file_variants(&flags, &config, &snap.template, &src) {
+            Ok(m) => m,
+            Err(errors) => {
+                errors.iter().for_each(|e| eprintln!("{}", e));
+                ExitCode::Failure.exit();
+            }
+        };
+        if matrix.variants.len() > snap.limit {
+            emit_error!(code = codes::INVALID_ARGUMENTS;
+                "`{}` has {} combinations of decisions, more than the limit of {}.", snap.template.display(), matrix.variants.len(), snap.limit);
+            ExitCode::Config.exit();
+        }
+        if let Err(e) = std::fs::create_dir_all(&snap.out) {
+            emit_error!(code = codes::LOAD_FAILED; "couldn't create `{}`:\n  {}", snap.out.display(), e);
+            ExitCode::IO.exit();
+        }
+        let ext = snap.template.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+        let mut failed = 0;
+        for variant in matrix.variants.values() {
+            let to = snap.out.join(format!("{}{}", snapshot_name(variant), ext));
+            match snapshot(&flags, &config, &snap.template, &src, variant) {
+                Ok(out) => {
+                    if let Err(e) = std::fs::write(&to, out) {
+                        emit_error!(code = codes::LOAD_FAILED; "couldn't write `{}`:\n  {}", to.display(), e);
+                        ExitCode::IO.exit();
+                    }
+                    println!("{}", to.display());
+                }
+                Err(errors) => {
+                    failed += 1;
+                    eprintln!("`{}` doesn't render:", to.display());
+                    errors.iter().for_each(|e| eprintln!("{}", e));
+                }
+            }
+        }
+        if failed > 0 {
+            ExitCode::Failure.exit();
+        }
+        ExitCode::Success.exit();
+    }
+    if flags.command == Command::Lsp {
+        let mut h = Handler::new(flags.eflags, SrcMap::new());
+        check_syntax(&config, &mut h);
+        h.abort_if_err();
+        let (stdin, stdout) = (std::io::stdin(), std::io::stdout());
+        match flan::lsp::serve(&flags, config, &mut stdin.lock(), &mut stdout.lock()) {
+            Ok(true) => ExitCode::Success.exit(),
+            // exited without a `shutdown`
+            Ok(false) => ExitCode::Failure.exit(),
+            Err(e) => {
+                emit_error!(code = codes::LOAD_FAILED; "couldn't talk to the client:\n  {}", e);
+                ExitCode::IO.exit();
+            }
+        }
+    }
+
+    let matrix = match flags.matrix.as_ref().map(path_to_matrix).transpose() {
+        Ok(m) => m,
         Err(e) => {
-            // @IMPROVEMENT error handling
-            eprintln!("fatal error:");
-            eprintln!("{}", e);
-            std::process::exit(FAILURE);
+            // @SAFETY unwrap(): only fails if a path was given
+            emit_error!(code = codes::LOAD_FAILED; "couldn't load matrix `{}`:\n  {}", flags.matrix.as_ref().unwrap().display(), e);
+            ExitCode::Config.exit();
         }
     };
-    let flags = Arc::new(flags);
 
-    let (source_map, sources) = load_sources(flags.as_ref(), config.paths.iter());
+    let mut h = Handler::new(flags.eflags, SrcMap::new());
+    check_output_flags(&flags, &mut h);
+    h.abort_if_err();
+
+    let (source_map, sources) = load_sources(flags.as_ref(), config.paths.iter(), &config.filters, &config.file_kinds);
+    config.load_into(&source_map);
     metrics.total_files(sources.len() as isize);
 
     let start = Instant::now();
     let mut hp = Handler::new(flags.eflags, source_map.clone());
-    if sources.len() == 0 {
-        hp.warn("no paths given")
+    if sources.is_empty() {
+        hp.warn(WarnKind::NoPaths, "no paths given")
             .note("see `[paths]` section in the configuration file")
             .print();
-        std::process::exit(SUCCESS);
+        ExitCode::Success.exit();
     }
-    let (trees, bins) = parse_sources(sources, &mut hp);
+    check_overlapping_paths(flags.as_ref(), config.paths.iter(), &mut hp);
+    check_destinations(&sources, &mut hp);
+    check_syntax(&config, &mut hp);
+    hp.abort_if_err();
+    let mut progress = Progress::new(&flags);
+    progress.start("parse", sources.len());
+    let (trees, bins) = parse_sources_timed(sources, &mut hp, &config.syntax, |f, d| {
+        metrics.record_parse(f, d);
+        progress.step(&f.path);
+    });
+    progress.finish();
     metrics.front(start);
+    metrics.mem_sources(source_map.mem_size());
+    metrics.mem_trees(trees.iter().map(|(_, t)| terms_mem_size(t)).sum());
+
+    if flags.command == Command::Matrix {
+        if !run_hooks(&config, HookStage::Pre, &mut hp) {
+            hp.abort();
+        }
+        // the command is only set by a matrix file or `--all-variants`
+        let matrix = matrix.unwrap_or_else(|| match labeled_env(&config, &trees, Handler::new(flags.eflags, source_map.clone())) {
+            Ok(env) => all_variants(&trees, &env, &config),
+            Err(mut h) => h.abort(),
+        });
+        let throttle = Throttle::new(flags.max_open_files);
+        let out = open_output(&flags);
+        let start = Instant::now();
+        let mk_handler = || Handler::new(flags.eflags, source_map.clone());
+        let (written, copied) = match render_matrix(&flags, &config, &matrix, (&trees, &bins), mk_handler, &out, &throttle) {
+            Ok(counts) => counts,
+            Err(MatrixError { variant, error: VariantError::Decisions(e), .. }) => {
+                emit_error!(code = codes::INVALID_ARGUMENTS; "invalid decisions for variant `{}`:\n  {}", variant, e);
+                ExitCode::Config.exit();
+            }
+            Err(MatrixError { error: VariantError::Diagnostics(mut h), .. }) => h.abort(),
+            Err(MatrixError { variant, error: VariantError::IO(e), written }) => {
+                emit_error!("couldn't render variant `{}`:\n  {}", variant, e);
+                partial_or_io(written > 0).exit();
+            }
+        };
+        if let Err(e) = out.finish() {
+            emit_error!(code = codes::LOAD_FAILED; "couldn't write the archive:\n  {}", e);
+            partial_or_io(written + copied > 0).exit();
+        }
+        if !run_hooks(&config, HookStage::Post, &mut hp) {
+            hp.abort();
+        }
+        metrics.processed(written as isize);
+        metrics.copied(copied as isize);
+        metrics.end(start);
+        metrics.finish(&flags, true);
+        ExitCode::Success.exit();
+    }
 
     let start = Instant::now();
-    let he = Handler::new(flags.eflags, source_map.clone());
-    let mut env = match make_env(&config, he) {
-        Err(mut he) => he.abort(),
-        Ok(e) => e,
-    };
+    let mk_handler = || Handler::new(flags.eflags, source_map.clone());
+    let mut env = decided_env(flags.as_ref(), &mut config, &trees, mk_handler).unwrap_or_else(|mut h| h.abort());
+    let (files, targets) = override_envs(flags.as_ref(), &config, &trees, &mut env, mk_handler).unwrap_or_else(|mut h| h.abort());
 
     // infer/check dimensions and collect+report if needed.
     if flags.command == Command::Query {
-        let terms = &mut trees.iter().map(|t| &t.1);
-        for (dim, ch) in collect_dims(terms, &mut env, &config.dimensions) {
-            println!("{}", pp_dim(&dim, &ch));
-        }
-    } else if trees.iter()
-                   .fold(false, |acc, (_, tree )| {
-                        infer::check(tree, &mut env).0 || acc }) {
+        print!("{}", pp_query(&query_dims(&trees, &mut env, &config.dimensions, flags.query_scope), flags.query_format));
+    } else if flags.command == Command::QueryVars {
+        print!("{}", pp_query_vars(&query_vars(&trees, &env, &config, flags.query_scope), flags.query_format));
+    } else if flags.command == Command::Graph {
+        print!("{}", pp_graph(&graph(&trees, &mut env, &config), flags.graph_format));
+    } else if check_trees(flags.as_ref(), &config, &trees, &files, &mut env, |f, d| metrics.record_check(&f.path, d)) {
         env.handler.abort();
     }
     metrics.infer(start);
 
     hp.abort_if_err();
-    if flags.command == Command::Query || flags.command == Command::DryRun {
+    let trees = if flags.eflags.partial { specialize_trees(trees, &env, &config.syntax) } else { trees };
+    let trees = if flags.headers && !flags.eflags.partial { inject_headers(trees, &env, &config) } else { trees };
+    if let Command::Clean { dry_run } = flags.command {
+        match clean(&flags, &trees, &bins, &env, &mut hp) {
+            Ok(removed) => {
+                hp.abort_if_err();
+                if dry_run {
+                    removed.iter().for_each(|p| println!("{}", p.display()));
+                }
+            }
+            Err(e) => {
+                emit_error!(code = codes::LOAD_FAILED; "couldn't remove the destinations:\n  {}", e);
+                ExitCode::IO.exit();
+            }
+        }
+        ExitCode::Success.exit();
+    }
+    if [Command::Query, Command::QueryVars, Command::Graph, Command::DryRun, Command::Check].contains(&flags.command) {
         // @TODO --dry-run
-        metrics.report();
-        std::process::exit(SUCCESS);
+        // the graph is piped to graphviz
+        metrics.finish(&flags, flags.command != Command::Graph);
+        ExitCode::Success.exit();
     }
     let start = Instant::now();
+    if !run_hooks(&config, HookStage::Pre, &mut hp) {
+        hp.abort();
+    }
+    let lock = flags.lock_file.as_ref().map(|_| env_to_lock(&env, &config));
+    let env = FileEnvs { base: env, files };
     // the most important point about spawning these threads is to capture panics
     // without paying the cost of `catch_unwind`
     // @TODO we need better error reporting inside, because panic! adds useless and
     //       ugly stuff to the error message.
+    let throttle = Arc::new(Throttle::new(flags.max_open_files));
+    let out = Arc::new(open_output(&flags));
     let flags_ = flags.clone();
+    let throttle_ = throttle.clone();
+    let out_ = out.clone();
     let write_th = std::thread::spawn(move || {
-        let mut count = 0;
-        // @TODO driver::write_files?
-        for (source, tree) in &trees {
-            match write(flags_.as_ref(), source.clone(), &tree, &env) {
-                Err(e) => panic!("io {}", e),
-                Ok(_) => count += 1,
-            }
+        let mut writes = Vec::new();
+        progress.start("write", trees.len() + targets.len());
+        let mut record = |f: &SrcFile, d, n| {
+            writes.push((f.path.clone(), d, n));
+            progress.step(&f.path);
+        };
+        let written = write_files(flags_.as_ref(), &trees, &env, &out_, &throttle_, &mut record)
+            .and_then(|count| Ok(count + write_targets(flags_.as_ref(), &trees, &targets, &out_, &throttle_, &mut record)?));
+        progress.finish();
+        let mapped = match &flags_.origin_map {
+            Some(map) => written.and_then(|count| write_origin_map(map, &trees, &env).map(|_| count)),
+            None => written,
+        };
+        match mapped {
+            Err(e) => panic!("io {}", e),
+            Ok(count) => (count as isize, writes),
         }
-        count
     });
     let flags_ = flags.clone();
+    let throttle_ = throttle.clone();
+    let out_ = out.clone();
     let bin_th = std::thread::spawn(move || {
-        let mut count = 0;
-        for bin in bins {
-            match copy_bin(flags_.as_ref(), bin.clone()) {
-                Err(e) => panic!("io {}", e),
-                Ok(_) => count += 1,
-            }
+        match copy_bins(flags_.as_ref(), bins, &out_, &throttle_) {
+            Err(e) => panic!("io {}", e),
+            Ok(count) => count as isize,
         }
-        count
     });
     match write_th.join() {
         Err(_) => {
             emit_error!("@TODO: cleanup resources");
             metrics.processed(-1)
         }
-        Ok(n) => metrics.processed(n),
+        Ok((n, writes)) => {
+            metrics.processed(n);
+            for (path, time, bytes) in writes {
+                metrics.record_write(&path, time, bytes);
+            }
+        }
     }
     match bin_th.join() {
         Err(_) => {
@@ -109,94 +398,56 @@ fn main() {
         Ok(n) => metrics.copied(n),
     }
     metrics.end(start);
-    if !flags.stdin.is_some() {
-        metrics.report();
+    let mut exit_code = match (metrics.proc_f, metrics.copy_f) {
+        (0.., 0..) => ExitCode::Success,
+        (n, m) => partial_or_io(n > 0 || m > 0),
+    };
+    if let Err(e) = out.finish() {
+        emit_error!(code = codes::LOAD_FAILED; "couldn't write the archive:\n  {}", e);
+        exit_code = ExitCode::IO;
     }
-}
-
-use std::time::{Duration, Instant};
-struct Metrics {
-    /// processed file count
-    pub proc_f: isize,
-    /// copied file count
-    pub copy_f: isize,
-    /// total file count (in paths)
-    pub total_f: isize,
-
-    /// start time of the program
-    start: Instant,
-    /// frontend duration
-    pub front: Duration,
-    /// typechecking/inference duration
-    pub infer: Duration,
-    /// backend duration
-    pub end: Duration,
-
-    /// total time
-    pub total: Duration,
-}
-impl Metrics {
-    pub fn new() -> Self {
-        Self {
-            proc_f: -1,
-            copy_f: -1,
-            total_f: 0,
-            start: Instant::now(),
-            front: Duration::ZERO,
-            infer: Duration::ZERO,
-            end:   Duration::ZERO,
-            total: Duration::ZERO,
+    if let (Some(path), Some(lock)) = (flags.lock_file.as_ref(), lock) {
+        if exit_code == ExitCode::Success {
+            if let Err(e) = write_lock(path, &lock) {
+                emit_error!(code = codes::LOAD_FAILED; "couldn't write the lockfile `{}`:\n  {}", path.display(), e);
+                exit_code = ExitCode::Partial;
+            }
         }
     }
-    pub fn total_files(&mut self, total_files: isize) {
-        self.total_f = total_files;
-    }
-    pub fn processed(&mut self, processed: isize) {
-        self.proc_f = processed
-    }
-    pub fn copied(&mut self, copied: isize) {
-        self.copy_f = copied;
-    }
-    pub fn front(&mut self, start: Instant) {
-        self.front = start.elapsed();
-    }
-    pub fn infer(&mut self, start: Instant) {
-        self.infer = start.elapsed();
-    }
-    pub fn end(&mut self, start: Instant) {
-        self.end = start.elapsed();
+    if exit_code == ExitCode::Success && !run_hooks(&config, HookStage::Post, &mut hp) {
+        exit_code = ExitCode::IO;
     }
-    pub fn report(&mut self) {
-        self.total = self.start.elapsed();
-        println!("\n");
-        self.report_files();
-        self.report_time();
+    if throttle.waits() > 0 {
+        hp.note(&format!(
+            "output was throttled {} times to stay under {} open files.",
+            throttle.waits(),
+            throttle.max_open_files()
+        ))
+        .suggest("raise `--max-open-files` (or `max-open-files` in `[options]`) if the system allows it.")
+        .print();
     }
-    pub fn report_files(&self) {
-        let any = self.proc_f >= 0 || self.copy_f >= 0;
-        if self.total_f >= 0 {
-            print!("{}", self.total_f);
-            if any {
-                let p = isize::max(self.proc_f, 0);
-                let b = isize::max(self.copy_f, 0);
-                print!("[{}+{}]", p, b);
-            }
-            println!(" file{}.", if self.total_f > 1 { "s" } else { "" });
+    metrics.finish(&flags, !flags.to_stdout());
+    exit_code.exit();
+}
+
+/// [`Output::new`], exits on errors
+fn open_output(flags: &flan::cfg::Flags) -> Output {
+    match Output::new(flags) {
+        Ok(out) => out,
+        Err(e) => {
+            emit_error!(code = codes::LOAD_FAILED; "couldn't create the archive:\n  {}", e);
+            ExitCode::IO.exit();
         }
     }
-    pub fn report_time(&self) {
-        println!("Total time: {}ms.", self.total.as_millis());
-        if !self.front.is_zero() {
-            println!(" ` front:  {}ms", self.front.as_millis());
-        }
-        if !self.infer.is_zero() {
-            println!(" ` infer:  {}ms", self.infer.as_millis());
-        }
-        if !self.end.is_zero() {
-            println!(" ` output: {}ms", self.end.as_millis());
-        }
+}
+
+/// some destinations were written before the failure, or none
+fn partial_or_io(written: bool) -> ExitCode {
+    if written {
+        ExitCode::Partial
+    } else {
+        ExitCode::IO
     }
 }
 
-const SUCCESS: i32 = 0;
-const FAILURE: i32 = 0x100;
+use std::time::Instant;
@@ -0,0 +1,288 @@
+//! `--out-archive`: the destinations are written as the entries of a tar or zip archive instead of files.
+//!
+//! * the entries aren't compressed: the zip entries are stored, so no compression library is needed, and a
+//!   `.tar.gz` would be bigger than the `.tar`
+//! * the modification times are zeroed (1980-01-01 in zip), two runs with the same outputs give the same entries
+//! * the names are the destinations relative to `--out-prefix`, they can't be absolute nor contain `..`
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Component, Path};
+use std::sync::Mutex;
+
+/// the format of an archive, from the extension of its path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Tar,
+    Zip,
+}
+impl Format {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".tar") {
+            Some(Format::Tar)
+        } else if name.ends_with(".zip") {
+            Some(Format::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// an entry of an archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entry<'a> {
+    /// the contents of a file and its unix permissions
+    File(&'a [u8], u32),
+    /// a symbolic link to the path, see [`crate::sourcemap::SourceInfo::Symlink`]
+    Symlink(&'a Path),
+}
+
+/// an archive being written, shared by the writers and copiers.
+/// [`Archive::finish`] writes the end of the archive, it's truncated without it.
+pub struct Archive {
+    format: Format,
+    inner: Mutex<Inner>,
+}
+struct Inner {
+    out: Counted<Box<dyn Write + Send>>,
+    names: HashSet<String>,
+    /// (name, crc, size, mode, offset) of the zip entries, for the central directory
+    zip: Vec<(String, u32, u32, u32, u32)>,
+    finished: bool,
+}
+
+impl Archive {
+    /// creates the archive at `path`, `force` overwrites an existing file. see [`Format::from_path`]
+    pub fn create(path: &Path, force: bool) -> io::Result<Self> {
+        let format = Format::from_path(path).ok_or_else(|| {
+            let msg = format!("`{}` isn't a `.tar` or `.zip` archive.", path.display());
+            io::Error::new(io::ErrorKind::InvalidInput, msg)
+        })?;
+        if !force && path.exists() {
+            let msg = format!("error: file `{}` already exists. [use --force to overwrite]", path.display());
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, msg));
+        }
+        super::create_parent(path)?;
+        let file = io::BufWriter::new(fs::File::create(path)?);
+        Self::new(format, Box::new(file))
+    }
+    /// an archive written to `out`
+    pub fn new(format: Format, out: Box<dyn Write + Send>) -> io::Result<Self> {
+        let inner = Inner { out: Counted { out, count: 0 }, names: HashSet::new(), zip: Vec::new(), finished: false };
+        Ok(Archive { format, inner: Mutex::new(inner) })
+    }
+    pub fn format(&self) -> Format {
+        self.format
+    }
+    /// adds `entry` as `name`, see [`entry_name`]. A name can only be added once.
+    pub fn add(&self, name: &Path, entry: Entry) -> io::Result<()> {
+        let name = entry_name(name)?;
+        // @SAFETY unwrap(): the writers don't panic while holding the lock
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.names.insert(name.clone()) {
+            let msg = format!("`{}` is written twice to the archive.", name);
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, msg));
+        }
+        match self.format {
+            Format::Tar => write_tar_entry(&mut inner.out, &name, entry),
+            Format::Zip => {
+                let offset = u32_or_err(inner.out.count)?;
+                let (crc, size, mode) = write_zip_entry(&mut inner.out, &name, entry)?;
+                inner.zip.push((name, crc, size, mode, offset));
+                Ok(())
+            }
+        }
+    }
+    /// writes the end of the archive and flushes it, the next calls do nothing
+    pub fn finish(&self) -> io::Result<()> {
+        // @SAFETY unwrap(): see [`Archive::add`]
+        let mut inner = self.inner.lock().unwrap();
+        if inner.finished {
+            return Ok(());
+        }
+        inner.finished = true;
+        let Inner { out, zip, .. } = &mut *inner;
+        match self.format {
+            Format::Tar => out.write_all(&[0; 2 * BLOCK])?,
+            Format::Zip => write_zip_directory(out, zip)?,
+        }
+        out.flush()
+    }
+}
+
+/// the name of the entry written for the destination `path`: its components joined with `/`.
+/// absolute paths and `..` are rejected, `.` is skipped.
+pub fn entry_name(path: &Path) -> io::Result<String> {
+    let mut parts = Vec::new();
+    for c in path.components() {
+        match c {
+            Component::Normal(s) => parts.push(s.to_string_lossy().into_owned()),
+            Component::CurDir => {}
+            _ => {
+                let msg = format!("destination `{}` can't be placed in the archive.", path.display());
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            }
+        }
+    }
+    if parts.is_empty() {
+        let msg = format!("destination `{}` can't be placed in the archive.", path.display());
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    }
+    Ok(parts.join("/"))
+}
+
+fn u32_or_err(n: u64) -> io::Result<u32> {
+    u32::try_from(n).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "the zip archive is over 4GiB."))
+}
+
+/// counts the bytes written, for the offsets of the zip entries
+struct Counted<W> {
+    out: W,
+    count: u64,
+}
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.out.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/* tar */
+
+const BLOCK: usize = 512;
+
+/// writes the ustar header of `name` and the contents padded to [`BLOCK`]
+fn write_tar_entry<W: Write>(out: &mut W, name: &str, entry: Entry) -> io::Result<()> {
+    let mut header = [0u8; BLOCK];
+    // names over 100 bytes are split at a `/` into the prefix and the name
+    let (prefix, name) = match name.len() {
+        0..=100 => ("", name),
+        _ => match name.char_indices().rev().find(|&(i, c)| c == '/' && i <= 155 && name.len() - i - 1 <= 100) {
+            Some((i, _)) => (&name[..i], &name[i + 1..]),
+            None => {
+                let msg = format!("the name `{}` is too long for a tar archive.", name);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            }
+        },
+    };
+    let (typeflag, mode, contents, link): (u8, u32, &[u8], &[u8]) = match entry {
+        Entry::File(contents, mode) => (b'0', mode, contents, b""),
+        Entry::Symlink(target) => (b'2', 0o777, b"", target.to_str().unwrap_or_default().as_bytes()),
+    };
+    if link.len() > 100 {
+        let msg = format!("the link target of `{}` is too long for a tar archive.", name);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    }
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    octal(&mut header[100..108], (mode & 0o7777) as u64);
+    octal(&mut header[108..116], 0);
+    octal(&mut header[116..124], 0);
+    octal(&mut header[124..136], contents.len() as u64);
+    octal(&mut header[136..148], 0);
+    header[148..156].copy_from_slice(b"        ");
+    header[156] = typeflag;
+    header[157..157 + link.len()].copy_from_slice(link);
+    header[257..265].copy_from_slice(b"ustar\x0000");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    let sum: u32 = header.iter().map(|&b| b as u32).sum();
+    octal(&mut header[148..155], sum as u64);
+    out.write_all(&header)?;
+    out.write_all(contents)?;
+    let pad = (BLOCK - contents.len() % BLOCK) % BLOCK;
+    out.write_all(&[0; BLOCK][..pad])
+}
+
+/// `n` as zero-padded octal digits ended by a NUL
+fn octal(field: &mut [u8], n: u64) {
+    let digits = format!("{:0width$o}", n, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()] = 0;
+}
+
+/* zip */
+
+/// 1980-01-01 00:00, the earliest DOS date
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+/// writes the local header of `name` and the stored contents, returns (crc, size, mode)
+fn write_zip_entry<W: Write>(out: &mut W, name: &str, entry: Entry) -> io::Result<(u32, u32, u32)> {
+    let (contents, mode) = match entry {
+        Entry::File(contents, mode) => (contents, 0o100000 | (mode & 0o7777)),
+        // the target is the contents of a link, with its file type in the unix attributes
+        Entry::Symlink(target) => (target.to_str().unwrap_or_default().as_bytes(), 0o120777),
+    };
+    let size = u32_or_err(contents.len() as u64)?;
+    let crc = crc32(0, contents);
+    out.write_all(&0x04034b50u32.to_le_bytes())?;
+    out.write_all(&10u16.to_le_bytes())?;
+    // utf-8 names
+    out.write_all(&0x0800u16.to_le_bytes())?;
+    out.write_all(&[0, 0, 0, 0])?;
+    out.write_all(&DOS_DATE.to_le_bytes())?;
+    out.write_all(&crc.to_le_bytes())?;
+    out.write_all(&size.to_le_bytes())?;
+    out.write_all(&size.to_le_bytes())?;
+    out.write_all(&(name.len() as u16).to_le_bytes())?;
+    out.write_all(&[0, 0])?;
+    out.write_all(name.as_bytes())?;
+    out.write_all(contents)?;
+    Ok((crc, size, mode))
+}
+
+fn write_zip_directory<W: Write>(out: &mut Counted<W>, entries: &[(String, u32, u32, u32, u32)]) -> io::Result<()> {
+    let count = u16::try_from(entries.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "the zip archive has over 65535 entries."))?;
+    let start = u32_or_err(out.count)?;
+    for (name, crc, size, mode, offset) in entries {
+        out.write_all(&0x02014b50u32.to_le_bytes())?;
+        // made by unix, so the permissions are read
+        out.write_all(&((3u16 << 8) | 10).to_le_bytes())?;
+        out.write_all(&10u16.to_le_bytes())?;
+        out.write_all(&0x0800u16.to_le_bytes())?;
+        out.write_all(&[0, 0, 0, 0])?;
+        out.write_all(&DOS_DATE.to_le_bytes())?;
+        out.write_all(&crc.to_le_bytes())?;
+        out.write_all(&size.to_le_bytes())?;
+        out.write_all(&size.to_le_bytes())?;
+        out.write_all(&(name.len() as u16).to_le_bytes())?;
+        // extra, comment, disk, internal attributes
+        out.write_all(&[0; 8])?;
+        out.write_all(&(mode << 16).to_le_bytes())?;
+        out.write_all(&offset.to_le_bytes())?;
+        out.write_all(name.as_bytes())?;
+    }
+    let size = u32_or_err(out.count)? - start;
+    out.write_all(&0x06054b50u32.to_le_bytes())?;
+    out.write_all(&[0, 0, 0, 0])?;
+    out.write_all(&count.to_le_bytes())?;
+    out.write_all(&count.to_le_bytes())?;
+    out.write_all(&size.to_le_bytes())?;
+    out.write_all(&start.to_le_bytes())?;
+    out.write_all(&[0, 0])
+}
+
+/// CRC-32 (IEEE) of `bytes`, continuing from `crc`
+pub fn crc32(crc: u32, bytes: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut c = i as u32;
+            let mut k = 0;
+            while k < 8 {
+                c = if c & 1 == 1 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+                k += 1;
+            }
+            table[i] = c;
+            i += 1;
+        }
+        table
+    };
+    !bytes.iter().fold(!crc, |c, &b| TABLE[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8))
+}
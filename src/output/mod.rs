@@ -1,64 +1,195 @@
+pub mod archive;
+pub mod origins;
+pub mod throttle;
+
 use std::fs;
 use std::io;
-use std::io::{BufRead, Write};
+use std::io::{BufRead, IoSlice, Write};
+use std::ops::Range;
+use std::path::Path;
 
-use crate::cfg;
-use crate::infer::Env;
-use crate::sourcemap::SrcFile;
+use crate::cfg::{self, Newline};
+use crate::sourcemap::{Destination, SourceInfo, SrcFile};
 use crate::syntax::{Term, TermK, Terms};
 
-/// write multiple terms to the output.  
-/// This will modify the ReadCtx to start span of each term.
+#[doc(inline)]
+pub use throttle::Throttle;
+
+/// write multiple terms to the output, `terms` have to be [`crate::infer::resolve`]d.  
+/// This will modify the ReadCtx to start span of each term.  
+/// The output is batched, [`WriteCtx::flush`] has to be called once everything is written.
 #[inline]
-pub fn write_terms<'a, R, W>(from: &mut ReadCtx<'a, R>, to: &mut WriteCtx<'a, W>, env: &Env, terms: &Terms) 
+pub fn write_terms<'a, R, W>(from: &mut ReadCtx<'a, R>, to: &mut WriteCtx<'a, W>, terms: &'a Terms) 
     -> io::Result<()> 
 where R : BufRead, W: Write {
     for t in terms {
         let off = t.span.lo.as_usize() - from.pos;
         from.consume(off);
         // @TODO check how much has been written?
-        write_term(from, to, env, t)?;
+        write_term(from, to, t)?;
         // @TODO maybe it would be better to set `from.pos` to `t.span.hi` after the call
     }
     Ok(())
 }
 
-/// writes one term.  
+/// writes one resolved term: a text is copied from the source, a value is written as is.  
 /// this won't mutate [`ReadCtx::pos`] if not needed.  
 /// @TODO maybe for consistency and better usage, we could set `from.pos` to `term.span.hi`
-pub fn write_term<'a, R, W>(from: &mut ReadCtx<'a, R>, to: &mut WriteCtx<'a, W>, env: &Env, term: &Term) 
+pub fn write_term<'a, R, W>(from: &mut ReadCtx<'a, R>, to: &mut WriteCtx<'a, W>, term: &'a Term) 
     -> io::Result<()> 
 where R: BufRead, W: Write {
     // can we keep panics here? normally everything should be fine after typechecking
-    // @TODO use write_vectored?
     match &term.node {
         TermK::Text => { pipe(from, to, term.span.len()) }
-        TermK::Var(name) => match env.get_var(name) {
-            Some(v) => {
-                to.write(v.as_bytes())?;
-                Ok(())
+        TermK::Value(v) => to.write_value(v.as_bytes()),
+        TermK::Def { .. } | TermK::Comment => Ok(()),
+        TermK::Var(name, _) => panic!("fatal write error: var `{}` not found", name),
+        TermK::Dimension { name, .. } => panic!("fatal write error: dim `{}` not decided", name),
+        TermK::Use { name, .. } => panic!("fatal write error: named block `{}` not found", name),
+        TermK::Guard { .. } => panic!("fatal write error: unresolved guard"),
+    }
+}
+
+/// where the destinations are written: files, or the entries of an `--out-archive`.
+/// stdout and in-memory destinations are written the same way to both.
+pub enum Output {
+    Files,
+    Archive(archive::Archive),
+}
+impl Output {
+    /// creates the `--out-archive` if given, see [`archive::Archive::create`]
+    pub fn new(flags: &cfg::Flags) -> io::Result<Self> {
+        match &flags.out_archive {
+            Some(path) => Ok(Output::Archive(archive::Archive::create(path, flags.force)?)),
+            None => Ok(Output::Files),
+        }
+    }
+    /// the archive and the name of the entry of `dest`, relative to `--out-prefix`, if it's written to an archive
+    pub fn entry<'a>(&'a self, flags: &cfg::Flags, dest: &'a Destination) -> Option<(&'a archive::Archive, &'a Path)> {
+        match (self, dest) {
+            (Output::Archive(a), Destination::Path(d)) => {
+                let name = flags.out_prefix.as_ref().and_then(|p| d.strip_prefix(p).ok()).unwrap_or(d);
+                Some((a, name))
             }
-            None if env.eflags().ignore_unset => Ok(()), // @FIXME verify if correct
-            None => panic!("fatal write error: var `{}` not found", name),
-        },
-        TermK::Dimension { name, children } => match env.get_dimension(name) {
-            Some(dim) => match children.get(dim.decision as usize) {
-                Some(child) => write_terms(from, to, env, child),
-                None => panic!("fatal write error: OOB decision for `{}`", name),
-            },
-            None => panic!("fatal write error: dim `{}` not found", name),
-        },
+            _ => None,
+        }
+    }
+    /// [`copy_bin_to`], or adds the file to the archive
+    pub fn copy_bin_to(&self, flags: &cfg::Flags, file: SrcFile, dest: &Destination) -> io::Result<()> {
+        match (self.entry(flags, dest), &file.src) {
+            (Some((a, name)), SourceInfo::Symlink(target)) => a.add(name, archive::Entry::Symlink(target)),
+            (Some((a, name)), _) => a.add(name, archive::Entry::File(&fs::read(&file.path)?, file_mode(&file.path)?)),
+            (None, _) => copy_bin_to(flags, file, dest),
+        }
+    }
+    /// ends the archive, see [`archive::Archive::finish`]
+    pub fn finish(&self) -> io::Result<()> {
+        match self {
+            Output::Files => Ok(()),
+            Output::Archive(a) => a.finish(),
+        }
     }
 }
 
+/// the permissions of the entry of `path` in an archive
+#[cfg(unix)]
+pub fn file_mode(path: &Path) -> io::Result<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(fs::metadata(path)?.permissions().mode() & 0o7777)
+}
+#[cfg(not(unix))]
+pub fn file_mode(path: &Path) -> io::Result<u32> {
+    let readonly = fs::metadata(path)?.permissions().readonly();
+    Ok(if readonly { 0o444 } else { 0o644 })
+}
+
 pub fn copy_bin(flags: &cfg::Flags, file: SrcFile) -> io::Result<()> {
-    if !flags.force && file.destination.exists() {
+    copy_bin_to(flags, file.clone(), &file.destination)
+}
+
+/// same as [`copy_bin`] but to `dest` instead of the file's destination.
+pub fn copy_bin_to(flags: &cfg::Flags, file: SrcFile, dest: &Destination) -> io::Result<()> {
+    let dest = match dest {
+        Destination::Path(dest) => dest,
+        Destination::Stdout => {
+            io::copy(&mut fs::File::open(&file.path)?, &mut io::stdout())?;
+            return Ok(());
+        }
+        Destination::InMemory => return Ok(()),
+    };
+    if let SourceInfo::Symlink(target) = &file.src {
+        return copy_link(flags, target, dest);
+    }
+    if flags.if_changed && dest.is_file() {
+        let mut new = io::BufReader::with_capacity(flags.io_buffer_size, fs::File::open(&file.path)?);
+        let mut old = io::BufReader::with_capacity(flags.io_buffer_size, fs::File::open(dest)?);
+        if same_contents(&mut new, &mut old)? {
+            return Ok(());
+        }
+    } else if !flags.force && dest.exists() {
         return Ok(());
     }
-    fs::copy(&file.path, &file.destination)?;
+    create_parent(dest)?;
+    fs::copy(&file.path, dest)?;
     Ok(())
 }
 
+/// creates a symbolic link to `target` at `dest`, see [`SourceInfo::Symlink`].
+/// An existing destination is replaced with `--force`, or with `--if-changed` if it isn't the same link.
+fn copy_link(flags: &cfg::Flags, target: &Path, dest: &Path) -> io::Result<()> {
+    // `exists` would follow the link
+    if let Ok(meta) = dest.symlink_metadata() {
+        let same = meta.file_type().is_symlink() && fs::read_link(dest)? == target;
+        if same || !(flags.force || flags.if_changed) {
+            return Ok(());
+        }
+        if meta.is_dir() {
+            fs::remove_dir_all(dest)?;
+        } else {
+            fs::remove_file(dest)?;
+        }
+    }
+    create_parent(dest)?;
+    symlink(target, dest)
+}
+#[cfg(unix)]
+fn symlink(target: &Path, dest: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, dest)
+}
+#[cfg(windows)]
+fn symlink(target: &Path, dest: &Path) -> io::Result<()> {
+    // relative targets are relative to the link
+    match dest.parent().unwrap_or(Path::new("")).join(target).is_dir() {
+        true => std::os::windows::fs::symlink_dir(target, dest),
+        false => std::os::windows::fs::symlink_file(target, dest),
+    }
+}
+
+/// whether `a` and `b` have the same bytes, read until one of them differs.  
+/// comparing the bytes is as cheap as hashing both sides, and can't collide.
+pub fn same_contents<A: BufRead, B: BufRead>(a: &mut A, b: &mut B) -> io::Result<bool> {
+    loop {
+        let (x, y) = (a.fill_buf()?, b.fill_buf()?);
+        if x.is_empty() || y.is_empty() {
+            return Ok(x.is_empty() && y.is_empty());
+        }
+        let n = usize::min(x.len(), y.len());
+        if x[..n] != y[..n] {
+            return Ok(false);
+        }
+        a.consume(n);
+        b.consume(n);
+    }
+}
+
+/// creates the missing directories leading to `dest`, e.g. for the sub-directories of a walked source.
+pub fn create_parent(dest: &Path) -> io::Result<()> {
+    match dest.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => fs::create_dir_all(parent),
+        _ => Ok(()),
+    }
+}
+
 /// a wrapper around [`BufRead`].  
 /// To avoid copies we use [`BufRead::fill_buf`] but this means we have to keep
 /// track ourselves of the position in the source file.  
@@ -84,31 +215,169 @@ impl<'a, R : BufRead> ReadCtx<'a, R> {
             Ok(&buf[..len])
         }
     }
+    /// skips `len` bytes, or up to the end of the reader.  
+    /// [`BufRead::consume`] can't skip more than what is buffered, so the buffer is refilled as needed.
     #[inline]
-    pub(self) fn consume(&mut self, len:usize) {
-        self.inner.consume(len);
-        self.pos += len;
+    pub(self) fn consume(&mut self, mut len: usize) {
+        while len > 0 {
+            let n = match self.inner.fill_buf() {
+                Ok([]) => break,
+                Ok(buf) => usize::min(buf.len(), len),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                // @TODO report it, the next read will fail anyway
+                Err(_) => break,
+            };
+            self.inner.consume(n);
+            self.pos += n;
+            len -= n;
+        }
     }
 }
 
 /// a wrapper around [`Write`].  
-/// for future use
+/// The text and the values of the variables are batched, and written with a single
+/// [`Write::write_vectored`] when there are enough of them, or on [`Self::flush`].
+/// The text is copied since the reader's buffer is reused, the values are borrowed.  
+/// With a [`Newline`] other than [`Newline::Preserve`], the line endings are rewritten while copying, see [`Self::newline`].
 pub struct WriteCtx<'a, W : Write> {
     inner: &'a mut W, 
+    /// pending segments, in order
+    segments: Vec<Segment<'a>>,
+    /// the pending text, see [`Segment::Text`]
+    text: Vec<u8>,
+    /// bytes written to `inner`, see [`Self::written`]
+    written: usize,
+    newline: Newline,
+    /// see [`Self::vectored`]
+    vectored: bool,
+    /// the last byte given was a `\r`, a `\r\n` can be split between two writes
+    cr: bool,
+}
+enum Segment<'a> {
+    /// range of [`WriteCtx::text`]
+    Text(Range<usize>),
+    Value(&'a [u8]),
 }
 impl<'a, W : Write> WriteCtx<'a, W> {
+    /// below `IOV_MAX` (1024 on linux)
+    const MAX_SEGMENTS: usize = 64;
+    const MAX_TEXT: usize = 8 * 1024;
     #[inline]
     pub fn new(inner: &'a mut W) -> Self {
-        WriteCtx { inner }
+        WriteCtx { inner, segments: Vec::new(), text: Vec::new(), written: 0, newline: Newline::Preserve, vectored: false, cr: false }
+    }
+    /// `inner` writes all the slices of a `write_vectored` at once (a `BufWriter`, a `Vec`...), so the pending
+    /// segments are written without being copied. `is_write_vectored` is unstable, the caller has to tell
+    pub fn vectored(mut self, vectored: bool) -> Self {
+        self.vectored = vectored;
+        self
+    }
+    /// rewrites the line endings of everything written to `newline`
+    pub fn newline(mut self, newline: Newline) -> Self {
+        self.newline = newline;
+        self
+    }
+    #[inline]
+    pub(self) fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        let start = self.text.len();
+        match self.newline {
+            Newline::Preserve => self.text.extend_from_slice(buf),
+            _ => self.push_lines(buf),
+        }
+        self.push_segment(start)
+    }
+    /// the text from `start` is a new segment, or extends the last one
+    fn push_segment(&mut self, start: usize) -> io::Result<()> {
+        match self.segments.last_mut() {
+            Some(Segment::Text(r)) if r.end == start => r.end = self.text.len(),
+            _ => self.segments.push(Segment::Text(start..self.text.len())),
+        }
+        self.flush_if_full()
     }
     #[inline]
-    pub(self) fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.inner.write(buf)
+    pub(self) fn write_value(&mut self, value: &'a [u8]) -> io::Result<()> {
+        if self.newline != Newline::Preserve && (self.cr || value.iter().any(|b| *b == b'\r' || *b == b'\n')) {
+            return self.write(value);
+        }
+        if !value.is_empty() {
+            self.segments.push(Segment::Value(value));
+        }
+        self.flush_if_full()
+    }
+    fn flush_if_full(&mut self) -> io::Result<()> {
+        if self.segments.len() >= Self::MAX_SEGMENTS || self.text.len() >= Self::MAX_TEXT {
+            self.write_pending()?;
+        }
+        Ok(())
+    }
+    /// copies `buf` to [`Self::text`] with the line endings of [`Self::newline`].
+    /// With [`Newline::Lf`], a `\r` is held back until the next byte shows whether it starts a `\r\n`.
+    fn push_lines(&mut self, buf: &[u8]) {
+        for &b in buf {
+            match (self.newline, b) {
+                (Newline::Lf, b'\r') => {
+                    if self.cr {
+                        self.text.push(b'\r');
+                    }
+                }
+                (Newline::Lf, b'\n') => self.text.push(b'\n'),
+                (Newline::Lf, _) if self.cr => self.text.extend_from_slice(&[b'\r', b]),
+                (Newline::Crlf, b'\n') if !self.cr => self.text.extend_from_slice(b"\r\n"),
+                _ => self.text.push(b),
+            }
+            self.cr = b == b'\r';
+        }
+    }
+    /// writes the pending segments, and flushes the inner writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.newline == Newline::Lf && self.cr {
+            // a `\r` ending the output isn't part of a `\r\n`
+            self.cr = false;
+            let start = self.text.len();
+            self.text.push(b'\r');
+            self.push_segment(start)?;
+        }
+        self.write_pending()?;
+        self.inner.flush()
+    }
+    /// number of bytes written so far, excluding the pending ones
+    pub fn written(&self) -> usize {
+        self.written
+    }
+    fn write_pending(&mut self) -> io::Result<()> {
+        let text = &self.text;
+        let bytes = |s: &Segment<'a>| match s {
+            Segment::Text(r) => &text[r.clone()],
+            Segment::Value(v) => *v,
+        };
+        if self.vectored {
+            let mut slices: Vec<_> = self.segments.iter().map(|s| IoSlice::new(bytes(s))).collect();
+            let mut slices = &mut slices[..];
+            while !slices.is_empty() {
+                match self.inner.write_vectored(slices) {
+                    Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write the whole output")),
+                    Ok(n) => {
+                        self.written += n;
+                        IoSlice::advance_slices(&mut slices, n)
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        } else {
+            // one `write` per segment otherwise, better copy them once
+            let buf: Vec<u8> = self.segments.iter().flat_map(|s| bytes(s).iter().copied()).collect();
+            self.inner.write_all(&buf)?;
+            self.written += buf.len();
+        }
+        self.segments.clear();
+        self.text.clear();
+        Ok(())
     }
 }
 
 /// pipe `len` bytes from `from` to `to`
-pub(self) fn pipe<'a, R, W>(from: &mut ReadCtx<'a, R>, to: &mut WriteCtx<'a, W>, len: usize) -> io::Result<()>
+fn pipe<'a, R, W>(from: &mut ReadCtx<'a, R>, to: &mut WriteCtx<'a, W>, len: usize) -> io::Result<()>
 where R : BufRead, W : Write {
         let end = from.pos + len;
         while from.pos < end {
@@ -0,0 +1,149 @@
+//! where the bytes of an output come from, written by `--origin-map` next to the outputs.
+//!
+//! Each [`Region`] of an output is either copied from the text of the source or substituted, e.g. the value of a
+//! variable, and points at the term of the source which produced it. The regions are computed like [`super::write_terms`]
+//! writes, without writing: their offsets are the ones of `--newline=preserve`.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::infer::{eval_cond, Env, EnvOf};
+use crate::sourcemap::SrcFile;
+use crate::syntax::{Name, Term, TermK, Terms};
+
+/// what produced a [`Region`]
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Origin {
+    /// copied from the source
+    Text,
+    /// a value of the tree, e.g. the template written by `--partial`
+    Value,
+    /// the value of a variable
+    Variable,
+    /// the default of an unset variable, `#$name:-default#`
+    Default,
+    /// the body of a named block, `#use(name)#`
+    Use,
+}
+
+/// a region of an output, and the term of the source it comes from
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Region {
+    /// byte range in the output
+    pub output: (usize, usize),
+    pub origin: Origin,
+    /// byte range of the term in the source file, the whole `#$name#` of a variable
+    pub source: (usize, usize),
+    /// the variable or named block
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<Name>,
+}
+
+/// the regions of an output, see [`map`]
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct FileMap {
+    pub source: PathBuf,
+    pub regions: Vec<Region>,
+}
+
+/// the regions of each destination, in the order of the output. The empty regions are left out.
+pub fn map<E: EnvOf>(trees: &[(SrcFile, Terms)], env: &E) -> BTreeMap<PathBuf, FileMap> {
+    trees
+        .iter()
+        .map(|(file, terms)| {
+            let mut m = Mapper { env: env.env_of(file), pos: 0, regions: Vec::new() };
+            m.terms(terms);
+            // `<stdout>` for the output of `--stdin`
+            let dest = file.destination.path().map_or_else(|| file.destination.to_string().into(), Path::to_path_buf);
+            (dest, FileMap { source: file.path.clone(), regions: m.regions })
+        })
+        .collect()
+}
+
+/// the regions of the `terms` of a file
+pub fn regions(terms: &Terms, env: &Env) -> Vec<Region> {
+    let mut m = Mapper { env, pos: 0, regions: Vec::new() };
+    m.terms(terms);
+    m.regions
+}
+
+struct Mapper<'a> {
+    env: &'a Env,
+    /// bytes of the output so far
+    pos: usize,
+    regions: Vec<Region>,
+}
+
+impl Mapper<'_> {
+    fn terms(&mut self, terms: &Terms) {
+        for t in terms {
+            self.term(t);
+        }
+    }
+    fn term(&mut self, term: &Term) {
+        let env = self.env;
+        match &term.node {
+            TermK::Text => self.push(term, Origin::Text, term.span.len(), None),
+            TermK::Value(v) => self.push(term, Origin::Value, v.len(), None),
+            TermK::Var(name, default) => match env.get_var(name) {
+                Some(v) => self.push(term, Origin::Variable, v.len(), Some(*name)),
+                None => {
+                    let len = default.as_ref().map_or(0, String::len);
+                    self.push(term, Origin::Default, len, Some(*name))
+                }
+            },
+            TermK::Dimension { name, children, default, .. } => {
+                let decision = env.get_dimension(name).map(|d| d.decision.as_usize());
+                if let Some(child) = decision.and_then(|d| children.get(d).or(default.as_ref())) {
+                    self.terms(child);
+                }
+            }
+            TermK::Guard { cond, then, otherwise } => match (eval_cond(cond, env), otherwise) {
+                (true, _) => self.terms(then),
+                (false, Some(otherwise)) => self.terms(otherwise),
+                (false, None) => {}
+            },
+            TermK::Def { .. } | TermK::Comment => {}
+            TermK::Use { name, args } => {
+                let len = env.get_def(name).map_or(0, |body| block_len(env, args, body));
+                self.push(term, Origin::Use, len, Some(*name))
+            }
+        }
+    }
+    fn push(&mut self, term: &Term, origin: Origin, len: usize, name: Option<Name>) {
+        if len == 0 {
+            return;
+        }
+        let source = (term.span.lo.as_usize(), term.span.hi.as_usize());
+        self.regions.push(Region { output: (self.pos, self.pos + len), origin, source, name });
+        self.pos += len;
+    }
+}
+
+/// the length of the output of a named block, see [`super::write_block`]
+fn block_len(env: &Env, args: &[(Name, String)], terms: &Terms) -> usize {
+    let mut len = 0;
+    for t in terms {
+        len += match &t.node {
+            TermK::Text | TermK::Def { .. } | TermK::Comment => 0,
+            TermK::Value(v) => v.len(),
+            TermK::Var(name, default) => {
+                let arg = args.iter().find(|(n, _)| n == name).map(|(_, v)| v);
+                arg.or_else(|| env.get_var(name)).or(default.as_ref()).map_or(0, String::len)
+            }
+            TermK::Dimension { name, children, default, .. } => {
+                let decision = env.get_dimension(name).map(|d| d.decision.as_usize());
+                decision.and_then(|d| children.get(d).or(default.as_ref())).map_or(0, |c| block_len(env, args, c))
+            }
+            TermK::Guard { cond, then, otherwise } => match (eval_cond(cond, env), otherwise) {
+                (true, _) => block_len(env, args, then),
+                (false, Some(otherwise)) => block_len(env, args, otherwise),
+                (false, None) => 0,
+            },
+            TermK::Use { name, args } => env.get_def(name).map_or(0, |body| block_len(env, args, body)),
+        };
+    }
+    len
+}
@@ -0,0 +1,68 @@
+//! bounding the number of files open at the same time in the output pipeline.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+
+/// file descriptors held by a single write or copy: the source and the destination.
+pub const FDS_PER_FILE: usize = 2;
+
+/// a counting semaphore over file descriptors, shared by the writers and the copiers.
+/// keeps track of how many times it had to make someone wait.
+pub struct Throttle {
+    max: usize,
+    available: Mutex<usize>,
+    released: Condvar,
+    waits: AtomicUsize,
+}
+
+/// file descriptors reserved from a [`Throttle`], given back on drop.
+pub struct Permit<'a> {
+    throttle: &'a Throttle,
+    fds: usize,
+}
+
+impl Throttle {
+    /// `max_open_files` is raised to [`FDS_PER_FILE`] so at least one file can be processed.
+    pub fn new(max_open_files: usize) -> Self {
+        let max = usize::max(max_open_files, FDS_PER_FILE);
+        Throttle {
+            max,
+            available: Mutex::new(max),
+            released: Condvar::new(),
+            waits: AtomicUsize::new(0),
+        }
+    }
+    /// maximum number of files that can be written at the same time.
+    pub fn batch_size(&self) -> usize {
+        self.max / FDS_PER_FILE
+    }
+    pub fn max_open_files(&self) -> usize {
+        self.max
+    }
+    /// number of times an [`Throttle::acquire`] had to block.
+    pub fn waits(&self) -> usize {
+        self.waits.load(Ordering::Relaxed)
+    }
+    /// blocks until [`FDS_PER_FILE`] descriptors are available.
+    pub fn acquire(&self) -> Permit<'_> {
+        let fds = FDS_PER_FILE;
+        // @SAFETY unwrap(): the lock is never held while panicking
+        let mut available = self.available.lock().unwrap();
+        if *available < fds {
+            self.waits.fetch_add(1, Ordering::Relaxed);
+            while *available < fds {
+                available = self.released.wait(available).unwrap();
+            }
+        }
+        *available -= fds;
+        Permit { throttle: self, fds }
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        // @SAFETY unwrap(): see `Throttle::acquire`
+        let mut available = self.throttle.available.lock().unwrap();
+        *available += self.fds;
+        self.throttle.released.notify_one();
+    }
+}
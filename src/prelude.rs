@@ -0,0 +1,8 @@
+//! what another crate needs to parse and render templates, without the command line: `use flan::prelude::*;`
+//!
+//! `default-features = false` leaves out the `cli` feature: structopt, [`crate::cfg::Opt`] and the metrics report.
+pub use crate::cfg::{parse_decisions, Config, Decision, File, Flags, Index};
+pub use crate::driver::{render, render_variants, run, RenderedVariant, Report, RunError, Session};
+pub use crate::error::{Error, ErrorFlags};
+pub use crate::sourcemap::{Span, SrcMap};
+pub use crate::syntax::{Term, TermK, Terms};
@@ -1,3 +1,5 @@
+use super::pos::BytePos;
+use super::srcmap::SrcFile;
 use super::span::Span;
 pub use std::borrow::Cow;
 
@@ -10,3 +12,23 @@ pub struct Loc<'a> {
     /// contents of the line
     pub line: Cow<'a, str>,
 }
+impl Loc<'_> {
+    /// column of `pos` in the line, counted in characters rather than bytes. Starts at 0.
+    /// A position past the end of the line is right after its last character.
+    pub fn column(&self, pos: BytePos) -> usize {
+        let rel = pos.as_usize().saturating_sub(self.span.lo.as_usize());
+        self.line.char_indices().take_while(|(i, _)| *i < rel).count()
+    }
+}
+
+/// the lines and columns of a span, see [`crate::sourcemap::SrcMap::lookup_loc`].
+/// They start at 1 and the columns are counted in characters, `col_end` is right after the last character of the span,
+/// on `end_line`.
+#[derive(Debug, Clone)]
+pub struct SpanLoc {
+    pub file: SrcFile,
+    pub line: usize,
+    pub col_start: usize,
+    pub end_line: usize,
+    pub col_end: usize,
+}
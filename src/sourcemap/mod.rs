@@ -1,13 +1,13 @@
 //! custom version of [https://docs.rs/codemap/](https://docs.rs/codemap/).
 pub mod loc;
 pub mod source_analysis;
-pub mod sourcemap;
+pub mod srcmap;
 pub mod span;
 pub mod pos;
 
 #[doc(inline)]
-pub use loc::Loc;
+pub use loc::{Loc, SpanLoc};
 #[doc(inline)]
-pub use sourcemap::{File, SourceInfo, SrcFile, SrcMap};
+pub use srcmap::{decode_lossy, Destination, File, FileKind, SourceInfo, SourceOrigin, SrcFile, SrcMap};
 #[doc(inline)]
-pub use span::{span, BytePos, Span, Spanned};
+pub use span::{span, span_in, BytePos, FileId, Span, Spanned};
@@ -26,9 +26,9 @@ BytePos_from!( i32 u32 u64 i64 usize isize );
 macro_rules! BytePos_into {
     ( $($TY: ty )+ ) => {
         $(
-        impl Into<$TY> for BytePos {
-            fn into(self) -> $TY {
-                self.0 as $TY
+        impl From<BytePos> for $TY {
+            fn from(pos: BytePos) -> $TY {
+                pos.0 as $TY
             }
         }
         )+
@@ -41,7 +41,7 @@ impl BytePos {
         self.0 as usize
     }
     pub fn as_u64(&self) -> u64 {
-        self.0 as u64
+        self.0
     }
 }
 impl std::fmt::Display for BytePos {
@@ -144,7 +144,7 @@ impl Pos {
         self.0 as usize
     }
     pub fn as_u64(&self) -> u64 {
-        self.0 as u64
+        self.0
     }
 }
 impl std::fmt::Display for Pos {
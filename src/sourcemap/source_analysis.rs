@@ -1,5 +1,8 @@
 use crate::sourcemap::BytePos;
 
+/// pushes the start of each line of `src` but the first to `lines`, 16 bytes at a time.
+/// # Safety
+/// the cpu must support `sse2`, see [`is_x86_feature_detected`]
 pub unsafe fn anal_src_sse2(src: &str, offset: BytePos, lines: &mut Vec<BytePos>) {
     // see: https://doc.rust-lang.org/nightly/nightly-rustc/src/rustc_span/analyze_source_file.rs.html
     #[cfg(target_arch = "x86")]
@@ -15,7 +18,7 @@ pub unsafe fn anal_src_sse2(src: &str, offset: BytePos, lines: &mut Vec<BytePos>
         let ptr = src_bytes.as_ptr() as *const __m128i;
         // loadu because we don't know if aligned to 16bytes
         // @TODO align before?
-        let chunk = _mm_loadu_si128(ptr.offset(chunk_index as isize));
+        let chunk = _mm_loadu_si128(ptr.add(chunk_index));
 
         let lines_test = _mm_cmpeq_epi8(chunk, _mm_set1_epi8(b'\n' as i8));
         let lines_mask = _mm_movemask_epi8(lines_test);
@@ -55,6 +58,9 @@ pub unsafe fn anal_src_sse2(src: &str, offset: BytePos, lines: &mut Vec<BytePos>
     }
 }
 
+/// [`anal_src_sse2`], 32 bytes at a time.
+/// # Safety
+/// the cpu must support `avx2`, see [`is_x86_feature_detected`]
 pub unsafe fn anal_src_avx2(src: &str, offset: BytePos, lines: &mut Vec<BytePos>) {
     // see: https://doc.rust-lang.org/nightly/nightly-rustc/src/rustc_span/analyze_source_file.rs.html
     #[cfg(target_arch = "x86")]
@@ -71,14 +77,14 @@ pub unsafe fn anal_src_avx2(src: &str, offset: BytePos, lines: &mut Vec<BytePos>
         let ptr = src_bytes.as_ptr() as *const __m256i;
         // loadu because we don't know if aligned to 16bytes
         // @TODO align before?
-        let chunk = _mm256_loadu_si256(ptr.offset(chunk_index as isize));
+        let chunk = _mm256_loadu_si256(ptr.add(chunk_index));
 
         let lines_test = _mm256_cmpeq_epi8(chunk, _mm256_set1_epi8(b'\n' as i8));
         let lines_mask = _mm256_movemask_epi8(lines_test);
 
         if lines_mask != 0 {
             // set the 16 irrelevant msb to '1'
-            let mut lines_mask: u32 = std::mem::transmute(lines_mask);
+            let mut lines_mask = lines_mask as u32;
             // + 1 because we want the BytePosition of the newline start, not the '\n' before
             let offset = offset + BytePos::from(chunk_index * CHUNK_SIZE + 1);
 
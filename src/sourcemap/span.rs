@@ -3,17 +3,36 @@
 pub use super::pos::{BytePos, BytePosInner};
 use std::ops::{Add, Range, RangeInclusive};
 
-/// an span inside the sourcemap
+/// index of a file in the source map, see [`crate::sourcemap::SrcMap::get`]
+#[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
+pub struct FileId(pub u32);
+impl FileId {
+    /// the spans of no file, e.g. of a string parsed by [`crate::driver::string_to_parser`]
+    pub const NONE: FileId = FileId(u32::MAX);
+}
+impl Default for FileId {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// an span inside a source file
 #[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
 pub struct Span {
-    /// first byte
+    /// the file of the span, [`FileId::NONE`] if it has none
+    pub file: FileId,
+    /// first byte, from the start of the file
     pub lo: BytePos,
     /// *after* last byte => not included
     pub hi: BytePos,
 }
-/// span ctor from [`BytePos`] values
+/// span ctor from [`BytePos`] values, in no file. see [`span_in`]
 pub fn span(lo: BytePos, hi: BytePos) -> Span {
-    Span { lo: lo, hi: hi }
+    span_in(FileId::NONE, lo, hi)
+}
+/// span ctor from [`BytePos`] values relative to the start of `file`
+pub fn span_in(file: FileId, lo: BytePos, hi: BytePos) -> Span {
+    Span { file, lo, hi }
 }
 /// I'm not sure what the invariants of Add are supBytePosed to be,
 /// but since BytePos is bounded (u64::MIN, u64::MAX) it is at least a Monoid
@@ -21,19 +40,23 @@ impl Add<Span> for Span {
     type Output = Span;
     fn add(self, other: Span) -> Span {
         use std::cmp;
+        // the identity has no file
+        let file = if self.file == FileId::NONE { other.file } else { self.file };
         Span {
+            file,
             lo: cmp::min(self.lo, other.lo),
             hi: cmp::max(self.hi, other.hi),
         }
     }
 }
 impl Span {
-    /// Span ctor from inner values
+    /// Span ctor from inner values, in no file. see [`span`]
     pub fn new(lo: BytePosInner, hi: BytePosInner) -> Span {
-        Span {
-            lo: BytePos(lo),
-            hi: BytePos(hi),
-        }
+        span(BytePos(lo), BytePos(hi))
+    }
+    /// the same span in `file`
+    pub fn in_file(self, file: FileId) -> Span {
+        Span { file, ..self }
     }
     /// makes a subspan from inside (`offset = span.lo`)
     /// Panics if begin and end are invalid
@@ -43,6 +66,7 @@ impl Span {
         assert!(end >= begin);
         assert!(self.lo + end <= self.hi);
         Span {
+            file: self.file,
             lo: self.lo + begin,
             hi: self.lo + end,
         }
@@ -58,6 +82,10 @@ impl Span {
     pub fn len(&self) -> usize {
         (self.hi.0 - self.lo.0) as usize
     }
+    /// the span covers no byte
+    pub fn is_empty(&self) -> bool {
+        self.lo == self.hi
+    }
     /// merges two spans, same as `+` operator
     pub fn merge(self, other: Span) -> Span {
         self + other
@@ -66,7 +94,7 @@ impl Span {
     pub fn correct(&self, offset: BytePos) -> Span {
         assert!(offset <= self.lo);
         assert!(offset <= self.hi);
-        span(self.lo - offset, self.hi - offset)
+        span_in(self.file, self.lo - offset, self.hi - offset)
     }
     pub fn lo_as_usize(&self) -> usize {
         self.lo.0 as usize
@@ -76,16 +104,19 @@ impl Span {
     }
     /// Identity for Span merging/addition
     pub const MEMPTY: Span = Span {
+        file: FileId::NONE,
         lo: BytePos(BytePosInner::MAX),
         hi: BytePos(BytePosInner::MIN),
     };
     /// Annihilator for Span merging/addition
     pub const NIL: Span = Span {
+        file: FileId::NONE,
         lo: BytePos(BytePosInner::MIN),
         hi: BytePos(BytePosInner::MAX),
     };
+    /// in any file
     pub fn is_nil(&self) -> bool {
-        *self == Self::NIL
+        self.lo == Self::NIL.lo && self.hi == Self::NIL.hi
     }
     /// lo .. hi
     pub fn as_range(&self) -> Range<usize> {
@@ -112,20 +143,14 @@ pub struct Spanned<T> {
 impl<T> Spanned<T> {
     pub fn new(node: T, lo: impl Into<BytePos>, hi: impl Into<BytePos>) -> Spanned<T> {
         Spanned {
-            node: node,
-            span: Span {
-                lo: lo.into(),
-                hi: hi.into(),
-            },
+            node,
+            span: span(lo.into(), hi.into()),
         }
     }
     pub fn new_lit(node: T, lo: impl Into<BytePos>, hi: impl Into<BytePos>) -> Self {
         Spanned {
-            node: node,
-            span: Span {
-                lo: lo.into(),
-                hi: hi.into(),
-            },
+            node,
+            span: span(lo.into(), hi.into()),
         }
     }
 }
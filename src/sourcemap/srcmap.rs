@@ -0,0 +1,386 @@
+//! Source file maps and Source files.
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::{fs, io};
+
+use super::loc::{Loc, SpanLoc};
+use super::span::*;
+
+#[derive(Hash, Debug, Clone, PartialEq)]
+/// Information about the Source
+pub enum SourceInfo {
+    Source(String),
+    /// we do not need the source for binary files
+    Binary,
+    /// a symbolic link and its target, recreated at the destination. see [`crate::cfg::Symlinks::CopyLink`]
+    Symlink(PathBuf),
+}
+/// how a file is loaded, see [`SrcMap::path_to_file`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Default)]
+pub enum FileKind {
+    /// a source if it is valid UTF-8, binary otherwise
+    #[default]
+    Auto,
+    /// never read nor parsed, only copied
+    Binary,
+    /// loading fails if it isn't valid UTF-8
+    Text,
+    /// each invalid UTF-8 byte is decoded as [`LOSSY_SUBSTITUTE`], see [`decode_lossy`]
+    Lossy,
+}
+
+/// where the source of a [`File`] is read from
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum SourceOrigin {
+    /// the file at [`File::path`], which the output reads again
+    Path,
+    /// `--stdin`, read once. [`File::path`] is `<stdin>` for the diagnostics
+    Stdin,
+    /// a string of the library or of the language server, [`File::path`] is only its name
+    InMemory,
+    /// downloaded from the URL [`File::path`], see [`crate::driver::fetch`]
+    Remote,
+}
+
+/// where the output of a [`File`] is written
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+pub enum Destination {
+    Path(PathBuf),
+    /// `--stdout`, `--stdin` without `OUT`, or `-i FILE` without `-o`
+    Stdout,
+    /// nowhere on the filesystem, e.g. see [`crate::driver::Session::render_source`]
+    InMemory,
+}
+impl Destination {
+    /// `None` for [`Destination::Stdout`] and [`Destination::InMemory`]
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Destination::Path(p) => Some(p),
+            Destination::Stdout | Destination::InMemory => None,
+        }
+    }
+}
+impl From<PathBuf> for Destination {
+    fn from(path: PathBuf) -> Self {
+        Destination::Path(path)
+    }
+}
+impl From<&str> for Destination {
+    fn from(path: &str) -> Self {
+        Destination::Path(path.into())
+    }
+}
+impl std::fmt::Display for Destination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Destination::Path(p) => write!(f, "{}", p.display()),
+            Destination::Stdout => write!(f, "<stdout>"),
+            Destination::InMemory => write!(f, "<memory>"),
+        }
+    }
+}
+
+/// the ASCII substitute character, a single byte so the offsets of the source are the ones of the file
+pub const LOSSY_SUBSTITUTE: char = '\u{1a}';
+
+/// decodes `bytes`, replacing each invalid byte by [`LOSSY_SUBSTITUTE`].
+/// The output reads the file again, so the invalid bytes are written unchanged where the text is kept.
+pub fn decode_lossy(bytes: Vec<u8>) -> String {
+    match String::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(e) => {
+            let bytes = e.into_bytes();
+            let mut s = String::with_capacity(bytes.len());
+            for chunk in bytes.utf8_chunks() {
+                s.push_str(chunk.valid());
+                s.extend(std::iter::repeat_n(LOSSY_SUBSTITUTE, chunk.invalid().len()));
+            }
+            s
+        }
+    }
+}
+/// File info + source
+#[derive(Debug)]
+pub struct File {
+    /// file name without path
+    pub name: String,
+    pub path: PathBuf,
+    pub origin: SourceOrigin,
+    pub destination: Destination,
+    /// Source or its state
+    pub src: SourceInfo,
+    /// start positions of lines
+    pub lines: Vec<BytePos>,
+    /// the file of its spans, set when it is added to the [`SrcMap`]
+    pub id: FileId,
+    /// end in bytes of the source file, eclusive (like size)
+    pub end: BytePos,
+}
+impl File {
+    /// panics if not a file name. The source is read from `path`, see [`SourceOrigin::Path`]
+    pub fn new(path: PathBuf, destination: Destination, src: SourceInfo) -> File {
+        let name = path.file_name().unwrap().to_string_lossy().into();
+        let end = match &src {
+            // @NOTE this is correct as [`String::len`] returns length in bytes, not chars
+            SourceInfo::Source(s) => s.len(),
+            _ => 1,
+        };
+        File {
+            name,
+            path,
+            origin: SourceOrigin::Path,
+            destination,
+            src,
+            lines: Vec::new(),
+            id: FileId::NONE,
+            end: BytePos::from(end),
+        }
+    }
+    pub fn is_source(&self) -> bool {
+        matches!(self.src, SourceInfo::Source(_))
+    }
+    /// binary files and symlinks are copied, not parsed
+    pub fn is_binary(&self) -> bool {
+        matches!(self.src, SourceInfo::Binary | SourceInfo::Symlink(_))
+    }
+    pub fn lookup_line(&self, pos: BytePos) -> Option<Loc<'_>> {
+        use crate::sourcemap as sm;
+        let index = self.get_line_num(pos)?;
+        let line = self.get_loc(index)?;
+        let start = *unsafe { self.lines.get_unchecked(index) };
+        let end: BytePos = self.lines.get(index + 1).map(|p| *p - 1).unwrap_or(self.end);
+        let span = sm::span_in(self.id, start, end);
+        Some(Loc { index, span, line })
+    }
+    /// the lines and columns of `span`, `None` if it isn't in the file or the file isn't a source. see [`SrcMap::lookup_loc`]
+    pub fn lookup_loc(self: &Arc<Self>, span: Span) -> Option<SpanLoc> {
+        if span.lo > span.hi || span.hi > self.end {
+            return None;
+        }
+        let (lo, hi) = (self.lookup_line(span.lo)?, self.lookup_line(span.hi)?);
+        Some(SpanLoc {
+            file: self.clone(),
+            line: lo.index + 1,
+            col_start: lo.column(span.lo) + 1,
+            end_line: hi.index + 1,
+            col_end: hi.column(span.hi) + 1,
+        })
+    }
+    /// character column of `pos` in its line, see [`Loc::column`]
+    pub fn lookup_column(&self, pos: BytePos) -> Option<usize> {
+        Some(self.lookup_line(pos)?.column(pos))
+    }
+    /// gets the index of the line containing `pos`.
+    /// This is not a line number.
+    pub fn get_line_num(&self, pos: BytePos) -> Option<usize> {
+        if self.lines.is_empty() {
+            return None;
+        }
+        let i = match self.lines.binary_search(&pos) {
+            Ok(i) => i,
+            Err(i) => i.saturating_sub(1),
+        };
+        assert!(i < self.lines.len());
+        Some(i)
+    }
+    /// gets the contents of the line of code from the source file.
+    pub fn get_loc(&self, line_num: usize) -> Option<Cow<'_, str>> {
+        let s = self.lines.get(line_num)?.as_usize();
+        if let SourceInfo::Source(src) = &self.src {
+            let lbeg = &src.as_str()[s..];
+            let loc = match src.as_str()[s..].find('\n') {
+                Some(e) => &lbeg[..e],
+                // until EOF
+                None => lbeg,
+            };
+            Some(Cow::from(loc))
+        } else {
+            None
+        }
+    }
+    pub fn contains(&self, span: Span) -> bool {
+        span.file == self.id && span.lo <= span.hi && span.hi <= self.end
+    }
+    pub fn is_stdin(&self) -> bool {
+        self.origin == SourceOrigin::Stdin
+    }
+    pub fn is_stdout(&self) -> bool {
+        self.destination == Destination::Stdout
+    }
+    pub fn size(&self) -> usize {
+        self.end.as_usize()
+    }
+    /// approximate number of bytes held in memory for this file (source and lines)
+    pub fn mem_size(&self) -> usize {
+        let src = match &self.src {
+            SourceInfo::Source(s) => s.capacity(),
+            SourceInfo::Binary => 0,
+            SourceInfo::Symlink(target) => target.capacity(),
+        };
+        src + self.lines.capacity() * std::mem::size_of::<BytePos>()
+    }
+}
+
+/// type synonym for easier refactoring
+pub type SrcFile = Arc<File>;
+
+#[derive(Debug)]
+/// A map of source files. @NOTE Maybe shouldn't be a new type.
+pub struct SrcMap {
+    /// indexed by [`FileId`]
+    pub sources: RwLock<Vec<SrcFile>>,
+}
+
+impl SrcMap {
+    pub fn new() -> Arc<Self> {
+        Arc::new(SrcMap {
+            sources: RwLock::new(Vec::new()),
+        })
+    }
+    /// load a file and add it to the map
+    pub fn load_file(&self, path: PathBuf, dest: Destination) -> io::Result<SrcFile> {
+        self.load_file_as(path, dest, FileKind::Auto)
+    }
+    /// same as [`Self::load_file`], but the file is loaded as `kind`
+    pub fn load_file_as(&self, path: PathBuf, dest: Destination, kind: FileKind) -> io::Result<SrcFile> {
+        Ok(self.add(Self::path_to_file(path, dest, kind)?))
+    }
+    /// read stdin and add it to the map as `<stdin>`, see [`SourceOrigin::Stdin`]
+    pub fn load_stdin(&self, dest: Destination) -> io::Result<SrcFile> {
+        let src = Self::read_stdin()?;
+        let lines = Self::anal_src(src.as_ref(), BytePos(0));
+        let mut file = File::new(PathBuf::from("<stdin>"), dest, SourceInfo::Source(src));
+        file.origin = SourceOrigin::Stdin;
+        file.lines = lines;
+        Ok(self.add(file))
+    }
+    /// add `src`, which isn't read from `path`, to the map. see [`SourceOrigin::InMemory`]
+    pub fn load_source(&self, path: PathBuf, dest: Destination, src: String) -> SrcFile {
+        let lines = Self::anal_src(src.as_ref(), BytePos(0));
+        let mut file = File::new(path, dest, SourceInfo::Source(src));
+        file.origin = SourceOrigin::InMemory;
+        file.lines = lines;
+        self.add(file)
+    }
+    /// add `src` downloaded from `url`, see [`SourceOrigin::Remote`]
+    pub fn load_remote(&self, url: PathBuf, dest: Destination, src: String) -> SrcFile {
+        let lines = Self::anal_src(src.as_ref(), BytePos(0));
+        let mut file = File::new(url, dest, SourceInfo::Source(src));
+        file.origin = SourceOrigin::Remote;
+        file.lines = lines;
+        self.add(file)
+    }
+    /// load a symbolic link without following it, see [`SourceInfo::Symlink`]
+    pub fn load_symlink(&self, path: PathBuf, dest: Destination) -> io::Result<SrcFile> {
+        let target = fs::read_link(&path)?;
+        Ok(self.add(File::new(path, dest, SourceInfo::Symlink(target))))
+    }
+    fn add(&self, mut file: File) -> SrcFile {
+        // the files are loaded concurrently, the id is taken under the lock
+        let mut sources = self.sources.write().unwrap();
+        file.id = FileId(sources.len() as u32);
+        let af = Arc::new(file);
+        sources.push(af.clone());
+        af
+    }
+    /// helper that builds a [`File`] from a path
+    pub fn path_to_file(path: PathBuf, destination: Destination, kind: FileKind) -> io::Result<File> {
+        use std::io::{Error, ErrorKind};
+        if !path.is_file() {
+            Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("`{}` not a file.", path.to_string_lossy()),
+            ))?;
+        }
+        let lines;
+        let start = BytePos(0);
+        let name = path.file_name().unwrap().to_string_lossy().into();
+        let read = match kind {
+            FileKind::Binary => Err(Error::from(ErrorKind::InvalidData)),
+            FileKind::Lossy => fs::read(&path).map(decode_lossy),
+            _ => fs::read_to_string(&path),
+        };
+        let (src, len) = match read {
+            Err(e) if e.kind() == ErrorKind::InvalidData && kind == FileKind::Text => {
+                return Err(Error::new(ErrorKind::InvalidData, "not valid UTF-8, but listed in `[files] text`."));
+            }
+            Err(e) => {
+                if e.kind() == ErrorKind::InvalidData {
+                    lines = vec![];
+                    // @TODO double check if size `1` doesn't lead to bugs
+                    (SourceInfo::Binary, 1)
+                } else {
+                    return Err(e);
+                }
+            }
+            Ok(s) => {
+                let l = s.len();
+                lines = Self::anal_src(s.as_ref(), start);
+                (SourceInfo::Source(s), l)
+            }
+        };
+        Ok(File {
+            name,
+            path,
+            origin: SourceOrigin::Path,
+            src,
+            destination, // @TODO absolute path?
+            lines,
+            id: FileId::NONE,
+            end: BytePos::from(len),
+        })
+    }
+    pub fn anal_src(src: &str, offset: BytePos) -> Vec<BytePos> {
+        use super::source_analysis::*;
+        let mut lines = vec![offset];
+        if cfg!(not(any(target_arch = "x86", target_arch = "x86_64"))) {
+            anal_src_slow(src, src.len(), offset, &mut lines);
+        }
+        if is_x86_feature_detected!("avx2") {
+            unsafe {
+                anal_src_avx2(src, offset, &mut lines);
+            }
+        } else if is_x86_feature_detected!("sse2") {
+            unsafe {
+                anal_src_sse2(src, offset, &mut lines);
+            }
+        }
+
+        lines
+    }
+    pub fn exists(&self, span: Span) -> bool {
+        self.lookup_span(span).is_some()
+    }
+    /// the file of `id`
+    pub fn get(&self, id: FileId) -> Option<SrcFile> {
+        self.sources.read().unwrap().get(id.0 as usize).cloned()
+    }
+    /// a new map with the same files, the files added to either aren't in the other
+    pub fn fork(&self) -> Arc<Self> {
+        Arc::new(SrcMap {
+            sources: RwLock::new(self.sources.read().unwrap().clone()),
+        })
+    }
+    /// the file containing the whole `span`, and the span, whose positions are relative to the start of the file.
+    /// `None` for the spans of no file, e.g. [`Span::NIL`]
+    pub fn lookup_span(&self, span: Span) -> Option<(SrcFile, Span)> {
+        let file = self.get(span.file).filter(|f| f.contains(span))?;
+        Some((file, span))
+    }
+    /// the file, lines and columns of `span`, see [`SpanLoc`]. `None` for the spans of no file and of binary files
+    pub fn lookup_loc(&self, span: Span) -> Option<SpanLoc> {
+        self.lookup_span(span)?.0.lookup_loc(span)
+    }
+    /// approximate number of bytes held in memory by all the sources. see [`File::mem_size`]
+    pub fn mem_size(&self) -> usize {
+        self.sources.read().unwrap().iter().map(|f| f.mem_size()).sum()
+    }
+
+    pub fn read_stdin() -> io::Result<String> {
+        use std::io::Read;
+        let mut buf = String::new();
+        std::io::stdin().read_to_string(&mut buf)?;
+        Ok(buf)
+    }
+}
@@ -0,0 +1,217 @@
+//! conditions of guards, e.g. `#when(os=linux && arch!=arm){...}#`
+//!
+//! ```bnf
+//! Cond   := And (`||` And)*
+//! And    := Unary (`&&` Unary)*
+//! Unary  := `!` Unary | `(` Cond `)` | DIMID (`=` | `!=`) CHOICE
+//! CHOICE := DIMID | [0-9]+                     // a choice name or an index
+//! ```
+//! whitespace is allowed between all of them.
+use crate::sourcemap::{span_in, BytePos, FileId, Span};
+use crate::infer::ChoiceIdx;
+use crate::syntax::{Lexer, Name};
+
+/// boolean expression over the decisions
+#[derive(Clone, PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
+pub enum Cond {
+    /// `dim=choice`, or `dim!=choice` if `negated`. `span` covers the whole comparison
+    Is { dim: Name, choice: Choice, negated: bool, span: Span },
+    Not(Box<Cond>),
+    And(Box<Cond>, Box<Cond>),
+    Or(Box<Cond>, Box<Cond>),
+}
+
+/// right hand side of a comparison
+#[derive(Clone, PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
+pub enum Choice {
+    Name(Name),
+    Num(ChoiceIdx),
+}
+impl std::fmt::Display for Choice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Choice::Name(n) => write!(f, "{}", n),
+            Choice::Num(i) => write!(f, "{}", i),
+        }
+    }
+}
+
+/// as written, with the parentheses the precedences need
+impl std::fmt::Display for Cond {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Cond::Is { dim, choice, negated, .. } => write!(f, "{}{}={}", dim, if *negated { "!" } else { "" }, choice),
+            Cond::Not(c) => match **c {
+                Cond::Is { .. } | Cond::Not(_) => write!(f, "!{}", c),
+                _ => write!(f, "!({})", c),
+            },
+            Cond::And(l, r) => {
+                let operand = |c: &Cond| match c {
+                    Cond::Or(..) => format!("({})", c),
+                    _ => c.to_string(),
+                };
+                write!(f, "{} && {}", operand(l), operand(r))
+            }
+            Cond::Or(l, r) => write!(f, "{} || {}", l, r),
+        }
+    }
+}
+
+impl Cond {
+    /// evaluates the condition, `is(dim, choice)` tells whether `choice` is the decision of `dim`
+    pub fn eval<F: Fn(&Name, &Choice) -> bool>(&self, is: &F) -> bool {
+        match self {
+            Cond::Is { dim, choice, negated, .. } => is(dim, choice) != *negated,
+            Cond::Not(c) => !c.eval(is),
+            Cond::And(l, r) => l.eval(is) && r.eval(is),
+            Cond::Or(l, r) => l.eval(is) || r.eval(is),
+        }
+    }
+    /// the comparisons, from left to right
+    pub fn comparisons(&self) -> Vec<&Cond> {
+        match self {
+            Cond::Is { .. } => vec![self],
+            Cond::Not(c) => c.comparisons(),
+            Cond::And(l, r) | Cond::Or(l, r) => {
+                let mut v = l.comparisons();
+                v.append(&mut r.comparisons());
+                v
+            }
+        }
+    }
+    /// approximate number of bytes held in memory
+    pub fn mem_size(&self) -> usize {
+        match self {
+            Cond::Is { .. } => 0,
+            Cond::Not(c) => std::mem::size_of::<Cond>() + c.mem_size(),
+            Cond::And(l, r) | Cond::Or(l, r) => 2 * std::mem::size_of::<Cond>() + l.mem_size() + r.mem_size(),
+        }
+    }
+}
+
+/// why a condition couldn't be parsed, and where
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CondError {
+    pub msg: String,
+    pub span: Span,
+}
+
+/// parses `src`, which starts at `offset` in `file`
+pub fn parse_cond(src: &str, file: FileId, offset: BytePos) -> Result<Cond, CondError> {
+    let mut p = CondParser { src, pos: 0, file, offset };
+    let cond = p.parse_or()?;
+    p.skip_ws();
+    match p.peek() {
+        None => Ok(cond),
+        Some(')') => Err(p.error("Unmatched `)` in condition.")),
+        Some(_) => Err(p.error("Expected `&&`, `||` or the end of the condition.")),
+    }
+}
+
+struct CondParser<'a> {
+    src: &'a str,
+    /// index in `src`
+    pos: usize,
+    file: FileId,
+    offset: BytePos,
+}
+impl CondParser<'_> {
+    fn parse_or(&mut self) -> Result<Cond, CondError> {
+        let mut l = self.parse_and()?;
+        while self.eat("||") {
+            let r = self.parse_and()?;
+            l = Cond::Or(Box::new(l), Box::new(r));
+        }
+        Ok(l)
+    }
+    fn parse_and(&mut self) -> Result<Cond, CondError> {
+        let mut l = self.parse_unary()?;
+        while self.eat("&&") {
+            let r = self.parse_unary()?;
+            l = Cond::And(Box::new(l), Box::new(r));
+        }
+        Ok(l)
+    }
+    fn parse_unary(&mut self) -> Result<Cond, CondError> {
+        self.skip_ws();
+        if self.eat("(") {
+            let c = self.parse_or()?;
+            if !self.eat(")") {
+                return Err(self.error("Expected `)` in condition."));
+            }
+            return Ok(c);
+        }
+        let start = self.pos;
+        if self.rest().starts_with('!') && !self.rest().starts_with("!=") {
+            self.pos += 1;
+            return Ok(Cond::Not(Box::new(self.parse_unary()?)));
+        }
+        let dim = match self.ident() {
+            Some(d) if d.starts_with(Lexer::is_varstart) => d,
+            _ => return Err(self.error("Expected a dimension name in condition.")),
+        };
+        let negated = if self.eat("!=") {
+            true
+        } else if self.eat("=") {
+            false
+        } else {
+            return Err(self.error(&format!("Expected `=` or `!=` after `{}` in condition.", dim)));
+        };
+        self.skip_ws();
+        let choice_start = self.pos;
+        let choice = match self.ident() {
+            Some(c) if c.starts_with(Lexer::is_varstart) => Choice::Name(c),
+            Some(c) => match c.parse::<ChoiceIdx>() {
+                Ok(i) => Choice::Num(i),
+                _ => return Err(self.error_at(&format!("`{}` is not a valid choice.", c), choice_start, self.pos)),
+            },
+            None => return Err(self.error(&format!("Expected a choice of `{}` in condition.", dim))),
+        };
+        let span = self.span(start, self.pos);
+        Ok(Cond::Is { dim, choice, negated, span })
+    }
+
+    fn rest(&self) -> &str {
+        &self.src[self.pos..]
+    }
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+    fn skip_ws(&mut self) {
+        let r = self.rest();
+        self.pos += r.len() - r.trim_start().len();
+    }
+    /// skips the whitespace, then `s` if it's next
+    fn eat(&mut self, s: &str) -> bool {
+        self.skip_ws();
+        if self.rest().starts_with(s) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+    /// alphanumerics or `_`, after whitespace
+    fn ident(&mut self) -> Option<Name> {
+        self.skip_ws();
+        let r = self.rest();
+        let len = r.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(r.len());
+        if len == 0 {
+            return None;
+        }
+        let ident = r[..len].into();
+        self.pos += len;
+        Some(ident)
+    }
+    /// span in the file of `src[lo..hi]`
+    fn span(&self, lo: usize, hi: usize) -> Span {
+        span_in(self.file, self.offset + BytePos::from(lo), self.offset + BytePos::from(hi))
+    }
+    /// error spanning the next character
+    fn error(&self, msg: &str) -> CondError {
+        self.error_at(msg, self.pos, self.pos + self.peek().map_or(0, char::len_utf8))
+    }
+    fn error_at(&self, msg: &str, lo: usize, hi: usize) -> CondError {
+        CondError { msg: msg.into(), span: self.span(lo, hi) }
+    }
+}
@@ -7,6 +7,9 @@ pub enum Error {
     NonTerminatedToken,
     IllegalCharacter,
     UnclosedDelimiter,
+    DuplicateLabel,
+    InvalidCondition,
+    InvalidBlock,
     UnexpectedEOF,
     FatalError,
     LexerError,
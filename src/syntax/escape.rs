@@ -0,0 +1,55 @@
+//! Escaping plain text so it can be used as a template.
+//!
+//! [`escape`] is the inverse of the lexer's handling of escapes: for any `s`, lexing and writing
+//! `escape(s)` yields `s` back, whether or not the result is put inside of a dimension.
+//! [`unescape`] is the inverse of [`escape`].
+use super::{Delimiters, Lexer};
+
+/// escapes all the sequences that would be interpreted by the lexer:
+/// `#` starting `#DIMID{`, `##`, `#!` or `#$`, `}` in `}#`, and `\` in `\#`, `\}`, `\\`, `\~` or `\:`.
+/// This is conservative, i.e. `#foo` is escaped even if it's not followed by `{`, and those
+/// characters are also escaped at the end of `src` so the result can be followed by any template text.
+pub fn escape(src: &str) -> String {
+    escape_with(src, Delimiters::default())
+}
+
+/// same as [`escape`] for templates lexed with other delimiters, see [`Lexer::with_delimiters`]
+pub fn escape_with(src: &str, delims: Delimiters) -> String {
+    let Delimiters { sigil, close, var, .. } = delims;
+    let mut buf = String::with_capacity(src.len());
+    let mut it = src.chars().peekable();
+    while let Some(c) = it.next() {
+        let next = it.peek().cloned();
+        let special = match (c, next) {
+            (c, None) if c == sigil || c == close || c == '\\' => true,
+            (c, Some(n)) if c == sigil => n == sigil || n == var || n == '!' || Lexer::is_varstart(n),
+            (c, Some(n)) if c == close => n == sigil,
+            ('\\', Some(n)) => n == sigil || n == close || n == '\\' || n == '~' || n == ':',
+            _ => false,
+        };
+        if special {
+            buf.push('\\');
+        }
+        buf.push(c);
+    }
+    buf
+}
+
+/// removes the escapes (`\#`, `\}` and `\\`), any other `\` is kept as is.
+pub fn unescape(src: &str) -> String {
+    let mut buf = String::with_capacity(src.len());
+    let mut it = src.chars().peekable();
+    while let Some(c) = it.next() {
+        if c == '\\' {
+            if let Some(&next) = it.peek() {
+                if next == '#' || next == '}' || next == '\\' {
+                    buf.push(next);
+                    it.next();
+                    continue;
+                }
+            }
+        }
+        buf.push(c);
+    }
+    buf
+}
@@ -1,27 +1,48 @@
 //! The Lexer module
 //!
-//! There are 4 meaningful tokens, anything else is considered text:
+//! There are 9 meaningful tokens, anything else is considered text:
 //! - `#DIMID{` dimension opening delimiter where `DIMID` is made of alphanumerics and underscore `_`. Cannot start with numeric.
+//! - `#when(COND){` guard opening delimiter, `COND` can't contain `#` or newlines (see [`crate::syntax::cond`])
+//! - `#def(DIMID){` named block opening delimiter
+//! - `#use(DIMID, IDENTIFIER=value...)#` use of a named block, the arguments can't contain `#` or newlines
 //! - `##` choices separator
+//! - `##!` default choice separator, the last choice of a dimension is selected when the decision is out of range
 //! - `}#` dimension closing delimiter
-//! - `#$IDENTIFIER#` variables where `IDENTIFIER` is made of alphanumeric characters or `!%&'*+-./:<=>?@_`
+//! - `#! comment !#` comments, they can contain anything (even newlines) but `!#` and are never written.
+//!   a `#!` without a `!#` after it is text, e.g. a shebang.
+//! - `#$IDENTIFIER#` variables where `IDENTIFIER` is made of alphanumeric characters or `!%&'*+-./:<=>?@_`.
+//!   `#$IDENTIFIER:-default#` gives a default value, which can contain anything but `#` and newlines.
 //!
-//! For now, there are two escapes (`\#` and `\\`), separators (`##`) need not to be escaped *outside* of dimensions.
+//! For now, there are three escapes (`\#`, `\}` and `\\`), separators (`##`) need not to be escaped *outside* of dimensions.
+//! Inside of dimensions `\~` produces nothing, it marks a choice as intentionally empty (e.g. `#dim{foo##\~}#`),
+//! and `\:` avoids a choice starting with `word: ` to be read as a label (see [`crate::syntax::parser`]).
+//!
+//! Inside of dimensions, a `-` right after an opening delimiter or `##` (`#dim{-`, `##-`), or right before `}#`, `##` or `##!`
+//! (`-}#`, `-##`) trims the whitespace and newlines on its side: it is left out of the text tokens, so it is never written.
+//! `\~` separates a `-` which isn't a trim marker from the delimiter (e.g. `#sign{+##-\~}#`).
+//!
+//! The delimiter characters themselves (`#`, `{`, `}` and `$`) can be replaced, see [`Delimiters`].
 //!
 //! @TODO whitespace escape  
-//! @TODO escape first whitespace after `#..{`, before `}#` and around `##`.  
 //! @TODO allow newline escapes inside dimensions
 
 use core::str::Chars;
 
-use crate::error::Handler;
-use crate::sourcemap::{span, BytePos, Spanned};
+use crate::error::{codes, Handler};
+use crate::sourcemap::{span_in, BytePos, FileId, Span, Spanned};
+use crate::syntax::marker_analysis::Markers;
 
 /// parser error
 pub struct Lexer<'a> {
     /// error handling
     pub handler: &'a mut Handler,
+    /// the whole source, see [`Self::bump_to_candidate`]
+    input: &'a str,
+    /// positions in `input` of every `#`, `}` and `\\`. see [`Markers`]
+    markers: Markers<'a>,
     src: Chars<'a>,
+    /// the file of the spans
+    file: FileId,
     /// current position in reader (index of `current`)
     pos: BytePos,
     /// next token = peek0
@@ -30,6 +51,12 @@ pub struct Lexer<'a> {
     current: Option<char>,
     /// number of Open dimension delimiters
     nest: usize, // @NOTE usize is probably overkill
+    /// the characters of the delimiters, `#`, `{`, `}` and `$` by default
+    delims: Delimiters,
+    /// there's no `!#` after the last `#!` looked at, so the next ones aren't comments either
+    no_comment_end: bool,
+    /// the `!#` found by the last [`Self::comment_end`], the first one after the `#!` it looked at
+    comment_close: Option<usize>,
 
     /// @REFACTOR
     failure: bool,
@@ -43,12 +70,22 @@ static VAR_SYMS: [char; 16] = [
 
 impl<'a> Lexer<'a> {
     /// `Lexer.prev` is not valid, set to null
-    pub fn new(h: &'a mut Handler, input: &'a str, offset: BytePos) -> Lexer<'a> {
+    pub fn new(h: &'a mut Handler, input: &'a str, file: FileId) -> Lexer<'a> {
+        Self::with_delimiters(h, input, file, Delimiters::default())
+    }
+    /// same as [`Self::new`] with other delimiters, they have to be [`Delimiters::validate`]d
+    pub fn with_delimiters(h: &'a mut Handler, input: &'a str, file: FileId, delims: Delimiters) -> Lexer<'a> {
         let mut l = Lexer {
+            input,
+            markers: Markers::new(input, delims.markers()),
             src: input.chars(),
+            file,
             // current position, therefore the index of the result of getc()
-            pos: offset,
+            pos: BytePos(0),
             nest: 0,
+            delims,
+            no_comment_end: false,
+            comment_close: None,
             handler: h,
             current: None,
             next: None,
@@ -58,6 +95,13 @@ impl<'a> Lexer<'a> {
         l.next = l.src.next();
         l
     }
+    /// a token of [`Self::file`]
+    fn token(&self, kind: TokenK, lo: BytePos, hi: BytePos) -> Token {
+        Spanned { node: kind, span: self.span(lo, hi) }
+    }
+    fn span(&self, lo: BytePos, hi: BytePos) -> Span {
+        span_in(self.file, lo, hi)
+    }
     /// did we encounter a failing lexing error
     pub fn failed(&self) -> bool {
         self.failure
@@ -70,69 +114,96 @@ impl<'a> Lexer<'a> {
     /// bumps the src iterator, sets [`Self::current`] and [`Self::next`], increments [`Self::pos`] based on current.
     /// returns the [`Self::current`]
     fn bump(&mut self) -> Option<char> {
+        // the position of the new current is right after the old one. Does not move past EOF.
+        self.pos += self.current.map_or(0, char::len_utf8);
         self.current = self.next;
         self.next = self.src.next();
-        // @FIXME don't increment more than once
-        // @FIXME is this correct... if we're None it should be zero???
-        self.pos += self.current.map_or(1, char::len_utf8);
-        self.current.clone()
+        self.current
+    }
+    /// like [`Self::bump`], but skips all the characters that can't start a lexeme:
+    /// jumps to the next `#`, `}` or `\` (or EOF) after current, and returns the new current.
+    /// This is sound because these are all ascii, i.e. never part of a multi-byte character.
+    fn bump_to_candidate(&mut self) -> Option<char> {
+        let from = self.pos.as_usize() + self.current.map_or(0, char::len_utf8);
+        // skips the markers that have already been consumed by other means (e.g. [`Self::bump`])
+        let to = self.markers.first_from(from).unwrap_or(self.input.len());
+        self.seek(to)
+    }
+    /// moves to `input[to]`, which starts a character, and returns the new current
+    fn seek(&mut self, to: usize) -> Option<char> {
+        self.src = self.input[to..].chars();
+        self.pos = BytePos::from(to);
+        self.current = self.src.next();
+        self.next = self.src.next();
+        self.current
     }
     /// lexes the next token
     pub fn next_token(&mut self) -> Token {
+        let Delimiters { sigil, close, var, .. } = self.delims;
         let mut start = self.pos;
         match self.current {
-            None => return Spanned::new(EOF, start, self.pos),
+            None => return self.token(EOF, start, self.pos),
             Some('\\') => match self.peek0() {
-                '#' | '}' | '\\' => {
+                c if c == sigil || c == close || c == '\\' => {
                     // we ignore the `\`, by updating `start` after eating it
                     self.bump();
                     start = self.pos;
                 }
+                // not a label, the text starts at ':'
+                ':' if self.nest > 0 => {
+                    self.bump();
+                    start = self.pos;
+                }
+                // empty choice marker, produces an empty text token
+                '~' if self.nest > 0 => {
+                    self.bump(); // eat '\'
+                    self.bump(); // eat '~'
+                    return self.token(Text, self.pos, self.pos);
+                }
                 _ => {}
             },
             // eat the '#' to avoid double `self.bump` in helper functions?
-            Some('#') => match self.peek0() {
-                '#' => {
-                    if self.nest > 0 {
-                        return self.lex_sepd(start);
-                    }
-                }
-                '$' => return self.lex_var(start),
+            Some(c) if c == sigil => match self.peek0() {
+                c if c == sigil && self.nest > 0 => return self.lex_sepd(start),
+                c if c == var => return self.lex_var(start),
                 c if Self::is_varstart(c) => {
                     if let Some(opend) = self.lex_opend_maybe(start) {
                         return opend;
                     }
                 }
+                '!' => {
+                    if let Some(end) = self.comment_end() {
+                        self.seek(end);
+                        return self.token(Comment, start, self.pos);
+                    }
+                }
                 // if None => return txt ?
                 _ => {} // fallthrough
             },
-            Some('}') => {
-                if self.next == Some('#') {
-                    return self.lex_closed(start);
-                }
-            }
+            Some(c) if c == close && self.next == Some(sigil) => return self.lex_closed(start),
             _ => {} // fall-through
         }
         // current isn't a meaningful lexeme start, so we can consume txt until next token
-        while let Some(c) = self.bump() {
+        while let Some(c) = self.bump_to_candidate() {
             match c {
-                '#' => match self.peek0() {
-                    '#' => {
+                c if c == sigil => match self.peek0() {
+                    c if c == sigil => {
                         if self.nest > 0 {
-                            return self.lex_txt(start);
+                            return self.lex_txt_trimmed(start);
                         }
                     }
-                    '$' => return self.lex_txt(start),
+                    c if c == var => return self.lex_txt(start),
                     c if Self::is_varstart(c) => return self.lex_txt(start), // can we avoid this
+                    '!' if self.comment_end().is_some() => return self.lex_txt(start),
                     _ => continue,
                 },
                 '\\' => {
                     return self.lex_txt(start);
                 }
                 // an escape is a meaningful token because we want 2 differnet text spans
-                '}' => {
-                    if self.peek0() == '#' {
-                        return self.lex_txt(start);
+                c if c == close => {
+                    if self.peek0() == sigil {
+                        return self.lex_txt_trimmed(start);
                     }
                 }
                 _ => continue,
@@ -141,8 +212,34 @@ impl<'a> Lexer<'a> {
         if start != self.pos {
             self.lex_txt(start)
         } else {
-            Spanned::new(EOF, start, self.pos)
+            self.token(EOF, start, self.pos)
+        }
+    }
+
+    /// the end in `input` of the comment starting at the current `#!`, after its `!#`.
+    /// An unterminated `#!` (e.g. a shebang) is text
+    fn comment_end(&mut self) -> Option<usize> {
+        if self.no_comment_end {
+            return None;
         }
+        let from = self.pos.as_usize() + 2;
+        // still the first `!#` after `from`, so the input is only searched once
+        let close = match self.comment_close.filter(|&c| c >= from) {
+            Some(c) => c,
+            None => {
+                let rest = &self.input[from..];
+                let sigil = self.delims.sigil;
+                match rest.match_indices('!').find(|(i, _)| rest[i + 1..].starts_with(sigil)) {
+                    Some((i, _)) => from + i,
+                    None => {
+                        self.no_comment_end = true;
+                        return None;
+                    }
+                }
+            }
+        };
+        self.comment_close = Some(close);
+        Some(close + 2)
     }
 
     pub fn is_varstart(c: char) -> bool {
@@ -153,7 +250,15 @@ impl<'a> Lexer<'a> {
     }
     /// Makes a [`TokenK::Text`] from `start` to `self.pos`, i.e. all of the Text has been "consumed"
     pub fn lex_txt(&self, start: BytePos) -> Token {
-        Token::new(Text, start, self.pos)
+        self.token(Text, start, self.pos)
+    }
+    /// same as [`Self::lex_txt`] before `}#` or `##`, without a `-` trim marker and the whitespace before it.
+    fn lex_txt_trimmed(&self, start: BytePos) -> Token {
+        let txt = &self.input[start.as_usize()..self.pos.as_usize()];
+        match txt.strip_suffix('-') {
+            Some(rest) if self.nest > 0 => self.token(Text, start, start + BytePos::from(rest.trim_end().len())),
+            _ => self.lex_txt(start),
+        }
     }
     pub fn lex_var(&mut self, start: BytePos) -> Token {
         let mut ill_char = false;
@@ -161,43 +266,56 @@ impl<'a> Lexer<'a> {
         self.bump(); // eat '#'
         self.bump(); // eat '$'
         while let Some(c) = self.bump() {
-            if Self::is_varsymbol(c) {
+            if c == ':' && self.peek0() == '-' {
+                return self.lex_var_default(start);
+            } else if Self::is_varsymbol(c) {
                 continue;
-            } else if c == '#' {
+            } else if c == self.delims.sigil {
                 self.bump(); // eat it
-                return Token::new(Var, start, self.pos);
+                return self.token(Var, start, self.pos);
             } else if c.is_whitespace() {
-                self.handler
-                    .error("Non-terminated variable. Expected `#`, Found whitespace instead.")
-                    .with_span(span(self.pos, self.pos))
-                    .at_span("add `#` here")
-                    .note("Variables have the following syntax: `#$variable#`")
-                    .print();
-                // return a wrong Var token, consumer of the TokenStream should check errors
-                // @FIXME why did I do this again?
-                return Token::new(Var, start, self.pos);
+                return self.lex_unterminated_var(start);
             } else if !ill_char {
                 // if we get none-whitespace illegal characters, and the variable token is still correctly terminated
                 // we can continue parsing
+                let at = self.span(self.pos, self.pos);
                 self.handler
                     .error(format!("Unexpected `{}` in variable name.", c).as_ref())
-                    .with_span(span(self.pos, self.pos))
+                    .code(codes::ILLEGAL_CHARACTER)
+                    .with_span(at)
                     .note(Self::identifier_note().as_ref())
                     .print();
                 ill_char = true;
             }
         }
+        self.lex_unterminated_var(start)
+    }
+    /// the rest of a variable after `:-`, see [`Self::lex_var`]
+    fn lex_var_default(&mut self, start: BytePos) -> Token {
+        self.bump(); // eat ':', the loop eats the '-' first
+        while let Some(c) = self.bump() {
+            if c == self.delims.sigil {
+                self.bump(); // eat it
+                return self.token(Var, start, self.pos);
+            } else if c == '\n' {
+                break;
+            }
+        }
+        self.lex_unterminated_var(start)
+    }
+    /// reports a variable that is never terminated (by whitespace or EOF) and recovers by treating
+    /// what has been consumed so far as [`TokenK::Text`]. Consumers of the TokenStream should check errors.
+    fn lex_unterminated_var(&mut self, start: BytePos) -> Token {
+        let at = self.span(start, start + 2);
         self.handler
-            .error("Non-terminated variable, expected `#`.")
-            .with_span(span(start, start + 2))
+            .error("Variable started here, never terminated.")
+            .code(codes::UNCLOSED_DELIMITER)
+            .with_span(at)
             .at_span("variable starts here")
-            .note("Variables have the following syntax: `#$VAR_NAME#`")
+            .suggest("did you forget `#`?")
+            .note("Variables have the following syntax: `#$VAR_NAME#` or `#$VAR_NAME:-default#`")
             .print();
-        self.failure = true;
-        // aborting here should be necessary because we're already at the end of the stream.
-        // but dunno of a clean way
-        assert!(false);
-        Token::new(Var, start, self.pos)
+        self.token(Text, start, self.pos)
     }
     pub fn lex_opend_maybe(&mut self, start: BytePos) -> Option<Token> {
         // eat opening '#'
@@ -205,10 +323,20 @@ impl<'a> Lexer<'a> {
         while let Some(c) = self.current {
             if c.is_alphanumeric() || c == '_' {
                 // fallthrough
-            } else if c == '{' {
+            } else if c == '(' {
+                let kind = match &self.input[start.as_usize() + 1..self.pos.as_usize()] {
+                    "when" => Opwhen,
+                    "def" => Opdef,
+                    "use" => Use,
+                    _ => return None,
+                };
+                return self.lex_parens_maybe(start, kind);
+            } else if c == self.delims.open {
                 self.bump(); // eat '{'
                 self.nest += 1;
-                return Some(Token::new(Opend, start, self.pos));
+                let opend = self.token(Opend, start, self.pos);
+                self.trim_after();
+                return Some(opend);
             } else {
                 return None;
             }
@@ -216,18 +344,59 @@ impl<'a> Lexer<'a> {
         }
         None
     }
+    /// the parenthesized part of `#when(COND){`, `#def(NAME){` or `#use(NAME, ..)#`, after the keyword.
+    /// Not a token if there's a `#` or a newline before `){` (or `)#` for [`TokenK::Use`]).
+    fn lex_parens_maybe(&mut self, start: BytePos, kind: TokenK) -> Option<Token> {
+        let close = if kind == Use { self.delims.sigil } else { self.delims.open };
+        loop {
+            // stop before, the `#` can start another token
+            if self.peek0() == self.delims.sigil || self.peek0() == '\n' {
+                return None;
+            }
+            match self.bump() {
+                Some(')') if self.peek0() == close => {
+                    self.bump(); // eat ')'
+                    self.bump(); // eat '{' or '#'
+                    let token = self.token(kind, start, self.pos);
+                    if kind != Use {
+                        self.nest += 1;
+                        self.trim_after();
+                    }
+                    return Some(token);
+                }
+                Some(_) => {}
+                None => return None,
+            }
+        }
+    }
     pub fn lex_closed(&mut self, start: BytePos) -> Token {
         // Just prevent underflow. The parser will catch the error.
         // should be asserts?
         self.bump(); // eat '}'
         self.bump(); // eat '#'
         self.nest = std::cmp::max(self.nest, 1) - 1;
-        Token::new(Closed, start, self.pos)
+        self.token(Closed, start, self.pos)
     }
     pub fn lex_sepd(&mut self, start: BytePos) -> Token {
         self.bump(); // eat the '#'
         self.bump(); // eat the '#'
-        Token::new(Sepd, start, self.pos)
+        if self.current == Some('!') {
+            self.bump(); // eat the '!'
+            return self.token(SepDefault, start, self.pos);
+        }
+        let sepd = self.token(Sepd, start, self.pos);
+        self.trim_after();
+        sepd
+    }
+    /// eats a `-` trim marker and the whitespace after it, which aren't part of any token.
+    /// see the [module documentation](self)
+    fn trim_after(&mut self) {
+        if self.current == Some('-') {
+            self.bump(); // eat the '-'
+            while self.current.is_some_and(char::is_whitespace) {
+                self.bump();
+            }
+        }
     }
 
     fn identifier_note() -> String {
@@ -247,6 +416,51 @@ impl<'a> Lexer<'a> {
     }
 }
 
+/// the characters replacing `#`, `{`, `}` and `$` in all the tokens, e.g. `^os[a^^b]^` and `^$var^`
+/// with `^`, `[`, `]` and `$`. See [`crate::cfg::Syntax`].  
+/// They are single ascii characters, so the tokens keep their lengths.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub struct Delimiters {
+    /// starts and ends all the tokens, `#`
+    pub sigil: char,
+    /// ends the opening delimiters, `{`
+    pub open: char,
+    /// starts the closing delimiter, `}`
+    pub close: char,
+    /// starts variables after the sigil, `$`
+    pub var: char,
+}
+impl Default for Delimiters {
+    fn default() -> Self {
+        Delimiters { sigil: '#', open: '{', close: '}', var: '$' }
+    }
+}
+impl Delimiters {
+    /// the bytes which can start a lexeme, see [`Markers`]
+    pub fn markers(&self) -> [u8; 3] {
+        // @SAFETY: `validate` only accepts ascii characters
+        [self.sigil as u8, self.close as u8, b'\\']
+    }
+    /// why the delimiters can't be lexed unambiguously, if they can't
+    pub fn validate(&self) -> Result<(), String> {
+        let all = [("sigil", self.sigil), ("open", self.open), ("close", self.close), ("var", self.var)];
+        // reserved by the escapes, the trim markers and the guards
+        let reserved = ['\\', '~', ':', '-', '!', '(', ')'];
+        for (i, (name, c)) in all.iter().enumerate() {
+            if !c.is_ascii_punctuation() || *c == '_' || reserved.contains(c) {
+                return Err(format!("`{}` can't be the `{}` delimiter, delimiters are ascii punctuation but `_\\~:-!()`.", c, name));
+            }
+            if let Some((other, _)) = all[..i].iter().find(|(_, o)| o == c) {
+                return Err(format!("`{}` is both the `{}` and the `{}` delimiters.", c, other, name));
+            }
+        }
+        if VAR_SYMS.contains(&self.sigil) {
+            return Err(format!("`{}` can't be the sigil, it can be part of a variable name.", self.sigil));
+        }
+        Ok(())
+    }
+}
+
 /// a Spanned Token Kind
 pub type Token = Spanned<TokenK>;
 
@@ -256,7 +470,7 @@ impl Token {
     }
     /// is the token related to dimension or eof?
     pub fn is_dimension_or_eof(&self) -> bool {
-        !(self.is(Var) || self.is(Text))
+        !(self.is(Var) || self.is(Text) || self.is(Comment))
     }
     /// @NOTE copying should be cheap, or is derefing cheaper?
     pub fn is(&self, k: TokenK) -> bool {
@@ -268,7 +482,7 @@ impl Token {
 }
 impl Default for Token {
     fn default() -> Token {
-        Token::new(EOF, BytePos::from(0), BytePos::from(0))
+        Spanned::new(EOF, BytePos::from(0), BytePos::from(0))
     }
 }
 
@@ -280,10 +494,20 @@ pub enum TokenK {
     Var,
     /// `#id{`
     Opend,
+    /// `#when(cond){`
+    Opwhen,
+    /// `#def(name){`
+    Opdef,
+    /// `#use(name, var=value)#`
+    Use,
+    /// `#! comment !#`
+    Comment,
     /// `}#`
     Closed,
     /// `##`
     Sepd,
+    /// `##!`
+    SepDefault,
     EOF,
 }
 #[doc(hidden)]
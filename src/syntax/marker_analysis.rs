@@ -0,0 +1,167 @@
+//! Pre-pass finding the positions of all the bytes that can start a lexeme (`#`, `}` and `\` by default,
+//! see [`super::lexer::Delimiters::markers`]).
+//!
+//! Mirrors [`crate::sourcemap::source_analysis`], the [`super::Lexer`] only inspects these offsets
+//! and skips everything in between as text.
+
+/// the markers of a source in order, found one block at a time so only the ones of a block are held at once.
+/// The blocks are scanned when they are reached, a block that is skipped over (see [`Self::first_from`]) isn't.
+pub struct Markers<'a> {
+    src: &'a [u8],
+    needles: [u8; 3],
+    /// end of the scanned bytes
+    scanned: usize,
+    /// the markers of the last scanned block
+    block: Vec<usize>,
+    /// index in `block` of the next marker
+    next: usize,
+}
+impl<'a> Markers<'a> {
+    /// bytes scanned at once
+    const BLOCK_SIZE: usize = 4096;
+    pub fn new(src: &'a str, needles: [u8; 3]) -> Self {
+        Markers { src: src.as_bytes(), needles, scanned: 0, block: Vec::new(), next: 0 }
+    }
+    /// the first marker at or after `from`, which stays the next one
+    pub fn first_from(&mut self, from: usize) -> Option<usize> {
+        if from > self.scanned {
+            // everything before `from` is skipped
+            self.block.clear();
+            self.next = 0;
+            self.scanned = from.min(self.src.len());
+        }
+        loop {
+            match self.block.get(self.next) {
+                Some(&m) if m < from => self.next += 1,
+                Some(&m) => return Some(m),
+                None if self.scanned >= self.src.len() => return None,
+                None => self.scan_block(),
+            }
+        }
+    }
+    fn scan_block(&mut self) {
+        let end = (self.scanned + Self::BLOCK_SIZE).min(self.src.len());
+        self.block.clear();
+        self.next = 0;
+        anal_markers(&self.src[self.scanned..end], self.needles, &mut self.block);
+        let offset = self.scanned;
+        self.block.iter_mut().for_each(|m| *m += offset);
+        self.scanned = end;
+    }
+}
+impl Iterator for Markers<'_> {
+    type Item = usize;
+    fn next(&mut self) -> Option<usize> {
+        let m = self.first_from(0)?;
+        self.next += 1;
+        Some(m)
+    }
+}
+
+/// adds the positions (relative to `src`) of all the markers to `markers`, using the best available implementation.
+pub fn anal_markers(src: &[u8], needles: [u8; 3], markers: &mut Vec<usize>) {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // @SAFETY: avx2 is available
+            unsafe { anal_markers_avx2(src, needles, markers) };
+            return;
+        } else if is_x86_feature_detected!("sse2") {
+            // @SAFETY: sse2 is available
+            unsafe { anal_markers_sse2(src, needles, markers) };
+            return;
+        }
+    }
+    anal_markers_slow(src, 0, needles, markers);
+}
+
+/// [`anal_markers`] 16 bytes at a time.
+///
+/// # Safety
+/// The CPU has to support `sse2`, e.g. checked with `is_x86_feature_detected!("sse2")`.
+#[target_feature(enable = "sse2")]
+pub unsafe fn anal_markers_sse2(src: &[u8], needles: [u8; 3], markers: &mut Vec<usize>) {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    const CHUNK_SIZE: usize = 16;
+    let src_bytes = src;
+    let chunk_count = src.len() / CHUNK_SIZE;
+
+    for chunk_index in 0..chunk_count {
+        let ptr = src_bytes.as_ptr() as *const __m128i;
+        // loadu because we don't know if aligned to 16bytes
+        let chunk = _mm_loadu_si128(ptr.add(chunk_index));
+
+        let hash = _mm_cmpeq_epi8(chunk, _mm_set1_epi8(needles[0] as i8));
+        let brace = _mm_cmpeq_epi8(chunk, _mm_set1_epi8(needles[1] as i8));
+        let bslash = _mm_cmpeq_epi8(chunk, _mm_set1_epi8(needles[2] as i8));
+        let mask = _mm_movemask_epi8(_mm_or_si128(hash, _mm_or_si128(brace, bslash)));
+
+        if mask != 0 {
+            // set the 16 irrelevant msb to '1'
+            let mut mask = 0xFFFF0000 | mask as u32;
+            let offset = chunk_index * CHUNK_SIZE;
+            loop {
+                let i = mask.trailing_zeros();
+                if i >= CHUNK_SIZE as u32 {
+                    // end of chunk
+                    break;
+                }
+                markers.push(offset + i as usize);
+                mask &= (!1) << i;
+            }
+        }
+    }
+    // non aligned bytes on tail
+    anal_markers_slow(src, chunk_count * CHUNK_SIZE, needles, markers);
+}
+
+/// [`anal_markers`] 32 bytes at a time.
+///
+/// # Safety
+/// The CPU has to support `avx2`, e.g. checked with `is_x86_feature_detected!("avx2")`.
+#[target_feature(enable = "avx2")]
+pub unsafe fn anal_markers_avx2(src: &[u8], needles: [u8; 3], markers: &mut Vec<usize>) {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    const CHUNK_SIZE: usize = 32;
+    let src_bytes = src;
+    let chunk_count = src.len() / CHUNK_SIZE;
+
+    for chunk_index in 0..chunk_count {
+        let ptr = src_bytes.as_ptr() as *const __m256i;
+        // loadu because we don't know if aligned to 32bytes
+        let chunk = _mm256_loadu_si256(ptr.add(chunk_index));
+
+        let hash = _mm256_cmpeq_epi8(chunk, _mm256_set1_epi8(needles[0] as i8));
+        let brace = _mm256_cmpeq_epi8(chunk, _mm256_set1_epi8(needles[1] as i8));
+        let bslash = _mm256_cmpeq_epi8(chunk, _mm256_set1_epi8(needles[2] as i8));
+        let mask = _mm256_movemask_epi8(_mm256_or_si256(hash, _mm256_or_si256(brace, bslash)));
+
+        if mask != 0 {
+            let mut mask = mask as u32;
+            let offset = chunk_index * CHUNK_SIZE;
+            // no irrelevant bits, all 32 are used
+            while mask != 0 {
+                let i = mask.trailing_zeros();
+                markers.push(offset + i as usize);
+                mask &= mask - 1;
+            }
+        }
+    }
+    // non aligned bytes on tail
+    anal_markers_slow(src, chunk_count * CHUNK_SIZE, needles, markers);
+}
+
+/// finds the markers in `src[from..]`
+pub fn anal_markers_slow(src: &[u8], from: usize, needles: [u8; 3], markers: &mut Vec<usize>) {
+    let bytes = &src[from..];
+    let [a, b, c] = needles;
+    markers.extend(memchr::memchr3_iter(a, b, c, bytes).map(|i| from + i));
+}
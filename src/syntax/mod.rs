@@ -1,16 +1,27 @@
 //! parser and lexer modules
+pub mod cond;
 pub mod errors;
+pub mod escape;
 pub mod lexer;
+pub mod marker_analysis;
 pub mod parser;
+pub mod printer;
+pub mod symbols;
 // pub use lexer::{Lexer, Token, TokenK};
 
+#[doc(inline)]
+pub use cond::Cond;
 #[doc(inline)]
 pub use errors::Error;
 #[doc(inline)]
-pub use lexer::Lexer;
+pub use lexer::{Delimiters, Lexer};
 #[doc(inline)]
 pub use parser::{Name, Term, TermK, Terms};
 #[doc(inline)]
+pub use symbols::Symbol;
+#[doc(inline)]
 pub use parser::{Parsed, Parser, TokenStream};
+#[doc(inline)]
+pub use parser::terms_mem_size;
 
 pub use crate::sourcemap::Spanned;
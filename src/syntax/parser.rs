@@ -6,21 +6,39 @@
 //! ```bnf
 //! Terms := Term*
 //! Term  :=  #$IDENTIFIER#                      // variables
-//!        | `#DIMID{` Terms (`##` Terms)* `}#`  // Dimensions
+//!        |  #$IDENTIFIER:-DEFAULT#             // variables with a default value if unset
+//!        | `#DIMID{` Choice (`##` Choice)* (`##!` Terms)? `}#`  // Dimensions, with an optional default
+//!        | `#when(` COND `){` Terms (`##` Terms)? `}#`          // Guards, with an optional else branch
+//!        | `#def(` DIMID `){` Terms `}#`                         // named blocks, written where they are used
+//!        | `#use(` DIMID (`,` IDENTIFIER `=` ARG)* `)#`          // uses of a named block
 //!        |  Text                               // anything else
 //!
+//! Choice := (DIMID `:`)? Terms                 // optionally labelled, e.g. `#os{linux: foo ## windows: bar}#`
 //! DIMID := (alpha | `_`)(alphanumeric | `_`)*
 //! IDENTIFIER := (alphanumeric | [!%&'*+-./:<=>?@_])+
+//! DEFAULT := [^#\n]*
+//! ARG := [^#,\n]*                              // trimmed
+//! COND := see [`crate::syntax::cond`]
 //! ```
+//! A label can be preceded by whitespace, and must be followed by whitespace or the end of the text.
+//! The label, its leading whitespace and the `:` are removed from the choice.
+//!
+//! The texts of a named block are copied into [`TermK::Value`]s, so its terms can be replayed in any file.
+//!
+//! The parser recovers from syntax errors by skipping the rest of the delimiter they are in, so a single run
+//! reports all of them. See [`Parser::parse_partial`].
 //!
 //! A whole lot of ascii symbols are accepted in identifiers, probably too much, but we can and I figured it might
 //! be interresting to have variables names of paths to contain slashes for example.
+use std::borrow::Cow;
 use std::collections::VecDeque;
 use std::ops::Range;
 
-use crate::error::Handler;
+use crate::error::{codes, Handler, WarnKind};
 use crate::sourcemap::{BytePos, Span, Spanned};
-use crate::syntax::lexer::{Token, TokenK};
+use crate::syntax::cond::{self, Cond};
+use crate::syntax::lexer::{Lexer, Token, TokenK};
+use crate::syntax::symbols::Symbol;
 use crate::syntax::Error;
 
 /// type of a parsed expression
@@ -31,41 +49,75 @@ pub struct Parser<'a> {
     pub handler: &'a mut Handler,
     pub current_token: Token,
     pub tokens: TokenStream,
-    /// needed?
-    pub src: String,
+    /// borrowed from the [`crate::sourcemap::SrcFile`] when possible, so large files are resident only once
+    pub src: Cow<'a, str>,
     /// unmatched open delimiters
     pub nest: u8,
-    /// absolute position in source map
-    pub offset: BytePos,
+    /// the first syntax error, the tree is poisoned. see [`Self::parse_partial`]
+    pub error: Option<Error>,
 }
 impl Parser<'_> {
-    pub fn new<'a>(h: &'a mut Handler, input: String, ts: TokenStream, offset: BytePos) -> Parser<'a> {
+    pub fn new<'a, S: Into<Cow<'a, str>>>(h: &'a mut Handler, input: S, ts: TokenStream) -> Parser<'a> {
         let mut p = Parser {
             handler: h,
             current_token: Token::default(),
             tokens: ts,
-            src: input,
+            src: input.into(),
             nest: 0,
-            offset,
+            error: None,
         };
         p.next_token();
         p
     }
     /// entry function for new parser
+    /// all the syntax errors are reported, see [`Self::parse_partial`]
     pub fn parse(&mut self) -> Parsed<Terms> {
-        self.parse_terms().and_then(|ts| {
-            if self.handler.err_count > 0 {
-                // @TODO could be improved
-                // valid parse tree but errors => non fatal lexing errors
-                Err(Error::LexerError)
-            } else {
-                Ok(ts)
+        let (terms, _) = self.parse_partial();
+        match self.error {
+            Some(e) => Err(e),
+            // @TODO could be improved
+            // valid parse tree but errors => non fatal lexing errors
+            None if self.handler.err_count > 0 => Err(Error::LexerError),
+            None => Ok(terms),
+        }
+    }
+    /// parses the whole stream, recovering from the syntax errors to report all of them: the delimiter
+    /// an error is in is skipped up to its `}#` (or the unexpected token at the top level), and parsing
+    /// resumes after it.  
+    /// Returns the tree without the skipped delimiters, and whether it is poisoned, i.e. there were
+    /// syntax errors. The later phases must skip poisoned trees.
+    pub fn parse_partial(&mut self) -> (Terms, bool) {
+        let mut terms = Vec::new();
+        while let Err(e) = self.parse_terms_into(&mut terms) {
+            self.error.get_or_insert(e);
+            if self.current_token.is_eof() {
+                break;
+            }
+            self.resync();
+        }
+        (terms, self.error.is_some())
+    }
+    /// skips the token a syntax error is about, and the rest of the delimiters it is in
+    fn resync(&mut self) {
+        self.next_token();
+        while self.nest > 0 {
+            match self.current_token.kind() {
+                TokenK::Opend | TokenK::Opwhen | TokenK::Opdef => self.nest += 1,
+                TokenK::Closed => self.nest -= 1,
+                TokenK::EOF => self.nest = 0,
+                _ => {}
             }
-        })
+            self.next_token();
+        }
     }
     /// parse multiple Terms
     pub fn parse_terms(&mut self) -> Parsed<Terms> {
         let mut terms = Vec::new();
+        self.parse_terms_into(&mut terms)?;
+        Ok(terms)
+    }
+    /// same as [`Self::parse_terms`], but pushes the terms in `terms` so they are kept on errors
+    fn parse_terms_into(&mut self, terms: &mut Terms) -> Parsed<()> {
         loop {
             match self.current_token.kind() {
                 TokenK::Text => terms.push(self.parse_txt()?),
@@ -75,7 +127,19 @@ impl Parser<'_> {
                     let t = self.parse_dim()?;
                     terms.push(t);
                 }
-                k @ TokenK::Closed | k @ TokenK::Sepd => {
+                TokenK::Opwhen => {
+                    self.nest += 1;
+                    let t = self.parse_guard()?;
+                    terms.push(t);
+                }
+                TokenK::Opdef => {
+                    self.nest += 1;
+                    let t = self.parse_def()?;
+                    terms.push(t);
+                }
+                TokenK::Use => terms.push(self.parse_use()?),
+                TokenK::Comment => terms.push(Term::comment(self.current_token.span)),
+                k @ TokenK::Closed | k @ TokenK::Sepd | k @ TokenK::SepDefault => {
                     if self.nest == 0 {
                         self.handler
                             .error(
@@ -84,11 +148,13 @@ impl Parser<'_> {
                                     match k {
                                         TokenK::Closed => "Dimension closing delimiter",
                                         TokenK::Sepd => "Dimension branch separator",
+                                        TokenK::SepDefault => "Dimension default branch separator",
                                         _ => unreachable!(),
                                     }
                                 )
                                 .as_ref(),
                             )
+                            .code(codes::UNEXPECTED_TOKEN)
                             .with_span(self.current_token.span)
                             .delay();
                         return Err(Error::UnexpectedToken);
@@ -96,9 +162,9 @@ impl Parser<'_> {
                         self.nest -= 1;
                     }
                     // return all the terms so far
-                    return Ok(terms);
+                    return Ok(());
                 }
-                TokenK::EOF => return Ok(terms),
+                TokenK::EOF => return Ok(()),
             };
             self.next_token();
         }
@@ -108,7 +174,11 @@ impl Parser<'_> {
         let hi = self.src_idx(self.current_token.span.hi);
         // @SAFETY: span is guaranteed to be valid by lexer
         let name = unsafe { self.src.get_unchecked(Self::var_name_range(lo..hi)) };
-        Ok(Term::var(name.into(), self.current_token.span))
+        let (name, default) = match name.find(":-") {
+            Some(i) => (&name[..i], Some(name[i + 2..].into())),
+            None => (name, None),
+        };
+        Ok(Term::var(name.into(), default, self.current_token.span))
     }
     pub fn parse_txt(&self) -> Parsed<Term> {
         Ok(Term::text(self.current_token.span))
@@ -120,6 +190,7 @@ impl Parser<'_> {
             let x = match self.current_token.kind() {
                 TokenK::Text => self.parse_txt()?,
                 TokenK::Var => self.parse_var()?,
+                TokenK::Comment => Term::comment(self.current_token.span),
                 _ => unreachable!(),
             };
             xs.push(x);
@@ -132,7 +203,7 @@ impl Parser<'_> {
         let lo = self.src_idx(self.current_token.span.lo);
         let hi = self.src_idx(self.current_token.span.hi);
         // @TODO use get_unchecked instead?
-        match self.src.get(Self::dim_name_range(lo..hi)).map(String::from) {
+        match self.src.get(Self::dim_name_range(lo..hi)).map(Name::from) {
             Some(s) => s,
             None => unreachable!("empty dimension name"),
         }
@@ -142,21 +213,68 @@ impl Parser<'_> {
         let name = self.get_dim_name();
         self.next_token(); // eat Opend
         let mut cs = Vec::new();
+        let mut labels = Vec::new();
+        let mut default = None;
+        // the separator (or `}#`) after each choice
+        let mut seps = Vec::new();
         loop {
-            let c = self.parse_terms()?;
+            let mut c = self.parse_terms()?;
+            if default.is_none() {
+                seps.push(self.current_token.span);
+            }
+            // the terms after `##!` are the default branch
+            if default.is_some() {
+                default = Some(c);
+            } else {
+                let label = self.take_label(&mut c);
+                if let Some(l) = &label {
+                    if labels.contains(&label) {
+                        self.handler
+                            .error(format!("Label `{}` is used twice in dimension `{}`.", l, name).as_ref())
+                            .code(codes::DUPLICATE_LABEL)
+                            .with_span(start)
+                            .at_span("in this dimension")
+                            .suggest("use `\\:` if the choice isn't meant to be labelled.")
+                            .delay();
+                        return Err(Error::DuplicateLabel);
+                    }
+                }
+                labels.push(label);
+                cs.push(c);
+            }
             match self.current_token.kind() {
                 TokenK::Closed => {
-                    cs.push(c);
-                    return Ok(Term::dim(name, cs, start + self.current_token.span));
+                    // only keep the labels if there are some
+                    if labels.iter().all(Option::is_none) {
+                        labels = Vec::new();
+                    }
+                    for (i, _) in cs.iter().enumerate().filter(|(_, c)| c.is_empty()) {
+                        let msg = format!("Choice {} of dimension `{}` is empty.", i, name);
+                        let trailing = i > 0 && i + 1 == cs.len() && default.is_none();
+                        self.lint_empty_branch(&msg, (start, "in this dimension"), &seps, i, trailing);
+                    }
+                    return Ok(Term::dim(name, cs, labels, default, start + self.current_token.span));
+                }
+                TokenK::Sepd | TokenK::SepDefault if default.is_some() => {
+                    self.handler
+                        .error("The default branch must be the last one of the dimension.")
+                        .code(codes::UNEXPECTED_TOKEN)
+                        .with_span(self.current_token.span)
+                        .note("`##!` starts the default branch, which extends to `}#`.")
+                        .delay();
+                    return Err(Error::UnexpectedToken);
                 }
                 TokenK::Sepd => {
-                    cs.push(c);
                     self.next_token(); // eat Sepd
-                    continue;
+                }
+                TokenK::SepDefault => {
+                    self.next_token(); // eat SepDefault
+                    default = Some(Vec::new());
                 }
                 TokenK::EOF => {
                     self.handler
                         .error("Unclosed dimension delimiter. Expected `}#`.")
+                        .code(codes::UNCLOSED_DELIMITER)
                         .with_span(start)
                         .at_span("dimension starts here")
                         .delay();
@@ -167,6 +285,201 @@ impl Parser<'_> {
         }
     }
 
+    /// `#when(cond){then##otherwise}#`
+    pub fn parse_guard(&mut self) -> Parsed<Term> {
+        let start = self.current_token.span;
+        // between `#when(` and `){`
+        let (lo, hi) = (self.src_idx(start.lo) + 6, self.src_idx(start.hi) - 2);
+        let cond = match cond::parse_cond(&self.src[lo..hi], start.file, start.lo + 6) {
+            Ok(c) => c,
+            Err(e) => {
+                self.handler
+                    .error(&e.msg)
+                    .code(codes::INVALID_CONDITION)
+                    .with_span(e.span)
+                    .note("conditions compare decisions, e.g. `#when(os=linux && arch!=arm){..}#`.")
+                    .delay();
+                return Err(Error::InvalidCondition);
+            }
+        };
+        self.next_token(); // eat Opwhen
+        let then = self.parse_terms()?;
+        let mut otherwise = None;
+        let mut sep = None;
+        loop {
+            match self.current_token.kind() {
+                TokenK::Closed => {
+                    let seps = [sep.unwrap_or(self.current_token.span), self.current_token.span];
+                    if then.is_empty() {
+                        self.lint_empty_branch("The branch of this guard is empty.", (start, "in this guard"), &seps, 0, false);
+                    }
+                    if otherwise.as_ref().is_some_and(Vec::is_empty) {
+                        self.lint_empty_branch("The `##` branch of this guard is empty.", (start, "in this guard"), &seps, 1, true);
+                    }
+                    return Ok(Term::guard(cond, then, otherwise, start + self.current_token.span));
+                }
+                TokenK::Sepd if otherwise.is_none() => {
+                    sep = Some(self.current_token.span);
+                    self.next_token(); // eat Sepd
+                    otherwise = Some(self.parse_terms()?);
+                }
+                TokenK::Sepd | TokenK::SepDefault => {
+                    self.handler
+                        .error("A guard only has a branch and an optional `##` else branch.")
+                        .code(codes::UNEXPECTED_TOKEN)
+                        .with_span(self.current_token.span)
+                        .note("guards have the syntax `#when(cond){then##otherwise}#`.")
+                        .delay();
+                    return Err(Error::UnexpectedToken);
+                }
+                TokenK::EOF => {
+                    self.handler
+                        .error("Unclosed guard delimiter. Expected `}#`.")
+                        .code(codes::UNCLOSED_DELIMITER)
+                        .with_span(start)
+                        .at_span("guard starts here")
+                        .delay();
+                    return Err(Error::UnclosedDelimiter);
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// `#def(name){body}#`
+    pub fn parse_def(&mut self) -> Parsed<Term> {
+        let start = self.current_token.span;
+        // between `#def(` and `){`
+        let (lo, hi) = (self.src_idx(start.lo) + 5, self.src_idx(start.hi) - 2);
+        let name = Self::block_name(self.handler, &self.src[lo..hi], start)?;
+        self.next_token(); // eat Opdef
+        let mut body = self.parse_terms()?;
+        match self.current_token.kind() {
+            TokenK::Closed => {
+                self.detach(&mut body);
+                Ok(Term::def(name, body, start + self.current_token.span))
+            }
+            TokenK::Sepd | TokenK::SepDefault => {
+                self.handler
+                    .error("A named block has no branches.")
+                    .code(codes::UNEXPECTED_TOKEN)
+                    .with_span(self.current_token.span)
+                    .note("named blocks have the syntax `#def(name){..}#`, escape the separator with `\\##`.")
+                    .delay();
+                Err(Error::UnexpectedToken)
+            }
+            TokenK::EOF => {
+                self.handler
+                    .error("Unclosed named block delimiter. Expected `}#`.")
+                    .code(codes::UNCLOSED_DELIMITER)
+                    .with_span(start)
+                    .at_span("named block starts here")
+                    .delay();
+                Err(Error::UnclosedDelimiter)
+            }
+            _ => unreachable!(),
+        }
+    }
+    /// `#use(name, var=value, ..)#`
+    pub fn parse_use(&mut self) -> Parsed<Term> {
+        let span = self.current_token.span;
+        // between `#use(` and `)#`
+        let (lo, hi) = (self.src_idx(span.lo) + 5, self.src_idx(span.hi) - 2);
+        let mut parts = self.src[lo..hi].split(',');
+        // @SAFETY unwrap(): `split` yields at least one part
+        let name = Self::block_name(self.handler, parts.next().unwrap(), span)?;
+        let mut args: Vec<(Name, String)> = Vec::new();
+        for arg in parts {
+            let (var, value) = match arg.split_once('=') {
+                Some((var, value)) => (var.trim(), value.trim()),
+                None => ("", ""),
+            };
+            let reason = if var.is_empty() || !var.chars().all(Lexer::is_varsymbol) {
+                format!("`{}` isn't a `variable=value` argument.", arg.trim())
+            } else if args.iter().any(|(v, _)| *v == var) {
+                format!("variable `{}` is bound twice.", var)
+            } else {
+                args.push((var.into(), value.into()));
+                continue;
+            };
+            self.handler
+                .error(format!("Invalid use of named block `{}`: {}", name, reason).as_ref())
+                .code(codes::INVALID_BLOCK)
+                .with_span(span)
+                .note("uses of named blocks have the syntax `#use(name, var=value)#`.")
+                .delay();
+            return Err(Error::InvalidBlock);
+        }
+        Ok(Term::use_def(name, args, span))
+    }
+    /// the name of a named block, from the text between the parentheses of `#def(..){` or `#use(..)#`
+    fn block_name(handler: &mut Handler, name: &str, span: Span) -> Parsed<Name> {
+        let name = name.trim();
+        if name.starts_with(Lexer::is_varstart) && name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Ok(name.into());
+        }
+        handler
+            .error(format!("`{}` isn't a valid named block name.", name).as_ref())
+            .code(codes::INVALID_BLOCK)
+            .with_span(span)
+            .note("named blocks are named like dimensions, e.g. `#def(header){..}#`.")
+            .delay();
+        Err(Error::InvalidBlock)
+    }
+    /// warns about the empty branch `idx` of the dimension or guard opened by `opend`, `seps` are the
+    /// separators (and the closing delimiter) after each branch. `trailing` if it's between a `##` and `}#`
+    fn lint_empty_branch(&mut self, msg: &str, opend: (Span, &str), seps: &[Span], idx: usize, trailing: bool) {
+        let mut eb = self.handler.warn(WarnKind::EmptyBranch, msg).with_span(opend.0).at_span(opend.1);
+        eb = match idx.checked_sub(1).map(|i| seps[i]) {
+            Some(sep) if trailing => eb.label(sep, "nothing after this separator").note("a trailing `##` adds an empty last branch."),
+            Some(sep) => eb.label(sep, "nothing between this separator and the next one"),
+            None => eb.label(seps[0], "nothing before this"),
+        };
+        eb.suggest("check for a misplaced `##`, or use `\\~` to mark an intentionally empty branch.").print();
+    }
+    /// replaces the texts of `terms` by a copy, so they don't depend on the source anymore
+    fn detach(&self, terms: &mut Terms) {
+        for t in terms {
+            match &mut t.node {
+                TermK::Text => t.node = TermK::Value(self.src[self.src_idx(t.span.lo)..self.src_idx(t.span.hi)].into()),
+                TermK::Dimension { children, default, .. } => {
+                    for child in children.iter_mut().chain(default) {
+                        self.detach(child);
+                    }
+                }
+                TermK::Guard { then, otherwise, .. } => {
+                    for child in std::iter::once(then).chain(otherwise) {
+                        self.detach(child);
+                    }
+                }
+                TermK::Def { body, .. } => self.detach(body),
+                TermK::Var(..) | TermK::Value(_) | TermK::Use { .. } | TermK::Comment => {}
+            }
+        }
+    }
+
+    /// removes the label from the first text of a choice, and returns it.
+    /// e.g. `linux: foo` gives `linux` and leaves ` foo`.
+    fn take_label(&self, choice: &mut Terms) -> Option<Name> {
+        let first = choice.first_mut().filter(|t| t.node == TermK::Text)?;
+        let txt = &self.src[self.src_idx(first.span.lo)..self.src_idx(first.span.hi)];
+        let trimmed = txt.trim_start();
+        let len = trimmed
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(trimmed.len());
+        let (label, rest) = trimmed.split_at(len);
+        let rest = rest.strip_prefix(':')?;
+        if !label.starts_with(Lexer::is_varstart) || !(rest.is_empty() || rest.starts_with(char::is_whitespace)) {
+            return None;
+        }
+        let label = Name::from(label);
+        first.span.lo += txt.len() - rest.len();
+        if rest.is_empty() {
+            choice.remove(0);
+        }
+        Some(label)
+    }
+
     fn next_token(&mut self) -> Token {
         self.current_token = match self.tokens.pop_front() {
             Some(t) => t,
@@ -182,9 +495,9 @@ impl Parser<'_> {
             self.tokens.get(n)
         }
     }
-    /// a source_map relative position to index in the source
+    /// a position in the file to index in the source
     fn src_idx(&self, p: BytePos) -> usize {
-        (p - self.offset).as_usize()
+        p.as_usize()
     }
     /// get the span of the actual name of the variable
     pub fn var_name_range(span: Range<usize>) -> Range<usize> { 
@@ -196,8 +509,8 @@ impl Parser<'_> {
     }
 }
 
-/// a Variable or Dimension name.
-pub type Name = String;
+/// a Variable or Dimension name, interned. see [`crate::syntax::symbols`]
+pub type Name = Symbol;
 /// a list of [`Terms`]
 pub type Terms = Vec<Term>;
 /// a Spanned [`TermK`]
@@ -209,15 +522,45 @@ impl Term {
             span,
         }
     }
-    pub fn var(name: Name, span: Span) -> Term {
+    pub fn var(name: Name, default: Option<String>, span: Span) -> Term {
+        Term {
+            node: TermK::Var(name, default),
+            span,
+        }
+    }
+    pub fn dim(name: Name, children: Vec<Terms>, labels: Vec<Option<Name>>, default: Option<Terms>, span: Span) -> Term {
+        Term {
+            node: TermK::Dimension { name, children, labels, default },
+            span,
+        }
+    }
+    pub fn guard(cond: Cond, then: Terms, otherwise: Option<Terms>, span: Span) -> Term {
         Term {
-            node: TermK::Var(name),
+            node: TermK::Guard { cond, then, otherwise },
             span,
         }
     }
-    pub fn dim(name: Name, children: Vec<Terms>, span: Span) -> Term {
+    pub fn def(name: Name, body: Terms, span: Span) -> Term {
         Term {
-            node: TermK::Dimension { name, children },
+            node: TermK::Def { name, body },
+            span,
+        }
+    }
+    pub fn use_def(name: Name, args: Vec<(Name, String)>, span: Span) -> Term {
+        Term {
+            node: TermK::Use { name, args },
+            span,
+        }
+    }
+    pub fn comment(span: Span) -> Term {
+        Term {
+            node: TermK::Comment,
+            span,
+        }
+    }
+    pub fn value(value: String, span: Span) -> Term {
+        Term {
+            node: TermK::Value(value),
             span,
         }
     }
@@ -228,9 +571,9 @@ impl Term {
     /// ```
     pub fn name_span(&self) -> Option<Span> {
         match &self.node {
-            TermK::Text => None,
-            TermK::Var(name) => {
-                let s = self.span.subspan(2 /* #$ */, name.len() as u64 - 1 /* # */);
+            TermK::Text | TermK::Value(_) | TermK::Guard { .. } | TermK::Def { .. } | TermK::Use { .. } | TermK::Comment => None,
+            TermK::Var(name, _) => {
+                let s = self.span.subspan(2 /* #$ */, 2 + name.len() as u64);
                 assert_eq!(s.len(), name.len());
                 Some(s)
             }
@@ -260,12 +603,51 @@ impl Term {
         }
     }
 }
+/// approximate number of bytes held in memory by a tree
+pub fn terms_mem_size(terms: &Terms) -> usize {
+    let mut size = terms.capacity() * std::mem::size_of::<Term>();
+    for t in terms {
+        match &t.node {
+            TermK::Text | TermK::Comment => {}
+            TermK::Var(_, default) => size += default.as_ref().map_or(0, String::capacity),
+            TermK::Value(name) => size += name.capacity(),
+            TermK::Dimension { children, labels, default, .. } => {
+                size += children.capacity() * std::mem::size_of::<Terms>();
+                size += labels.capacity() * std::mem::size_of::<Option<Name>>();
+                size += children.iter().chain(default).map(terms_mem_size).sum::<usize>();
+            }
+            TermK::Guard { cond, then, otherwise } => {
+                size += cond.mem_size() + terms_mem_size(then) + otherwise.as_ref().map_or(0, terms_mem_size);
+            }
+            TermK::Def { body, .. } => size += terms_mem_size(body),
+            TermK::Use { args, .. } => {
+                size += args.capacity() * std::mem::size_of::<(Name, String)>();
+                size += args.iter().map(|(_, v)| v.capacity()).sum::<usize>();
+            }
+        }
+    }
+    size
+}
 /// the kind of a Term
 #[derive(Clone, PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
 pub enum TermK {
     Text,
-    Var(Name),
-    Dimension { name: String, children: Vec<Terms> },
+    /// a variable, and its default value
+    Var(Name, Option<String>),
+    /// `default` is chosen when the decision is out of the `children`'s range.
+    /// `labels` has the label of each child, or is empty if none of them is labelled
+    Dimension { name: Name, children: Vec<Terms>, labels: Vec<Option<Name>>, default: Option<Terms> },
+    /// `then` if `cond` holds, otherwise `otherwise` (or nothing)
+    Guard { cond: Cond, then: Terms, otherwise: Option<Terms> },
+    /// a named block, writes nothing where it is defined. Its texts are [`TermK::Value`]s
+    Def { name: Name, body: Terms },
+    /// writes the `body` of the named block `name`, its variables are bound by `args` first
+    Use { name: Name, args: Vec<(Name, String)> },
+    /// `#! comment !#`, never written
+    Comment,
+    /// the value of a resolved variable, written in place of the term's span.
+    /// see [`crate::infer::resolve`]
+    Value(String),
 }
 
 pub type TokenStream = VecDeque<Token>;
@@ -0,0 +1,249 @@
+//! regenerates the flan syntax of a tree, the inverse of the [`Parser`](super::Parser).
+//!
+//! Parsing the printed template gives back an equivalent tree: the same terms with the same texts, except that
+//! the texts split by escapes are merged. The texts are escaped where the lexer would interpret them
+//! (see [`escape_with`]), and where their place in a branch would make them a label, a `-` trim marker or
+//! the `!` of `##!`. The whitespace trimmed by the `-` of the source isn't in the tree, so it isn't printed;
+//! the comments aren't in the tree either and are copied from the source.
+//!
+//! [`format`] also lays out the terms written over several lines as indented blocks, their delimiters on their own
+//! lines and trimmed by `-` markers:
+//! ```text
+//! #os{-
+//!     linux: apt
+//! -##-
+//!     windows: choco
+//! -}#
+//! ```
+//! A term keeps its branches on one line if the trims would remove the whitespace at their edges.
+use super::escape::escape_with;
+use super::{Delimiters, Lexer, Name, Term, TermK, Terms};
+
+/// prints `terms` with the default delimiters. Their texts and comments are read from `src`, the source of their file
+pub fn print(terms: &Terms, src: &str) -> String {
+    print_with(terms, src, Delimiters::default())
+}
+
+/// same as [`print`] with other delimiters, see [`Lexer::with_delimiters`]
+pub fn print_with(terms: &Terms, src: &str, delims: Delimiters) -> String {
+    let mut p = Printer { src, delims, buf: String::with_capacity(src.len()), blocks: None, depth: 0 };
+    p.terms(terms);
+    p.buf
+}
+
+/// same as [`print_with`], with the canonical layout of `flan fmt`, see the [module documentation](self)
+pub fn format(terms: &Terms, src: &str, delims: Delimiters) -> String {
+    let newline = if src.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut p = Printer { src, delims, buf: String::with_capacity(src.len()), blocks: Some(newline), depth: 0 };
+    p.terms(terms);
+    p.buf
+}
+
+/// the indentation of each nesting level of the blocks
+pub const INDENT: &str = "    ";
+
+/// what precedes a branch, the lexer trims whitespace after `{` and `##` but not after `##!`
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum After {
+    Open,
+    Sep,
+    SepDefault,
+}
+
+struct Printer<'a> {
+    src: &'a str,
+    delims: Delimiters,
+    buf: String,
+    /// the newline of the blocks, if the terms over several lines are laid out as blocks
+    blocks: Option<&'static str>,
+    depth: usize,
+}
+
+/// a branch of a dimension, guard or named block, with what precedes it
+struct Branch<'t> {
+    terms: &'t Terms,
+    after: After,
+    /// `Some` for the choices of a dimension, which can be labelled
+    label: Option<Option<&'t Name>>,
+}
+
+impl<'a> Printer<'a> {
+    /// the source of a term
+    fn slice(&self, t: &Term) -> &'a str {
+        &self.src[t.span.as_range()]
+    }
+    /// the escaped text of the runs of texts, the other terms in between
+    fn terms(&mut self, terms: &Terms) {
+        let mut i = 0;
+        while i < terms.len() {
+            let len = terms[i..].iter().take_while(|t| is_text(t)).count();
+            if len == 0 {
+                self.term(&terms[i]);
+                i += 1;
+                continue;
+            }
+            let run: String = terms[i..i + len].iter().map(|t| self.text(t)).collect();
+            self.buf.push_str(&escape_with(&run, self.delims));
+            i += len;
+        }
+    }
+    /// the source of a text, or the value copied from it
+    fn text<'b>(&self, t: &'b Term) -> &'b str
+    where
+        'a: 'b,
+    {
+        match &t.node {
+            TermK::Value(v) => v,
+            _ => self.slice(t),
+        }
+    }
+    fn term(&mut self, t: &Term) {
+        let Delimiters { sigil, open, var, .. } = self.delims;
+        match &t.node {
+            TermK::Text | TermK::Value(_) => unreachable!("texts are printed by runs"),
+            TermK::Comment => self.buf.push_str(self.slice(t)),
+            TermK::Var(name, default) => {
+                self.buf.extend([sigil, var]);
+                self.buf.push_str(name);
+                if let Some(d) = default {
+                    self.buf.push_str(":-");
+                    self.buf.push_str(d);
+                }
+                self.buf.push(sigil);
+            }
+            TermK::Dimension { name, children, labels, default } => {
+                let mut branches: Vec<_> = children
+                    .iter()
+                    .enumerate()
+                    .map(|(i, c)| Branch {
+                        terms: c,
+                        after: if i == 0 { After::Open } else { After::Sep },
+                        label: Some(labels.get(i).and_then(Option::as_ref)),
+                    })
+                    .collect();
+                if let Some(d) = default {
+                    branches.push(Branch { terms: d, after: After::SepDefault, label: None });
+                }
+                self.delimited(t, &format!("{}{}{}", sigil, name, open), &branches);
+            }
+            TermK::Guard { cond, then, otherwise } => {
+                let mut branches = vec![Branch { terms: then, after: After::Open, label: None }];
+                if let Some(o) = otherwise {
+                    branches.push(Branch { terms: o, after: After::Sep, label: None });
+                }
+                self.delimited(t, &format!("{}when({}){}", sigil, cond, open), &branches);
+            }
+            TermK::Def { name, body } => {
+                let branches = [Branch { terms: body, after: After::Open, label: None }];
+                self.delimited(t, &format!("{}def({}){}", sigil, name, open), &branches);
+            }
+            TermK::Use { name, args } => {
+                self.buf.push(sigil);
+                self.buf.push_str(&format!("use({}", name));
+                for (n, v) in args {
+                    self.buf.push_str(&format!(", {}={}", n, v));
+                }
+                self.buf.push(')');
+                self.buf.push(sigil);
+            }
+        }
+    }
+    /// `head` is the opening delimiter of `t`, e.g. `#os{`
+    fn delimited(&mut self, t: &Term, head: &str, branches: &[Branch]) {
+        let Delimiters { sigil, close, .. } = self.delims;
+        self.depth += 1;
+        let bodies: Vec<String> = branches
+            .iter()
+            .map(|b| {
+                let buf = std::mem::take(&mut self.buf);
+                self.branch(b.terms, b.after, b.label);
+                std::mem::replace(&mut self.buf, buf)
+            })
+            .collect();
+        self.depth -= 1;
+        self.buf.push_str(head);
+        let newline = match self.blocks {
+            Some(nl) if self.slice(t).contains('\n') || bodies.iter().any(|b| b.contains('\n')) => nl,
+            _ => "",
+        };
+        // the trims would remove the whitespace at the edges of the branches
+        let trimmable = |b: &String| !(b.is_empty() || b.starts_with(char::is_whitespace) || b.ends_with(char::is_whitespace));
+        if newline.is_empty() || !bodies.iter().all(trimmable) {
+            for (b, body) in branches.iter().zip(&bodies) {
+                match b.after {
+                    After::Open => {}
+                    After::Sep => self.buf.extend([sigil, sigil]),
+                    After::SepDefault => self.buf.extend([sigil, sigil, '!']),
+                }
+                self.buf.push_str(body);
+            }
+            self.buf.extend([close, sigil]);
+            return;
+        }
+        let (outer, inner) = (INDENT.repeat(self.depth), INDENT.repeat(self.depth + 1));
+        for (b, body) in branches.iter().zip(&bodies) {
+            match b.after {
+                After::Open => self.buf.push('-'),
+                After::Sep => self.buf.push_str(&format!("{}{}-{}{}-", newline, outer, sigil, sigil)),
+                // `##!` doesn't trim after it
+                After::SepDefault => {
+                    self.buf.push_str(&format!("{}{}-{}{}!{}", newline, outer, sigil, sigil, body));
+                    continue;
+                }
+            }
+            self.buf.push_str(newline);
+            self.buf.push_str(&inner);
+            self.buf.push_str(body);
+        }
+        self.buf.push_str(&format!("{}{}-{}{}", newline, outer, close, sigil));
+    }
+    /// the terms between two delimiters. `label` is `Some` for the choices of a dimension, which can be labelled
+    fn branch(&mut self, terms: &Terms, after: After, label: Option<Option<&Name>>) {
+        if let Some(Some(l)) = label {
+            self.buf.push_str(l);
+            self.buf.push(':');
+        }
+        let body = self.buf.len();
+        // `\~` is an empty text, the lexer produces it for intentionally empty branches
+        if !terms.is_empty() && terms.iter().all(|t| is_text(t) && self.text(t).is_empty()) {
+            self.buf.push_str("\\~");
+            return;
+        }
+        self.terms(terms);
+        if terms.first().is_some_and(is_text) {
+            let s = &self.buf[body..];
+            let guarded = match label {
+                // a label is followed by whitespace or ends its text
+                Some(Some(_)) => !s.starts_with(char::is_whitespace),
+                _ => (after != After::SepDefault && s.starts_with('-')) || (after == After::Sep && s.starts_with('!')),
+            };
+            if guarded {
+                self.buf.insert_str(body, "\\~");
+            } else if let Some(colon) = label.filter(Option::is_none).and_then(|_| label_colon(s)) {
+                self.buf.insert(body + colon, '\\');
+            }
+        }
+        // a `-` before `##` or `}#` trims the whitespace before it
+        if terms.last().is_some_and(is_text) && self.buf.len() > body && self.buf.ends_with('-') {
+            self.buf.push_str("\\~");
+        }
+    }
+}
+
+fn is_text(t: &Term) -> bool {
+    matches!(t.node, TermK::Text | TermK::Value(_))
+}
+
+/// the index of the `:` of the label the parser would take from the start of the printed choice `s`,
+/// see [`super::Parser`]. Its first text token ends at the first escape
+fn label_colon(s: &str) -> Option<usize> {
+    let token = &s[..s.find('\\').unwrap_or(s.len())];
+    let trimmed = token.trim_start();
+    let len = trimmed.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(trimmed.len());
+    let (label, rest) = trimmed.split_at(len);
+    let rest = rest.strip_prefix(':')?;
+    if !label.starts_with(Lexer::is_varstart) || !(rest.is_empty() || rest.starts_with(char::is_whitespace)) {
+        return None;
+    }
+    Some(token.len() - trimmed.len() + len)
+}
@@ -0,0 +1,129 @@
+//! interned names of dimensions, variables and choices, see [`Symbol`].
+//!
+//! The interner is global: the names are few but compared, hashed and cloned all the time, while
+//! the terms and the [`crate::infer::Env`] are shared between the threads.
+//! Interned strings are never freed.
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{OnceLock, RwLock};
+
+/// an interned string, cheap to copy, compare and hash.
+/// The ordering is the one of the strings, so sorting symbols doesn't depend on the interning order.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+#[derive(Default)]
+struct Interner {
+    symbols: HashMap<&'static str, Symbol>,
+    strings: Vec<&'static str>,
+}
+
+fn interner() -> &'static RwLock<Interner> {
+    static INTERNER: OnceLock<RwLock<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(Default::default)
+}
+
+impl Symbol {
+    pub fn intern(s: &str) -> Symbol {
+        // @SAFETY unwrap(): the lock is never held while panicking
+        if let Some(sym) = interner().read().unwrap().symbols.get(s) {
+            return *sym;
+        }
+        let mut i = interner().write().unwrap();
+        // it could have been interned since the read lock was released
+        if let Some(sym) = i.symbols.get(s) {
+            return *sym;
+        }
+        let s: &'static str = Box::leak(s.into());
+        let sym = Symbol(i.strings.len() as u32);
+        i.strings.push(s);
+        i.symbols.insert(s, sym);
+        sym
+    }
+    /// the interned string
+    pub fn as_str(&self) -> &'static str {
+        resolve(*self)
+    }
+}
+
+/// the string of `sym`, for display
+pub fn resolve(sym: Symbol) -> &'static str {
+    // @SAFETY unwrap(): symbols are only created by the interner
+    interner().read().unwrap().strings[sym.0 as usize]
+}
+
+impl std::ops::Deref for Symbol {
+    type Target = str;
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        if self == other {
+            std::cmp::Ordering::Equal
+        } else {
+            self.as_str().cmp(other.as_str())
+        }
+    }
+}
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+impl From<&str> for Symbol {
+    fn from(s: &str) -> Self {
+        Symbol::intern(s)
+    }
+}
+impl From<&String> for Symbol {
+    fn from(s: &String) -> Self {
+        Symbol::intern(s)
+    }
+}
+impl From<String> for Symbol {
+    fn from(s: String) -> Self {
+        Symbol::intern(&s)
+    }
+}
+impl From<Symbol> for String {
+    fn from(s: Symbol) -> Self {
+        s.as_str().into()
+    }
+}
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+impl PartialEq<String> for Symbol {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other
+    }
+}
+impl serde::Serialize for Symbol {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self.as_str())
+    }
+}
@@ -0,0 +1,69 @@
+//! minimal glob patterns for paths.
+//!
+//! * `*` matches any sequence of characters inside of a path component
+//! * `?` matches a single character inside of a path component
+//! * `**` as a whole component matches any number of components (including none)
+//!
+//! a pattern without any `/` (e.g. `*.png`) matches the file name at any depth, like in a `.gitignore`.
+use std::path::Path;
+
+/// whether `path` (relative, with `/` separators) matches `pattern`
+pub fn glob_match(pattern: &str, path: &Path) -> bool {
+    let path: Vec<_> = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    let path: Vec<&str> = path.iter().map(|c| c.as_ref()).collect();
+    let pattern = pattern.trim_end_matches('/');
+    if !pattern.contains('/') {
+        return path.last().is_some_and(|name| component_match(pattern, name));
+    }
+    let pattern: Vec<_> = pattern.trim_start_matches('/').split('/').collect();
+    components_match(&pattern, &path)
+}
+
+fn components_match(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(&"**"), _) => {
+            components_match(&pattern[1..], path)
+                || (!path.is_empty() && components_match(pattern, &path[1..]))
+        }
+        (Some(p), Some(c)) => component_match(p, c) && components_match(&pattern[1..], &path[1..]),
+        _ => false,
+    }
+}
+
+/// matches `*` and `?` inside of a single component
+fn component_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // backtracking on the last `*`
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some('?') => {
+                p += 1;
+                n += 1;
+            }
+            Some(c) if *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((sp, sn)) => {
+                    p = sp + 1;
+                    n = sn + 1;
+                    star = Some((sp, sn + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
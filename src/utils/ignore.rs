@@ -0,0 +1,80 @@
+//! ignore files (`.gitignore`, `.flanignore`) of the walked directories.
+//!
+//! * empty lines and lines starting with `#` are skipped, `\#` and `\!` escape a leading `#` or `!`
+//! * `!` negates a pattern, i.e. includes back a file ignored by a previous pattern
+//! * a trailing `/` only matches directories
+//! * the patterns are matched with [`glob_match`] against the paths relative to the ignore file's directory
+//!
+//! the last matching pattern wins, and the ignore files of a subdirectory take precedence over their parent's.
+use super::glob::glob_match;
+use std::path::{Path, PathBuf};
+
+/// names of the ignore files read in each directory, in order
+pub const IGNORE_FILES: [&str; 2] = [".gitignore", ".flanignore"];
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: String,
+    negated: bool,
+    dir_only: bool,
+}
+
+/// the rules of the ignore files of one directory
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreFile {
+    /// directory of the ignore files, relative to the walked directory
+    pub dir: PathBuf,
+    rules: Vec<Rule>,
+}
+impl IgnoreFile {
+    pub fn new(dir: PathBuf) -> Self {
+        IgnoreFile { dir, rules: Vec::new() }
+    }
+    /// reads the [`IGNORE_FILES`] of `root/dir` that exist
+    pub fn read(root: &Path, dir: &Path) -> std::io::Result<Self> {
+        let mut file = IgnoreFile::new(dir.to_path_buf());
+        for name in IGNORE_FILES.iter() {
+            let path = root.join(dir).join(name);
+            if path.is_file() {
+                file.parse(&std::fs::read_to_string(path)?);
+            }
+        }
+        Ok(file)
+    }
+    /// adds the patterns of `src`
+    pub fn parse(&mut self, src: &str) {
+        for line in src.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let dir_only = line.ends_with('/');
+            let pattern = line.trim_end_matches('/');
+            if !pattern.is_empty() {
+                self.rules.push(Rule { pattern: pattern.to_string(), negated, dir_only });
+            }
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+    /// `Some(true)` if `rel` (relative to the walked directory) is ignored, `Some(false)` if a negated pattern
+    /// includes it back, `None` if no pattern matches it.
+    pub fn matched(&self, rel: &Path, is_dir: bool) -> Option<bool> {
+        let rel = rel.strip_prefix(&self.dir).ok()?;
+        self.rules
+            .iter()
+            .rev()
+            .find(|r| (is_dir || !r.dir_only) && glob_match(&r.pattern, rel))
+            .map(|r| !r.negated)
+    }
+}
+
+/// whether `rel` is ignored by the `stack` of ignore files, from the walked directory to the deepest one
+pub fn is_ignored(stack: &[IgnoreFile], rel: &Path, is_dir: bool) -> bool {
+    stack.iter().rev().find_map(|f| f.matched(rel, is_dir)).unwrap_or(false)
+}
@@ -1,28 +1,137 @@
+pub mod glob;
+pub mod ignore;
 pub mod path;
 
 /// a strict version of haskell's [sequence](https://hackage.haskell.org/package/base-4.12.0.0/docs/src/Data.Traversable.html#sequence)
 pub trait Sequenceable<T> {
-    fn sequence<F: FnOnce(&T) -> ()>(self, f: F) -> Self;
+    fn sequence<F: FnOnce(&T)>(self, f: F) -> Self;
 }
 
 impl<T> Sequenceable<T> for Option<T> {
-    fn sequence<F: FnOnce(&T) -> ()>(self, f: F) -> Option<T> {
-        self.map(|x| {
-            f(&x);
-            x
-        })
+    fn sequence<F: FnOnce(&T)>(self, f: F) -> Option<T> {
+        self.inspect(f)
     }
 }
 
 impl<T, E> Sequenceable<T> for Result<T, E> {
-    fn sequence<F: FnOnce(&T) -> ()>(self, f: F) -> Result<T, E> {
-        self.map(|x| {
-            f(&x);
-            x
-        })
+    fn sequence<F: FnOnce(&T)>(self, f: F) -> Result<T, E> {
+        self.inspect(f)
     }
 }
 
+/// peak resident set size of the process in bytes, only available on linux.
+pub fn peak_rss() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    // e.g. `VmHWM:      1234 kB`
+    let kb: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// pretty prints a size in bytes, e.g. `12.3MiB`
+pub fn pp_bytes(size: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}{}", size, UNITS[unit])
+    } else {
+        format!("{:.1}{}", size, UNITS[unit])
+    }
+}
+
+/// lines of context around the changes of [`pp_diff`]
+const DIFF_CONTEXT: usize = 2;
+
+/// the unified diff of the lines of `new` against `old`, empty if they are the same.
+/// A last line without a newline is followed by `\ no newline at the end`
+pub fn pp_diff(old: &str, new: &str) -> String {
+    // @SAFETY write does not fail on `String`
+    #![allow(unused_must_use)]
+    use std::fmt::Write;
+    let (a, b): (Vec<_>, Vec<_>) = (old.split_inclusive('\n').collect(), new.split_inclusive('\n').collect());
+    // lcs[i][j]: length of the longest common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+    // (' ' | '-' | '+', line of `a`, line of `b`), the line is the one of the side of the op
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            ops.push((' ', i, j));
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(('-', i, j));
+            i += 1;
+        } else {
+            ops.push(('+', i, j));
+            j += 1;
+        }
+    }
+    let changed: Vec<_> = ops.iter().enumerate().filter(|(_, op)| op.0 != ' ').map(|(k, _)| k).collect();
+    let mut buf = String::new();
+    let mut k = 0;
+    while k < changed.len() {
+        // the changes closer than twice the context are in the same hunk
+        let mut end = k;
+        while end + 1 < changed.len() && changed[end + 1] - changed[end] <= 2 * DIFF_CONTEXT + 1 {
+            end += 1;
+        }
+        let (lo, hi) = (changed[k].saturating_sub(DIFF_CONTEXT), (changed[end] + DIFF_CONTEXT + 1).min(ops.len()));
+        let hunk = &ops[lo..hi];
+        let (old_n, new_n) = (hunk.iter().filter(|op| op.0 != '+').count(), hunk.iter().filter(|op| op.0 != '-').count());
+        writeln!(buf, "@@ -{},{} +{},{} @@", hunk[0].1 + 1, old_n, hunk[0].2 + 1, new_n);
+        for &(op, i, j) in hunk {
+            let line = if op == '+' { b[j] } else { a[i] };
+            writeln!(buf, "{}{}", op, line.strip_suffix('\n').unwrap_or(line));
+            if !line.ends_with('\n') {
+                buf.push_str("\\ no newline at the end\n");
+            }
+        }
+        k = end + 1;
+    }
+    buf
+}
+
+/// levenshtein distance between `a` and `b`, in characters
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // distances between the prefix of `a` read so far and every prefix of `b`
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let sub = diag + if ca == *cb { 0 } else { 1 };
+            diag = row[j + 1];
+            row[j + 1] = sub.min(row[j] + 1).min(diag + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// the candidate closest to `name` for a "did you mean" suggestion, ignoring the case:
+/// at most a third of its characters (or one) away. The ties go to the smallest candidate
+pub fn closest<'a, It: IntoIterator<Item = &'a str>>(name: &str, candidates: It) -> Option<&'a str> {
+    let name = name.to_lowercase();
+    let max = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(&name, &c.to_lowercase()), c))
+        .filter(|(d, _)| *d <= max)
+        .min()
+        .map(|(_, c)| c)
+}
+
 #[macro_export]
 macro_rules! debug {
     () => {#[cfg(debug_assertions)] println!("@DEBUG")};
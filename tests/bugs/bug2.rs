@@ -41,11 +41,16 @@ fn get_names(ts: Terms) -> Vec<Names> {
     let mut v = Vec::new();
     for Spanned { node, span: _ } in ts {
         match node {
-            TermK::Text => {}
-            TermK::Var(n) => v.push(V(n)),
-            TermK::Dimension { name, children } => {
-                v.push(D(name));
-                for c in children {
+            TermK::Text | TermK::Value(_) | TermK::Def { .. } | TermK::Use { .. } | TermK::Comment => {}
+            TermK::Var(n, _) => v.push(V(n.into())),
+            TermK::Dimension { name, children, default, .. } => {
+                v.push(D(name.into()));
+                for c in children.into_iter().chain(default) {
+                    v.append(&mut get_names(c));
+                }
+            }
+            TermK::Guard { then, otherwise, .. } => {
+                for c in std::iter::once(then).chain(otherwise) {
                     v.append(&mut get_names(c));
                 }
             }
@@ -1,14 +1,39 @@
 use flan::cfg::*;
+use flan::env::ChoiceIdx;
+use std::str::FromStr;
 
 #[test]
-fn invalid_size_choice() {
-    assert!(!Choices::Size(128).valid());
+fn too_many_choices() {
+    let xs: Vec<_> = (0..=ChoiceIdx::MAX_CHOICES).map(|i| format!("c{}", i)).collect();
+    assert!(!Choices::Names(xs).valid());
+    assert_eq!(ChoiceIdx::new(ChoiceIdx::MAX_CHOICES), None);
+    assert_eq!(ChoiceIdx::count(ChoiceIdx::MAX_CHOICES + 1), None);
+    assert!(Decision::from_str("os=65535").is_err());
+    assert_eq!(Decision::from_str("os=300").unwrap(), Decision::WithDim("os".into(), Index::Num(ChoiceIdx(300))));
+
+    use flan::driver::{make_env, string_to_parser};
+    use flan::error::{codes, ErrorFlags, Handler, MemorySink};
+    use flan::infer::check;
+    use flan::sourcemap::SrcMap;
+    let (names, pairs) = parse_decisions(&["big=300"]).unwrap();
+    let sink = MemorySink::new();
+    let h = Handler::with_sink(ErrorFlags::default(), SrcMap::new(), Box::new(sink.clone()));
+    let mut env = make_env(&Config::new(names, pairs, File::default()), h).unwrap();
+    let mut h = Handler::new(ErrorFlags::default(), SrcMap::new());
+    let src = |n: usize| format!("#big{{{}}}#", (0..n).map(|i| i.to_string()).collect::<Vec<_>>().join("##"));
+    let terms = string_to_parser(&mut h, src(301)).unwrap().parse().unwrap();
+    assert!(!check(&terms, &mut env).0);
+    assert_eq!(flan::infer::resolve(&terms, &env).len(), 1);
+    let terms = string_to_parser(&mut h, src(ChoiceIdx::MAX_CHOICES + 1)).unwrap().parse().unwrap();
+    assert!(check(&terms, &mut env).0);
+    assert_eq!(sink.errors()[0].code(), Some(codes::TOO_MANY_CHOICES));
 }
 
 #[test]
 fn valid_size_choice() {
     assert!(Choices::Size(0).valid());
     assert!(Choices::Size(127).valid());
+    assert!(Choices::Size(u16::MAX).valid());
 }
 
 #[test]
@@ -21,27 +46,1105 @@ fn dups_decisions() {
 
 #[test]
 fn invalid_id() {
-    assert!(Decision::from_str(&"6ajaofjo").is_err());
-    assert!(Decision::from_str(&"+ajaofjo").is_err());
+    assert!(Decision::from_str("6ajaofjo").is_err());
+    assert!(Decision::from_str("+ajaofjo").is_err());
 }
 
 #[test]
 fn valid_id() {
     let expected = Decision::Name("_aja3791o_fjo8319".into());
-    let actual = Decision::from_str(&"_aja3791o_fjo8319");
+    let actual = Decision::from_str("_aja3791o_fjo8319");
     assert!(actual.is_ok());
     assert_eq!(expected, actual.unwrap());
 }
 
 #[test]
 fn valid_dim() {
-    let expected = Decision::WithDim("foo".into(), Index::Num(0));
-    let actual = Decision::from_str(&"foo=0");
+    let expected = Decision::WithDim("foo".into(), Index::Num(ChoiceIdx(0)));
+    let actual = Decision::from_str("foo=0");
     assert!(actual.is_ok());
     assert_eq!(expected, actual.unwrap());
 
     let expected = Decision::WithDim("foo".into(), Index::Name("bar".into()));
-    let actual = Decision::from_str(&"foo=bar");
+    let actual = Decision::from_str("foo=bar");
     assert!(actual.is_ok());
     assert_eq!(expected, actual.unwrap());
 }
+
+#[test]
+fn matrix_file() {
+    use flan::cfg::matrix::Matrix;
+    use std::path::PathBuf;
+    let toml = r#"
+        [debug]
+        decisions = ["debug", "os=linux"]
+        [release]
+        decisions = ["release"]
+        out-prefix = "dist/rel"
+    "#;
+    let json = r#"{ "debug": { "decisions": ["debug", "os=linux"] },
+                    "release": { "decisions": ["release"], "out-prefix": "dist/rel" } }"#;
+    for m in [Matrix::from_toml(toml), Matrix::from_json(json)] {
+        let m = m.unwrap();
+        let names: Vec<_> = m.variants.keys().cloned().collect();
+        assert_eq!(names, vec!["debug", "release"]);
+
+        let debug = &m.variants["debug"];
+        let (ns, ps) = debug.parse_decisions().unwrap();
+        assert!(ns.contains("debug"));
+        assert_eq!(ps.get("os"), Some(&Index::Name("linux".into())));
+        let prefix = PathBuf::from("dist");
+        assert_eq!(debug.out_prefix("debug", Some(&prefix)), PathBuf::from("dist/debug"));
+        assert_eq!(debug.out_prefix("debug", None), PathBuf::from("debug"));
+        let release = &m.variants["release"];
+        assert_eq!(release.out_prefix("release", Some(&prefix)), PathBuf::from("dist/rel"));
+    }
+}
+
+#[test]
+fn variant_destinations() {
+    use flan::driver::variant_destination;
+    use std::path::{Path, PathBuf};
+    let out = PathBuf::from("dist");
+    let dest = variant_destination(Path::new("dist/a/b.txt"), Some(&out), Path::new("dist/debug"));
+    assert_eq!(dest, Some(PathBuf::from("dist/debug/a/b.txt")));
+    let dest = variant_destination(Path::new("a/b.txt"), None, Path::new("debug"));
+    assert_eq!(dest, Some(PathBuf::from("debug/a/b.txt")));
+    assert_eq!(variant_destination(Path::new("/etc/b.txt"), None, Path::new("debug")), None);
+}
+
+#[test]
+fn default_flags() {
+    // the flags of the library, without structopt
+    assert_eq!(Flags::default(), Flags::new(&Opt::from_iter(&["flan"]), None));
+}
+
+#[test]
+fn single_file_flag() {
+    let flags = |args: &[&str]| Flags::new(&Opt::from_iter(args.iter()), None);
+    // tests run from the crate root
+    let f = flags(&["flan", "-i", "Cargo.toml"]);
+    assert_eq!(f.single_file(), Some(&"Cargo.toml".into()));
+    assert!(f.to_stdout());
+    let f = flags(&["flan", "-i", "Cargo.toml", "-o", "out.toml"]);
+    assert!(!f.to_stdout());
+    let f = flags(&["flan", "-i", "src"]);
+    assert_eq!(f.single_file(), None);
+}
+
+#[test]
+fn decision_origins() {
+    use flan::driver::make_env;
+    use flan::env::Origin;
+    use flan::error::{ErrorFlags, Handler};
+    use flan::sourcemap::SrcMap;
+    let file = File::from_str("[dimensions]\nos = [\"linux\", \"windows\"]\nsz = 2\n").unwrap();
+    let (names, pairs) = parse_decisions(&["windows", "sz=1", "undecl=0"]).unwrap();
+    let config = Config::new(names, pairs, file);
+    let env = make_env(&config, Handler::new(ErrorFlags::default(), SrcMap::new())).unwrap();
+
+    let origin = |d: &str| env.get_dimension(&d.into()).map(|d| (d.decision, d.origin));
+    assert_eq!(origin("os"), Some((ChoiceIdx(1), Origin::Name)));
+    assert_eq!(origin("sz"), Some((ChoiceIdx(1), Origin::Pair)));
+    assert_eq!(origin("undecl"), Some((ChoiceIdx(0), Origin::UndeclaredPair)));
+
+    // command line > decisions file > profile > lockfile, then the `[paths]` overrides
+    let decl = "[dimensions]\nos = [\"linux\", \"windows\"]\nsz = 2\nmode = [\"dev\", \"prod\"]\nlvl = 3\n\
+                [profiles.rel]\nmode = \"prod\"\nsz = 0";
+    let lock = Lock::from_str("[decisions]\nsz = 1\nlvl = 2\nx = 1").unwrap();
+    let (names, pairs) = parse_decisions(&["linux"]).unwrap();
+    let config = Config::new(names, pairs, File::from_str(decl).unwrap())
+        .with_decisions_file(Profile::from_str("sz = 1").unwrap())
+        .select_profile("rel")
+        .unwrap()
+        .with_lock(lock);
+    let mut config2 = config.clone();
+    config2.decisions_pair.extend(config.locked.clone());
+    let env = make_env(&config2, Handler::new(ErrorFlags::default(), SrcMap::new())).unwrap();
+    let origin = |d: &str| env.get_dimension(&d.into()).map(|d| d.origin);
+    assert_eq!(origin("os"), Some(Origin::Name));
+    assert_eq!(origin("sz"), Some(Origin::DecisionsFile));
+    assert_eq!(origin("mode"), Some(Origin::Profile));
+    assert_eq!(origin("lvl"), Some(Origin::Lock));
+    assert_eq!(origin("x"), Some(Origin::Lock));
+
+    let o = PathOverride { destination: None, decisions: vec!["windows".into(), "lvl=0".into()], variables: Default::default() };
+    let overridden = flan::driver::override_config(&config2, &o).unwrap();
+    let env = make_env(&overridden, Handler::new(ErrorFlags::default(), SrcMap::new())).unwrap();
+    let origin = |d: &str| env.get_dimension(&d.into()).map(|d| d.origin);
+    assert_eq!((origin("os"), origin("lvl")), (Some(Origin::PathOverride), Some(Origin::PathOverride)));
+    assert_eq!((origin("sz"), origin("x")), (Some(Origin::DecisionsFile), Some(Origin::Lock)));
+}
+
+#[test]
+fn overlapping_paths() {
+    use flan::driver::check_overlapping_paths;
+    use flan::error::{ErrorFlags, Handler, MemorySink};
+    use flan::sourcemap::SrcMap;
+    use std::path::PathBuf;
+    let flags = Flags::new(&Opt::from_iter(&["flan"]), None);
+    let paths: Vec<(PathBuf, PathBuf)> = vec![
+        ("conf".into(), "dist".into()),
+        ("./conf/app".into(), "dist2".into()),
+        ("conf2".into(), "dist3".into()),
+        ("other/file".into(), "dist4".into()),
+    ];
+    let sink = MemorySink::new();
+    let mut h = Handler::with_sink(ErrorFlags::default(), SrcMap::new(), Box::new(sink.clone()));
+    check_overlapping_paths(&flags, paths.iter().map(|(s, d)| (s, d)), &mut h);
+    let errs = sink.errors();
+    assert_eq!(errs.len(), 1);
+    assert_eq!(errs[0].msg(), "Paths `conf` and `./conf/app` overlap.");
+}
+
+#[test]
+fn choices_bind_vars() {
+    use flan::driver::make_env;
+    use flan::error::{ErrorFlags, Handler, MemorySink};
+    use flan::sourcemap::SrcMap;
+    let src = r#"
+        [variables]
+        pkg = "none"
+        editor = "vi"
+        [dimensions.os.choices.windows]
+        vars = { pkg = "choco" }
+        [dimensions.os.choices.linux]
+        vars = { pkg = "apt", sep = "/" }
+        [dimensions.os.choices.bsd]
+    "#;
+    let file = File::from_str(src).unwrap();
+    let names = file.dimensions().next().and_then(|(_, chs)| chs.names());
+    assert_eq!(names, Some(vec!["windows".into(), "linux".into(), "bsd".into()]));
+
+    let (names, pairs) = parse_decisions(&["os=linux"]).unwrap();
+    let config = Config::new(names, pairs, file);
+    let env = make_env(&config, Handler::new(ErrorFlags::default(), SrcMap::new())).unwrap();
+    assert_eq!(env.get_dimension(&"os".into()).map(|d| d.decision), Some(ChoiceIdx(1)));
+    assert_eq!(env.get_var(&"pkg".into()), Some(&"apt".into()));
+    assert_eq!(env.get_var(&"sep".into()), Some(&"/".into()));
+    assert_eq!(env.get_var(&"editor".into()), Some(&"vi".into()));
+
+    // two decisions binding the same variable
+    let src = r#"
+        [dimensions.os.choices.linux]
+        vars = { pkg = "apt" }
+        [dimensions.distro.choices.arch]
+        vars = { pkg = "pacman" }
+    "#;
+    let (names, pairs) = parse_decisions(&["linux", "arch"]).unwrap();
+    let config = Config::new(names, pairs, File::from_str(src).unwrap());
+    let sink = MemorySink::new();
+    let h = Handler::with_sink(ErrorFlags::default(), SrcMap::new(), Box::new(sink.clone()));
+    assert!(make_env(&config, h).is_err());
+    assert!(sink.errors().iter().any(|e| e.msg().starts_with("variable `pkg` is bound by both")));
+}
+
+#[test]
+fn deterministic_order() {
+    use flan::driver::make_env;
+    use flan::error::{ErrorFlags, Handler, MemorySink};
+    use flan::sourcemap::SrcMap;
+    // the same diagnostics in the same order, whatever the hashes of this run
+    let src = r#"
+        [paths]
+        "z" = "out/z"
+        "a" = "out/a"
+        "m/n" = "out/m"
+        [dimensions.os.choices.linux]
+        vars = { pkg = "apt", x = "1" }
+        [dimensions.distro.choices.arch]
+        vars = { pkg = "pacman", x = "2" }
+        [dimensions.shell.choices.zsh]
+        vars = { pkg = "zsh" }
+    "#;
+    let (names, pairs) = parse_decisions::<&str>(&[]).unwrap();
+    let config = Config::new(names, pairs, File::from_str(src).unwrap());
+    let sources: Vec<_> = config.paths.keys().map(|p| p.to_str().unwrap()).collect();
+    assert_eq!(sources, vec!["a", "m/n", "z"]);
+    let (names, pairs) = parse_decisions(&["linux", "arch", "zsh"]).unwrap();
+    let config = Config::new(names, pairs, File::from_str(src).unwrap());
+    let sink = MemorySink::new();
+    let h = Handler::with_sink(ErrorFlags::default(), SrcMap::new(), Box::new(sink.clone()));
+    assert!(make_env(&config, h).is_err());
+    let msgs: Vec<_> = sink.errors().iter().map(|e| e.msg().to_string()).collect();
+    assert_eq!(msgs, vec![
+        "variable `pkg` is bound by both `distro=arch` and `os=linux`.",
+        "variable `x` is bound by both `distro=arch` and `os=linux`.",
+        "variable `pkg` is bound by both `distro=arch` and `shell=zsh`.",
+    ]);
+}
+
+#[test]
+fn stdout_flag() {
+    use flan::sourcemap::Destination;
+    let flags = |args: &[&str]| Flags::new(&Opt::from_iter(args.iter()), None);
+    let f = flags(&["flan", "-i", "Cargo.toml", "--stdout"]);
+    assert!(f.stdout && f.to_stdout());
+    // `--stdout` wins over the output of `--stdin`
+    let f = flags(&["flan", "--stdin=out.txt", "--stdout"]);
+    assert_eq!(f.stdin, Some(Destination::Stdout));
+    // the output of `--stdin` needs a `=`, the next argument is a decision
+    let opt = Opt::from_iter(&["flan", "--stdin", "os=linux"]);
+    assert_eq!(Flags::new(&opt, None).stdin, Some(Destination::Stdout));
+    assert_eq!(opt.decisions(), vec!["os=linux"]);
+    assert_eq!(flags(&["flan", "--stdin=out.txt", "linux"]).stdin, Some("out.txt".into()));
+    assert!(Opt::from_iter_safe(&["flan", "-i", "Cargo.toml", "-o", "out", "--stdout"]).is_err());
+}
+
+#[test]
+fn progress_flag() {
+    use flan::driver::progress::Progress;
+    let flags = |args: &[&str]| Flags::new(&Opt::from_iter(args.iter()), None);
+    assert!(flags(&["flan", "--progress"]).progress);
+    assert!(!flags(&["flan", "--progress", "--silence"]).progress);
+    assert!(!flags(&["flan"]).progress);
+    // no progress in the middle of the output
+    assert!(!Progress::new(&flags(&["flan", "--progress", "-i", "Cargo.toml"])).enabled());
+    let mut p = Progress::silent();
+    p.start("parse", 2);
+    p.step("a".as_ref());
+    p.finish();
+    assert!(!p.enabled());
+}
+
+#[test]
+fn subcommands() {
+    let opt = |args: &[&str]| Opt::from_iter_safe(args.iter()).unwrap();
+    let flags = |args: &[&str]| Flags::new(&opt(args), None);
+    // the subcommands and the flags they replace give the same flags
+    let same = |sub: &[&str], legacy: &[&str]| assert_eq!(flags(sub), flags(legacy));
+    same(&["flan", "build", "linux", "--force"], &["flan", "--force", "linux"]);
+    same(&["flan", "build", "--dry-run"], &["flan", "--dry-run"]);
+    same(&["flan", "query", "--per-file", "--format", "json"], &["flan", "-q=per-file", "--query-format", "json"]);
+    same(&["flan", "query", "--variables", "--per-file"], &["flan", "--query-variables=per-file"]);
+    assert_eq!(flags(&["flan", "--query-variables", "--query-format", "json"]).command, Command::QueryVars);
+    assert!(Opt::from_iter_safe(&["flan", "--query-format", "json"]).is_err());
+    same(&["flan", "-c", "flan.toml", "explain", "F0203"], &["flan", "--explain", "F0203", "-c", "flan.toml"]);
+    assert_eq!(flags(&["flan", "unescape", "a"]).command, Command::Unescape);
+    assert_eq!(flags(&["flan", "build", "--matrix", "m.toml"]).command, Command::Matrix);
+    same(&["flan", "build", "--all-variants"], &["flan", "--all-variants"]);
+    assert_eq!(flags(&["flan", "--all-variants"]).command, Command::Matrix);
+    assert_eq!(opt(&["flan", "os=linux", "query", "arm"]).decisions(), vec!["os=linux", "arm"]);
+
+    // conflicting modes are rejected
+    assert!(Opt::from_iter_safe(&["flan", "-q", "--dry-run"]).is_err());
+    assert!(Opt::from_iter_safe(&["flan", "-q", "--query-variables"]).is_err());
+    assert!(Opt::from_iter_safe(&["flan", "query", "--dry-run"]).is_err());
+    assert!(Opt::from_iter_safe(&["flan", "build", "--dry-run", "--matrix", "m.toml"]).is_err());
+    assert!(Opt::from_iter_safe(&["flan", "build", "--all-variants", "--matrix", "m.toml"]).is_err());
+    assert!(opt(&["flan", "--dry-run", "query"]).check().is_err());
+    assert!(opt(&["flan", "--force", "build"]).check().is_ok());
+}
+
+#[test]
+fn path_filters() {
+    use flan::utils::glob::glob_match;
+    use std::path::Path;
+    assert!(glob_match("*.png", Path::new("a/b/img.png")));
+    assert!(!glob_match("*.png", Path::new("img.png.txt")));
+    assert!(glob_match("target/**", Path::new("target/debug/flan")));
+    assert!(!glob_match("target/**", Path::new("src/target/flan")));
+    assert!(glob_match("**/b?/*.rs", Path::new("a/bc/main.rs")));
+    assert!(glob_match("src/*", Path::new("src/main.rs")));
+
+    let src = r#"
+        [paths]
+        "src/" = "out/"
+        ignore = ["*.png", "target/**"]
+        include = ["**/*.conf"]
+    "#;
+    let config = Config::new(Default::default(), Default::default(), File::from_str(src).unwrap());
+    assert_eq!(config.paths.len(), 1);
+    let filters = &config.filters;
+    assert!(filters.included(Path::new("a/x.conf"), false));
+    assert!(!filters.included(Path::new("a/x.txt"), false));
+    assert!(filters.included(Path::new("a"), true));
+    // the whole directory is pruned
+    assert!(!filters.included(Path::new("target"), true));
+    assert!(!filters.included(Path::new("a/x.png"), false));
+}
+
+#[test]
+fn nested_vars() {
+    use flan::driver::expand_vars;
+    use flan::error::{ErrorFlags, Handler, MemorySink};
+    use flan::sourcemap::SrcMap;
+    use std::collections::HashMap;
+    let vars = |vs: &[(&str, &str)]| -> HashMap<String, String> {
+        vs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    };
+    let sink = MemorySink::new();
+    let mut h = Handler::with_sink(ErrorFlags::default(), SrcMap::new(), Box::new(sink.clone()));
+    let expanded = expand_vars(
+        &vars(&[
+            ("home", "/home/#$user#"),
+            ("user", "flan"),
+            ("prefix", "#$home#/bin:#$opt:-/opt#"),
+            ("raw", "a #$ b #$not closed"),
+        ]),
+        &mut h,
+    );
+    assert_eq!(expanded["prefix"], "/home/flan/bin:/opt");
+    assert_eq!(expanded["raw"], "a #$ b #$not closed");
+    assert_eq!(h.err_count, 0);
+
+    let expanded = expand_vars(&vars(&[("a", "#$b#"), ("b", "x#$a#"), ("c", "#$a#"), ("d", "#$nope#")]), &mut h);
+    assert!(expanded.is_empty());
+    h.print_all();
+    let mut msgs: Vec<_> = sink.errors().iter().map(|e| e.msg().to_string()).collect();
+    msgs.sort();
+    assert_eq!(msgs, vec![
+        "variable `a` references itself.",
+        "variable `d` references the undeclared variable `nope`.",
+    ]);
+}
+
+#[test]
+fn lockfile() {
+    use flan::cfg::lock::{lock_path, Lock};
+    use flan::driver::{env_to_lock, make_env};
+    use flan::error::{ErrorFlags, Handler};
+    use flan::sourcemap::SrcMap;
+    assert_eq!(lock_path(None::<&str>), std::path::PathBuf::from(".flan.lock"));
+    assert_eq!(lock_path(Some("cfg/flan.toml")), std::path::PathBuf::from("cfg/flan.toml.lock"));
+
+    let decl = "[variables]\nuser = \"flan\"\n[dimensions]\nos = [\"linux\", \"windows\"]\nsz = 2\nmode = [\"dev\", \"prod\"]";
+    let lock = Lock::from_str("[decisions]\nos = \"windows\"\nsz = 1\nmode = \"prod\"\nx = 3\n[variables]\nuser = \"lock\"").unwrap();
+    assert_eq!(lock.decisions["sz"], Index::Num(ChoiceIdx(1)));
+    assert_eq!(Lock::from_str(&lock.to_string()).unwrap(), lock);
+
+    // the command line decides `os` by name and `mode` by pair
+    let (names, pairs) = parse_decisions(&["linux", "mode=dev"]).unwrap();
+    let config = Config::new(names, pairs, File::from_str(decl).unwrap()).with_lock(lock);
+    assert_eq!(config.variables["user"], "lock");
+    assert_eq!(config.decisions_pair.get("os"), None);
+    assert_eq!(config.decisions_pair["mode"], Index::Name("dev".into()));
+    assert_eq!(config.decisions_pair["sz"], Index::Num(ChoiceIdx(1)));
+    assert_eq!(config.locked.keys().collect::<Vec<_>>(), vec!["x"]);
+
+    let env = make_env(&config, Handler::new(ErrorFlags::default(), SrcMap::new())).unwrap();
+    let lock = env_to_lock(&env, &config);
+    assert_eq!(lock.decisions["os"], Index::Name("linux".into()));
+    assert_eq!(lock.decisions["sz"], Index::Num(ChoiceIdx(1)));
+    assert_eq!(lock.variables["user"], "lock");
+}
+
+#[test]
+fn lockfile_sourced_variables() {
+    use flan::cfg::lock::Lock;
+    use flan::driver::{env_to_lock, make_env};
+    use flan::error::{ErrorFlags, Handler};
+    use flan::sourcemap::SrcMap;
+    let decl = r##"
+        [variables]
+        user = { env = "FLAN_TEST_LOCK_USER" }
+        home = "/home/#$user#"
+        bin = "#$home#/bin"
+        ver = { cmd = "echo 1.0" }
+    "##;
+    let run = |lock: Option<Lock>| {
+        let config = Config::new(Default::default(), Default::default(), File::from_str(decl).unwrap());
+        let config = match lock {
+            Some(lock) => config.with_lock(lock),
+            None => config,
+        };
+        let env = make_env(&config, Handler::new(ErrorFlags::default(), SrcMap::new())).unwrap();
+        (env_to_lock(&env, &config), env.get_var(&"bin".into()).cloned(), config)
+    };
+    std::env::set_var("FLAN_TEST_LOCK_USER", "nobody");
+    let (lock, bin, _) = run(None);
+    assert_eq!(bin.as_deref(), Some("/home/nobody/bin"));
+    // the values aren't expanded, the environment and the commands aren't locked
+    let mut vars: Vec<_> = lock.variables.iter().map(|(n, v)| (n.as_str(), v.as_str())).collect();
+    vars.sort();
+    assert_eq!(vars, vec![("bin", "#$home#/bin"), ("home", "/home/#$user#")]);
+
+    std::env::set_var("FLAN_TEST_LOCK_USER", "flan");
+    let mut old = lock.clone();
+    old.variables.insert("user".into(), "nobody".into());
+    old.variables.insert("ver".into(), "0.1".into());
+    let (relock, bin, config) = run(Some(old));
+    assert_eq!(bin.as_deref(), Some("/home/flan/bin"));
+    assert_eq!(relock, lock);
+    assert!(config.env_variables.contains_key("user") && config.cmd_variables.contains_key("ver"));
+}
+
+#[test]
+fn decisions_file() {
+    let profile = Profile::from_str("os = \"windows\"\nsz = 1\nx = 2\n[variables]\nuser = \"profile\"").unwrap();
+    assert_eq!(profile.decisions["sz"], Index::Num(ChoiceIdx(1)));
+    assert_eq!(profile.variables["user"], "profile");
+    let dir = std::env::temp_dir().join(format!("flan-decisions-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("release.toml"), "mode = \"prod\"").unwrap();
+    assert_eq!(path_to_profile(dir.join("release.toml")).unwrap().decisions["mode"], Index::Name("prod".into()));
+    assert!(path_to_profile(dir.join("missing.toml")).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // command line > profile > lockfile > config file
+    let decl = "[variables]\nuser = \"flan\"\n[dimensions]\nos = [\"linux\", \"windows\"]\nsz = 2\nmode = [\"dev\", \"prod\"]";
+    let lock = Lock::from_str("[decisions]\nmode = \"prod\"\nsz = 0\nx = 3\ny = 0\n[variables]\nuser = \"lock\"").unwrap();
+    let (names, pairs) = parse_decisions(&["linux"]).unwrap();
+    let config = Config::new(names, pairs, File::from_str(decl).unwrap()).with_profile(profile).with_lock(lock);
+    assert_eq!(config.variables["user"], "profile");
+    assert_eq!(config.decisions_pair.get("os"), None);
+    assert_eq!(config.decisions_pair["sz"], Index::Num(ChoiceIdx(1)));
+    assert_eq!(config.decisions_pair["mode"], Index::Name("prod".into()));
+    assert_eq!((config.locked["x"].clone(), config.locked["y"].clone()), (Index::Num(ChoiceIdx(2)), Index::Num(ChoiceIdx(0))));
+}
+
+#[test]
+fn profiles() {
+    let decl = "[dimensions]\nos = [\"linux\", \"windows\"]\nsz = 2\n[variables]\nuser = \"flan\"\n\
+                [profiles.linux-dev]\nos = \"linux\"\nsz = 1\n[profiles.linux-dev.variables]\nuser = \"dev\"\npkg = \"apt\"\n\
+                [profiles.win]\nos = 1";
+    let file = File::from_str(decl).unwrap();
+    assert_eq!(file.profiles.as_ref().unwrap().len(), 2);
+
+    // command line > decisions-file > profile
+    let (names, pairs) = parse_decisions(&["sz=0"]).unwrap();
+    let decisions = Profile::from_str("[variables]\nuser = \"file\"").unwrap();
+    let config = Config::new(names, pairs, file).with_profile(decisions).select_profile("linux-dev").unwrap();
+    assert_eq!(config.decisions_pair["os"], Index::Name("linux".into()));
+    assert_eq!(config.decisions_pair["sz"], Index::Num(ChoiceIdx(0)));
+    assert_eq!(config.variables["user"], "file");
+    assert_eq!(config.variables["pkg"], "apt");
+
+    match Config::new(Default::default(), Default::default(), File::from_str(decl).unwrap()).select_profile("mac") {
+        Err(Error::Cfg { msg, .. }) => assert!(msg.contains("`linux-dev`, `win`"), "{}", msg),
+        _ => panic!("`mac` isn't a profile"),
+    }
+}
+
+#[test]
+fn extends() {
+    let dir = std::env::temp_dir().join(format!("flan-extends-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("project")).unwrap();
+    std::fs::write(
+        dir.join("base.flan"),
+        "[options]\nforce = true\nverbosity = 1\n[variables]\nuser = \"base\"\nshell = \"zsh\"\n\
+         [dimensions]\nos = [\"linux\", \"mac\"]\n[paths]\nignore = [\"*.bak\"]\n\"zshrc\" = \"~/.zshrc\"",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("project/.flan"),
+        "extends = \"../base.flan\"\n[options]\nverbosity = 3\n[variables]\nuser = \"project\"\n[paths]\nignore = [\"*.swp\"]",
+    )
+    .unwrap();
+    let file = path_to_cfgfile(Some(dir.join("project/.flan"))).unwrap();
+    let options = file.options.as_ref().unwrap();
+    assert_eq!((options.force, options.verbosity), (Some(true), Some(3)));
+    assert_eq!(file.paths.as_ref().unwrap().ignore, vec!["*.bak", "*.swp"]);
+    assert_eq!(file.paths().count(), 1);
+    let config = Config::new(Default::default(), Default::default(), file);
+    assert_eq!((config.variables["user"].as_str(), config.variables["shell"].as_str()), ("project", "zsh"));
+    assert!(config.dimensions.contains_key("os"));
+    assert!(config.declared_in("dimensions.os").contains("base.flan"));
+    assert!(!config.origins.contains_key("variables.user"));
+
+    std::fs::write(dir.join("base.flan"), "extends = \"project/.flan\"").unwrap();
+    match path_to_cfgfile(Some(dir.join("project/.flan"))) {
+        Err(Error::Cfg { msg, .. }) => assert!(msg.contains("extends itself"), "{}", msg),
+        r => panic!("expected a cycle, got {:?}", r),
+    }
+    std::fs::write(dir.join("base.flan"), "[variables").unwrap();
+    match path_to_cfgfile(Some(dir.join("project/.flan"))) {
+        Err(Error::Syntax { path, .. }) => assert!(path.ends_with("base.flan")),
+        r => panic!("expected a parse error, got {:?}", r),
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn dimension_aliases() {
+    use flan::driver::{make_env, string_to_parser};
+    use flan::error::{ErrorFlags, Handler};
+    use flan::infer::check;
+    use flan::sourcemap::SrcMap;
+    let handler = || Handler::new(ErrorFlags::default(), SrcMap::new());
+    let decl = "[dimensions]\nos = [\"linux\", \"mac\"]\n[dimensions.aliases]\nplatform = \"os\"\nsystem = \"os\"";
+    let file = File::from_str(decl).unwrap();
+    assert_eq!(file.dimensions().count(), 1);
+    assert_eq!(file.aliases().count(), 2);
+
+    let (names, pairs) = parse_decisions(&["platform=mac"]).unwrap();
+    let env = make_env(&Config::new(names, pairs, file), handler()).unwrap();
+    assert_eq!(env.get_dimension(&"os".into()).unwrap().decision, ChoiceIdx(1));
+    assert_eq!(env.get_dimension(&"platform".into()).unwrap().decision, ChoiceIdx(1));
+
+    // the alias and the dimension are the same, including their number of choices
+    let mut env = env;
+    let mut h = handler();
+    let terms = string_to_parser(&mut h, "#os{a##b}# #platform{c##d}#".into()).unwrap().parse().unwrap();
+    assert!(!check(&terms, &mut env).0);
+    let terms = string_to_parser(&mut h, "#system{a##b##c}#".into()).unwrap().parse().unwrap();
+    assert!(check(&terms, &mut env).0);
+
+    // deciding a dimension by several of its names
+    let (names, pairs) = parse_decisions(&["platform=mac", "os=linux"]).unwrap();
+    assert!(make_env(&Config::new(names, pairs, File::from_str(decl).unwrap()), handler()).is_err());
+    // aliases of aliases, and aliases which are declared dimensions
+    for aliases in &["a = \"platform\"", "os = \"sz\""] {
+        let decl = format!("{}\n{}", decl, aliases);
+        let config = Config::new(Default::default(), Default::default(), File::from_str(&decl).unwrap());
+        assert!(make_env(&config, handler()).is_err(), "{}", aliases);
+    }
+}
+
+#[test]
+fn config_validation() {
+    use flan::driver::make_env;
+    use flan::error::{codes, ErrorFlags, Handler, MemorySink};
+    use flan::sourcemap::SrcMap;
+    let many: Vec<_> = (0..=ChoiceIdx::MAX_CHOICES).map(|i| format!("\"c{}\"", i)).collect();
+    let decl = format!(
+        "[variables]\n\"user name\" = \"foo\"\n\"a:-b\" = \"c\"\n[dimensions]\nos = [\"linux\", \"mac\", \"linux\"]\n\
+         arch = [\"x86-64\", \"arm\"]\n\"2d\" = 2\nbig = [{}]\n[dimensions.aliases]\n\"os-alias\" = \"os\"\n[paths]\n\"src\" = \"\"",
+        many.join(", ")
+    );
+    assert!(!Choices::Names(many).valid());
+    let config = Config::new(Default::default(), Default::default(), File::from_str(&decl).unwrap());
+    let sink = MemorySink::new();
+    assert!(make_env(&config, Handler::with_sink(ErrorFlags::default(), SrcMap::new(), Box::new(sink.clone()))).is_err());
+    let errs = sink.errors();
+    // the delayed errors are printed in order
+    let msgs: Vec<_> = errs.iter().map(|e| e.msg()).collect();
+    assert_eq!(
+        msgs,
+        vec![
+            "dimension name `2d` isn't an identifier.",
+            "choice `x86-64` of dimension `arch` isn't an identifier.",
+            "dimension `big` has duplicate choices, or more than 65535 of them.",
+            "dimension `os` has duplicate choices, or more than 65535 of them.",
+            "alias `os-alias` isn't an identifier.",
+            "variable name `a:-b` can't be used as `#$a:-b#`.",
+            "variable name `user name` can't be used as `#$user name#`.",
+            "path `src` = `` has an empty source or destination.",
+        ]
+    );
+    assert!(errs.iter().all(|e| e.code() == Some(codes::INVALID_DECLARATION)));
+}
+
+#[test]
+fn env_variables() {
+    use flan::driver::make_env;
+    use flan::error::{ErrorFlags, Handler};
+    use flan::sourcemap::SrcMap;
+    std::env::set_var("FLAN_TEST_ENV_USER", "flan");
+    std::env::remove_var("FLAN_TEST_ENV_UNSET");
+    let decl = r#"
+        [variables]
+        user = { env = "FLAN_TEST_ENV_USER", default = "nobody" }
+        home = { env = "FLAN_TEST_ENV_UNSET", default = "/home/#$user#" }
+        plain = "x"
+    "#;
+    let file = File::from_str(decl).unwrap();
+    let expected = Variable::Env(EnvVar { env: "FLAN_TEST_ENV_USER".into(), default: Some("nobody".into()) });
+    assert_eq!(file.variables().find(|(n, _)| *n == "user").map(|(_, v)| v), Some(&expected));
+    let config = Config::new(Default::default(), Default::default(), file);
+    assert_eq!(config.env_variables.len(), 2);
+    let env = make_env(&config, Handler::new(ErrorFlags::default(), SrcMap::new())).unwrap();
+    assert_eq!(env.get_var(&"user".into()), Some(&"flan".into()));
+    assert_eq!(env.get_var(&"home".into()), Some(&"/home/flan".into()));
+    assert_eq!(env.get_var(&"plain".into()), Some(&"x".into()));
+
+    // unset without a default
+    let file = File::from_str("[variables]\nhome = { env = \"FLAN_TEST_ENV_UNSET\" }").unwrap();
+    let config = Config::new(Default::default(), Default::default(), file);
+    assert!(make_env(&config, Handler::new(ErrorFlags::default(), SrcMap::new())).is_err());
+}
+
+#[test]
+#[cfg(unix)]
+fn cmd_variables() {
+    use flan::driver::make_env;
+    use flan::error::{ErrorFlags, Handler};
+    use flan::sourcemap::SrcMap;
+    let handler = || Handler::new(ErrorFlags::default(), SrcMap::new());
+    let runs = std::env::temp_dir().join(format!("flan-cmd-runs-{}", std::process::id()));
+    let _ = std::fs::remove_file(&runs);
+    let decl = format!(
+        "[variables]\nversion = {{ cmd = \"printf 'v1.0\\\\n\\\\n'\" }}\nruns = {{ cmd = \"echo >> {0}; wc -l < {0}\" }}",
+        runs.display()
+    );
+    let config = Config::new(Default::default(), Default::default(), File::from_str(&decl).unwrap());
+    let env = make_env(&config, handler()).unwrap();
+    assert_eq!(env.get_var(&"version".into()), Some(&"v1.0".into()));
+    // the commands only run once
+    let env = make_env(&config.clone(), handler()).unwrap();
+    assert_eq!(env.get_var(&"runs".into()).map(|s| s.trim()), Some("1"));
+    let _ = std::fs::remove_file(&runs);
+
+    let file = File::from_str("[variables]\nfails = { cmd = \"exit 3\" }").unwrap();
+    let mut config = Config::new(Default::default(), Default::default(), file);
+    assert!(make_env(&config, handler()).is_err());
+    // `--no-exec`
+    config.exec = false;
+    let env = make_env(&config, handler()).unwrap();
+    assert_eq!(env.get_var(&"fails".into()), None);
+}
+
+#[test]
+fn allow_deny_warnings() {
+    use flan::error::{codes, Handler, Level, MemorySink, WarnKind};
+    use flan::sourcemap::SrcMap;
+    let opt = Opt::from_iter(&["flan", "--allow", "redundant-decision", "--deny", "empty-choice", "linux", "--Werror", "--allow", "no-paths"]);
+    assert_eq!(opt.decisions, vec!["linux".to_string()]);
+    assert!(Opt::from_iter_safe(&["flan", "--allow", "nope"]).is_err());
+    let flags = Flags::new(&opt, None);
+    let sink = MemorySink::new();
+    let mut h = Handler::with_sink(flags.eflags, SrcMap::new(), Box::new(sink.clone()));
+    h.warn(WarnKind::RedundantDecision, "redundant").print();
+    h.warn(WarnKind::NoPaths, "no paths").print();
+    h.warn(WarnKind::EmptyBranch, "empty").print();
+    h.warn(WarnKind::OverlappingPaths, "overlap").print();
+    let errs = sink.errors();
+    assert_eq!(errs.iter().map(|e| e.msg()).collect::<Vec<_>>(), vec!["empty", "overlap"]);
+    assert!(errs.iter().all(|e| e.level() == Level::Error));
+    assert_eq!(errs[0].code(), Some(codes::EMPTY_BRANCH));
+    // `--deny` wins over `--allow`
+    let opt = Opt::from_iter(&["flan", "--allow", "no-paths", "--deny", "no-paths"]);
+    let sink = MemorySink::new();
+    let mut h = Handler::with_sink(Flags::new(&opt, None).eflags, SrcMap::new(), Box::new(sink.clone()));
+    h.warn(WarnKind::NoPaths, "no paths").print();
+    assert_eq!(sink.errors()[0].level(), Level::Error);
+    // `empty-choice` is the former name of `empty-branch`
+    assert_eq!(WarnKind::from_name("empty-choice"), WarnKind::from_name("empty-branch"));
+}
+
+#[test]
+fn unused_declarations() {
+    use flan::driver::{check_unused, make_env, string_to_parser};
+    use flan::error::{codes, ErrorFlags, Handler, MemorySink};
+    use flan::infer::check;
+    use flan::sourcemap::SrcMap;
+    let decl = "[variables]\nuser = \"foo\"\nemail = \"#$user#@bar.com\"\nunused = \"x\"\nhome = { env = \"HOME\", default = \"/\" }\n\
+[dimensions]\nos = [\"linux\", \"mac\"]\narch = 2\nsize = 3\n[dimensions.aliases]\nplatform = \"os\"";
+    let (names, pairs) = parse_decisions(&["linux", "arch=0", "size=1"]).unwrap();
+    let mut config = Config::new(names, pairs, File::from_str(decl).unwrap());
+    config.path = Some(".flan".into());
+    let sink = MemorySink::new();
+    let h = Handler::with_sink(ErrorFlags::default(), SrcMap::new(), Box::new(sink.clone()));
+    let mut env = make_env(&config, h).unwrap();
+    let mut h = Handler::new(ErrorFlags::default(), SrcMap::new());
+    let src = "#platform{a##b}# #when(arch=1){x}# #$email#";
+    let terms = string_to_parser(&mut h, src.into()).unwrap().parse().unwrap();
+    assert!(!check(&terms, &mut env).0);
+    check_unused(&config, &mut env);
+    let errs = sink.errors();
+    let msgs: Vec<_> = errs.iter().map(|e| e.msg()).collect();
+    assert_eq!(msgs, vec!["dimension `size` is never used.", "variable `home` is never used.", "variable `unused` is never used."]);
+    assert_eq!(errs[0].code(), Some(codes::UNUSED_DIMENSION));
+    assert!(errs[1].render(None).contains("note: declared in `.flan`."));
+}
+
+#[test]
+fn unused_decisions() {
+    use flan::driver::{check_unused_decisions, make_env, string_to_parser};
+    use flan::error::{codes, Handler, Level, MemorySink};
+    use flan::infer::{check, unify_labels};
+    use flan::sourcemap::SrcMap;
+    let run = |args: &[&str]| {
+        let opt = Opt::from_iter(args);
+        let (names, pairs) = opt.parse_decisions().unwrap();
+        let config = Config::new(names, pairs, File::from_str("[dimensions]\nos = [\"linux\", \"mac\"]\narch = 2").unwrap());
+        let sink = MemorySink::new();
+        let h = Handler::with_sink(Flags::new(&opt, None).eflags, SrcMap::new(), Box::new(sink.clone()));
+        let mut env = make_env(&config, h).unwrap();
+        let mut h = Handler::new(Default::default(), SrcMap::new());
+        let terms = string_to_parser(&mut h, "#dist{debian: a##alpine: b}# #sz{x##y}#".into()).unwrap().parse().unwrap();
+        assert!(!unify_labels(std::iter::once(&terms), &mut env));
+        assert!(!check(&terms, &mut env).0);
+        check_unused_decisions(&config, &mut env);
+        sink.errors()
+    };
+    // declared, used or matching a label
+    assert!(run(&["flan", "linux", "arch=1", "sz=0", "debian"]).is_empty());
+    let errs = run(&["flan", "linxu", "foo=1", "os=mac", "arch=0", "sz=1", "alpine"]);
+    let msgs: Vec<_> = errs.iter().map(|e| e.msg()).collect();
+    assert_eq!(msgs, vec!["decision `foo=1` matches no dimension.", "decision `linxu` matches no dimension."]);
+    assert!(errs.iter().all(|e| e.level() == Level::Warning && e.code() == Some(codes::UNUSED_DECISION)));
+    let errs = run(&["flan", "--strict-decisions", "foo=1", "linux", "arch=0", "sz=1", "alpine"]);
+    assert_eq!(errs[0].level(), Level::Error);
+}
+
+#[test]
+fn did_you_mean() {
+    use flan::driver::{make_env, string_to_parser};
+    use flan::error::{codes, ErrorFlags, Handler, MemorySink};
+    use flan::sourcemap::SrcMap;
+    use flan::utils::{closest, edit_distance};
+    assert_eq!(edit_distance("linx", "linux"), 1);
+    assert_eq!(edit_distance("", "abc"), 3);
+    assert_eq!(closest("Linux", vec!["mac", "linux"]), Some("linux"));
+    assert_eq!(closest("wnidows", vec!["windows", "linux"]), Some("windows"));
+    assert_eq!(closest("bsd", vec!["windows", "linux"]), None);
+
+    let decl = "[dimensions]\nos = [\"linux\", \"mac\"]";
+    let (names, pairs) = parse_decisions(&["os=linx"]).unwrap();
+    let sink = MemorySink::new();
+    let h = Handler::with_sink(ErrorFlags::default(), SrcMap::new(), Box::new(sink.clone()));
+    assert!(make_env(&Config::new(names, pairs, File::from_str(decl).unwrap()), h).is_err());
+    let errs = sink.errors();
+    assert_eq!((errs[0].code(), errs[0].msg()), (Some(codes::UNKNOWN_CHOICE), "no choice `linx` in dimension `os`."));
+    assert_eq!(errs[0].extra().last().unwrap(), "suggestion: did you mean `linux`?");
+
+    let (names, pairs) = parse_decisions(&["linux"]).unwrap();
+    let sink = MemorySink::new();
+    let h = Handler::with_sink(ErrorFlags::default(), SrcMap::new(), Box::new(sink.clone()));
+    let mut env = make_env(&Config::new(names, pairs, File::from_str(decl).unwrap()), h).unwrap();
+    let mut h = Handler::new(ErrorFlags::default(), SrcMap::new());
+    let terms = string_to_parser(&mut h, "#OS{a##b}# #when(os=max){c}#".into()).unwrap().parse().unwrap();
+    assert!(flan::infer::check(&terms, &mut env).0);
+    let suggestions: Vec<_> = sink.errors().iter().map(|e| e.extra().last().unwrap().clone()).collect();
+    assert_eq!(suggestions, vec!["suggestion: did you mean `os`?", "suggestion: did you mean `mac`?"]);
+}
+
+#[test]
+fn dimension_docs() {
+    use flan::driver::{make_env, pp_query, query_dims, string_to_parser};
+    use flan::error::{ErrorFlags, Handler, MemorySink};
+    use flan::sourcemap::SrcMap;
+    use std::collections::HashMap;
+    let decl = "[dimensions]\nos = { choices = [\"linux\", \"mac\"], doc = \"target operating system\" }\nsize = 2\n\
+                [dimensions.pm]\ndoc = \"package manager\"\nchoices.apt.vars = { cmd = \"apt\" }\nchoices.brew = {}";
+    let file = File::from_str(decl).unwrap();
+    let dims: HashMap<_, _> = file.dimensions_cloned().collect();
+    assert_eq!(dims["os"].names(), Some(vec!["linux".into(), "mac".into()]));
+    assert_eq!(dims["os"].doc(), Some("target operating system"));
+    assert_eq!(dims["pm"].names(), Some(vec!["apt".into(), "brew".into()]));
+    assert_eq!(dims["size"].doc(), None);
+
+    let (names, pairs) = parse_decisions(&["os=linx", "pm=apt", "size=0"]).unwrap();
+    let sink = MemorySink::new();
+    let h = Handler::with_sink(ErrorFlags::default(), SrcMap::new(), Box::new(sink.clone()));
+    assert!(make_env(&Config::new(names, pairs, File::from_str(decl).unwrap()), h).is_err());
+    assert!(sink.errors()[0].extra().contains(&"note: `os`: target operating system".to_string()));
+
+    let (names, pairs) = parse_decisions(&["mac", "pm=apt", "size=0"]).unwrap();
+    let config = Config::new(names, pairs, File::from_str(decl).unwrap());
+    let mut env = make_env(&config, Handler::new(ErrorFlags::default(), SrcMap::new())).unwrap();
+    let sm = SrcMap::new();
+    let mut h = Handler::new(ErrorFlags::default(), sm.clone());
+    let f = sm.load_source("a".into(), "a".into(), "#os{a##b}#".into());
+    let trees = vec![(f, string_to_parser(&mut h, "#os{a##b}#".into()).unwrap().parse().unwrap())];
+    let query = query_dims(&trees, &mut env, &config.dimensions, QueryScope::All);
+    assert_eq!(pp_query(&query, QueryFormat::Human), "dim os = [\"linux\", \"mac\"] -> mac (choice name)  # target operating system\n");
+}
+
+#[test]
+fn config_spans() {
+    use flan::cfg::file::locate_key;
+    use flan::driver::{check_unused, make_env};
+    use flan::error::{ErrorFlags, Handler, MemorySink};
+    use flan::sourcemap::SrcMap;
+    let decl = "[variables]\nuser = \"foo\"\n\n[dimensions]\n\"os\" = [\"linux\", \"mac\"]\n[dimensions.arch.choices.x86]\n\
+                [dimensions.aliases]\nplatform = \"os\"";
+    assert_eq!(locate_key(decl, &["variables", "user"]), Some((12, 16)));
+    assert_eq!(locate_key(decl, &["dimensions", "os"]).map(|(lo, hi)| &decl[lo..hi]), Some("\"os\""));
+    assert_eq!(locate_key(decl, &["dimensions", "arch"]).map(|(lo, hi)| &decl[lo..hi]), Some("[dimensions.arch.choices.x86]"));
+    assert_eq!(locate_key(decl, &["dimensions", "aliases", "platform"]).map(|(lo, _)| lo), decl.find("platform"));
+    assert_eq!(locate_key(decl, &["variables", "email"]), None);
+
+    let dir = std::env::temp_dir().join(format!("flan-spans-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(".flan"), "[variables]\nuser = \"foo\"").unwrap();
+    let mut config = Config::new(Default::default(), Default::default(), path_to_cfgfile(Some(dir.join(".flan"))).unwrap());
+    config.path = Some(dir.join(".flan"));
+    let sources = SrcMap::new();
+    config.load_into(&sources);
+    let sink = MemorySink::new();
+    let mut env = make_env(&config, Handler::with_sink(ErrorFlags::default(), sources, Box::new(sink.clone()))).unwrap();
+    check_unused(&config, &mut env);
+    let errs = sink.errors();
+    assert_eq!(errs.len(), 1);
+    assert_eq!(errs[0].span(), config.span("variables.user").unwrap());
+    assert!(errs[0].render(env.handler.sources.lookup_span(errs[0].span()).map(|(f, _)| f)).contains("user = \"foo\""));
+
+    std::fs::write(dir.join(".flan"), "[variables]\nuser = \"foo\"\nemail = @").unwrap();
+    match path_to_cfgfile(Some(dir.join(".flan"))) {
+        Err(Error::Syntax { error, .. }) => assert_eq!(error.line_col().map(|(l, _)| l), Some(2)),
+        r => panic!("expected a syntax error, got {:?}", r),
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn duplicate_destinations() {
+    use flan::driver::{check_destinations, load_sources};
+    use flan::error::{codes, ErrorFlags, Handler, MemorySink};
+    use std::path::PathBuf;
+    let dir = std::env::temp_dir().join(format!("flan-dests-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("d")).unwrap();
+    for f in &["a", "b", "d/c"] {
+        std::fs::write(dir.join(f), "x").unwrap();
+    }
+    let out = dir.join("out");
+    let paths: Vec<(PathBuf, PathBuf)> = vec![
+        (dir.join("a"), out.join("f")),
+        (dir.join("b"), out.join(".").join("f")),
+        (dir.join("d"), out.join("d")),
+    ];
+    let flags = Flags::new(&Opt::from_iter(&["flan"]), None);
+    let (source_map, sources) = load_sources(&flags, paths.iter().map(|(s, d)| (s, d)), &PathFilters::default(), &FileKinds::default());
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(sources.len(), 3);
+    let sink = MemorySink::new();
+    let mut h = Handler::with_sink(ErrorFlags::default(), source_map, Box::new(sink.clone()));
+    check_destinations(&sources, &mut h);
+    let errs = sink.errors();
+    assert_eq!(errs.len(), 1);
+    assert_eq!(errs[0].code(), Some(codes::DUPLICATE_DESTINATION));
+    assert_eq!(errs[0].msg(), format!("`{}` and `{}` are written to the same destination.", dir.join("a").display(), dir.join("b").display()));
+}
+
+#[test]
+fn file_kinds() {
+    use flan::driver::load_sources;
+    use flan::sourcemap::FileKind;
+    use std::path::{Path, PathBuf};
+    let file = File::from_str("[files]\nbinary = [\"*.png\", \"vendor/**\"]\ntext = [\"*.conf\"]").unwrap();
+    let config = Config::new(Default::default(), Default::default(), file);
+    assert_eq!(config.file_kinds.kind(Path::new("img/a.png")), FileKind::Binary);
+    assert_eq!(config.file_kinds.kind(Path::new("vendor/x.conf")), FileKind::Binary);
+    assert_eq!(config.file_kinds.kind(Path::new("x.conf")), FileKind::Text);
+    assert_eq!(config.file_kinds.kind(Path::new("x.txt")), FileKind::Auto);
+
+    let dir = std::env::temp_dir().join(format!("flan-kinds-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a.png"), "#$not_parsed#").unwrap();
+    std::fs::write(dir.join("b.conf"), b"\xff\xfe").unwrap();
+    std::fs::write(dir.join("c"), b"\xff\xfe").unwrap();
+    std::fs::write(dir.join("d.conf"), "text").unwrap();
+    let paths: Vec<(PathBuf, PathBuf)> = vec![(dir.clone(), "out".into())];
+    let flags = Flags::new(&Opt::from_iter(&["flan"]), None);
+    let (_, sources) = load_sources(&flags, paths.iter().map(|(s, d)| (s, d)), &config.filters, &config.file_kinds);
+    std::fs::remove_dir_all(&dir).unwrap();
+    // `b.conf` isn't valid UTF-8 and couldn't be loaded
+    let loaded: Vec<_> = sources.iter().map(|f| (f.name.as_str(), f.is_binary())).collect();
+    assert_eq!(loaded, vec![("a.png", true), ("c", true), ("d.conf", false)]);
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_policies() {
+    use flan::driver::{copy_bin, load_sources};
+    use flan::sourcemap::SourceInfo;
+    use std::os::unix::fs::symlink;
+    use std::path::{Path, PathBuf};
+    let dir = std::env::temp_dir().join(format!("flan-symlinks-{}", std::process::id()));
+    let src = dir.join("src");
+    std::fs::create_dir_all(src.join("sub")).unwrap();
+    std::fs::write(src.join("a"), "a").unwrap();
+    std::fs::write(src.join("sub/b"), "b").unwrap();
+    symlink("a", src.join("link")).unwrap();
+    symlink("..", src.join("sub/loop")).unwrap();
+    let paths: Vec<(PathBuf, PathBuf)> = vec![(src.clone(), dir.join("out"))];
+    let load = |policy: &str| {
+        let flags = Flags::new(&Opt::from_iter(&["flan", "--symlinks", policy]), None);
+        let (_, sources) = load_sources(&flags, paths.iter().map(|(s, d)| (s, d)), &PathFilters::default(), &FileKinds::default());
+        (flags, sources)
+    };
+    let rel = |sources: &[flan::sourcemap::SrcFile]| -> Vec<_> {
+        sources.iter().map(|f| f.path.strip_prefix(&src).unwrap().to_path_buf()).collect()
+    };
+    let p = |s: &str| PathBuf::from(s);
+
+    assert_eq!(Flags::new(&Opt::from_iter(&["flan"]), None).symlinks, Symlinks::Follow);
+    // `sub/loop` is `src` again, it isn't walked
+    assert_eq!(rel(&load("follow").1), vec![p("a"), p("link"), p("sub/b")]);
+    assert_eq!(rel(&load("skip").1), vec![p("a"), p("sub/b")]);
+    let (flags, sources) = load("copy-link");
+    assert_eq!(rel(&sources), vec![p("a"), p("link"), p("sub/b"), p("sub/loop")]);
+    assert_eq!(sources[1].src, SourceInfo::Symlink("a".into()));
+    assert!(sources[1].is_binary());
+    copy_bin(&flags, sources[3].clone()).unwrap();
+    assert_eq!(std::fs::read_link(dir.join("out/sub/loop")).unwrap(), Path::new(".."));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn ignore_files() {
+    use flan::driver::load_sources;
+    use std::path::PathBuf;
+    let dir = std::env::temp_dir().join(format!("flan-ignore-files-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("target")).unwrap();
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::fs::write(dir.join(".gitignore"), "# build\ntarget/\n*.swp\n!keep.swp\n").unwrap();
+    std::fs::write(dir.join("sub/.flanignore"), "/a\n!b.swp\n").unwrap();
+    for f in &["a", "b.swp", "keep.swp", "target/x", "sub/a", "sub/b.swp", "sub/c.swp"] {
+        std::fs::write(dir.join(f), "").unwrap();
+    }
+    let paths: Vec<(PathBuf, PathBuf)> = vec![(dir.clone(), "out".into())];
+    let flags = Flags::new(&Opt::from_iter(&["flan"]), None);
+    let load = |ignore_files: bool| {
+        let filters = PathFilters { ignore_files, ..Default::default() };
+        let (_, sources) = load_sources(&flags, paths.iter().map(|(s, d)| (s, d)), &filters, &FileKinds::default());
+        sources.iter().map(|f| f.path.strip_prefix(&dir).unwrap().to_string_lossy().into_owned()).collect::<Vec<_>>()
+    };
+    assert!(Opt::from_iter(&["flan", "--ignore-files"]).ignore_files);
+    assert_eq!(load(false).len(), 9);
+    // `.flanignore` isn't loaded, `sub/a` is anchored and `sub/b.swp` included back
+    assert_eq!(load(true), vec![".gitignore", "a", "keep.swp", "sub/b.swp"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn overlays() {
+    use flan::driver::load_sources_with;
+    use flan::error::{Handler, MemorySink};
+    use flan::sourcemap::SrcMap;
+    use std::path::PathBuf;
+    let dir = std::env::temp_dir().join(format!("flan-overlays-{}", std::process::id()));
+    for f in &["base/conf/a", "base/conf/b", "base/only", "top/conf/b", "top/conf/c"] {
+        std::fs::create_dir_all(dir.join(f).parent().unwrap()).unwrap();
+        std::fs::write(dir.join(f), f).unwrap();
+    }
+    let (base, top) = (dir.join("base"), dir.join("top"));
+    let opt = Opt::from_iter(&["flan", "-v", "-i", base.to_str().unwrap(), "-i", top.to_str().unwrap()]);
+    let flags = Flags::new(&opt, None);
+    assert_eq!(flags.overlays, vec![top.clone()]);
+    assert!(flags.single_file().is_none());
+    let paths: Vec<(PathBuf, PathBuf)> = vec![("conf".into(), "out".into()), ("./only".into(), "only".into())];
+    let sink = MemorySink::new();
+    let mut h = Handler::with_sink(flags.eflags, SrcMap::new(), Box::new(sink.clone()));
+    let sources = load_sources_with(&flags, paths.iter().map(|(s, d)| (s, d)), &Default::default(), &FileKinds::default(), &mut h);
+    let loaded: Vec<_> = sources.iter().map(|f| (f.path.strip_prefix(&dir).unwrap().to_string_lossy().into_owned(), f.destination.to_string())).collect();
+    // `b` of the top layer replaces the base one in place, `only` is missing from the top layer
+    assert_eq!(loaded, vec![
+        ("base/conf/a".into(), "out/a".into()),
+        ("top/conf/b".into(), "out/b".into()),
+        ("base/only".into(), "only".into()),
+        ("top/conf/c".into(), "out/c".into()),
+    ] as Vec<(String, String)>);
+    // `--verbose` tells the layer of each file, without a level
+    let only = format!("`{}` is from layer `{}`.\n", base.join("only").display(), base.display());
+    assert!(sink.errors().iter().any(|e| e.render(None) == only));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn constraints() {
+    use flan::driver::{make_env, parse_sources, all_variants};
+    use flan::error::{codes, ErrorFlags, Handler, MemorySink};
+    use flan::sourcemap::SrcMap;
+    let decl = r#"
+        [dimensions]
+        os = ["linux", "windows"]
+        init = ["systemd", "openrc"]
+        [constraints]
+        no-systemd-on-windows = "!(os=windows && init=systemd)"
+        "undecided arch" = "arch=arm"
+    "#;
+    let env = |decisions: &[&str]| {
+        let (names, pairs) = parse_decisions(decisions).unwrap();
+        let config = Config::new(names, pairs, File::from_str(decl).unwrap());
+        let sink = MemorySink::new();
+        let env = make_env(&config, Handler::with_sink(ErrorFlags::default(), SrcMap::new(), Box::new(sink.clone())));
+        (env, sink.errors())
+    };
+    assert!(env(&["linux", "systemd"]).0.is_ok());
+    assert!(env(&["windows", "openrc"]).0.is_ok());
+    let (env_, errs) = env(&["windows", "systemd"]);
+    assert!(env_.is_err());
+    assert_eq!(errs.len(), 1);
+    assert_eq!(errs[0].code(), Some(codes::VIOLATED_CONSTRAINT));
+    assert_eq!(errs[0].msg(), "the decisions violate constraint `no-systemd-on-windows`.");
+    assert_eq!(
+        errs[0].extra().last().unwrap(),
+        "note: `!(os=windows && init=systemd)` doesn't hold with `init=systemd`, `os=windows`."
+    );
+
+    // an invalid condition is a declaration error
+    let config = Config::new(Default::default(), Default::default(), File::from_str("[constraints]\nbad = \"os=\"").unwrap());
+    let sink = MemorySink::new();
+    assert!(make_env(&config, Handler::with_sink(ErrorFlags::default(), SrcMap::new(), Box::new(sink.clone()))).is_err());
+    assert_eq!(sink.errors()[0].code(), Some(codes::INVALID_DECLARATION));
+
+    // the variants violating a constraint are left out
+    let sources = SrcMap::new();
+    let mut h = Handler::new(ErrorFlags::default(), sources.clone());
+    let f = sources.load_source("a".into(), "b".into(), "#os{a##b}##init{c##d}#".into());
+    let (trees, _) = parse_sources(vec![f], &mut h);
+    let config = Config::new(Default::default(), Default::default(), File::from_str(decl).unwrap());
+    let env = make_env(&config, h).unwrap();
+    let names: Vec<_> = all_variants(&trees, &env, &config).variants.into_keys().collect();
+    assert_eq!(names, vec!["openrc-linux", "openrc-windows", "systemd-linux"]);
+}
+
+#[test]
+fn derived_dimensions() {
+    use flan::driver::make_env;
+    use flan::env::Origin;
+    use flan::error::{codes, ErrorFlags, Handler, MemorySink};
+    use flan::sourcemap::SrcMap;
+    let decl = r#"
+        [dimensions]
+        os = ["linux", "windows"]
+        libc = { when = { os = "linux" }, choose = "glibc", else = "msvcrt" }
+        loader = { when = "libc=glibc", choose = "ld_so", else = "none" }
+    "#;
+    let env = |decisions: &[&str], decl: &str| {
+        let (names, pairs) = parse_decisions(decisions).unwrap();
+        let config = Config::new(names, pairs, File::from_str(decl).unwrap());
+        let sink = MemorySink::new();
+        let env = make_env(&config, Handler::with_sink(ErrorFlags::default(), SrcMap::new(), Box::new(sink.clone())));
+        (env, sink.errors())
+    };
+    let decision = |env: &flan::env::Env, dn: &str| env.get_dimension(&dn.into()).map(|d| (d.decision, d.origin));
+    let (e, _) = env(&["linux"], decl);
+    let e = e.unwrap();
+    assert_eq!(decision(&e, "libc"), Some((ChoiceIdx(0), Origin::Derived)));
+    assert_eq!(decision(&e, "loader"), Some((ChoiceIdx(0), Origin::Derived)));
+    let e = env(&["windows"], decl).0.unwrap();
+    assert_eq!(decision(&e, "libc"), Some((ChoiceIdx(1), Origin::Derived)));
+    assert_eq!(decision(&e, "loader"), Some((ChoiceIdx(1), Origin::Derived)));
+    // a decision overrides the derivation
+    let e = env(&["linux", "msvcrt"], decl).0.unwrap();
+    assert_eq!(decision(&e, "libc"), Some((ChoiceIdx(1), Origin::Name)));
+    assert_eq!(decision(&e, "loader"), Some((ChoiceIdx(1), Origin::Derived)));
+    // without `os`, neither can be derived
+    let (e, errs) = env(&[], decl);
+    assert!(decision(&e.unwrap(), "libc").is_none());
+    assert!(errs.iter().all(|e| e.code() == Some(codes::NO_DECISION)));
+
+    let cycle = r#"
+        [dimensions]
+        a = { when = "b=x", choose = "x", else = "y" }
+        b = { when = { a = "x" }, choose = "x", else = "y" }
+    "#;
+    let (e, errs) = env(&[], cycle);
+    assert!(e.is_err());
+    assert_eq!(errs[0].msg(), "derived dimensions `a`, `b` depend on each other.");
+    assert_eq!(errs[0].code(), Some(codes::INVALID_DECLARATION));
+}
+
+#[test]
+fn syntax_delimiters() {
+    use flan::driver::{check_syntax, source_to_stream_in, source_to_stream_with};
+    use flan::error::{codes, ErrorFlags, Handler, MemorySink};
+    use flan::sourcemap::{FileId, SrcMap};
+    use flan::syntax::Delimiters;
+    use std::path::Path;
+    let src = "[syntax]\nsigil = \"^\"\n[syntax.files.\"*.sh\"]\nopen = \"[\"\nclose = \"]\"\n";
+    let config = Config::new(Default::default(), Default::default(), File::from_str(src).unwrap());
+    let sh = Delimiters { sigil: '^', open: '[', close: ']', var: '$' };
+    assert_eq!(config.syntax.delimiters_of(Path::new("bin/run.sh")), sh);
+    assert_eq!(config.syntax.delimiters_of(Path::new("a.c")), Delimiters { sigil: '^', ..Delimiters::default() });
+
+    let mut h = Handler::new(ErrorFlags::default(), SrcMap::new());
+    let kinds = |ts: Option<flan::syntax::TokenStream>| ts.unwrap().iter().map(|t| t.node).collect::<Vec<_>>();
+    let custom = kinds(source_to_stream_with(&mut h, "^os[a^^- b]^ #x{ ^$v^ \\^ ^when(os=a)[y]^", FileId::NONE, sh));
+    let default = kinds(source_to_stream_in(&mut h, "#os{a##- b}# ^x[ #$v# \\# #when(os=a){y}#", FileId::NONE));
+    assert_eq!(custom, default);
+
+    // `.` can be part of variable names
+    let config = Config::new(Default::default(), Default::default(), File::from_str("[syntax]\nsigil = \".\"").unwrap());
+    let sink = MemorySink::new();
+    let mut h = Handler::with_sink(ErrorFlags::default(), SrcMap::new(), Box::new(sink.clone()));
+    check_syntax(&config, &mut h);
+    h.print_all();
+    assert_eq!(sink.errors().len(), 1);
+    assert_eq!(sink.errors()[0].code(), Some(codes::INVALID_DECLARATION));
+    assert!(File::from_str("[syntax]\nopen = \"<<\"").is_err());
+}
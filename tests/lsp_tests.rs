@@ -0,0 +1,90 @@
+use std::io::Cursor;
+
+use serde_json::{json, Value};
+
+use flan::cfg::*;
+use flan::lsp::rpc::{path_to_uri, read_message, uri_to_path, write_message};
+
+#[test]
+fn session() {
+    let dir = std::env::temp_dir().join(format!("flan-lsp-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let decl = "[variables]\nuser = \"flan\"\n[dimensions]\nos = [\"linux\", \"windows\"]\n";
+    let path = dir.join("flan.toml");
+    std::fs::write(&path, decl).unwrap();
+    let (names, pairs) = parse_decisions(&["os=windows"]).unwrap();
+    let mut config = Config::new(names, pairs, path_to_cfgfile(Some(&path)).unwrap());
+    config.path = Some(path.clone());
+    let flags = Flags::new(&Opt::from_iter(&["flan", "lsp"]), None);
+
+    let uri = path_to_uri(&dir.join("a.txt"));
+    assert_eq!(uri_to_path(&uri), Some(dir.join("a.txt")));
+    let doc = json!({ "uri": uri });
+    let at = |id: u64, method: &str, line: u64, character: u64| {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method,
+                "params": { "textDocument": doc, "position": { "line": line, "character": character } } })
+    };
+    let messages = vec![
+        json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize",
+                "params": { "capabilities": { "general": { "positionEncodings": ["utf-16", "utf-32"] } } } }),
+        json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+        json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": uri, "version": 1, "text": "é #$user# #$nope#\n#os{l##w}# #sz{a}#\n" } } }),
+        at(2, "textDocument/hover", 1, 1),
+        at(3, "textDocument/hover", 0, 4),
+        at(4, "textDocument/definition", 1, 1),
+        json!({ "jsonrpc": "2.0", "id": 5, "method": "textDocument/rename", "params": {} }),
+        json!({ "jsonrpc": "2.0", "id": 6, "method": "shutdown" }),
+        json!({ "jsonrpc": "2.0", "method": "exit" }),
+    ];
+    let mut input = Vec::new();
+    for m in &messages {
+        write_message(&mut input, m).unwrap();
+    }
+    let mut output = Vec::new();
+    assert!(flan::lsp::serve(&flags, config, &mut Cursor::new(input), &mut output).unwrap());
+    let mut output = Cursor::new(output);
+    let mut replies: Vec<Value> = Vec::new();
+    while let Some(m) = read_message(&mut output).unwrap() {
+        replies.push(m.unwrap());
+    }
+    assert_eq!(replies.len(), 7);
+    assert_eq!(replies[0]["result"]["capabilities"]["positionEncoding"], "utf-32");
+
+    let diagnostics = replies[1]["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(replies[1]["params"]["version"], 1);
+    let found: Vec<_> = diagnostics.iter().map(|d| (d["code"].as_str().unwrap(), d["range"]["start"].clone())).collect();
+    assert_eq!(found, vec![
+        ("F0101", json!({ "line": 0, "character": 10 })),
+        ("F0201", json!({ "line": 1, "character": 11 })),
+    ]);
+
+    let hover = replies[2]["result"]["contents"]["value"].as_str().unwrap();
+    assert!(hover.contains("dim os = [\"linux\", \"windows\"] -> windows"), "{}", hover);
+    assert_eq!(replies[2]["result"]["range"]["end"], json!({ "line": 1, "character": 3 }));
+    assert!(replies[3]["result"]["contents"]["value"].as_str().unwrap().contains("var user = \"flan\""));
+
+    assert_eq!(replies[4]["result"]["uri"], path_to_uri(&path));
+    assert_eq!(replies[4]["result"]["range"]["start"], json!({ "line": 3, "character": 0 }));
+    assert_eq!(replies[5]["error"]["code"], flan::lsp::rpc::METHOD_NOT_FOUND);
+    assert_eq!(replies[6]["result"], Value::Null);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn invalid_messages() {
+    use flan::lsp::rpc::{INVALID_REQUEST, PARSE_ERROR};
+    let config = Config::new(Default::default(), Default::default(), Default::default());
+    let flags = Flags::new(&Opt::from_iter(&["flan", "lsp"]), None);
+    // the body of a huge `Content-Length` isn't allocated, the input ends before it
+    let input = b"Content-Length: 4\r\n\r\nnope\
+                  Content-Length: 99999999999\r\n\r\n{}".to_vec();
+    let mut output = Vec::new();
+    assert!(!flan::lsp::serve(&flags, config, &mut Cursor::new(input), &mut output).unwrap());
+    let mut output = Cursor::new(output);
+    let mut codes = Vec::new();
+    while let Some(m) = read_message(&mut output).unwrap() {
+        codes.push(m.unwrap()["error"]["code"].clone());
+    }
+    assert_eq!(codes, vec![json!(PARSE_ERROR), json!(INVALID_REQUEST)]);
+}
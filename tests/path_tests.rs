@@ -73,7 +73,7 @@ pub fn leading_current() {
 pub fn leading_parent() {
     let expected = {
         let cur_dir = current_dir().unwrap();
-        let dir = cur_dir.components().last().unwrap().as_os_str();
+        let dir = cur_dir.components().next_back().unwrap().as_os_str();
         let mut p = PathBuf::from("..");
         p.push(dir);
         p.push(test_dir!());
@@ -0,0 +1,16 @@
+//! the library without the `cli` feature, see `flan::prelude`
+use flan::prelude::*;
+
+#[test]
+fn prelude() {
+    let (_, pairs) = parse_decisions(&["os=linux"]).unwrap();
+    let decisions: Vec<_> = pairs.into_iter().map(|(d, i)| Decision::WithDim(d, i)).collect();
+    let vars = vec![("user".to_string(), "flan".to_string())].into_iter().collect();
+    assert_eq!(render("#os{linux: l##windows: w}# #$user#", &decisions, &vars).unwrap(), " l flan");
+
+    let mut s = Session::in_memory();
+    assert_eq!(s.flags, Flags::default());
+    assert!(matches!(s.render_to_string("#os{a##b}#"), Err(RunError::Diagnostics(_))));
+    let variants: Vec<_> = render_variants("#os{a##b}#", &vars).unwrap().map(|(_, out)| out.unwrap()).collect();
+    assert_eq!(variants, vec!["a", "b"]);
+}
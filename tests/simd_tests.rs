@@ -37,13 +37,12 @@ fn mask_end() {
 }
 #[test]
 fn mask_end_avx() {
-    use std::mem::transmute;
     let m = unsafe {
         let c = _mm256_loadu_si256(b"aaaabbbbccccddddaaaabbbbccccddd\n".as_ptr() as *const __m256i);
         let t = _mm256_cmpeq_epi8(c, _mm256_set1_epi8(b'\n' as i8));
         _mm256_movemask_epi8(t)
     };
-    assert_eq!(m, unsafe { transmute(0x8000_0000 as u32) });
+    assert_eq!(m as u32, 0x8000_0000);
 }
 
 #[test]
@@ -1,6 +1,6 @@
 #![allow(non_upper_case_globals)]
 use flan::sourcemap::source_analysis;
-use flan::sourcemap::BytePos;
+use flan::sourcemap::{BytePos, Destination, SourceOrigin};
 
 static no_nl_128: &str = "aaaabbbbccccddddaaaabbbbccccdddd";
 static two_nl_128: &str = "aaa\nbbbbc\nccddddaaaabbbbccccdddd";
@@ -20,7 +20,7 @@ fn two_slow() {
     source_analysis::anal_src_slow(two_nl_128, two_nl_128.len(), BytePos(0), &mut lines);
     assert_eq!(
         lines,
-        vec![4, 10]
+        [4, 10]
             .iter()
             .map(|i: &u64| BytePos(*i))
             .collect::<Vec<_>>()
@@ -69,3 +69,179 @@ fn all_avx2() {
     }
     assert_eq!(vec![l0, l1, l2], vec![k0, k1, k2]);
 }
+
+#[test]
+fn lookup_later_files() {
+    use flan::error::Error;
+    use flan::sourcemap::SrcMap;
+    let dir = std::env::temp_dir().join(format!("flan-srcmap-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a"), "aaa\nbbb\n").unwrap();
+    std::fs::write(dir.join("b"), "x\nyy #$nope# z\n").unwrap();
+    let sm = SrcMap::new();
+    sm.load_file(dir.join("a"), "a".into()).unwrap();
+    let b = sm.load_file(dir.join("b"), "b".into()).unwrap();
+    let pos = BytePos(5);
+    let at = flan::sourcemap::span_in(b.id, pos, pos + BytePos(7));
+    assert_eq!(sm.lookup_span(at).map(|(f, _)| f.path.clone()), Some(dir.join("b")));
+    let loc = b.lookup_line(pos).unwrap();
+    assert_eq!((loc.index, loc.line.as_ref()), (1, "yy #$nope# z"));
+
+    let err = Error::error_span(at, "Undeclared variable `nope`.".into());
+    let json: serde_json::Value = serde_json::from_str(&err.render_json(Some(b.clone()))).unwrap();
+    assert_eq!(json["level"], "error");
+    assert_eq!((json["line"].as_u64(), json["column"].as_u64()), (Some(2), Some(4)));
+    assert_eq!((json["span"]["lo"].as_u64(), json["span"]["hi"].as_u64()), (Some(5), Some(12)));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn error_codes() {
+    use flan::driver::string_to_parser;
+    use flan::error::{codes, Code, ErrorFlags, Handler, MemorySink};
+    use flan::sourcemap::SrcMap;
+    assert_eq!(Code::lookup("F0203"), Some(codes::CONFLICTING_DECISIONS));
+    assert_eq!(Code::lookup("F9999"), None);
+    assert_eq!(codes::CONFLICTING_DECISIONS.to_string(), "F0203");
+    let mut all: Vec<_> = Code::all().collect();
+    all.dedup();
+    assert_eq!(all.len(), Code::all().count());
+    assert!(codes::explain("F0203").unwrap().starts_with("F0203: conflicting decisions\n"));
+
+    let sink = MemorySink::new();
+    let mut h = Handler::with_sink(ErrorFlags::default(), SrcMap::new(), Box::new(sink.clone()));
+    assert!(string_to_parser(&mut h, "#os{a##b".into()).unwrap().parse().is_err());
+    h.print_all();
+    let errs = sink.errors();
+    assert_eq!(errs[0].code(), Some(codes::UNCLOSED_DELIMITER));
+    assert!(errs[0].render(None).starts_with("error[F0001]: "));
+    let json: serde_json::Value = serde_json::from_str(&errs[0].render_json(None)).unwrap();
+    assert_eq!(json["code"], "F0001");
+}
+
+#[test]
+fn render_multiline_span() {
+    use flan::error::Error;
+    use flan::sourcemap::{span_in, SrcMap};
+    let src = "a\nbb #os{x\n1\n2\n3\n4\n5 }# c\n";
+    let path = std::env::temp_dir().join(format!("flan-multiline-{}", std::process::id()));
+    std::fs::write(&path, src).unwrap();
+    let f = SrcMap::new().load_file(path.clone(), "f".into()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let lo = BytePos::from(5usize);
+    let hi = BytePos::from(src.find("}#").unwrap() + 2);
+    let mut err = Error::error_span(span_in(f.id, lo, hi), "Unclosed.".into());
+    let expected = "\
+error: Unclosed.
+in {}:2:4
+  |
+2 |   bb #os{x
+  |  ____^
+3 | | 1
+4 | | 2
+  | | ...
+6 | | 4
+7 | | 5 }# c
+  | |____^ 
+  |
+";
+    assert_eq!(err.render(Some(f.clone())), expected.replace("{}", &path.display().to_string()));
+    // single line spans are unchanged
+    err = Error::error_span(span_in(f.id, lo, lo + BytePos::from(4usize)), "Unclosed.".into());
+    let expected = format!("error: Unclosed.\nin {}:2:4\n  |\n2 | bb #os{{x\n  |    ^^^^ \n  |\n", path.display());
+    assert_eq!(err.render(Some(f)), expected);
+}
+
+#[test]
+fn size_conflict_label() {
+    use flan::driver::file_to_parser;
+    use flan::error::{ErrorFlags, Handler, MemorySink};
+    use flan::infer::{check, ChoiceIdx, Dim, Env};
+    use flan::sourcemap::SrcMap;
+    use std::collections::HashMap;
+    let path = std::env::temp_dir().join(format!("flan-label-{}", std::process::id()));
+    std::fs::write(&path, "#os{a##b}#\n#os{a##b##c}#\n").unwrap();
+    let sources = SrcMap::new();
+    let f = sources.load_file(path.clone(), "f".into()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let sink = MemorySink::new();
+    let mut h = Handler::with_sink(ErrorFlags::default(), sources, Box::new(sink.clone()));
+    let terms = file_to_parser(&mut h, &f).unwrap().parse().unwrap();
+    let dims: HashMap<_, _> = vec![("os".into(), Dim::new(ChoiceIdx(0)))].into_iter().collect();
+    let mut env = Env::new(HashMap::new(), dims, h);
+    assert!(check(&terms, &mut env).0);
+    env.handler.print_all();
+    let errs = sink.errors();
+    assert_eq!(errs[0].labels().len(), 1);
+    assert_eq!(errs[0].labels()[0].msg, "first used here with 2 choices");
+    let rendered = errs[0].render(Some(f));
+    assert!(rendered.contains("1 | #os{a##b}#\n  | --- first used here with 2 choices\n"), "{}", rendered);
+}
+
+#[test]
+fn utf8_columns() {
+    use flan::error::Error;
+    use flan::sourcemap::{span_in, SrcMap};
+    let src = "héllo wörld #os{x}#\n";
+    let path = std::env::temp_dir().join(format!("flan-utf8-{}", std::process::id()));
+    std::fs::write(&path, src).unwrap();
+    let f = SrcMap::new().load_file(path.clone(), "f".into()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let lo = BytePos::from(src.find('#').unwrap());
+    assert_eq!(f.lookup_column(lo), Some(12));
+    let err = Error::error_span(span_in(f.id, lo, lo + BytePos::from(3usize)), "Unknown.".into());
+    let expected = format!("error: Unknown.\nin {}:1:13\n  |\n1 | {}\n  |             ^^^ \n  |\n", path.display(), src.trim_end());
+    assert_eq!(err.render(Some(f.clone())), expected);
+    let json: serde_json::Value = serde_json::from_str(&err.render_json(Some(f))).unwrap();
+    assert_eq!(json["column"], 13);
+    assert_eq!(json["span"]["lo"], 14);
+}
+
+#[test]
+fn lookup_span() {
+    use flan::sourcemap::{span, span_in, Span, SrcMap};
+    let sources = SrcMap::new();
+    let files: Vec<_> = ["abc", "defgh", "ij"]
+        .iter()
+        .map(|s| sources.load_source(format!("{}.txt", s).into(), Destination::InMemory, s.to_string()))
+        .collect();
+    for f in files.iter() {
+        assert_eq!(sources.get(f.id).unwrap().path, f.path);
+        // the end is included, e.g. an error at the end of the file
+        let eof = span_in(f.id, f.end, f.end);
+        assert_eq!(sources.lookup_span(eof).unwrap().0.path, f.path);
+    }
+    let f = &files[1];
+    let (file, relative) = sources.lookup_span(span_in(f.id, BytePos(1), BytePos(3))).unwrap();
+    assert_eq!(file.path, f.path);
+    assert_eq!(relative, span_in(f.id, BytePos(1), BytePos(3)));
+    // the spans past the end of their file or of no file aren't looked up
+    assert!(sources.lookup_span(span_in(files[0].id, BytePos(0), files[1].end)).is_none());
+    assert!(sources.lookup_span(span(BytePos(1), BytePos(3))).is_none());
+    assert!(sources.lookup_span(Span::NIL).is_none());
+}
+
+#[test]
+fn lookup_loc() {
+    use flan::sourcemap::{span, span_in, SrcMap};
+    let sources = SrcMap::new();
+    let a = sources.load_source("a".into(), Destination::InMemory, "héllo\nwörld ✓ #$x#\n".into());
+    let b = sources.load_source("b".into(), Destination::InMemory, "#$y#".into());
+    assert_eq!((a.origin, a.is_stdin(), a.is_stdout()), (SourceOrigin::InMemory, false, false));
+    let at = |f: &flan::sourcemap::SrcFile, lo: usize, hi: usize| {
+        let loc = sources.lookup_loc(span_in(f.id, BytePos::from(lo), BytePos::from(hi))).unwrap();
+        assert_eq!(loc.file.path, f.path);
+        (loc.line, loc.col_start, loc.end_line, loc.col_end)
+    };
+    // `é`, `ö` and `✓` are several bytes but a single column
+    let var = "héllo\nwörld ✓ ".len();
+    assert_eq!(at(&a, var, var + 4), (2, 9, 2, 13));
+    assert_eq!(at(&a, 1, 3), (1, 2, 1, 3));
+    // over the newline, and at the end of the file
+    assert_eq!(at(&a, 0, 8), (1, 1, 2, 2));
+    assert_eq!(at(&a, a.size(), a.size()), (3, 1, 3, 1));
+    // the positions are in their own file
+    assert_eq!(at(&b, 0, 4), (1, 1, 1, 5));
+    assert!(sources.lookup_loc(span_in(b.id, BytePos(0), BytePos(5))).is_none());
+    assert!(sources.lookup_loc(span(BytePos(0), BytePos(1))).is_none());
+}
@@ -1,4 +1,6 @@
-use flan::syntax::lexer::TokenK;
+use flan::syntax::lexer::{Delimiters, TokenK};
+use flan::error::Level;
+use flan::sourcemap::Span;
 
 mod utils;
 use utils::*;
@@ -48,7 +50,7 @@ fn multi_escapes() {
         if let Kind::Text(s) = k {
             cnt += if s.contains('\\') { 1 } else { 0 };
         } else {
-            assert!(false, "{:?}", k);
+            panic!("{:?}", k);
         }
     }
     assert!(cnt == 1);
@@ -291,4 +293,379 @@ fn regtest_nested_dim() {
     assert!(r_ts.is_ok());
     let ts = r_ts.unwrap();
     assert_eq!(expected, get_full_kinds(ts, src));
-}
\ No newline at end of file
+}
+
+#[test]
+fn empty_choice_marker() {
+    use flan::syntax::lexer::Token;
+    use TokenK::*;
+    let src = r#"#foo{a##\~}# \~"#;
+    let toks = stream_str(src);
+    let expected = vec![
+        Token::new(Opend,  0,  5),
+        Token::new(Text,   5,  6),
+        Token::new(Sepd,   6,  8),
+        // `\~` is ignored span(8, 10)
+        Token::new(Text,   10, 10),
+        Token::new(Closed, 10, 12),
+        // outside of dimensions `\~` is text
+        Token::new(Text,   12, 13),
+        Token::new(Text,   13, 15),
+        Token::new(EOF,    15, 15),
+    ];
+    assert_eq!(expected, toks);
+    let r_ts = parse_str(src);
+    assert!(r_ts.is_ok());
+    assert_eq!(vec![kdim("foo", vec![vec![ktext("a")], vec![ktext("")]]), ktext(" "), ktext(r#"\~"#)],
+               get_full_kinds(r_ts.unwrap(), src));
+}
+#[test]
+fn unterminated_var() {
+    use TokenK::*;
+    // terminated by whitespace
+    let src = "#$foo bar";
+    assert_eq!(vec![Text, Text, EOF], lex_str(src));
+    assert!(parse_str(src).is_err());
+    // terminated by EOF
+    let src = "foo #$bar";
+    assert_eq!(vec![Text, Text, EOF], lex_str(src));
+    assert!(parse_str(src).is_err());
+
+    let errs = diagnostics_str(src);
+    assert!(errs.iter().any(|e| e.level() == Level::Error
+                             && e.msg() == "Variable started here, never terminated."
+                             && e.span() == Span::new(4, 6)));
+}
+#[test]
+fn multibyte_spans() {
+    use flan::syntax::lexer::Token;
+    use TokenK::*;
+    let src = "é#$x#ü}##{ĳ}#";
+    let toks = stream_str(src);
+    let expected = vec![
+        Token::new(Text,   0,  2),
+        Token::new(Var,    2,  6),
+        Token::new(Text,   6,  8),
+        Token::new(Closed, 8,  10),
+        Token::new(Text,   10, 14),
+        Token::new(Closed, 14, 16),
+        Token::new(EOF,    16, 16),
+    ];
+    assert_eq!(expected, toks);
+}
+
+#[test]
+fn all_markers() {
+    use flan::syntax::marker_analysis::*;
+    let srcs = [
+        "",
+        "no markers in here, nor in this longer part of the text",
+        r#"#$foo# \# some text }# #dim{a##b}# ... \\ ### }}}} \} ##"#,
+        "é#ü}ĳ\\ ééééééééééééééé#ééééééééééééééééé}",
+    ];
+    for src in srcs.iter() {
+        let expected: Vec<usize> = src.bytes()
+                                      .enumerate()
+                                      .filter(|(_, b)| *b == b'#' || *b == b'}' || *b == b'\\')
+                                      .map(|(i, _)| i)
+                                      .collect();
+        let mut slow = Vec::new();
+        let mut sse2 = Vec::new();
+        let mut avx2 = Vec::new();
+        anal_markers_slow(src.as_bytes(), 0, Delimiters::default().markers(), &mut slow);
+        unsafe {
+            anal_markers_sse2(src.as_bytes(), Delimiters::default().markers(), &mut sse2);
+            if is_x86_feature_detected!("avx2") {
+                anal_markers_avx2(src.as_bytes(), Delimiters::default().markers(), &mut avx2);
+            } else {
+                avx2 = expected.clone();
+            }
+        }
+        assert_eq!(expected, slow);
+        assert_eq!(expected, sse2);
+        assert_eq!(expected, avx2);
+        assert_eq!(expected, Markers::new(src, Delimiters::default().markers()).collect::<Vec<_>>());
+    }
+    // over several blocks, the skipped ones aren't scanned
+    let src = format!("{}#{}#", "a".repeat(5000), "b".repeat(9000));
+    let mut markers = Markers::new(&src, Delimiters::default().markers());
+    assert_eq!(markers.first_from(0), Some(5000));
+    assert_eq!(markers.first_from(5000), Some(5000));
+    assert_eq!(markers.first_from(5001), Some(14001));
+    assert_eq!(markers.first_from(14002), None);
+}
+
+#[test]
+fn default_branch() {
+    use flan::syntax::TermK;
+    use TokenK::*;
+    assert_eq!(vec![Opend, Text, Sepd, Text, SepDefault, Text, Closed, EOF], lex_str("#d{a##b##!c}#"));
+    // `##!` is only meaningful inside of dimensions
+    assert_eq!(vec![Text, EOF], lex_str("a ##! b"));
+    let tree = parse_str("#d{a##b##!c}#").unwrap();
+    match &tree[0].node {
+        TermK::Dimension { children, default, .. } => {
+            assert_eq!(children.len(), 2);
+            assert!(default.is_some());
+        }
+        _ => panic!("expected a dimension"),
+    }
+    assert!(parse_str("#d{a##!b##c}#").is_err());
+    assert!(diagnostics_str("#d{a##!b##!c}#").iter().any(|e| e.msg().starts_with("The default branch must be the last")));
+}
+
+#[test]
+fn choice_labels() {
+    use flan::syntax::TermK;
+    let src = "#os{linux: apt ## \\:bsd: pkg ##windows:#$choco#}#";
+    let tree = parse_str(src).unwrap();
+    match &tree[0].node {
+        TermK::Dimension { children, labels, .. } => {
+            assert_eq!(labels, &vec![Some("linux".into()), None, Some("windows".into())]);
+            // the label and its `:` are stripped, `windows:` was the whole text
+            assert_eq!(&src[children[0][0].span.as_range()], " apt ");
+            assert_eq!(children[2].len(), 1);
+        }
+        _ => panic!("expected a dimension"),
+    }
+    // not labels
+    for src in &["#url{http://a##https://b}#", "#os{a:b##c}#", "a: b #os{x##y}#"] {
+        match &parse_str(src).unwrap().iter().find(|t| t.node != TermK::Text).unwrap().node {
+            TermK::Dimension { labels, .. } => assert!(labels.is_empty()),
+            _ => panic!("expected a dimension"),
+        }
+    }
+    assert!(diagnostics_str("#os{a: x##a: y}#").iter().any(|e| e.msg().starts_with("Label `a` is used twice")));
+}
+
+#[test]
+fn var_default() {
+    use flan::syntax::TermK;
+    use TokenK::*;
+    assert_eq!(vec![Var, EOF], lex_str("#$user:-a guest, really#"));
+    assert_eq!(vec![Text, Text, EOF], lex_str("#$user:-guest\n#"));
+    let src = "#$user:-guest#";
+    let tree = parse_str(src).unwrap();
+    assert_eq!(tree[0].node, TermK::Var("user".into(), Some("guest".into())));
+    assert_eq!(&src[tree[0].name_span().unwrap().as_range()], "user");
+    // an empty default
+    assert_eq!(parse_str("#$user:-#").unwrap()[0].node, TermK::Var("user".into(), Some("".into())));
+}
+
+#[test]
+fn guards() {
+    use flan::sourcemap::{BytePos, FileId};
+    use flan::env::ChoiceIdx;
+    use flan::syntax::cond::{parse_cond, Choice, Cond};
+    use TokenK::*;
+    assert_eq!(vec![Opwhen, Text, Sepd, Text, Closed, EOF], lex_str("#when(os=linux){a##b}#"));
+    // not guards
+    assert_eq!(vec![Opend, Text, Closed, EOF], lex_str("#when{a}#"));
+    assert_eq!(vec![Text, EOF], lex_str("#when(a=b\n){x"));
+    assert_eq!(vec![Text, Var, Text, EOF], lex_str("#when(#$a#){x"));
+
+    let src = "#when(os=linux && arch!=arm){a #d{x##y}#}#";
+    assert_eq!(get_kinds(parse_str(src).unwrap()), vec![kguard(vec![vec![ktxt(), kdim("d", vec![vec![ktxt()], vec![ktxt()]])]])]);
+    let cond = parse_cond(" !(a=x || b=1) && c!=y ", FileId::NONE, BytePos(0)).unwrap();
+    let is = |dim: &str, choice: Choice, negated: bool, lo: u64, hi: u64| Cond::Is {
+        dim: dim.into(), choice, negated, span: flan::sourcemap::span(BytePos(lo), BytePos(hi)),
+    };
+    let ab = Cond::Or(Box::new(is("a", Choice::Name("x".into()), false, 3, 6)), Box::new(is("b", Choice::Num(ChoiceIdx(1)), false, 10, 13)));
+    assert_eq!(cond, Cond::And(Box::new(Cond::Not(Box::new(ab))), Box::new(is("c", Choice::Name("y".into()), true, 18, 22))));
+
+    let msgs = |src: &str| diagnostics_str(src).iter().map(|e| e.msg().to_string()).collect::<Vec<_>>();
+    assert_eq!(msgs("#when(os=){a}#"), vec!["Expected a choice of `os` in condition."]);
+    assert_eq!(msgs("#when(os linux){a}#"), vec!["Expected `=` or `!=` after `os` in condition."]);
+    assert_eq!(msgs("#when((a=b){a}#"), vec!["Expected `)` in condition."]);
+    assert_eq!(msgs("#when(a=b c=d){a}#"), vec!["Expected `&&`, `||` or the end of the condition."]);
+    assert_eq!(msgs("#when(a=b){x##y##z}#"), vec!["A guard only has a branch and an optional `##` else branch."]);
+}
+
+#[test]
+fn named_blocks() {
+    use flan::syntax::TermK;
+    use TokenK::*;
+    assert_eq!(vec![Opdef, Text, Var, Closed, Text, Use, EOF], lex_str("#def(h){a #$t#}# #use(h, t=x)#"));
+    // not named blocks
+    assert_eq!(vec![Text, EOF], lex_str("#use(h x"));
+    assert_eq!(vec![Text, Var, Text, EOF], lex_str("#def(#$a#){x"));
+
+    let tree = parse_str("#def(h){a #d{x##y}#}# #use(h)#").unwrap();
+    assert_eq!(get_kinds(tree.clone()), vec![kdef("h", vec![ktxt(), kdim("d", vec![vec![ktxt()], vec![ktxt()]])]), ktxt(), kuse("h")]);
+    match &tree[0].node {
+        TermK::Def { body, .. } => assert_eq!(body[0].node, TermK::Value("a ".into())),
+        _ => unreachable!(),
+    }
+    match &parse_str("#use( h , t = x y,u=)#").unwrap()[0].node {
+        TermK::Use { name, args } => {
+            assert_eq!(name, "h");
+            assert_eq!(args, &vec![("t".into(), "x y".into()), ("u".into(), "".into())]);
+        }
+        _ => unreachable!(),
+    }
+
+    let msgs = |src: &str| diagnostics_str(src).iter().map(|e| e.msg().to_string()).collect::<Vec<_>>();
+    assert_eq!(msgs("#def(a b){x}#"), vec!["`a b` isn't a valid named block name."]);
+    assert_eq!(msgs("#use(h, t)#"), vec!["Invalid use of named block `h`: `t` isn't a `variable=value` argument."]);
+    assert_eq!(msgs("#use(h, t=a, t=b)#"), vec!["Invalid use of named block `h`: variable `t` is bound twice."]);
+    assert_eq!(msgs("#def(h){x##y}#"), vec!["A named block has no branches."]);
+    assert_eq!(msgs("#def(h){x"), vec!["Unclosed named block delimiter. Expected `}#`."]);
+}
+
+#[test]
+fn parser_borrows_source() {
+    use flan::driver::file_to_parser;
+    use flan::error::{ErrorFlags, Handler};
+    use flan::sourcemap::{SourceInfo, SrcMap};
+    use std::borrow::Cow;
+    let path = std::env::temp_dir().join(format!("flan-borrow-{}", std::process::id()));
+    std::fs::write(&path, "a #os{b##c}# #$d#").unwrap();
+    let sources = SrcMap::new();
+    let f = sources.load_file(path.clone(), "f".into()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let mut h = Handler::new(ErrorFlags::default(), sources);
+    let p = file_to_parser(&mut h, &f).unwrap();
+    match (&p.src, &f.src) {
+        (Cow::Borrowed(s), SourceInfo::Source(src)) => assert!(std::ptr::eq(*s, src.as_str())),
+        _ => panic!("the parser copied the source"),
+    }
+}
+
+#[test]
+fn interned_names() {
+    use flan::syntax::symbols::resolve;
+    use flan::syntax::{Symbol, TermK};
+    let (b, a, b2) = (Symbol::intern("sym_b"), Symbol::intern("sym_a"), Symbol::from(String::from("sym_b")));
+    assert_eq!(b, b2);
+    assert_ne!(a, b);
+    // ordered by string, not by interning order
+    assert!(a < b);
+    assert_eq!(resolve(a), "sym_a");
+    assert_eq!(format!("{} {:?}", b, b), "sym_b \"sym_b\"");
+
+    let names: Vec<_> = parse_str("#os{a##b}# #$os# #os{c##d}#")
+        .unwrap()
+        .into_iter()
+        .filter_map(|t| match t.node {
+            TermK::Var(n, _) | TermK::Dimension { name: n, .. } => Some(n),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(names, vec![Symbol::intern("os"); 3]);
+}
+
+#[test]
+fn comments() {
+    use TokenK::*;
+    assert_eq!(vec![Text, Comment, Text, EOF], lex_str("a #! #dim{ ## !# b"));
+    assert_eq!(vec![Opend, Comment, Text, Sepd, Text, Closed, EOF], lex_str("#dim{#!x!#a##b}#"));
+    assert_eq!(vec![Comment, Text, EOF], lex_str("#!!#\\#! x !#"));
+    // unterminated: text
+    assert_eq!(vec![Text, Var, Text, EOF], lex_str("#!/bin/sh #$a# #! a"));
+    // a `#!` inside of a comment doesn't start another one
+    assert_eq!(vec![Text, Comment, Text, Comment, EOF], lex_str("x #! a #! b !# c #!!#"));
+    let tree = parse_str("#d{#! a !#x##y}#").unwrap();
+    assert_eq!(get_kinds(tree), vec![kdim("d", vec![vec![Kind::Comment, ktxt()], vec![ktxt()]])]);
+}
+
+#[test]
+fn empty_branches() {
+    use flan::error::codes;
+    let errs = diagnostics_str("#d{a##}#");
+    assert_eq!(errs.len(), 1);
+    assert_eq!(errs[0].level(), Level::Warning);
+    assert_eq!(errs[0].code(), Some(codes::EMPTY_BRANCH));
+    assert_eq!(errs[0].msg(), "Choice 1 of dimension `d` is empty.");
+    // points at the trailing `##`
+    assert_eq!((errs[0].labels()[0].span, errs[0].labels()[0].msg.as_str()), (Span::new(4, 6), "nothing after this separator"));
+    let msgs = |src: &str| diagnostics_str(src).iter().map(|e| e.msg().to_string()).collect::<Vec<_>>();
+    assert_eq!(msgs("#d{##a####!}#"), vec!["Choice 0 of dimension `d` is empty.", "Choice 2 of dimension `d` is empty."]);
+    assert_eq!(msgs("#when(d=0){a##}# #when(d=1){}#"), vec!["The `##` branch of this guard is empty.", "The branch of this guard is empty."]);
+    assert!(msgs("#d{a##\\~}# #d{#! none !###b}#").is_empty());
+}
+
+#[test]
+fn recover_errors() {
+    use flan::driver::string_to_parser;
+    use flan::error::{ErrorFlags, Handler};
+    use flan::sourcemap::SrcMap;
+    use flan::syntax::Error;
+    let src = "}# #a{x##!y##z}# #when(=){#c{w}#}# #b{ok}# #d{#e{a: x##a: y}# v}# #use(h, t)# #$v#";
+    let msgs: Vec<_> = diagnostics_str(src).iter().map(|e| e.msg().to_string()).collect();
+    assert_eq!(msgs.len(), 5, "{:?}", msgs);
+    assert!(msgs[0].starts_with("Unexpected Dimension closing delimiter"));
+    assert!(msgs[1].starts_with("The default branch must be the last"));
+    assert!(msgs[3].starts_with("Label `a` is used twice"));
+    assert!(msgs[4].starts_with("Invalid use of named block `h`"));
+
+    let mut h = Handler::new(ErrorFlags::default(), SrcMap::new());
+    let mut p = string_to_parser(&mut h, src.into()).unwrap();
+    let (tree, poisoned) = p.parse_partial();
+    assert!(poisoned);
+    assert_eq!(p.error, Some(Error::UnexpectedToken));
+    // the delimiters with errors are left out
+    assert_eq!(get_kinds(tree), vec![ktxt(), ktxt(), ktxt(), kdim("b", vec![vec![ktxt()]]), ktxt(), ktxt(), ktxt(), kvar("v")]);
+}
+
+#[test]
+fn debug_dumps() {
+    use flan::driver::{debug_terms, debug_tokens};
+    use flan::error::{ErrorFlags, Handler};
+    use flan::sourcemap::SrcMap;
+    let sources = SrcMap::new();
+    // not at the start of the map: the spans are relative to the file
+    sources.load_source("before".into(), "b".into(), "padding".into());
+    let f = sources.load_source("f".into(), "f".into(), "a #d{x##l: y}# #when(!(d=0 || d=l)){#$v#}#".into());
+    let mut h = Handler::new(ErrorFlags::default(), sources);
+    let tokens = debug_tokens(&mut h, &f, Delimiters::default()).unwrap();
+    let lines: Vec<_> = tokens.lines().map(|l| l.split_whitespace().collect::<Vec<_>>()).collect();
+    assert_eq!(lines[0], ["Text", "0:2", "\"a", "\""]);
+    assert_eq!(lines[1], ["Opend", "2:5", "\"#d{\""]);
+    assert_eq!(lines.last().unwrap()[0], "EOF");
+    let tree = debug_terms(&mut h, &f, Delimiters::default()).unwrap();
+    assert_eq!(
+        tree,
+        "Text 0:2 \"a \"\n\
+         Dimension d, 2 choices 2:14\n  choice 0\n    Text 5:6 \"x\"\n  choice 1 l\n    Text 10:12 \" y\"\n\
+         Text 14:15 \" \"\n\
+         Guard !(d=0 || d=l) 15:42\n  then\n    Var v 36:40\n"
+    );
+    assert_eq!(h.err_count, 0);
+}
+
+#[test]
+fn printer_roundtrip() {
+    use flan::syntax::printer::print;
+    let corpus = [
+        "foobar", "0123456789", "hello, world!", "foo ## bar ## baz", "foo \\## bar \\## baz",
+        "foo \\#foo{ one ## two \\}# baz", "foo \\#$foo# \\\\ ...", "foo \\#$foo# \\#$ \\#$non terminated var",
+        "#!/bin/sh\n#$name#", "a#! note !#b", "#foo{}#", "#foo{##}#", "#_{##}#",
+        "#$user:-guest# #$name:-nobody#", "#$user:-#", ".#$foo#.",
+        "some text #$_var1# #$_2# #dim{#$inside### more text }# another #$last_var#",
+        "#dim1{#dim0{yahallo##hello}###byebye!}#, #dim1{flan##remi}#",
+        "#dim2{hello, world ## ignored ##hello, #$name#}# from 2hu",
+        "a #$var1#, #dim0{#$name###$var1#}#; #$name# b",
+        "#dim2{#! first !#x##y##z #!\n #dim0{## !#w}#",
+        "#dim2{a -## b -##- c -}#.", "#dim0{+x -\\~}# - a -", "#dim1{a -##! b}#",
+        "a\n#dim0{-\n  x\n  -##-\n  y\n-}#\nb", "a\r\nb\n#$name#\r\r\n#dim0{x\r##y}#\r",
+        "#dim2{a##b##!fallback}# #dim0{a##!fallback}#", "#d{a##b##!c}#",
+        "#os{linux: apt ## \\:bsd: pkg ##windows:#$choco#}#", "#mode{d##p}# #os{linux: a##windows: b}# #sz{x##y}# #x{0##!n}#",
+        "#url{http://a##https://b}#", "#os{a:b##c}#", "a: b #os{x##y}#",
+        "#when(dim0=0 && dim2=c){x##y}# #when(dim1!=0 || !(dim2=2)){z}#.", "#when(dim0=0){- x -}#",
+        "#when(dim0=1){x###dim1{y##z}#}#", "#when(!(a=x || b=1) && c!=y){a #d{x##y}#}#",
+        "#def(a){#$name#}##def(b){#use(a)# #$name#}##use(b, name=x)#", "#use( h , t = x y,u=)#",
+        "#def(hi){hello #$name#, #dim{a##b}##}#\n#use(hi)#\n#use(hi, name=you)#",
+        // escapes depending on the place of the text
+        "#d{\\~-a##\\~!b##c\\: d##l:\\~x##\\~}#", "#d{a-\\~##b}# #when(a=0){\\~-##\\~!}#", "#d{#\\##}\\#}#",
+    ];
+    for src in corpus.iter() {
+        let tree = parse_str(src).unwrap_or_else(|e| panic!("{:?} in {:?}", e, src));
+        let printed = print(&tree, src);
+        let reparsed = parse_str(&printed).unwrap_or_else(|e| panic!("{:?} in {:?} printed from {:?}", e, printed, src));
+        assert_eq!(merge_texts(get_full_kinds(tree, src)), merge_texts(get_full_kinds(reparsed.clone(), &printed)), "{:?}", printed);
+        // labels, conditions, defaults and arguments included
+        assert_eq!(print(&reparsed, &printed), printed);
+    }
+    let src = "#d{l: a##b\\: c##!-x-\\~}#";
+    assert_eq!(print(&parse_str(src).unwrap(), src), src);
+}
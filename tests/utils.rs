@@ -1,9 +1,12 @@
 #![allow(dead_code)]
 use std::io::{Cursor, BufRead, Write, self};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
+use flan::cfg::{Config, File, Flags, Opt, StructOpt};
 use flan::driver::*;
 use flan::env::Env;
-use flan::error::{ErrorFlags, Handler};
+use flan::error::{Error, ErrorFlags, Handler, MemorySink};
 use flan::output::{ReadCtx, WriteCtx};
 use flan::output;
 use flan::sourcemap::{Spanned, SrcMap};
@@ -18,9 +21,17 @@ pub enum Kind {
     /// full text where contents are also checked
     Text(String),
     /// variable
-    Var(String),
+    Var(Name),
     /// dimension name
-    Dim(String, Vec<Kinds>),
+    Dim(Name, Vec<Kinds>),
+    /// guard branches
+    Guard(Vec<Kinds>),
+    /// named block and its body
+    Def(Name, Kinds),
+    /// use of a named block
+    Use(Name),
+    /// comment
+    Comment,
 }
 impl PartialEq for Kind {
     fn eq(&self, other: &Self) -> bool {
@@ -30,6 +41,9 @@ impl PartialEq for Kind {
             (Self::Text(l0), Self::Text(r0)) => l0 == r0,
             (Self::Var(l0), Self::Var(r0)) => l0 == r0,
             (Self::Dim(l0, l1), Self::Dim(r0, r1)) => l0 == r0 && l1 == r1,
+            (Self::Guard(l0), Self::Guard(r0)) => l0 == r0,
+            (Self::Def(l0, l1), Self::Def(r0, r1)) => l0 == r0 && l1 == r1,
+            (Self::Use(l0), Self::Use(r0)) => l0 == r0,
             _ => core::mem::discriminant(self) == core::mem::discriminant(other),
         }
     }
@@ -41,6 +55,15 @@ pub fn kvar(name : impl Into<Name>) -> Kind { Kind::Var(name.into()) }
 pub fn kdim(name : impl Into<Name>, children: Vec<Kinds>) -> Kind { 
     Kind::Dim(name.into(), children)
 }
+pub fn kguard(branches: Vec<Kinds>) -> Kind {
+    Kind::Guard(branches)
+}
+pub fn kdef(name: impl Into<Name>, body: Kinds) -> Kind {
+    Kind::Def(name.into(), body)
+}
+pub fn kuse(name: impl Into<Name>) -> Kind {
+    Kind::Use(name.into())
+}
 /// get kinds, but use [`Kind::Txt`] for text.
 pub fn get_kinds(ts: Terms) -> Kinds {
     mk_kinds(ts, None)
@@ -62,14 +85,39 @@ fn mk_kinds(ts: Terms, src: Option<&str>) -> Kinds {
                     v.push(Text(src[span.as_range()].into()))
                 }
             }
-            TermK::Var(n) => v.push(Var(n)),
-            TermK::Dimension { name, children } => {
+            TermK::Var(n, _) => v.push(Var(n)),
+            TermK::Value(_) => v.push(Txt),
+            TermK::Dimension { name, children, .. } => {
                 let mut cs = Vec::new();
                 for c in children {
                     cs.push(mk_kinds(c, src));
                 }
                 v.push(Dim(name, cs))
             }
+            TermK::Guard { then, otherwise, .. } => {
+                let bs = std::iter::once(then).chain(otherwise).map(|b| mk_kinds(b, src)).collect();
+                v.push(Guard(bs))
+            }
+            TermK::Def { name, body } => v.push(Def(name, mk_kinds(body, src))),
+            TermK::Use { name, .. } => v.push(Use(name)),
+            TermK::Comment => v.push(Comment),
+        }
+    }
+    v
+}
+/// merges the adjacent [`Kind::Text`]s and drops the empty ones, e.g. to compare the trees of
+/// differently escaped sources
+pub fn merge_texts(ks: Kinds) -> Kinds {
+    use Kind::*;
+    let mut v: Kinds = Vec::new();
+    for k in ks {
+        match (v.last_mut(), k) {
+            (_, Text(t)) if t.is_empty() => {}
+            (Some(Text(prev)), Text(t)) => prev.push_str(&t),
+            (_, Dim(n, cs)) => v.push(Dim(n, cs.into_iter().map(merge_texts).collect())),
+            (_, Guard(bs)) => v.push(Guard(bs.into_iter().map(merge_texts).collect())),
+            (_, Def(n, body)) => v.push(Def(n, merge_texts(body))),
+            (_, k) => v.push(k),
         }
     }
     v
@@ -86,6 +134,17 @@ pub fn lex_str(src: &str) -> Vec<TokenK> {
     assert!(s.is_some());
     s.unwrap().iter().map(|t| t.node).collect()
 }
+/// lex and parse `src`, returning the emitted diagnostics instead of printing them
+pub fn diagnostics_str(src: &str) -> Vec<Error> {
+    let sink = MemorySink::new();
+    let mut h = Handler::with_sink(ErrorFlags::default(), SrcMap::new(), Box::new(sink.clone()));
+    if let Some(mut p) = string_to_parser(&mut h, src.into()) {
+        #[allow(unused_must_use)]
+        { p.parse(); }
+    }
+    h.print_all();
+    sink.errors()
+}
 pub fn stream_str(src: &str) -> Vec<Token> {
     let mut h = Handler::new(ErrorFlags::default(), SrcMap::new());
     let s = source_to_stream(&mut h, src);
@@ -97,18 +156,67 @@ pub fn stream_str(src: &str) -> Vec<Token> {
     v
 }
 
-pub fn write_str<'a>(src: &'a str, env: &Env) -> String {
+/// parses, [`flan::infer::resolve`]s and writes `src`
+pub fn write_str(src: &str, env: &Env) -> String {
     let terms = {
         let t = parse_str(src);
         assert!(t.is_ok());
-        t.unwrap()
+        flan::infer::resolve(&t.unwrap(), env)
     };
     let (mut from, mut to) = (Cursor::new(src.as_bytes()), Cursor::new(vec![]));
-    assert!(write_terms(&mut from, 0usize, &mut to, env, &terms).is_ok());
-    return std::str::from_utf8(to.get_ref()).unwrap().into();
+    assert!(write_terms(&mut from, 0usize, &mut to, &terms).is_ok());
+    std::str::from_utf8(to.get_ref()).unwrap().into()
 }
 
-pub fn write_terms<R, W>(from: &mut R, start: impl Into<usize>, to: &mut W, env: &Env, terms: &Terms) -> io::Result<()> 
+pub fn write_terms<R, W>(from: &mut R, start: impl Into<usize>, to: &mut W, terms: &Terms) -> io::Result<()> 
 where R: BufRead, W : Write {
-    output::write_terms(&mut ReadCtx::new(from, start), &mut WriteCtx::new(to), env, terms)
-}
\ No newline at end of file
+    let mut to = WriteCtx::new(to);
+    output::write_terms(&mut ReadCtx::new(from, start), &mut to, terms)?;
+    to.flush()
+}
+
+/// a directory `flan-NAME-PID` of the temporary directory, removed when dropped (even if the test fails).
+/// The tests running at the same time need different names.
+pub struct TestDir(PathBuf);
+impl TestDir {
+    /// the directory, emptied
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("flan-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TestDir(path)
+    }
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+    pub fn join<P: AsRef<Path>>(&self, rel: P) -> PathBuf {
+        self.0.join(rel)
+    }
+    /// writes `contents` to `rel`, and creates its parent directories
+    pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(&self, rel: P, contents: C) -> PathBuf {
+        let path = self.join(rel);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+    pub fn read<P: AsRef<Path>>(&self, rel: P) -> String {
+        std::fs::read_to_string(self.join(rel)).unwrap()
+    }
+    /// `s` with `DIR` replaced by the path of the directory
+    pub fn subst(&self, s: &str) -> String {
+        s.replace("DIR", &self.0.display().to_string())
+    }
+    /// [`run`] with the arguments `args` and the config file `toml`, in both `DIR` is the path of the directory.
+    /// The decisions of the arguments are parsed like the binary does.
+    pub fn run(&self, args: &[&str], toml: &str) -> Result<Report, RunError> {
+        let args: Vec<_> = args.iter().map(|a| self.subst(a)).collect();
+        let opt = Opt::from_iter(std::iter::once("flan").chain(args.iter().map(String::as_str)));
+        let (names, pairs) = opt.parse_decisions().unwrap();
+        run(&Flags::new(&opt, None), &Config::new(names, pairs, File::from_str(&self.subst(toml)).unwrap()))
+    }
+}
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
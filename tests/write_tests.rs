@@ -1,12 +1,13 @@
 use std::collections::HashMap;
 use std::iter::FromIterator;
+use std::str::FromStr;
 
-use flan::env::{Dim, Env};
+use flan::env::{ChoiceIdx, Dim, Env};
 use flan::error::{ErrorFlags, Handler};
 use flan::sourcemap::SrcMap;
 
 mod utils;
-use utils::{write_str, write_terms};
+use utils::{parse_str, write_str, write_terms, TestDir};
 
 macro_rules! mock_env {
     () => {
@@ -16,9 +17,9 @@ macro_rules! mock_env {
                 ("name".into(), "flan".into()),
             ]),
             HashMap::from_iter(vec![
-                ("dim0".into(), Dim::new(0)),
-                ("dim1".into(), Dim::new(0)),
-                ("dim2".into(), Dim::new(2)),
+                ("dim0".into(), Dim::new(ChoiceIdx(0))),
+                ("dim1".into(), Dim::new(ChoiceIdx(0))),
+                ("dim2".into(), Dim::new(ChoiceIdx(2))),
             ]),
             Handler::new(ErrorFlags::default(), SrcMap::new()),
         )
@@ -45,7 +46,7 @@ fn skip_txt() {
     let src = "hello, world!";
     let (mut from, mut to) = (Cursor::new(src.as_bytes()), Cursor::new(Vec::new()));
     let terms = vec![Term::new(TermK::Text, 0, 5), Term::new(TermK::Text, 6, 12)];
-    assert!(write_terms(&mut from, 0usize, &mut to, &mock_env!(), &terms).is_ok());
+    assert!(write_terms(&mut from, 0usize, &mut to, &terms).is_ok());
     let actual = std::str::from_utf8(to.get_ref()).unwrap();
     assert_eq!("hello world", actual);
 }
@@ -115,3 +116,1180 @@ fn escape_end() {
     let actual = write_str(src, &mock_env!());
     assert_eq!(expected, actual);
 }
+
+#[test]
+fn empty_choice_marker() {
+    let src = r#"#dim0{\~##foo}##dim2{foo##bar##\~}#flan"#;
+    let expected = "flan";
+    let actual = write_str(src, &mock_env!());
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn escape_roundtrip() {
+    use flan::syntax::escape::{escape, unescape};
+    let srcs = vec![
+        "#dim0{foo##bar}# #$var1# \\#escaped \\} \\\\ \\~",
+        "### }## #{ }# #é \\#a \\ \\$ #$",
+        "} \\",
+        "a#",
+        "b}",
+        "#!/bin/sh #! not a comment !#",
+        "x\\: y",
+    ];
+    for src in srcs {
+        let escaped = escape(src);
+        assert_eq!(src, unescape(&escaped));
+        assert_eq!(src, write_str(&escaped, &mock_env!()));
+        // escaped text is also left untouched inside of dimensions
+        let nested = format!("#dim0{{{}##}}#", escaped);
+        assert_eq!(src, write_str(&nested, &mock_env!()));
+    }
+}
+
+#[test]
+fn throttle_waits() {
+    use flan::output::Throttle;
+    use std::sync::Arc;
+    // raised to the descriptors needed by one file
+    let throttle = Arc::new(Throttle::new(1));
+    assert_eq!(throttle.max_open_files(), 2);
+    assert_eq!(throttle.batch_size(), 1);
+
+    let permit = throttle.acquire();
+    let throttle_ = throttle.clone();
+    let th = std::thread::spawn(move || {
+        let _permit = throttle_.acquire();
+    });
+    while throttle.waits() == 0 {
+        std::thread::yield_now();
+    }
+    drop(permit);
+    th.join().unwrap();
+    assert_eq!(throttle.waits(), 1);
+    // all the descriptors were given back
+    let _permit = throttle.acquire();
+    assert_eq!(throttle.waits(), 1);
+}
+
+#[test]
+fn resolved_write() {
+    use flan::infer::resolve;
+    use flan::syntax::TermK;
+    let srcs = vec![
+        ("foo #$var1# bar", "foo val1 bar"),
+        ("#dim0{foo##bar}# #dim2{a##b##c #$name#}#", "foo c flan"),
+        ("x #dim2{a##b###dim1{nested #$var1###no}# tail}# y", "x nested val1 tail y"),
+        ("#dim0{\\~##foo}##dim2{foo##bar##\\~}#flan", "flan"),
+    ];
+    for (src, expected) in srcs {
+        assert_eq!(write_str(src, &mock_env!()), expected);
+    }
+    let terms = resolve(&parse_str("a #dim1{b##c}# #$name# d").unwrap(), &mock_env!());
+    assert!(terms.iter().all(|t| matches!(t.node, TermK::Text | TermK::Value(_))));
+    assert_eq!(terms.last().unwrap().node, TermK::Text);
+    assert_eq!(terms[terms.len() - 2].node, TermK::Value("flan".into()));
+}
+
+#[test]
+fn default_branch() {
+    // dim2 is out of range, dim0 isn't
+    let src = "#dim2{a##b##!fallback}# #dim0{a##!fallback}#";
+    assert_eq!("fallback a", write_str(src, &mock_env!()));
+    assert_eq!("fallback a", write_str(src, &mock_env!()));
+}
+
+#[test]
+fn choice_labels() {
+    use flan::cfg::{parse_decisions, Config, File};
+    use flan::driver::make_env;
+    use flan::infer::{check, unify_labels};
+    let mk_env = |decl: &str, decisions: &[&str]| {
+        let (names, pairs) = parse_decisions(decisions).unwrap();
+        let config = Config::new(names, pairs, File::from_str(decl).unwrap());
+        make_env(&config, Handler::new(ErrorFlags::default(), SrcMap::new())).unwrap()
+    };
+    let src = "#os{linux: apt##windows: choco}#";
+    let terms = parse_str(src).unwrap();
+    // undeclared dimension, decided by name or by a pair
+    for decisions in &[&["windows"][..], &["os=windows"][..]] {
+        let mut env = mk_env("", decisions);
+        assert!(!unify_labels(vec![&terms], &mut env));
+        assert!(!check(&terms, &mut env).0);
+        assert_eq!(" choco", write_str(src, &env));
+    }
+    // labels have to agree with the declared names
+    let mut env = mk_env("[dimensions]\nos = [\"windows\", \"linux\"]", &["linux"]);
+    assert!(unify_labels(vec![&terms], &mut env));
+    let mut env = mk_env("[dimensions]\nos = [\"linux\", \"windows\"]", &["linux"]);
+    assert!(!unify_labels(vec![&terms], &mut env));
+    // and with the other uses
+    let other = parse_str("#os{windows: x##linux: y}#").unwrap();
+    let mut env = mk_env("", &["linux"]);
+    assert!(unify_labels(vec![&terms, &other], &mut env));
+}
+
+#[test]
+fn var_default() {
+    use flan::infer::check;
+    let src = "#$user:-guest# #$name:-nobody#";
+    let mut env = mock_env!();
+    assert!(!check(&parse_str(src).unwrap(), &mut env).0);
+    assert_eq!("guest flan", write_str(src, &env));
+    assert_eq!("guest flan", write_str(src, &env));
+}
+
+#[test]
+fn library_run() {
+    use flan::driver::RunError;
+    let dir = TestDir::new("run");
+    let args = ["-i", "DIR/in.conf", "-o", "DIR/out.conf", "linux"];
+    let decl = "[dimensions]\nos = [\"linux\", \"windows\"]";
+
+    dir.write("in.conf", "pkg = #os{apt##choco}#");
+    let report = dir.run(&args, decl).unwrap();
+    assert_eq!((report.files, report.written, report.copied), (1, 1, 0));
+    assert_eq!(dir.read("out.conf"), "pkg = apt");
+
+    // the errors are returned instead of aborting
+    dir.write("in.conf", "#$undeclared#");
+    match dir.run(&args, decl) {
+        Err(RunError::Diagnostics(errs)) => assert!(errs.iter().any(|e| e.msg().contains("`undeclared`"))),
+        r => panic!("expected diagnostics, got {:?}", r),
+    }
+}
+
+#[test]
+fn path_overrides() {
+    let dir = TestDir::new("overrides");
+    for f in &["app.conf", "dev.conf"] {
+        dir.write(format!("src/{}", f), "debug=#debug{0##1}# env=#$env#");
+    }
+    let toml = r#"
+        [variables]
+        env = "staging"
+        [dimensions]
+        debug = ["off", "on"]
+        [paths]
+        "DIR/src" = "DIR/out"
+        [paths."DIR/src/app.conf"]
+        decisions = ["debug=off"]
+        variables = { env = "production" }
+    "#;
+    let report = dir.run(&["on"], toml).unwrap();
+    assert_eq!(report.written, 2);
+    // the decision by name of the command line is replaced for `app.conf` only
+    assert_eq!(dir.read("out/app.conf"), "debug=0 env=production");
+    assert_eq!(dir.read("out/dev.conf"), "debug=1 env=staging");
+}
+
+#[test]
+fn path_targets() {
+    use flan::driver::RunError;
+    let dir = TestDir::new("targets");
+    dir.write("config", "pkg = #os{apt##choco}#");
+    let run_with = |targets: &str| dir.run(&[], &format!("[dimensions]\nos = [\"linux\", \"windows\"]\n[paths]\n\"DIR/config\" = [{}]", targets));
+    let report = run_with(r#"{ dest = "DIR/config.linux", decisions = ["linux"] }, { dest = "DIR/config.windows", decisions = ["os=windows"] }"#).unwrap();
+    assert_eq!(report.written, 2);
+    assert_eq!(dir.read("config.linux"), "pkg = apt");
+    assert_eq!(dir.read("config.windows"), "pkg = choco");
+    match run_with(r#"{ dest = "DIR/twice", decisions = ["linux"] }, { dest = "DIR/./twice" }"#) {
+        Err(RunError::Diagnostics(errs)) => assert!(errs.iter().any(|e| e.code() == Some(flan::error::codes::DUPLICATE_DESTINATION))),
+        r => panic!("expected diagnostics, got {:?}", r),
+    }
+}
+
+#[test]
+fn run_load_failed() {
+    let dir = TestDir::new("load-failed");
+    dir.write("a.conf", "a");
+    // the missing source is reported with the other diagnostics, the others are still written
+    let report = dir.run(&[], "[paths]\n\"DIR/a.conf\" = \"DIR/out/a.conf\"\n\"DIR/missing\" = \"DIR/out/missing\"").unwrap();
+    assert_eq!(report.written, 1);
+    assert!(report.diagnostics.iter().any(|e| e.code() == Some(flan::error::codes::LOAD_FAILED) && e.msg().contains("missing")));
+}
+
+#[test]
+fn archive_entries() {
+    use flan::output::archive::{crc32, entry_name, Format};
+    use std::path::Path;
+    assert_eq!(crc32(0, b"123456789"), 0xcbf43926);
+    assert_eq!(Format::from_path(Path::new("out.ZIP")), Some(Format::Zip));
+    // not compressed, see [`flan::output::archive`]
+    assert_eq!(Format::from_path(Path::new("out.tar.gz")), None);
+    assert_eq!(Format::from_path(Path::new("out.rar")), None);
+    assert_eq!(entry_name(Path::new("./out/a.conf")).unwrap(), "out/a.conf");
+    assert!(entry_name(Path::new("../a.conf")).is_err());
+}
+
+#[test]
+fn out_archive() {
+    use flan::output::archive::crc32;
+    let dir = TestDir::new("archive");
+    dir.write("in.conf", "pkg = #$pm#");
+    let write = |name: &str| {
+        let archive = format!("DIR/{}", name);
+        let report = dir.run(&["--out-archive", &archive], "[variables]\npm = \"apt\"\n[paths]\n\"DIR/in.conf\" = \"etc/app.conf\"").unwrap();
+        assert_eq!(report.written, 1);
+        std::fs::read(dir.join(name)).unwrap()
+    };
+    let tar = write("out.tar");
+    assert_eq!(&tar[..13], b"etc/app.conf\0");
+    // size, then the contents in the next block and two empty blocks
+    assert_eq!(&tar[124..136], b"00000000011\0");
+    assert_eq!(&tar[257..263], b"ustar\0");
+    assert_eq!(&tar[512..521], b"pkg = apt");
+    assert_eq!(tar.len(), 4 * 512);
+    let zip = write("out.zip");
+    assert_eq!(&zip[..4], b"PK\x03\x04");
+    assert_eq!(&zip[14..18], &crc32(0, b"pkg = apt").to_le_bytes());
+    // the end of the central directory, with a single entry
+    assert_eq!(&zip[zip.len() - 22..zip.len() - 18], b"PK\x05\x06");
+    assert_eq!(&zip[zip.len() - 12..zip.len() - 10], &[1, 0]);
+    assert!(!dir.join("etc").exists());
+}
+
+#[test]
+fn remote_sources() {
+    use flan::cfg::{Flags, Opt, StructOpt};
+    use flan::driver::fetch::{cache_path, fetch, is_url};
+    use flan::driver::{load_sources, RunError};
+    use flan::sourcemap::SourceOrigin;
+    use std::path::Path;
+    assert!(is_url(Path::new("https://example.com/app.conf")));
+    assert!(!is_url(Path::new("src/app.conf")));
+
+    let dir = TestDir::new("remote");
+    let cache = dir.join("cache");
+    let url = "https://example.com/shared/app.conf";
+    assert!(fetch(url, &cache, true).is_err());
+    std::fs::create_dir_all(&cache).unwrap();
+    assert!(cache_path(&cache, url).to_string_lossy().ends_with("-app.conf"));
+    std::fs::write(cache_path(&cache, url), "user = #$user#").unwrap();
+    let run_with = |vars: &str| {
+        let toml = format!("{}[paths]\n\"{}\" = \"DIR/app.conf\"", vars, url);
+        dir.run(&["--offline", "--cache-dir", "DIR/cache", "--force"], &toml)
+    };
+    assert_eq!(run_with("[variables]\nuser = \"flan\"\n").unwrap().written, 1);
+    assert_eq!(dir.read("app.conf"), "user = flan");
+    match run_with("") {
+        Err(RunError::Diagnostics(errs)) => assert!(errs.iter().any(|e| e.msg().contains("`user`"))),
+        r => panic!("expected diagnostics, got {:?}", r),
+    }
+    // loaded under the URL, which the diagnostics point at
+    let flags = Flags::new(&Opt::from_iter(&["flan", "--offline", "--cache-dir", cache.to_str().unwrap()]), None);
+    let paths = [(std::path::PathBuf::from(url), dir.join("app.conf"))];
+    let (_, sources) = load_sources(&flags, paths.iter().map(|(s, d)| (s, d)), &Default::default(), &Default::default());
+    assert_eq!((sources[0].path.to_str(), &sources[0].origin), (Some(url), &SourceOrigin::Remote));
+}
+
+/// serves `response` to a single request on localhost, the thread returns the request
+fn serve_once(response: &'static str) -> (String, std::thread::JoinHandle<String>) {
+    use std::io::{Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/app.conf", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = vec![0; 4096];
+        let n = stream.read(&mut request).unwrap();
+        stream.write_all(response.as_bytes()).unwrap();
+        String::from_utf8_lossy(&request[..n]).into_owned()
+    });
+    (url, server)
+}
+
+#[test]
+fn fetch_not_modified() {
+    use flan::driver::fetch::{cache_path, fetch, Fetched};
+    let dir = TestDir::new("not-modified");
+    let (url, server) = serve_once("HTTP/1.1 200 OK\r\nContent-Length: 6\r\nConnection: close\r\n\r\nfirst\n");
+    assert_eq!(fetch(&url, dir.path(), false).unwrap(), ("first\n".into(), Fetched::Downloaded));
+    assert!(!server.join().unwrap().contains("If-Modified-Since"));
+    // the cached copy is sent as the time condition, the server answers it didn't change
+    let (url2, server) = serve_once("HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n");
+    std::fs::copy(cache_path(dir.path(), &url), cache_path(dir.path(), &url2)).unwrap();
+    assert_eq!(fetch(&url2, dir.path(), false).unwrap(), ("first\n".into(), Fetched::NotModified));
+    assert!(server.join().unwrap().contains("If-Modified-Since"));
+}
+
+#[test]
+fn fetch_fallback() {
+    use flan::driver::fetch::{cache_path, fetch, Fetched};
+    use flan::error::codes;
+    let dir = TestDir::new("fallback");
+    let cache = dir.join("cache");
+    // nothing listens on the port after the server is gone
+    let url = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}/app.conf", listener.local_addr().unwrap())
+    };
+    assert!(fetch(&url, &cache, false).is_err());
+    std::fs::create_dir_all(&cache).unwrap();
+    std::fs::write(cache_path(&cache, &url), "user = flan").unwrap();
+    match fetch(&url, &cache, false).unwrap() {
+        (src, Fetched::Stale(e)) => assert!(src == "user = flan" && e.contains("`curl` failed"), "{}", e),
+        r => panic!("expected the cached copy, got {:?}", r),
+    }
+    let report = dir.run(&["--cache-dir", "DIR/cache"], &format!("[paths]\n\"{}\" = \"DIR/app.conf\"", url)).unwrap();
+    assert_eq!(report.written, 1);
+    assert!(report.diagnostics.iter().any(|e| e.code() == Some(codes::STALE_CACHE)));
+    assert_eq!(dir.read("app.conf"), "user = flan");
+}
+
+#[test]
+fn code_exit_codes() {
+    use flan::error::{codes, ExitCode};
+    assert_eq!(codes::UNCLOSED_DELIMITER.exit_code(), ExitCode::Parse);
+    assert_eq!(codes::UNDECLARED_VARIABLE.exit_code(), ExitCode::Type);
+    assert_eq!(codes::DUPLICATE_DESTINATION.exit_code(), ExitCode::Config);
+    assert_eq!(codes::LOAD_FAILED.exit_code(), ExitCode::IO);
+
+    // the first error gives the class
+    let mut h = Handler::new(ErrorFlags::default(), SrcMap::new());
+    assert_eq!(h.exit_code, ExitCode::Success);
+    h.warn(flan::error::WarnKind::CommandNotRun, "not an error").print();
+    h.error("parse").code(codes::UNEXPECTED_TOKEN).print();
+    h.error("type").code(codes::UNKNOWN_DIMENSION).print();
+    assert_eq!(h.exit_code, ExitCode::Parse);
+}
+
+#[test]
+fn run_exit_codes() {
+    use flan::error::ExitCode;
+    let dir = TestDir::new("exit");
+    let exit_code = |text: &str| {
+        dir.write("in.conf", text);
+        dir.run(&["-i", "DIR/in.conf", "-o", "DIR/out"], "").err().map(|e| e.exit_code())
+    };
+    assert_eq!(exit_code("#os{a##b"), Some(ExitCode::Parse));
+    assert_eq!(exit_code("#$undeclared#"), Some(ExitCode::Type));
+    assert_eq!(exit_code("text"), None);
+}
+
+#[test]
+fn check_command() {
+    use flan::cfg::{Command, Flags, Opt, StructOpt};
+    use flan::driver::RunError;
+    assert_eq!(Flags::new(&Opt::from_iter(&["flan", "check"]), None).command, Command::Check);
+    let dir = TestDir::new("check");
+    dir.write("in.conf", "pkg = #os{apt##choco}#");
+    let run_with = |sub: &[&str], toml: &str| {
+        let args: Vec<_> = ["-i", "DIR/in.conf", "-o", "DIR/out.conf"].iter().chain(sub).copied().collect();
+        dir.run(&args, toml)
+    };
+    // codes of the errors, without the notes and warnings
+    let codes = |r| match r {
+        Err(RunError::Diagnostics(errs)) => errs
+            .iter()
+            .filter(|e: &&flan::error::Error| e.level().as_u8() < flan::error::Level::Warning.as_u8())
+            .filter_map(|e| e.code().map(|c| c.to_string()))
+            .collect::<Vec<_>>(),
+        r => panic!("expected diagnostics, got {:?}", r),
+    };
+
+    let decl = "[dimensions]\nos = [\"linux\", \"windows\"]\narch = [\"x86\", \"arm\"]";
+    assert_eq!(run_with(&["check", "linux", "arm"], decl).unwrap().written, 0);
+    assert!(!dir.join("out.conf").exists());
+    // `arch` is unused, so only `check` needs a decision for it
+    assert!(run_with(&["build", "--dry-run", "linux"], decl).is_ok());
+    assert_eq!(codes(run_with(&["check", "linux"], decl)), vec!["F0205"]);
+    let decl = "[dimensions]\nos = [\"linux\", \"windows\", \"linux\"]";
+    dir.write("in.conf", "pkg = #os{apt##choco##apt}#");
+    assert_eq!(codes(run_with(&["check", "windows"], decl)), vec!["F0209"]);
+}
+
+#[test]
+fn run_lock() {
+    use flan::cfg::lock::Lock;
+    use flan::cfg::Index;
+    let dir = TestDir::new("run-lock");
+    dir.write("in.conf", "pkg = #os{apt##choco}# #$bin#");
+    let decl = "[variables]\nbin = \"#$home#/bin\"\nhome = \"/home\"\n[dimensions]\nos = [\"linux\", \"windows\"]\n[paths]\n\"DIR/in.conf\" = \"DIR/out.conf\"";
+    let run_with = |args: &[&str]| dir.run(&[&["-c", "DIR/flan.toml", "--lock", "--force"], args].concat(), decl);
+    assert!(run_with(&["windows"]).unwrap().diagnostics.is_empty());
+    assert_eq!(dir.read("out.conf"), "pkg = choco /home/bin");
+    let lock = Lock::from_str(&dir.read("flan.toml.lock")).unwrap();
+    assert_eq!(lock.decisions["os"], Index::Name("windows".into()));
+    assert_eq!(lock.variables["bin"], "#$home#/bin");
+    // a failed run keeps the lockfile
+    dir.write("in.conf", "#$undeclared#");
+    assert!(run_with(&["linux"]).is_err());
+    assert_eq!(Lock::from_str(&dir.read("flan.toml.lock")).unwrap(), lock);
+}
+
+#[test]
+fn run_matrix() {
+    use flan::driver::RunError;
+    let dir = TestDir::new("run-matrix");
+    dir.write("in.conf", "pkg = #os{apt##choco}#");
+    let decl = "[dimensions]\nos = [\"linux\", \"windows\"]\n[paths]\n\"DIR/in.conf\" = \"app.conf\"";
+    // each variant under the out prefix, by name
+    let report = dir.run(&["--all-variants", "-o", "DIR/all"], decl).unwrap();
+    assert_eq!((report.files, report.written), (1, 2));
+    assert_eq!(dir.read("all/linux/app.conf"), "pkg = apt");
+    assert_eq!(dir.read("all/windows/app.conf"), "pkg = choco");
+    dir.write("matrix.toml", dir.subst("[win]\ndecisions = [\"windows\"]\nout-prefix = \"DIR/win\""));
+    assert_eq!(dir.run(&["--matrix", "DIR/matrix.toml"], decl).unwrap().written, 1);
+    assert_eq!(dir.read("win/app.conf"), "pkg = choco");
+    dir.write("matrix.toml", "[bad]\ndecisions = [\"os=linux=windows\"]");
+    match dir.run(&["--matrix", "DIR/matrix.toml"], decl) {
+        Err(e @ RunError::Config(_)) => assert_eq!(e.exit_code(), flan::error::ExitCode::Config),
+        r => panic!("expected a config error, got {:?}", r),
+    }
+    assert!(matches!(dir.run(&["--matrix", "DIR/missing.toml"], decl), Err(RunError::Config(_))));
+}
+
+#[test]
+fn query_formats() {
+    use std::sync::Arc;
+    use flan::cfg::{parse_decisions, Config, File, QueryFormat, QueryScope};
+    use flan::driver::{make_env, pp_query, query_dims};
+    use flan::sourcemap::{self, SourceInfo};
+    let (names, pairs) = parse_decisions(&["windows", "sz=1"]).unwrap();
+    let config = Config::new(names, pairs, File::from_str("[dimensions]\nsz = 2").unwrap());
+    let mut env = make_env(&config, Handler::new(ErrorFlags::default(), SrcMap::new())).unwrap();
+    let src = |path: &str, s: &str| {
+        let f = sourcemap::File::new(path.into(), path.into(), SourceInfo::Source(s.into()));
+        (Arc::new(f), parse_str(s).unwrap())
+    };
+    let trees = vec![src("a", "#os{linux: a##windows: b}#"), src("b", "#sz{x##y}# #os{c##d}#")];
+    flan::infer::unify_labels(trees.iter().map(|t| &t.1), &mut env);
+
+    let query = query_dims(&trees, &mut env, &config.dimensions, QueryScope::All);
+    assert_eq!(query.iter().map(|d| d.name.as_str()).collect::<Vec<_>>(), vec!["os", "sz"]);
+    let (os, sz) = (&query[0], &query[1]);
+    assert_eq!(os.choices, Some(vec!["linux".into(), "windows".into()]));
+    assert_eq!((os.declared, os.decision, os.decision_name.as_deref()), (false, Some(ChoiceIdx(1)), Some("windows")));
+    assert_eq!(os.files, vec![std::path::PathBuf::from("a"), "b".into()]);
+    assert_eq!((sz.choices.as_ref(), sz.declared, sz.decision), (None, true, Some(ChoiceIdx(1))));
+
+    let json: serde_json::Value = serde_json::from_str(&pp_query(&query, QueryFormat::Json)).unwrap();
+    assert_eq!(json["dimensions"][0]["decision_name"], "windows");
+    let toml: toml::Value = toml::from_str(&pp_query(&query, QueryFormat::Toml)).unwrap();
+    assert_eq!(toml["dimensions"][1]["name"].as_str(), Some("sz"));
+    assert!(pp_query(&query, QueryFormat::Human).starts_with("dim os = [\"linux\", \"windows\"] (inferred) -> windows"));
+}
+
+#[test]
+fn graph() {
+    use std::sync::Arc;
+    use flan::cfg::{parse_decisions, Config, File, GraphFormat, Opt, StructOpt};
+    use flan::driver::{graph, make_env, pp_graph};
+    use flan::sourcemap::{self, SourceInfo};
+    assert_eq!(GraphFormat::from_opt(&Opt::from_iter(&["flan", "graph", "--format", "json"])), GraphFormat::Json);
+    let (names, pairs) = parse_decisions(&["os=1", "sz=1"]).unwrap();
+    let decl = "[dimensions]\nsz = 2\nmode = [\"dev\", \"prod\"]\n[variables]\nuser = \"flan\"";
+    let config = Config::new(names, pairs, File::from_str(decl).unwrap());
+    let mut env = make_env(&config, Handler::new(ErrorFlags::default(), SrcMap::new())).unwrap();
+    let src = |path: &str, s: &str| {
+        let f = sourcemap::File::new(path.into(), path.into(), SourceInfo::Source(s.into()));
+        (Arc::new(f), parse_str(s).unwrap())
+    };
+    let trees = vec![src("b", "#os{linux: a##windows: b}# #$host:-h#"), src("a", "#sz{#$user# ## #$host:-h#}# #when(os=linux){x}#")];
+    flan::infer::unify_labels(trees.iter().map(|t| &t.1), &mut env);
+
+    let g = graph(&trees, &mut env, &config);
+    assert_eq!(g.files, vec![std::path::PathBuf::from("a"), "b".into()]);
+    assert_eq!(g.dimensions.iter().map(|d| d.name.as_str()).collect::<Vec<_>>(), vec!["mode", "os", "sz"]);
+    let (mode, os) = (&g.dimensions[0], &g.dimensions[1]);
+    assert_eq!((mode.declared, mode.uses.len()), (true, 0));
+    assert_eq!((os.declared, os.choices.clone()), (false, Some(vec!["linux".into(), "windows".into()])));
+    let uses: Vec<_> = os.uses.iter().map(|u| (u.file.to_str().unwrap(), u.spans.clone())).collect();
+    assert_eq!(uses, vec![("b", vec![(0, 3)]), ("a", vec![(34, 42)])]);
+    assert_eq!(g.variables.iter().map(|v| (v.name.as_str(), v.declared, v.uses.len())).collect::<Vec<_>>(),
+               vec![("host", false, 2), ("user", true, 1)]);
+
+    let dot = pp_graph(&g, GraphFormat::Dot);
+    assert!(dot.starts_with("digraph flan {"));
+    assert!(dot.contains("\"dim:os\" [shape=ellipse, label=\"os\\nlinux | windows\", style=dashed];"), "{}", dot);
+    assert!(dot.contains("\"file:a\" -> \"var:host\" [label=\"1\"];"));
+    let json: serde_json::Value = serde_json::from_str(&pp_graph(&g, GraphFormat::Json)).unwrap();
+    assert_eq!(json["variables"][1]["uses"][0]["file"], "a");
+}
+
+#[test]
+fn template_tests() {
+    use flan::cfg::{Config, File, Flags, Opt, StructOpt};
+    use flan::driver::{run_test, TestOutcome};
+    use flan::utils::pp_diff;
+    assert_eq!(pp_diff("a\nb\nc\n", "a\nb\nc\n"), "");
+    assert_eq!(pp_diff("1\n2\n3\n4\n5\n6\n7\n", "1\n2\n3\nfour\n5\n6\n7"),
+               "@@ -2,6 +2,6 @@\n 2\n 3\n-4\n+four\n 5\n 6\n-7\n+7\n\\ no newline at the end\n");
+
+    let dir = TestDir::new("test");
+    std::fs::write(dir.join("t"), "#os{linux##windows}# #$user#\n").unwrap();
+    std::fs::write(dir.join("expected"), "windows root\n").unwrap();
+    let decl = format!(
+        "[variables]\nuser = \"flan\"\n[dimensions]\nos = 2\n\
+         [tests.linux]\ntemplate = {t:?}\ndecisions = [\"os=0\"]\nexpected = \"linux flan\\n\"\n\
+         [tests.windows]\ntemplate = {t:?}\ndecisions = [\"os=1\"]\nvariables = {{ user = \"root\" }}\nexpected_file = {e:?}\n\
+         [tests.wrong]\ntemplate = {t:?}\ndecisions = [\"os=1\"]\nexpected = \"linux flan\\n\"\n\
+         [tests.undecided]\ntemplate = {t:?}\nexpected = \"\"\n",
+        t = dir.join("t"), e = dir.join("expected"));
+    let (names, pairs) = flan::cfg::parse_decisions(&["os=1"]).unwrap();
+    let config = Config::new(names, pairs, File::from_str(&decl).unwrap());
+    let flags = Flags::new(&Opt::from_iter(&["flan", "test"]), None);
+    let run = |name: &str| run_test(&flags, &config, name, &config.tests[name]);
+    assert_eq!(config.tests.keys().collect::<Vec<_>>(), vec!["linux", "undecided", "windows", "wrong"]);
+    assert_eq!(run("linux"), TestOutcome::Passed);
+    assert_eq!(run("windows"), TestOutcome::Passed);
+    assert_eq!(run("wrong"), TestOutcome::Failed("@@ -1,1 +1,1 @@\n-linux flan\n+windows flan\n".into()));
+    // the decisions of the command line don't apply
+    match run("undecided") {
+        TestOutcome::Error(errors) => assert!(errors.iter().any(|e| e.contains("Unknown dimension `os`")), "{:?}", errors),
+        o => panic!("{:?}", o),
+    }
+}
+
+#[test]
+fn snapshots() {
+    use flan::cfg::{Command, Config, File, Flags, Opt, StructOpt};
+    use flan::driver::{file_variants, snapshot, snapshot_name};
+    let flags = Flags::new(&Opt::from_iter(&["flan", "matrix", "t.txt", "--out", "snap", "--limit", "3"]), None);
+    assert_eq!(flags.command, Command::Snapshots);
+    let snap = flags.snapshots.clone().unwrap();
+    assert_eq!((snap.template.to_str(), snap.out.to_str(), snap.limit), (Some("t.txt"), Some("snap"), 3));
+
+    let config = Config::new(Default::default(), Default::default(),
+                             File::from_str("[dimensions]\nos = [\"linux\", \"windows\"]\ndebug = 2\nunused = 2").unwrap());
+    let (path, src) = (std::path::Path::new("t.txt"), "#os{a##b}# #debug{off##on}#\n");
+    let matrix = file_variants(&flags, &config, path, src).unwrap();
+    let rendered: Vec<_> = matrix.variants.values()
+        .map(|v| (snapshot_name(v), snapshot(&flags, &config, path, src, v).unwrap()))
+        .collect();
+    assert_eq!(rendered, vec![
+        ("debug=0,os=linux".to_string(), "a off\n".to_string()),
+        ("debug=0,os=windows".into(), "b off\n".into()),
+        ("debug=1,os=linux".into(), "a on\n".into()),
+        ("debug=1,os=windows".into(), "b on\n".into()),
+    ]);
+    let matrix = file_variants(&flags, &config, path, "no dimensions\n").unwrap();
+    assert_eq!(matrix.variants.values().map(snapshot_name).collect::<Vec<_>>(), vec!["default"]);
+    assert!(file_variants(&flags, &config, path, "#os{a##b").is_err());
+}
+
+#[test]
+fn prompt_decisions() {
+    use flan::cfg::{parse_decisions, Config, File};
+    use flan::driver::{make_env, prompt_decisions, undecided_dims};
+    let (names, pairs) = parse_decisions(&["sz=1"]).unwrap();
+    let mut config = Config::new(names, pairs, File::from_str("[dimensions]\nsz = 2\nmode = [\"dev\", \"prod\"]").unwrap());
+    let src = "#mode{d##p}# #os{linux: a##windows: b}# #sz{x##y}# #x{0##!n}#";
+    let terms = parse_str(src).unwrap();
+    let mk_env = |config: &Config| {
+        let mut env = make_env(config, Handler::new(ErrorFlags::default(), SrcMap::new())).unwrap();
+        flan::infer::unify_labels(vec![&terms], &mut env);
+        env
+    };
+    let undecided = undecided_dims(vec![&terms].into_iter(), &mk_env(&config), &config.dimensions);
+    assert_eq!(undecided.iter().map(|d| d.name.as_str()).collect::<Vec<_>>(), vec!["mode", "os", "x"]);
+    assert_eq!(undecided[1].choices, Some(vec!["linux".into(), "windows".into()]));
+    assert!(undecided[2].default);
+
+    // invalid answers are asked again
+    let mut input = std::io::Cursor::new("staging\nprod\n2\nwindows\n5\n");
+    let mut output = Vec::new();
+    let decisions = prompt_decisions(&undecided, &mut input, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("  1: prod\n"));
+    assert!(output.contains("`staging` isn't a choice of dimension `mode`."));
+    assert!(output.contains("`2` isn't a choice of dimension `os`."));
+    config.decisions_pair.extend(decisions);
+    assert_eq!("p  b y n", write_str(src, &mk_env(&config)));
+
+    // running out of answers
+    let mut input = std::io::Cursor::new("dev\n");
+    let err = prompt_decisions(&undecided, &mut input, &mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn query_per_file() {
+    use flan::cfg::{Config, File, Opt, QueryFormat, QueryScope, StructOpt};
+    use flan::driver::{make_env, parse_sources, pp_query, query_dims};
+    let opt = Opt::from_iter(&["flan", "--query-dimensions=per-file"]);
+    assert_eq!(QueryScope::from_opt(&opt), QueryScope::PerFile);
+    assert_eq!(QueryScope::from_opt(&Opt::from_iter(&["flan", "-q"])), QueryScope::All);
+
+    let dir = TestDir::new("query");
+    std::fs::write(dir.join("a"), "#os{x##y}#\n\n#os{z##w}# #sz{1##2}#\n").unwrap();
+    std::fs::write(dir.join("b"), "text\n#sz{3##4}#\n").unwrap();
+    let sm = SrcMap::new();
+    let sources = vec![
+        sm.load_file(dir.join("a"), "a".into()).unwrap(),
+        sm.load_file(dir.join("b"), "b".into()).unwrap(),
+    ];
+    let mut h = Handler::new(ErrorFlags::default(), sm.clone());
+    let (trees, _) = parse_sources(sources, &mut h);
+    let (names, pairs) = flan::cfg::parse_decisions(&["os=0", "sz=1"]).unwrap();
+    let config = Config::new(names, pairs, File::default());
+    let mut env = make_env(&config, Handler::new(ErrorFlags::default(), sm)).unwrap();
+
+    let query = query_dims(&trees, &mut env, &config.dimensions, QueryScope::PerFile);
+    let lines = |d: usize| query[d].uses.iter().map(|u| (u.file.clone(), u.lines.clone())).collect::<Vec<_>>();
+    assert_eq!(lines(0), vec![(dir.join("a"), vec![1, 3])]);
+    assert_eq!(lines(1), vec![(dir.join("a"), vec![3]), (dir.join("b"), vec![2])]);
+    let human = pp_query(&query, QueryFormat::Human);
+    assert!(human.contains(&format!("  {}:1,3\n", dir.join("a").display())));
+
+    // only listed per file
+    let query = query_dims(&trees, &mut env, &config.dimensions, QueryScope::All);
+    assert!(query.iter().all(|d| d.uses.is_empty()));
+    assert!(!pp_query(&query, QueryFormat::Json).contains("uses"));
+}
+
+#[test]
+fn query_variables() {
+    use flan::cfg::{Config, File, QueryFormat, QueryScope};
+    use flan::driver::{make_env, pp_query_vars, query_vars};
+    let config = Config::new(Default::default(), Default::default(), File::from_str("[variables]\nwho = \"flan\"\nunused = \"x\"").unwrap());
+    let env = make_env(&config, Handler::new(ErrorFlags::default(), SrcMap::new())).unwrap();
+    let sm = SrcMap::new();
+    let src = |path: &str, s: &str| (sm.load_source(path.into(), path.into(), s.into()), parse_str(s).unwrap());
+    let trees = vec![src("a", "#$who#\n#os{#$other#}#\n"), src("b", "\n#$who# #$who#")];
+
+    let query = query_vars(&trees, &env, &config, QueryScope::PerFile);
+    assert_eq!(query.iter().map(|v| v.name.as_str()).collect::<Vec<_>>(), vec!["other", "who"]);
+    let (other, who) = (&query[0], &query[1]);
+    assert_eq!((other.declared, other.value.as_deref()), (false, None));
+    assert_eq!((who.declared, who.value.as_deref()), (true, Some("flan")));
+    assert_eq!(who.files, vec![std::path::PathBuf::from("a"), "b".into()]);
+    assert_eq!(who.uses.iter().map(|u| u.lines.clone()).collect::<Vec<_>>(), vec![vec![1], vec![2]]);
+    assert_eq!(pp_query_vars(&query, QueryFormat::Human), "var other (undeclared) -> unset\n  a:2\nvar who = \"flan\"\n  a:1\n  b:2\n");
+
+    let json: serde_json::Value = serde_json::from_str(&pp_query_vars(&query, QueryFormat::Json)).unwrap();
+    assert_eq!(json["variables"][1]["value"], "flan");
+    assert!(query_vars(&trees, &env, &config, QueryScope::All).iter().all(|v| v.uses.is_empty()));
+}
+
+#[test]
+fn guards() {
+    use flan::infer::check;
+    let mut env = mock_env!();
+    env.labels.insert("dim2".into(), vec![Some("a".into()), Some("b".into()), Some("c".into())]);
+    let src = "#when(dim0=0 && dim2=c){x##y}# #when(dim1!=0 || !(dim2=2)){z}#.";
+    assert!(!check(&parse_str(src).unwrap(), &mut env).0);
+    assert_eq!("x .", write_str(src, &env));
+    assert_eq!("x .", write_str(src, &env));
+    let src = "#when(dim0=1){x###dim1{y##z}#}#";
+    assert_eq!("y", write_str(src, &env));
+    assert_eq!("y", write_str(src, &env));
+
+    // unknown dimensions and choices
+    for src in &["#when(nope=0){x}#", "#when(dim2=d){x}#", "#when(dim0=a){x}#"] {
+        assert!(check(&parse_str(src).unwrap(), &mut env).0, "{}", src);
+    }
+}
+
+#[test]
+fn vectored_writes() {
+    use flan::output::{self, ReadCtx, WriteCtx};
+    use std::io::{self, Cursor, IoSlice, Write};
+
+    /// counts the calls, and writes at most 7 bytes of each call
+    struct Counting {
+        out: Vec<u8>,
+        calls: usize,
+        vectored: bool,
+    }
+    impl Write for Counting {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.calls += 1;
+            let n = buf.len().min(7);
+            self.out.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+        fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+            if !self.vectored {
+                return self.write(bufs.iter().find(|b| !b.is_empty()).map_or(&[][..], |b| &**b));
+            }
+            self.calls += 1;
+            let mut n = 0;
+            for b in bufs {
+                let m = b.len().min(7 - n);
+                self.out.extend_from_slice(&b[..m]);
+                n += m;
+            }
+            Ok(n)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    let src = "a #$var1#, #dim0{#$name###$var1#}#; #$name# b".repeat(20);
+    let terms = flan::infer::resolve(&parse_str(&src).unwrap(), &mock_env!());
+    let expected = "a val1, flan; flan b".repeat(20);
+    for vectored in [true, false] {
+        let mut to = Counting { out: Vec::new(), calls: 0, vectored };
+        let mut from = Cursor::new(src.as_bytes());
+        let mut ctx = WriteCtx::new(&mut to).vectored(vectored);
+        assert!(output::write_terms(&mut ReadCtx::new(&mut from, 0usize), &mut ctx, &terms).is_ok());
+        assert!(ctx.flush().is_ok());
+        assert_eq!(std::str::from_utf8(&to.out).unwrap(), expected);
+        // one call per 7 bytes, instead of one per term
+        assert_eq!(to.calls, expected.len().div_ceil(7));
+    }
+}
+
+#[test]
+fn buffered_file_output() {
+    use flan::cfg::{Flags, Opt, IO_BUFFER_SIZE_DEFAULT};
+    use flan::driver::{file_to_parser, write_to};
+    use structopt::StructOpt;
+
+    assert_eq!(Flags::new(&Opt::from_iter(&["flan"]), None).io_buffer_size, IO_BUFFER_SIZE_DEFAULT);
+    let mut flags = Flags::new(&Opt::from_iter(&["flan", "--io-buffer-size", "4", "--force"]), None);
+    assert_eq!(flags.io_buffer_size, 4);
+
+    let dir = TestDir::new("buffered");
+    // the skipped choices are longer than the buffer
+    let src = "#dim2{a skipped choice##another one##chosen}# and #$var1#, #dim0{x##a long skipped choice}#.";
+    std::fs::write(dir.join("src"), src).unwrap();
+    let sources = SrcMap::new();
+    let f = sources.load_file(dir.join("src"), dir.join("dest").into()).unwrap();
+    let mut h = Handler::new(ErrorFlags::default(), sources);
+    let terms = file_to_parser(&mut h, &f).unwrap().parse().unwrap();
+    let expected = "chosen and val1, x.";
+    for size in [4, 1, 4096] {
+        flags.io_buffer_size = size;
+        assert_eq!(write_to(&flags, f.clone(), &dir.join("dest").into(), &terms, &mock_env!()).unwrap(), expected.len());
+        assert_eq!(std::fs::read_to_string(dir.join("dest")).unwrap(), expected);
+    }
+}
+
+#[test]
+fn if_changed() {
+    use flan::cfg::{Flags, Opt};
+    use flan::driver::{file_to_parser, write_to};
+    use std::time::{Duration, SystemTime};
+    use structopt::StructOpt;
+
+    let dir = TestDir::new("if-changed");
+    std::fs::write(dir.join("src"), "#$var1# #dim2{a##b##c}#").unwrap();
+    let sources = SrcMap::new();
+    let f = sources.load_file(dir.join("src"), dir.join("dest").into()).unwrap();
+    let mut h = Handler::new(ErrorFlags::default(), sources);
+    let terms = file_to_parser(&mut h, &f).unwrap().parse().unwrap();
+    let (dest, past) = (dir.join("dest"), SystemTime::now() - Duration::from_secs(3600));
+    let to = f.destination.clone();
+    let set_mtime = || std::fs::File::options().write(true).open(&dest).unwrap().set_modified(past).unwrap();
+    let mtime = || std::fs::metadata(&dest).unwrap().modified().unwrap();
+
+    let flags = Flags::new(&Opt::from_iter(&["flan", "--if-changed"]), None);
+    assert!(flags.if_changed);
+    assert_eq!(write_to(&flags, f.clone(), &to, &terms, &mock_env!()).unwrap(), 6);
+    // unchanged: not rewritten
+    set_mtime();
+    assert_eq!(write_to(&flags, f.clone(), &to, &terms, &mock_env!()).unwrap(), 0);
+    assert_eq!(mtime(), past);
+    // changed: replaced, even without `--force`
+    std::fs::write(&dest, "val1 b").unwrap();
+    set_mtime();
+    assert_eq!(write_to(&flags, f.clone(), &to, &terms, &mock_env!()).unwrap(), 6);
+    assert_eq!(std::fs::read_to_string(&dest).unwrap(), "val1 c");
+    assert_ne!(mtime(), past);
+    // a prefix of the output isn't the same content either
+    std::fs::write(&dest, "val1").unwrap();
+    assert_eq!(write_to(&flags, f.clone(), &to, &terms, &mock_env!()).unwrap(), 6);
+
+    let flags = Flags::new(&Opt::from_iter(&["flan"]), None);
+    assert!(write_to(&flags, f.clone(), &to, &terms, &mock_env!()).is_err());
+}
+
+#[test]
+fn lossy_decode() {
+    use flan::cfg::{FileKinds, Flags, Opt};
+    use flan::driver::{file_to_parser, write_to};
+    use flan::sourcemap::{decode_lossy, FileKind};
+    use std::path::Path;
+    use structopt::StructOpt;
+
+    assert_eq!(decode_lossy(b"a\xffb\xe2\x82".to_vec()), "a\u{1a}b\u{1a}\u{1a}");
+    let kinds = FileKinds { lossy: vec!["*.ini".into()], ..Default::default() };
+    assert_eq!(kinds.kind(Path::new("a.ini")), FileKind::Lossy);
+    assert_eq!(kinds.kind(Path::new("a.conf")), FileKind::Auto);
+
+    let dir = TestDir::new("lossy");
+    std::fs::write(dir.join("src"), b"caf\xe9 #$var1# #dim2{\xff##\xfe##\xfd}#").unwrap();
+    let sources = SrcMap::new();
+    assert!(sources.load_file(dir.join("src"), dir.join("dest").into()).unwrap().is_binary());
+    let f = sources.load_file_as(dir.join("src"), dir.join("dest").into(), FileKind::Lossy).unwrap();
+    assert!(f.is_source());
+    assert_eq!(f.size(), 28);
+    let mut h = Handler::new(ErrorFlags::default(), sources);
+    let terms = file_to_parser(&mut h, &f).unwrap().parse().unwrap();
+    let flags = Flags::new(&Opt::from_iter(&["flan", "--lossy-decode"]), None);
+    write_to(&flags, f.clone(), &dir.join("dest").into(), &terms, &mock_env!()).unwrap();
+    // the invalid bytes of the kept text are written unchanged
+    assert_eq!(std::fs::read(dir.join("dest")).unwrap(), b"caf\xe9 val1 \xfd");
+}
+
+#[test]
+fn clean_destinations() {
+    use flan::cfg::{Command, Flags, Opt};
+    use flan::driver::{clean, file_to_parser, write_to};
+    use structopt::StructOpt;
+
+    let dir = TestDir::new("clean");
+    std::fs::write(dir.join("text"), "#$var1#").unwrap();
+    std::fs::write(dir.join("bin"), b"\xff").unwrap();
+    let sources = SrcMap::new();
+    let f = sources.load_file(dir.join("text"), dir.join("out/text").into()).unwrap();
+    let bin = sources.load_file(dir.join("bin"), dir.join("out/bin").into()).unwrap();
+    let mut h = Handler::new(ErrorFlags::default(), sources);
+    let trees = vec![(f.clone(), file_to_parser(&mut h, &f).unwrap().parse().unwrap())];
+    let bins = vec![bin.clone()];
+    let flags = |args: &[&str]| Flags::new(&Opt::from_iter(["flan", "clean"].iter().chain(args)), None);
+    let build = || {
+        write_to(&flags(&["--force"]), f.clone(), &f.destination, &trees[0].1, &mock_env!()).unwrap();
+        flan::driver::copy_bin(&flags(&["--force"]), bin.clone()).unwrap();
+    };
+    let env = mock_env!();
+
+    build();
+    assert_eq!(flags(&["--dry-run"]).command, Command::Clean { dry_run: true });
+    let listed = clean(&flags(&["--dry-run"]), &trees, &bins, &env, &mut h).unwrap();
+    assert_eq!(listed, vec![dir.join("out/text"), dir.join("out/bin")]);
+    assert!(dir.join("out/text").exists());
+    assert_eq!(clean(&flags(&[]), &trees, &bins, &env, &mut h).unwrap().len(), 2);
+    assert!(!dir.join("out/text").exists() && !dir.join("out/bin").exists());
+    // already clean
+    assert!(clean(&flags(&[]), &trees, &bins, &env, &mut h).unwrap().is_empty());
+
+    // a modified destination keeps all of them, unless forced
+    build();
+    std::fs::write(dir.join("out/text"), "edited").unwrap();
+    assert!(clean(&flags(&[]), &trees, &bins, &env, &mut h).unwrap().is_empty());
+    assert_eq!(h.err_count, 1);
+    assert!(dir.join("out/bin").exists());
+    assert_eq!(clean(&flags(&["--force"]), &trees, &bins, &env, &mut h).unwrap().len(), 2);
+    assert!(!dir.join("out/text").exists());
+}
+
+#[test]
+fn session() {
+    use flan::cfg::{Choices, Index};
+    use flan::driver::{RunError, Session};
+    let mut s = Session::in_memory();
+    s.config.variables.insert("name".into(), "flan".into());
+    s.config.dimensions.insert("os".into(), Choices::Names(vec!["linux".into(), "windows".into()]));
+    s.config.decisions_pair.insert("os".into(), Index::Name("windows".into()));
+    assert_eq!(s.render_to_string("#$name# on #os{a##b}#.").unwrap(), "flan on b.");
+    assert!(s.diagnostics().is_empty());
+
+    // the diagnostics of every source point into the same source map
+    assert!(s.parse_str("#os{").is_none());
+    assert!(matches!(s.render_to_string("#$user#"), Err(RunError::Diagnostics(_))));
+    let errs = s.diagnostics();
+    assert_eq!(errs.len(), 2);
+    assert!(errs.iter().all(|e| s.sources.lookup_span(e.span()).is_some()));
+}
+
+#[test]
+fn render_in_memory() {
+    use flan::cfg::{Decision, Index};
+    let vars = HashMap::from_iter(vec![("name".to_string(), "flan".to_string())]);
+    let decisions = vec![Decision::WithDim("dim".into(), Index::Num(ChoiceIdx(1))), Decision::Name("linux".into())];
+    let src = "#$name#: #dim{a##b}##os{windows: w##linux: l}#";
+    assert_eq!(flan::render(src, &decisions, &vars).unwrap(), "flan: b l");
+
+    let errs = flan::render("#$user# #dim{a}#", &[], &vars).unwrap_err();
+    assert!(!errs.is_empty());
+    assert!(flan::render("#dim{", &[], &vars).is_err());
+}
+
+#[test]
+fn trim_markers() {
+    let env = mock_env!();
+    let src = "a\n#dim0{-\n  x\n  -##-\n  y\n-}#\nb";
+    assert_eq!(write_str(src, &env), "a\nx\nb");
+    assert_eq!(write_str(src, &env), "a\nx\nb");
+    assert_eq!(write_str("#dim2{a -## b -##- c -}#.", &env), "c.");
+    assert_eq!(write_str("#dim1{a -##! b}#", &env), "a");
+    assert_eq!(write_str("#when(dim0=0){- x -}#", &env), "x");
+    // not trim markers
+    assert_eq!(write_str("#dim0{+x -\\~}# - a -", &env), "+x - - a -");
+}
+
+#[test]
+fn newline() {
+    use flan::cfg::Newline;
+    use flan::output::{self, ReadCtx, WriteCtx};
+    let env = mock_env!();
+    let src = "a\r\nb\n#$name#\r\r\n#dim0{x\r##y}#\r";
+    let terms = flan::infer::resolve(&parse_str(src).unwrap(), &env);
+    let render = |newline: Newline| {
+        // a single byte buffer splits the `\r\n`s between writes
+        let mut from = std::io::BufReader::with_capacity(1, src.as_bytes());
+        let mut out = Vec::new();
+        let mut to = WriteCtx::new(&mut out).newline(newline);
+        output::write_terms(&mut ReadCtx::new(&mut from, 0usize), &mut to, &terms).unwrap();
+        to.flush().unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    assert_eq!(render(Newline::Preserve), "a\r\nb\nflan\r\r\nx\r\r");
+    assert_eq!(render(Newline::Lf), "a\nb\nflan\r\nx\r\r");
+    assert_eq!(render(Newline::Crlf), "a\r\nb\r\nflan\r\r\nx\r\r");
+}
+
+#[test]
+fn named_blocks() {
+    let vars = HashMap::from_iter(vec![("name".to_string(), "flan".to_string())]);
+    let src = "#def(hi){hello #$name#, #dim{a##b}##}#\n#use(hi)#\n#use(hi, name=you)#";
+    let decisions = vec![flan::cfg::Decision::WithDim("dim".into(), flan::cfg::Index::Num(ChoiceIdx(1)))];
+    assert_eq!(flan::render(src, &decisions, &vars).unwrap(), "\nhello flan, b#\nhello you, b#");
+    // the blocks they use don't see their arguments
+    let src = "#def(a){#$name#}##def(b){#use(a)# #$name#}##use(b, name=x)#";
+    assert_eq!(flan::render(src, &[], &vars).unwrap(), "flan x");
+    // written in place, or resolved
+    let src = "#def(hi){#dim2{a##b##c}# #$name#}#: #use(hi, name=x)#, #use(hi)#.";
+    let mut env = mock_env!();
+    assert!(!flan::infer::unify_labels(vec![&parse_str(src).unwrap()], &mut env));
+    assert_eq!(write_str(src, &env), ": c x, c flan.");
+    assert_eq!(write_str(src, &env), ": c x, c flan.");
+
+    use flan::error::codes;
+    let codes = |src: &str| flan::render(src, &[], &vars).unwrap_err().iter().map(|e| e.code()).collect::<Vec<_>>();
+    assert_eq!(codes("#use(hi)#"), vec![Some(codes::UNKNOWN_BLOCK)]);
+    assert_eq!(codes("#def(a){}##def(a){}#"), vec![Some(codes::DUPLICATE_BLOCK)]);
+    assert_eq!(codes("#def(a){#use(b)#}##def(b){#use(a)#}#"), vec![Some(codes::RECURSIVE_BLOCK); 2]);
+    assert_eq!(codes("#def(a){#$name#}##use(a, user=x)#"), vec![Some(codes::INVALID_BLOCK)]);
+}
+
+#[test]
+fn named_block_parameters() {
+    let vars = HashMap::new();
+    // `who` is only bound by the use, it isn't declared
+    let src = "#def(h){Hello #$who#!}#\nA: #use(h, who=bob)#\n";
+    assert_eq!(flan::render(src, &[], &vars).unwrap(), "\nA: Hello bob!\n");
+    let src = "#def(h){Hello #$who:-you#!}##use(h)#";
+    assert_eq!(flan::render(src, &[], &vars).unwrap(), "Hello you!");
+}
+
+#[test]
+fn named_block_unbound() {
+    use flan::error::codes;
+    let vars = HashMap::new();
+    // the second use leaves `who` unbound, the error points at it
+    let src = "#def(h){Hello #$who#, #$who#!}##use(h, who=bob)# #use(h)#";
+    let errs = flan::render(src, &[], &vars).unwrap_err();
+    assert_eq!(errs.iter().map(|e| e.code()).collect::<Vec<_>>(), vec![Some(codes::UNDECLARED_VARIABLE)]);
+    assert_eq!(errs[0].span().lo, flan::sourcemap::BytePos::from(src.rfind("#use").unwrap()));
+}
+
+#[test]
+fn all_variants() {
+    use flan::cfg::{Decision, Index};
+    let vars = HashMap::new();
+    let src = "#os{linux: l##windows: w}##sz{s##m##l}#";
+    let variants: Vec<_> = flan::driver::render_variants(src, &vars).unwrap().collect();
+    assert_eq!(variants.len(), 6);
+    let (decisions, out) = &variants[0];
+    assert_eq!(decisions, &vec![
+        Decision::WithDim("os".into(), Index::Name("linux".into())),
+        Decision::WithDim("sz".into(), Index::Num(ChoiceIdx(0))),
+    ]);
+    assert_eq!(out.as_ref().unwrap(), " ls");
+    let outs: Vec<_> = variants.into_iter().map(|(_, out)| out.unwrap()).collect();
+    assert_eq!(outs, vec![" ls", " lm", " ll", " ws", " wm", " wl"]);
+
+    // a single variant without dimensions
+    assert_eq!(flan::driver::render_variants("txt", &vars).unwrap().count(), 1);
+}
+
+#[test]
+fn comments() {
+    let env = mock_env!();
+    assert_eq!(write_str("a#! note !#b", &env), "ab");
+    assert_eq!(write_str("#dim2{#! first !#x##y##z #!\n #dim0{## !#w}#", &env), "z w");
+    assert_eq!(write_str("#dim2{#! first !#x##y##z #!\n #dim0{## !#w}#", &env), "z w");
+    // a shebang isn't a comment
+    assert_eq!(write_str("#!/bin/sh\n#$name#", &env), "#!/bin/sh\nflan");
+}
+
+#[test]
+fn fmt_keeps_outputs() {
+    use flan::syntax::printer::format;
+    use flan::syntax::Delimiters;
+    let srcs = [
+        "#dim0{-\n  x: a\n  -##- y: b\n    c -}#\n#dim2{a -##- b ##- c}#",
+        "#when(dim0=0){-\n#dim2{-\nx\n-##-\n#$name#-##!z}#\n-}# #dim1{\n  spaced\n##\n}#",
+        "#def(h){-\n  #dim0{a\n##b}#\n-}##use(h) #dim1{\\~-\nx##-}#",
+        "a\r\n#dim0{-\r\n  x\r\n-##-\r\n  y\r\n-}#\r\n",
+    ];
+    for src in srcs.iter() {
+        let formatted = format(&parse_str(src).unwrap(), src, Delimiters::default());
+        assert_eq!(write_str(src, &mock_env!()), write_str(&formatted, &mock_env!()), "{}", formatted);
+        assert_eq!(format(&parse_str(&formatted).unwrap(), &formatted, Delimiters::default()), formatted);
+    }
+    let src = "#dim0{-\n  x: a\n  -##- y: b\n    c -}#\n#dim2{a -##- b}#";
+    let formatted = format(&parse_str(src).unwrap(), src, Delimiters::default());
+    assert_eq!(formatted, "#dim0{-\n    x: a\n-##-\n    y: b\n    c\n-}#\n#dim2{a##b}#");
+}
+
+#[test]
+fn specialize_keeps_undecided() {
+    use flan::infer::specialize;
+    use flan::syntax::printer::print;
+    let src = "#dim0{a##b}# #$name# #$unset# #os{x: #$var1# ##-#dim2{0##1##2}#}# #when(os=x){y##n}# #when(dim1=0){\\#$v#}#";
+    let tree = parse_str(src).unwrap();
+    let partial = print(&specialize(&tree, &mock_env!()), src);
+    assert_eq!(partial, "a flan #$unset# #os{x: val1 ##2}# #when(os=x){y##n}# \\#$v\\#");
+    // what is left is kept as is
+    let again = print(&specialize(&parse_str(&partial).unwrap(), &mock_env!()), &partial);
+    assert_eq!(again, partial);
+}
+
+#[test]
+fn origin_regions() {
+    use flan::output::origins::{regions, Origin};
+    let src = "a #$name# #dim2{x##y##z}# #$unset:-d# #when(dim0=0){b}##def(h){#$var1#}# #use(h)#.";
+    let mut env = mock_env!();
+    let tree = parse_str(src).unwrap();
+    assert!(!flan::infer::unify_labels(vec![&tree], &mut env));
+    let out = write_str(src, &env);
+    let regions = regions(&tree, &env);
+    let origins: Vec<_> = regions.iter().map(|r| r.origin).collect();
+    use Origin::*;
+    assert_eq!(origins, vec![Text, Variable, Text, Text, Text, Default, Text, Text, Text, Use, Text]);
+    // the regions cover the output, and the copied ones are the same in the source
+    assert_eq!(regions.last().unwrap().output.1, out.len());
+    for (r, next) in regions.iter().zip(regions.iter().skip(1)) {
+        assert_eq!(r.output.1, next.output.0);
+    }
+    for r in regions.iter().filter(|r| r.origin == Text) {
+        assert_eq!(&out[r.output.0..r.output.1], &src[r.source.0..r.source.1]);
+    }
+    assert_eq!(&src[regions[1].source.0..regions[1].source.1], "#$name#");
+    assert_eq!(&out[regions[9].output.0..regions[9].output.1], "val1");
+}
+
+#[test]
+fn headers() {
+    use flan::cfg::{Config, File, Flags, Opt};
+    use flan::driver::{file_to_parser, inject_headers, write_to};
+    use std::collections::HashSet;
+    use structopt::StructOpt;
+
+    let config = "[headers]\n\"off.sh\" = false\n\"*.css\" = [\"/*\", \"*/\"]\n";
+    let config = Config::new(HashSet::new(), HashMap::new(), File::from_str(config).unwrap());
+    let flags = Flags::new(&Opt::from_iter(&["flan", "--headers", "--force"]), None);
+    assert!(flags.headers);
+    let dir = TestDir::new("headers");
+    let files = [
+        ("run.sh", "#!/bin/sh\necho #dim2{a##b##c}#\n"),
+        ("off.sh", "echo #$var1#\n"),
+        ("a.css", "p {}\r\n"),
+        ("none", "x"),
+    ];
+    let sources = SrcMap::new();
+    let mut h = Handler::new(ErrorFlags::default(), sources.clone());
+    let trees = files
+        .iter()
+        .map(|(name, src)| {
+            std::fs::write(dir.join(name), src).unwrap();
+            let f = sources.load_file(dir.join(name), dir.join(format!("{}.out", name)).into()).unwrap();
+            let terms = file_to_parser(&mut h, &f).unwrap().parse().unwrap();
+            (f, terms)
+        })
+        .collect();
+    let env = mock_env!();
+    let expected = [
+        format!("#!/bin/sh\n# GENERATED by flan from {} (decisions: dim2=2)\necho c\n", dir.join("run.sh").display()),
+        "echo val1\n".into(),
+        format!("/* GENERATED by flan from {} */\r\np {{}}\r\n", dir.join("a.css").display()),
+        "x".into(),
+    ];
+    for ((f, terms), expected) in inject_headers(trees, &env, &config).into_iter().zip(expected.iter()) {
+        let dest = f.destination.clone();
+        write_to(&flags, f, &dest, &terms, &env).unwrap();
+        assert_eq!(&std::fs::read_to_string(dest.path().unwrap()).unwrap(), expected);
+    }
+}
+
+#[test]
+fn metrics_formats() {
+    use flan::cfg::MetricsFormat;
+    use flan::driver::metrics::Metrics;
+    use std::path::Path;
+    use std::time::Duration;
+
+    let mut m = Metrics::new();
+    m.total_files(2);
+    m.processed(2);
+    m.record_check(Path::new("a,b"), Duration::from_millis(2));
+    m.record_write(Path::new("a,b"), Duration::from_millis(3), 10);
+    m.record_write(Path::new("c"), Duration::from_millis(1), 4);
+    assert_eq!(m.bytes_w, 14);
+    let write = |format| {
+        let mut out = Vec::new();
+        m.write(format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    let csv = write(MetricsFormat::Csv);
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines[0], "path,bytes,parse_ms,check_ms,write_ms,written");
+    assert_eq!(lines[1], "\"a,b\",0,,2.000,3.000,10");
+    assert_eq!(lines[2], "c,0,,,1.000,4");
+    assert!(lines[3].starts_with(",0,") && lines[3].ends_with(",14"));
+    let json: serde_json::Value = serde_json::from_str(&write(MetricsFormat::Json)).unwrap();
+    assert_eq!(json["files"]["processed"], 2);
+    assert_eq!(json["files"]["copied"], serde_json::Value::Null);
+    assert_eq!(json["per_file"][0]["path"], "a,b");
+    assert_eq!(json["per_file"][1]["write_ms"], 1.0);
+    assert_eq!(json["bytes"]["written"], 14);
+}
+
+#[test]
+#[cfg(unix)]
+fn hooks() {
+    use flan::cfg::{Config, File, Flags, Opt, StructOpt};
+    use flan::driver::{run, RunError};
+    let dir = TestDir::new("hooks");
+    dir.write("run.sh.in", "echo #$msg#");
+    let out = dir.join("run.sh");
+    let run_with = |hooks: &str, run_hooks: bool| {
+        let toml = dir.subst(&format!("[variables]\nmsg = \"hi\"\n[paths]\n\"DIR/run.sh.in\" = \"DIR/run.sh\"\n[hooks]\n{}", hooks));
+        let mut config = Config::new(Default::default(), Default::default(), File::from_str(&toml).unwrap());
+        // set by `--no-hooks`
+        config.run_hooks = run_hooks;
+        run(&Flags::new(&Opt::from_iter(&["flan", "--force"]), None), &config)
+    };
+    let report = run_with(r#"pre = ["echo pre > DIR/log"]
+post = ["chmod +x DIR/run.sh", "cat DIR/run.sh >> DIR/log"]"#, true).unwrap();
+    assert_eq!(report.written, 1);
+    assert_eq!(std::fs::read_to_string(dir.join("log")).unwrap(), "pre\necho hi");
+    use std::os::unix::fs::PermissionsExt;
+    assert_eq!(std::fs::metadata(&out).unwrap().permissions().mode() & 0o100, 0o100);
+
+    // a failed `pre` hook stops before writing
+    std::fs::remove_file(&out).unwrap();
+    match run_with(r#"pre = ["exit 3", "echo never > DIR/log"]"#, true) {
+        Err(RunError::Diagnostics(errs)) => assert_eq!(errs[0].code(), Some(flan::error::codes::HOOK_FAILED)),
+        r => panic!("expected diagnostics, got {:?}", r),
+    }
+    assert!(!out.exists());
+    assert_eq!(std::fs::read_to_string(dir.join("log")).unwrap(), "pre\necho hi");
+    assert_eq!(run_with(r#"pre = ["exit 3"]"#, false).unwrap().written, 1);
+}
+
+#[test]
+fn ignore_unset() {
+    use flan::cfg::{File, Flags, Opt, StructOpt};
+    use flan::driver::Session;
+    let render = |args: &[&str], options: &str| {
+        let file = File::from_str(&format!("[options]\n{}", options)).unwrap();
+        let flags = Flags::new(&Opt::from_iter(["flan"].iter().chain(args)), file.options.as_ref());
+        let mut s = Session::from_config(flags, flan::cfg::Config::new(Default::default(), Default::default(), File::default()));
+        s.config.variables.insert("set".into(), "S".into());
+        s.render_to_string("#$set# #$a# #def(b){[#$c#]}##use(b)#").unwrap()
+    };
+    assert_eq!(render(&["--ignore-unset"], ""), "S  []");
+    assert_eq!(render(&["--ignore-unset=verbatim"], ""), "S #$a# [#$c#]");
+    assert_eq!(render(&["--ignore-unset=placeholder"], ""), "S ${a} [${c}]");
+    assert_eq!(render(&[], "ignore-unset = true"), "S  []");
+    assert_eq!(render(&[], "ignore-unset = \"verbatim\""), "S #$a# [#$c#]");
+    // the command line takes precedence
+    assert_eq!(render(&["--ignore-unset=placeholder"], "ignore-unset = \"verbatim\""), "S ${a} [${c}]");
+    assert!(File::from_str("[options]\nignore-unset = \"nothing\"").is_err());
+}